@@ -1484,13 +1484,17 @@ async function loadLB() {
         const prevCls = lbStatusColor(prev);
         const nextCls = lbStatusColor(next);
         const failsafeTag = e.failsafe ? ' <span class="badge err" style="font-size:9px">FS</span>' : '';
+        // Admin state changes are not status transitions: show the status as is.
+        const transition = (e.admin_state && !e.previous_status)
+          ? `<span style="color:${nextCls}">${esc(next)}</span>`
+          : `<span style="color:${prevCls}">${esc(prev)}</span><span class="lb-log-arrow">→</span><span style="color:${nextCls}">${esc(next)}</span>`;
         const detail = (e.detail || '').slice(0, 60);
         return `<tr>
           <td class="lb-log-time" style="white-space:nowrap">${esc(tStr)}</td>
           <td class="mono">${esc(e.fqdn)}</td>
           <td class="mono">${esc(e.ip)}</td>
           <td>
-            <span style="color:${prevCls}">${esc(prev)}</span><span class="lb-log-arrow">→</span><span style="color:${nextCls}">${esc(next)}</span>${failsafeTag}
+            ${transition}${failsafeTag}
             ${detail ? `<div style="color:var(--text-muted);font-size:10px;margin-top:2px">${esc(detail)}</div>` : ''}
           </td>
           <td><span class="lb-tag">${esc(e.probe_type)}</span></td>
@@ -1,7 +1,10 @@
 use super::proto;
 use crate::security::validate_dns_name;
+use crate::LbHandles;
 use microdns_core::db::Db;
-use microdns_core::types::{Lease, LeaseState, Record, RecordData, RecordSource, SoaData, Zone};
+use microdns_core::types::{
+    AdminState, Lease, LeaseState, Record, RecordData, RecordSource, SoaData, Zone,
+};
 use microdns_federation::heartbeat::HeartbeatTracker;
 use redb::{ReadableTable, TableDefinition};
 use std::sync::Arc;
@@ -17,6 +20,7 @@ pub struct MicroDnsGrpcService {
     db: Db,
     instance_id: String,
     heartbeat_tracker: Option<Arc<HeartbeatTracker>>,
+    lb: Option<LbHandles>,
 }
 
 impl MicroDnsGrpcService {
@@ -29,8 +33,14 @@ impl MicroDnsGrpcService {
             db,
             instance_id: instance_id.to_string(),
             heartbeat_tracker,
+            lb: None,
         }
     }
+
+    pub fn with_lb(mut self, lb: Option<LbHandles>) -> Self {
+        self.lb = lb;
+        self
+    }
 }
 
 fn zone_to_proto(z: &Zone) -> proto::Zone {
@@ -455,6 +465,13 @@ impl proto::health_service_server::HealthService for MicroDnsGrpcService {
 
             for record in &zone_records {
                 if record.health_check.is_some() {
+                    let admin_state = self
+                        .db
+                        .get_lb_health(&record.id)
+                        .ok()
+                        .flatten()
+                        .map(|h| h.admin_state)
+                        .unwrap_or_default();
                     records.push(proto::RecordHealth {
                         record_id: record.id.to_string(),
                         record_name: record.name.clone(),
@@ -462,6 +479,7 @@ impl proto::health_service_server::HealthService for MicroDnsGrpcService {
                         healthy: record.enabled,
                         success_count: 0,
                         failure_count: 0,
+                        admin_state: admin_state.to_string(),
                     });
                 }
             }
@@ -469,4 +487,40 @@ impl proto::health_service_server::HealthService for MicroDnsGrpcService {
 
         Ok(Response::new(proto::HealthStatusResponse { records }))
     }
+
+    async fn set_admin_state(
+        &self,
+        request: Request<proto::SetAdminStateRequest>,
+    ) -> Result<Response<proto::SetAdminStateResponse>, Status> {
+        let req = request.into_inner();
+        let record_id: Uuid = req
+            .record_id
+            .parse()
+            .map_err(|_| Status::invalid_argument("invalid record_id"))?;
+        let admin: AdminState = req.admin_state.parse().map_err(Status::invalid_argument)?;
+        let lb = self
+            .lb
+            .as_ref()
+            .ok_or_else(|| Status::unavailable("load balancer is not running"))?;
+
+        let change = microdns_lb::admin::set_admin_state(
+            &self.db,
+            &lb.state,
+            &lb.events,
+            record_id,
+            admin,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!("internal error: {e}");
+            Status::internal("internal server error")
+        })?
+        .ok_or_else(|| Status::not_found("record not found or has no health check"))?;
+
+        Ok(Response::new(proto::SetAdminStateResponse {
+            record_id: record_id.to_string(),
+            admin_state: admin.to_string(),
+            status: change.status.to_string(),
+        }))
+    }
 }
@@ -1,5 +1,6 @@
 pub mod dashboard;
 pub mod grpc;
+pub mod metrics;
 pub mod rest;
 pub mod security;
 pub mod ws;
@@ -9,17 +10,23 @@ use axum::response::Redirect;
 use axum::routing::get;
 use axum::Router;
 use tower_http::cors::{Any, CorsLayer};
+use tower_http::trace::TraceLayer;
 use microdns_core::config::{IpamPool, PeerConfig};
 use microdns_core::db::Db;
 use microdns_core::log_buffer::LogBuffer;
 use microdns_core::query_tracker::QueryTracker;
 use microdns_core::types::ProbeType;
 use microdns_federation::heartbeat::HeartbeatTracker;
+use microdns_federation::trace_context;
 use microdns_lb::halfopen::HalfOpenManager;
 use microdns_lb::monitor::StateChangeLog;
 use microdns_lb::{HealthState, StateChange};
+use metrics_exporter_prometheus::PrometheusHandle;
 use microdns_msg::MessageBus;
 use microdns_recursor::cache::DnsCache;
+use microdns_recursor::local_data::LocalDataState;
+use microdns_recursor::rpz::RpzState;
+use microdns_recursor::upstream::UpstreamTracker;
 use std::net::SocketAddr;
 use std::sync::atomic::AtomicUsize;
 use std::sync::Arc;
@@ -49,9 +56,13 @@ pub struct ApiServer {
     message_bus: Option<Arc<dyn MessageBus>>,
     event_tx: broadcast::Sender<DashboardEvent>,
     recursor_cache: Option<Arc<DnsCache>>,
+    recursor_upstreams: Option<Arc<UpstreamTracker>>,
+    recursor_rpz: Option<RpzState>,
+    recursor_local_data: Option<LocalDataState>,
     lb: Option<LbHandles>,
     mdns: Option<microdns_mdns::MdnsHandle>,
     query_tracker: Option<Arc<QueryTracker>>,
+    metrics: Option<PrometheusHandle>,
 }
 
 /// Hooks the API needs to surface load-balancer state and emit/receive
@@ -78,7 +89,8 @@ pub enum DashboardEvent {
     ZoneChanged { action: String, zone_id: String, zone_name: String },
     RecordChanged { action: String, zone_id: String, record_name: String },
     /// Load-balancer state-change. Emitted whenever a record's HealthStatus
-    /// flips, or whenever failsafe activates on a record.
+    /// flips, whenever failsafe activates on a record, or when an operator
+    /// changes its admin state.
     LbStateChange {
         record_id: String,
         zone_id: String,
@@ -90,6 +102,10 @@ pub enum DashboardEvent {
         failsafe: bool,
         probe_type: String,
         detail: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        cert_days_left: Option<i64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        admin_state: Option<microdns_core::types::AdminState>,
         at: String,
     },
 }
@@ -108,11 +124,19 @@ pub struct AppState {
     pub message_bus: Option<Arc<dyn MessageBus>>,
     pub event_tx: broadcast::Sender<DashboardEvent>,
     pub recursor_cache: Option<Arc<DnsCache>>,
+    /// Per-upstream latency and failure state, when the recursor is running.
+    pub recursor_upstreams: Option<Arc<UpstreamTracker>>,
+    /// Response policy in force, when the recursor is running.
+    pub recursor_rpz: Option<RpzState>,
+    /// Local data in force, when the recursor is running.
+    pub recursor_local_data: Option<LocalDataState>,
     pub started_at: Instant,
     pub lb: Option<LbHandles>,
     /// Live view of the mDNS discovery cache, when that source is running.
     pub mdns: Option<microdns_mdns::MdnsHandle>,
     pub query_tracker: Option<Arc<QueryTracker>>,
+    /// Renders `/metrics`, when a recorder is installed.
+    pub metrics: Option<PrometheusHandle>,
 }
 
 impl ApiServer {
@@ -132,9 +156,13 @@ impl ApiServer {
             message_bus: None,
             event_tx,
             recursor_cache: None,
+            recursor_upstreams: None,
+            recursor_rpz: None,
+            recursor_local_data: None,
             lb: None,
             mdns: None,
             query_tracker: None,
+            metrics: None,
         }
     }
 
@@ -153,11 +181,32 @@ impl ApiServer {
         self
     }
 
+    /// Serve what the installed recorder holds at `/metrics`.
+    pub fn with_metrics(mut self, handle: PrometheusHandle) -> Self {
+        self.metrics = Some(handle);
+        self
+    }
+
     pub fn with_recursor_cache(mut self, cache: Arc<DnsCache>) -> Self {
         self.recursor_cache = Some(cache);
         self
     }
 
+    pub fn with_recursor_upstreams(mut self, upstreams: Arc<UpstreamTracker>) -> Self {
+        self.recursor_upstreams = Some(upstreams);
+        self
+    }
+
+    pub fn with_recursor_rpz(mut self, rpz: RpzState) -> Self {
+        self.recursor_rpz = Some(rpz);
+        self
+    }
+
+    pub fn with_recursor_local_data(mut self, local_data: LocalDataState) -> Self {
+        self.recursor_local_data = Some(local_data);
+        self
+    }
+
     pub fn with_message_bus(mut self, bus: Arc<dyn MessageBus>) -> Self {
         self.message_bus = Some(bus);
         self
@@ -220,10 +269,14 @@ impl ApiServer {
             message_bus: self.message_bus,
             event_tx: self.event_tx,
             recursor_cache: self.recursor_cache,
+            recursor_upstreams: self.recursor_upstreams,
+            recursor_rpz: self.recursor_rpz,
+            recursor_local_data: self.recursor_local_data,
             started_at: Instant::now(),
             lb: self.lb,
             mdns: self.mdns,
             query_tracker: self.query_tracker,
+            metrics: self.metrics,
         };
 
         // Bridge LB state-changes onto the dashboard broadcast so the
@@ -249,6 +302,8 @@ impl ApiServer {
                                         failsafe: change.failsafe,
                                         probe_type: change.probe_type.to_string(),
                                         detail: change.detail,
+                                        cert_days_left: change.cert_days_left,
+                                        admin_state: change.admin_state,
                                         at: change.at.to_rfc3339(),
                                     });
                                 }
@@ -267,6 +322,7 @@ impl ApiServer {
         // API router: /api/v1 routes with body limit + api_key auth + CORS
         let api_app = Router::new()
             .nest("/api/v1", rest::router())
+            .merge(metrics::router())
             .layer(DefaultBodyLimit::max(MAX_BODY_SIZE))
             .layer(axum::middleware::from_fn_with_state(
                 state.clone(),
@@ -278,6 +334,17 @@ impl ApiServer {
                     .allow_methods(Any)
                     .allow_headers(Any),
             )
+            .layer(TraceLayer::new_for_http().make_span_with(
+                |request: &axum::http::Request<axum::body::Body>| {
+                    let span = tracing::info_span!(
+                        "http",
+                        method = %request.method(),
+                        path = %request.uri().path(),
+                    );
+                    trace_context::set_parent(&span, request.headers());
+                    span
+                },
+            ))
             .with_state(state.clone());
 
         let api_listener = tokio::net::TcpListener::bind(self.listen_addr).await?;
@@ -344,6 +411,7 @@ pub struct GrpcServer {
     db: Db,
     instance_id: String,
     heartbeat_tracker: Option<Arc<HeartbeatTracker>>,
+    lb: Option<LbHandles>,
 }
 
 impl GrpcServer {
@@ -353,6 +421,7 @@ impl GrpcServer {
             db,
             instance_id: String::new(),
             heartbeat_tracker: None,
+            lb: None,
         }
     }
 
@@ -366,6 +435,12 @@ impl GrpcServer {
         self
     }
 
+    /// Serve `HealthService.SetAdminState` against the running load balancer.
+    pub fn with_lb(mut self, lb: LbHandles) -> Self {
+        self.lb = Some(lb);
+        self
+    }
+
     pub async fn run(self, shutdown: watch::Receiver<bool>) -> anyhow::Result<()> {
         use grpc::proto::{
             cluster_service_server::ClusterServiceServer,
@@ -379,7 +454,8 @@ impl GrpcServer {
             self.db,
             &self.instance_id,
             self.heartbeat_tracker,
-        );
+        )
+        .with_lb(self.lb);
 
         // tonic requires separate service instances since they get moved
         // We use Arc to share the underlying state
@@ -389,6 +465,11 @@ impl GrpcServer {
 
         let mut shutdown = shutdown;
         tonic::transport::Server::builder()
+            .trace_fn(|request| {
+                let span = tracing::info_span!("grpc", path = %request.uri().path());
+                trace_context::set_parent(&span, request.headers());
+                span
+            })
             .add_service(ZoneServiceServer::from_arc(svc.clone()).max_decoding_message_size(1024 * 1024))
             .add_service(RecordServiceServer::from_arc(svc.clone()).max_decoding_message_size(1024 * 1024))
             .add_service(LeaseServiceServer::from_arc(svc.clone()).max_decoding_message_size(1024 * 1024))
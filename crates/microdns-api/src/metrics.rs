@@ -0,0 +1,169 @@
+//! Prometheus metrics at `/metrics`.
+//!
+//! The servers record counters and histograms as they work (queries, DHCP
+//! messages, probes). Levels that already live in a subsystem's own state —
+//! cache occupancy, upstream RTT, mDNS counters, peer staleness — are read from
+//! it when scraped rather than recorded twice.
+
+use crate::AppState;
+use axum::extract::State;
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Histogram buckets for every `*_seconds` metric: DNS answers from cache take
+/// well under a millisecond, probes and upstream queries up to a few seconds.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/metrics", get(render))
+}
+
+/// A recorder configured the way `/metrics` expects.
+pub fn builder() -> Result<PrometheusBuilder, BuildError> {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)
+}
+
+/// Install the process-wide recorder. The caller should call
+/// [`PrometheusHandle::run_upkeep`] every few seconds, or histograms grow
+/// between scrapes.
+pub fn install() -> Result<PrometheusHandle, BuildError> {
+    builder()?.install_recorder()
+}
+
+/// Everything recorded, in the Prometheus text format.
+async fn render(State(state): State<AppState>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let handle = state.metrics.as_ref().ok_or((
+        StatusCode::NOT_FOUND,
+        "metrics are not recorded on this instance".to_string(),
+    ))?;
+    refresh(&state).await;
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        handle.render(),
+    ))
+}
+
+/// Copy the levels held by each running subsystem into gauges.
+async fn refresh(state: &AppState) {
+    if let Some(cache) = &state.recursor_cache {
+        let stats = cache.stats();
+        metrics::gauge!("microdns_recursor_cache_entries").set(stats.entries as f64);
+        metrics::gauge!("microdns_recursor_cache_bytes").set(stats.bytes as f64);
+        metrics::gauge!("microdns_recursor_cache_max_bytes").set(stats.max_bytes as f64);
+        metrics::counter!("microdns_recursor_cache_hits_total").absolute(stats.hits as u64);
+        metrics::counter!("microdns_recursor_cache_misses_total").absolute(stats.misses as u64);
+        metrics::counter!("microdns_recursor_cache_evictions_total")
+            .absolute(stats.evictions as u64);
+        metrics::counter!("microdns_recursor_cache_expirations_total")
+            .absolute(stats.expirations as u64);
+    }
+
+    if let Some(upstreams) = &state.recursor_upstreams {
+        for upstream in upstreams.snapshot() {
+            let server = upstream.server;
+            if let Some(srtt) = upstream.srtt_ms {
+                metrics::gauge!(
+                    "microdns_recursor_upstream_srtt_seconds",
+                    "upstream" => server.clone(),
+                )
+                .set(srtt as f64 / 1000.0);
+            }
+            metrics::gauge!("microdns_recursor_upstream_healthy", "upstream" => server)
+                .set(if upstream.healthy { 1.0 } else { 0.0 });
+        }
+    }
+
+    if let Some(lb) = &state.lb {
+        let aggregate = lb.state.lock().await.aggregate();
+        for (status, count) in [
+            ("healthy", aggregate.healthy),
+            ("unhealthy", aggregate.unhealthy),
+            ("unknown", aggregate.unknown),
+        ] {
+            metrics::gauge!("microdns_lb_records", "status" => status).set(count as f64);
+        }
+    }
+
+    if let Some(mdns) = &state.mdns {
+        let cache = mdns.cache.lock().unwrap();
+        metrics::gauge!("microdns_mdns_cache_entries").set(cache.len() as f64);
+        metrics::counter!("microdns_mdns_packets_total").absolute(cache.stats.packets);
+        metrics::counter!("microdns_mdns_records_learned_total")
+            .absolute(cache.stats.records_learned);
+        metrics::counter!("microdns_mdns_goodbyes_total").absolute(cache.stats.goodbyes);
+        metrics::counter!("microdns_mdns_expired_total").absolute(cache.stats.expired);
+        metrics::counter!("microdns_mdns_queries_sent_total").absolute(cache.stats.queries_sent);
+    }
+
+    if let Some(tracker) = &state.heartbeat_tracker {
+        let now = chrono::Utc::now();
+        for peer in tracker.get_all_status().await {
+            let age = (now - peer.last_seen).num_milliseconds().max(0) as f64 / 1000.0;
+            metrics::gauge!(
+                "microdns_federation_peer_last_seen_seconds",
+                "instance" => peer.instance_id.clone(),
+            )
+            .set(age);
+            metrics::gauge!("microdns_federation_peer_healthy", "instance" => peer.instance_id)
+                .set(if peer.healthy { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use hickory_proto::serialize::binary::BinEncodable;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    #[test]
+    fn queries_render_as_counters_and_latency_buckets() {
+        let recorder = builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+
+        let mut query = Message::new();
+        query.add_query(Query::query(
+            Name::from_str("www.example.test.").unwrap(),
+            RecordType::A,
+        ));
+        let query = query.to_bytes().unwrap();
+        metrics::with_local_recorder(&recorder, || {
+            microdns_core::metrics::record_dns_query(
+                "auth",
+                Some("Example.Test."),
+                &query,
+                &query,
+                Duration::from_millis(3),
+            );
+        });
+
+        let text = handle.render();
+        assert!(
+            text.contains(
+                r#"microdns_dns_queries_total{server="auth",qtype="A",rcode="NOERROR",zone="example.test"} 1"#
+            ),
+            "{text}"
+        );
+        assert!(
+            text.contains(
+                r#"microdns_dns_query_duration_seconds_bucket{server="auth",le="0.005"} 1"#
+            ),
+            "{text}"
+        );
+        assert!(
+            text.contains(
+                r#"microdns_dns_query_duration_seconds_bucket{server="auth",le="0.0025"} 0"#
+            ),
+            "{text}"
+        );
+    }
+}
@@ -2,6 +2,7 @@ use crate::AppState;
 use axum::extract::State;
 use axum::routing::get;
 use axum::{Json, Router};
+use microdns_core::net::frame_dns_tcp;
 use serde::Serialize;
 use std::net::SocketAddr;
 use std::time::{Duration, Instant};
@@ -134,10 +135,7 @@ async fn probe_dns_tcp(addr: SocketAddr) -> ProbeResult {
 
     let result = tokio::time::timeout(timeout, async {
         let mut stream = TcpStream::connect(addr).await?;
-        let query = build_probe_query();
-        let len = query.len() as u16;
-        stream.write_all(&len.to_be_bytes()).await?;
-        stream.write_all(&query).await?;
+        stream.write_all(&frame_dns_tcp(&build_probe_query())).await?;
         stream.flush().await?;
 
         let resp_len = stream.read_u16().await? as usize;
@@ -157,15 +155,6 @@ async fn probe_dns_tcp(addr: SocketAddr) -> ProbeResult {
     }
 }
 
-/// Frame a DNS message for TCP transport (prepend 2-byte length).
-pub fn frame_dns_tcp(msg: &[u8]) -> Vec<u8> {
-    let len = msg.len() as u16;
-    let mut framed = Vec::with_capacity(2 + msg.len());
-    framed.extend_from_slice(&len.to_be_bytes());
-    framed.extend_from_slice(msg);
-    framed
-}
-
 async fn probe_http(url: &str) -> ProbeResult {
     let timeout = Duration::from_secs(3);
     let start = Instant::now();
@@ -240,17 +229,6 @@ mod tests {
         assert_eq!(query[16], 0x01);
     }
 
-    #[test]
-    fn test_frame_dns_tcp() {
-        let msg = vec![0x12, 0x34, 0x01, 0x00];
-        let framed = frame_dns_tcp(&msg);
-        assert_eq!(framed.len(), 6);
-        // 2-byte length prefix = 4
-        assert_eq!(framed[0], 0x00);
-        assert_eq!(framed[1], 0x04);
-        assert_eq!(&framed[2..], &msg);
-    }
-
     #[test]
     fn test_probe_result_success() {
         let r = ProbeResult::success(Duration::from_millis(42));
@@ -47,6 +47,19 @@ async fn create_forwarder(
     if req.servers.is_empty() {
         return Err((StatusCode::BAD_REQUEST, "servers list cannot be empty".to_string()));
     }
+    // An entry the recursor cannot parse is silently skipped at query time;
+    // refuse it here instead.
+    for server in &req.servers {
+        if microdns_recursor::upstream::Upstream::parse(server).is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!(
+                    "'{server}' is not an upstream: expected ip[:port], \
+                     tls://host[:port][#name] or https://host/path"
+                ),
+            ));
+        }
+    }
 
     let now = Utc::now();
     let fwd = DnsForwarder {
@@ -6,9 +6,9 @@ use axum::routing::{delete, get, post, put};
 use axum::{Json, Router};
 use chrono::{DateTime, Utc};
 use microdns_core::types::{
-    HealthCheck, HealthStatus, ProbeType, RecordData, RecordType,
+    AdminState, HealthCheck, HealthStatus, ProbeType, RecordData, RecordType,
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 use uuid::Uuid;
@@ -22,6 +22,7 @@ pub fn router() -> Router<AppState> {
         .route("/lb/resolutions", get(lb_resolutions))
         .route("/lb/debug", get(lb_debug))
         .route("/lb/probe/{record_id}", post(lb_probe))
+        .route("/lb/records/{record_id}/admin", put(lb_set_admin))
         .route(
             "/zones/{zone_id}/records/lb/{name}/{rtype}",
             put(lb_bulk_set),
@@ -95,6 +96,12 @@ struct RecordRow {
     last_probe_detail: String,
     consecutive_successes: u32,
     consecutive_failures: u32,
+    /// Days left on the member's certificate, for `tls` probes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cert_days_left: Option<i64>,
+    /// Healthy, but the certificate is inside the probe's `warn_days_left`.
+    cert_expiring: bool,
+    admin_state: AdminState,
     /// `now − last_checked_at` exceeded `2 × check_interval_secs`.
     stale: bool,
     age_seconds: Option<i64>,
@@ -299,6 +306,9 @@ async fn lb_records(
             last_probe_detail: h.last_probe_detail.clone(),
             consecutive_successes: h.success_count,
             consecutive_failures: h.failure_count,
+            cert_days_left: h.cert_days_left,
+            cert_expiring: h.cert_expiring,
+            admin_state: h.admin_state,
             stale,
             age_seconds,
         });
@@ -334,6 +344,9 @@ struct LogEntry {
     failsafe: bool,
     probe_type: ProbeType,
     detail: String,
+    /// Set on entries recording an admin-state change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    admin_state: Option<AdminState>,
 }
 
 async fn lb_log(
@@ -363,6 +376,7 @@ async fn lb_log(
             failsafe: c.failsafe,
             probe_type: c.probe_type,
             detail: c.detail,
+            admin_state: c.admin_state,
         })
         .collect();
     Ok(Json(rows))
@@ -419,6 +433,10 @@ struct ResolutionAnswer {
     failsafe: bool,
     /// Record TTL in seconds — what clients will cache the answer for.
     ttl: u32,
+    /// Share of traffic under `weighted_random` and `ratio` selection.
+    weight: u32,
+    /// Failover tier; 0 is primary.
+    priority: u32,
 }
 
 async fn lb_resolutions(
@@ -439,6 +457,8 @@ async fn lb_resolutions(
         status: HealthStatus,
         enabled: bool,
         ttl: u32,
+        weight: u32,
+        priority: u32,
         last_state_change_at: Option<DateTime<Utc>>,
     }
     #[derive(Default)]
@@ -465,6 +485,8 @@ async fn lb_resolutions(
             status: h.status,
             enabled: rec.enabled,
             ttl: rec.ttl,
+            weight: rec.health_check.as_ref().map_or(1, HealthCheck::weight),
+            priority: rec.health_check.as_ref().map_or(0, HealthCheck::priority),
             last_state_change_at: h.last_state_change_at,
         });
     }
@@ -529,6 +551,8 @@ async fn lb_resolutions(
                     status: m.status,
                     failsafe,
                     ttl: m.ttl,
+                    weight: m.weight,
+                    priority: m.priority,
                 })
             })
             .collect();
@@ -620,6 +644,7 @@ struct DebugPersistedRow {
     last_probe_detail: String,
     consecutive_successes: u32,
     consecutive_failures: u32,
+    admin_state: AdminState,
 }
 
 async fn lb_debug(
@@ -698,6 +723,7 @@ async fn lb_debug(
             last_probe_detail: p.last_probe_detail,
             consecutive_successes: p.consecutive_successes,
             consecutive_failures: p.consecutive_failures,
+            admin_state: p.admin_state,
         })
         .collect();
 
@@ -754,14 +780,7 @@ async fn lb_probe(
     });
     let ping_count: u8 = 3;
 
-    let result = microdns_lb::probe::run_probe(
-        hc.probe_type,
-        target,
-        timeout,
-        hc.endpoint.as_deref(),
-        ping_count,
-    )
-    .await;
+    let result = microdns_lb::probe::run_probe(&hc, target, timeout, ping_count).await;
 
     Ok(Json(serde_json::json!({
         "record_id": record_id,
@@ -769,10 +788,52 @@ async fn lb_probe(
         "probe_type": hc.probe_type,
         "success": result.success,
         "detail": result.detail,
+        "cert_days_left": result.cert_days_left,
+        "cert_expiring": result.cert_expiring,
         "latency_ms": result.latency.as_millis(),
     })))
 }
 
+// ─── PUT /lb/records/{record_id}/admin ──────────────────────────────────────
+//
+// Take a member out of rotation (`draining`, `maintenance`) or put it back
+// (`active`). Only monitored records have an admin state.
+
+#[derive(Deserialize)]
+struct AdminRequest {
+    admin_state: AdminState,
+}
+
+async fn lb_set_admin(
+    State(state): State<AppState>,
+    Path(record_id): Path<Uuid>,
+    Json(req): Json<AdminRequest>,
+) -> Result<Json<serde_json::Value>, (StatusCode, String)> {
+    let lb = state.lb.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "load balancer is not running".to_string(),
+    ))?;
+    let change = microdns_lb::admin::set_admin_state(
+        &state.db,
+        &lb.state,
+        &lb.events,
+        record_id,
+        req.admin_state,
+    )
+    .await
+    .map_err(internal_error)?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        "record not found or has no health check".to_string(),
+    ))?;
+
+    Ok(Json(serde_json::json!({
+        "record_id": record_id,
+        "admin_state": req.admin_state,
+        "status": change.status,
+    })))
+}
+
 // ─── PUT /zones/{zone_id}/records/lb/{name}/{rtype} ─────────────────────────
 
 #[derive(Serialize)]
@@ -786,6 +847,7 @@ async fn lb_bulk_set(
     Path((zone_id, name, rtype)): Path<(Uuid, String, String)>,
     Json(hc): Json<HealthCheck>,
 ) -> Result<Json<BulkResponse>, (StatusCode, String)> {
+    microdns_lb::probe::validate(&hc).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
     let rtype_norm = rtype.to_uppercase();
     let mut matched = 0usize;
     let mut updated = 0usize;
@@ -796,12 +858,21 @@ async fn lb_bulk_set(
             continue;
         }
         matched += 1;
+        // Weights and tiers are per member: one left out keeps each
+        // member's own.
+        let mut member_hc = hc.clone();
+        if member_hc.weight.is_none() {
+            member_hc.weight = r.health_check.as_ref().and_then(|h| h.weight);
+        }
+        if member_hc.priority.is_none() {
+            member_hc.priority = r.health_check.as_ref().and_then(|h| h.priority);
+        }
         let needs_update = match &r.health_check {
-            Some(existing) => !health_check_eq(existing, &hc),
+            Some(existing) => !health_check_eq(existing, &member_hc),
             None => true,
         };
         if needs_update {
-            r.health_check = Some(hc.clone());
+            r.health_check = Some(member_hc);
             state.db.update_record(&r).map_err(internal_error)?;
             updated += 1;
         }
@@ -871,4 +942,15 @@ fn health_check_eq(a: &HealthCheck, b: &HealthCheck) -> bool {
         && a.unhealthy_threshold == b.unhealthy_threshold
         && a.healthy_threshold == b.healthy_threshold
         && a.endpoint == b.endpoint
+        && a.weight == b.weight
+        && a.selection == b.selection
+        && a.answer_count == b.answer_count
+        && a.order == b.order
+        && a.max_answers == b.max_answers
+        && a.priority == b.priority
+        && a.preempt_delay_secs == b.preempt_delay_secs
+        && a.dns == b.dns
+        && a.http == b.http
+        && a.tls == b.tls
+        && a.grpc == b.grpc
 }
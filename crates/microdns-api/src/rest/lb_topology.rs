@@ -0,0 +1,84 @@
+//! Topology steering for load-balanced groups: which members clients in each
+//! network are answered with first.
+//!
+//! Stored in the database and applied live by the DNS servers, like the
+//! recursion access settings.
+
+use crate::security::internal_error;
+use crate::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{delete, get, put};
+use axum::{Json, Router};
+use microdns_core::config::LbTopologyConfig;
+use microdns_core::net::IpNet;
+
+/// Database section the rules live under. Shared with the binary, which
+/// seeds it from the config file on first run.
+pub const CONFIG_SECTION: &str = "lb_topology";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/lb/topology/config", get(get_config))
+        .route("/lb/topology/config", put(put_config))
+        .route("/lb/topology/config", delete(delete_config))
+}
+
+/// The stored rules, or 404 when none have been stored on this instance.
+async fn get_config(
+    State(state): State<AppState>,
+) -> Result<Json<LbTopologyConfig>, (StatusCode, String)> {
+    match state
+        .db
+        .get_runtime_section::<LbTopologyConfig>(CONFIG_SECTION)
+        .map_err(internal_error)?
+    {
+        Some(config) => Ok(Json(config)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "lb topology has not been configured on this instance".to_string(),
+        )),
+    }
+}
+
+/// Store the rules. The DNS servers apply them within seconds, without a
+/// restart.
+async fn put_config(
+    State(state): State<AppState>,
+    Json(config): Json<LbTopologyConfig>,
+) -> Result<Json<LbTopologyConfig>, (StatusCode, String)> {
+    for rule in &config.rules {
+        if rule.clients.is_empty() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("rule '{}' has no clients", rule.name),
+            ));
+        }
+        for cidr in rule.clients.iter().chain(&rule.members) {
+            if IpNet::parse(cidr).is_none() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    format!(
+                        "'{cidr}' in rule '{}' is not an address or CIDR block",
+                        rule.name
+                    ),
+                ));
+            }
+        }
+    }
+
+    state
+        .db
+        .set_runtime_section(CONFIG_SECTION, &config)
+        .map_err(internal_error)?;
+    Ok(Json(config))
+}
+
+/// Forget the rules: every client is answered from every healthy member.
+async fn delete_config(State(state): State<AppState>) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .db
+        .delete_runtime_section(CONFIG_SECTION)
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
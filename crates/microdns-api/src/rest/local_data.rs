@@ -0,0 +1,186 @@
+//! Recursor local data: per-name overrides and hosts files the recursor answers
+//! from before it forwards anything, without a zone to hold them.
+//!
+//! The settings are stored in the database and applied live, like the other
+//! recursor settings. Hosts files are re-read when they change on disk.
+
+use crate::security::{internal_error, validate_dns_name};
+use crate::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{delete, get, put};
+use axum::{Json, Router};
+use microdns_core::config::LocalDataConfig;
+use microdns_core::types::RecordData;
+use microdns_recursor::local_data::{HostsPolicy, LocalRecord};
+use std::collections::HashMap;
+
+/// Database section the settings live under. Shared with the binary, which
+/// seeds it from the config file on first run.
+pub const CONFIG_SECTION: &str = "local_data";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/recursor/local-data", get(list_records))
+        .route("/recursor/local-data/config", get(get_config))
+        .route("/recursor/local-data/config", put(put_config))
+        .route("/recursor/local-data/config", delete(delete_config))
+}
+
+/// Every record the recursor answers from local data, overrides and hosts
+/// file entries alike, with `source: "local"` and the file in `origin`.
+async fn list_records(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LocalRecord>>, (StatusCode, String)> {
+    let local_data = state.recursor_local_data.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the recursor is not running on this instance".to_string(),
+    ))?;
+    Ok(Json(local_data.current().records()))
+}
+
+/// The stored settings, or 404 when none have been stored on this instance.
+async fn get_config(
+    State(state): State<AppState>,
+) -> Result<Json<LocalDataConfig>, (StatusCode, String)> {
+    match state
+        .db
+        .get_runtime_section::<LocalDataConfig>(CONFIG_SECTION)
+        .map_err(internal_error)?
+    {
+        Some(config) => Ok(Json(config)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "local data has not been configured on this instance".to_string(),
+        )),
+    }
+}
+
+/// Store the overrides and hosts file list. A hosts file need not exist yet —
+/// it applies nothing until it does — but must be one the recursor's
+/// [`HostsPolicy`] lets it read.
+async fn put_config(
+    State(state): State<AppState>,
+    Json(config): Json<LocalDataConfig>,
+) -> Result<Json<LocalDataConfig>, (StatusCode, String)> {
+    let policy = state
+        .recursor_local_data
+        .as_ref()
+        .and_then(|local_data| local_data.hosts_policy());
+    validate(&config, policy.as_ref()).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    state
+        .db
+        .set_runtime_section(CONFIG_SECTION, &config)
+        .map_err(internal_error)?;
+    Ok(Json(config))
+}
+
+/// Forget the settings: no local data is answered.
+async fn delete_config(State(state): State<AppState>) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .db
+        .delete_runtime_section(CONFIG_SECTION)
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn validate(config: &LocalDataConfig, policy: Option<&HostsPolicy>) -> Result<(), String> {
+    let mut cnames = Vec::new();
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for record in &config.records {
+        let name = record.name.trim_end_matches('.').to_lowercase();
+        if name == "@" || name.starts_with('*') {
+            return Err(format!(
+                "'{}': local data is per name; wildcards and '@' are not supported",
+                record.name
+            ));
+        }
+        validate_dns_name(&record.name).map_err(|e| format!("'{}': {e}", record.name))?;
+        if matches!(record.data, RecordData::SOA(_)) {
+            return Err(format!(
+                "'{}': SOA records cannot be local data",
+                record.name
+            ));
+        }
+        if matches!(record.data, RecordData::CNAME(_)) {
+            cnames.push(name.clone());
+        }
+        *counts.entry(name).or_default() += 1;
+    }
+    if let Some(name) = cnames.iter().find(|name| counts[*name] > 1) {
+        return Err(format!(
+            "'{name}': a CNAME must be the only record at its name"
+        ));
+    }
+    for path in &config.hosts_files {
+        if !std::path::Path::new(path).is_absolute() {
+            return Err(format!("hosts file '{path}' must be an absolute path"));
+        }
+        if let Some(policy) = policy {
+            policy.check(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdns_core::config::LocalDataRecord;
+
+    fn record(name: &str, data: RecordData) -> LocalDataRecord {
+        LocalDataRecord {
+            name: name.into(),
+            ttl: 300,
+            data,
+        }
+    }
+
+    #[test]
+    fn rejects_what_the_recursor_cannot_answer() {
+        let a = RecordData::A("192.168.1.40".parse().unwrap());
+        let cname = RecordData::CNAME("printer.corp.example.".into());
+        let ok = LocalDataConfig {
+            records: vec![
+                record("printer.corp.example", a.clone()),
+                record("scanner.corp.example", cname.clone()),
+            ],
+            hosts_files: vec!["/etc/hosts".into()],
+        };
+        assert!(validate(&ok, None).is_ok());
+
+        for records in [
+            vec![record("*.corp.example", a.clone())],
+            vec![record("bad..name", a.clone())],
+            vec![
+                record("scanner.corp.example", cname.clone()),
+                record("Scanner.corp.example.", a.clone()),
+            ],
+        ] {
+            let config = LocalDataConfig {
+                records,
+                hosts_files: Vec::new(),
+            };
+            assert!(validate(&config, None).is_err(), "{config:?}");
+        }
+        assert!(validate(
+            &LocalDataConfig {
+                records: Vec::new(),
+                hosts_files: vec!["hosts".into()],
+            },
+            None
+        )
+        .is_err());
+
+        // With the recursor running, only files it may read are taken.
+        let policy = HostsPolicy::new("/var/lib/microdns".into(), vec!["/etc/hosts".into()]);
+        let hosts = |path: &str| LocalDataConfig {
+            records: Vec::new(),
+            hosts_files: vec![path.into()],
+        };
+        assert!(validate(&hosts("/etc/hosts"), Some(&policy)).is_ok());
+        assert!(validate(&hosts("/var/lib/microdns/lab.hosts"), Some(&policy)).is_ok());
+        assert!(validate(&hosts("/etc/shadow"), Some(&policy)).is_err());
+        assert!(validate(&hosts("/var/lib/microdns/../../etc/shadow"), Some(&policy)).is_err());
+    }
+}
@@ -9,10 +9,15 @@ pub mod dns_forwarders;
 pub mod health;
 pub mod ipam;
 pub mod lb;
+pub mod lb_topology;
 pub mod leases;
+pub mod local_data;
 pub mod logs;
 pub mod mdns;
 pub mod records;
+pub mod recursion_access;
+pub mod recursor;
+pub mod rpz;
 pub mod watch;
 pub mod zone_transfer;
 pub mod zones;
@@ -24,6 +29,10 @@ pub fn router() -> Router<AppState> {
     Router::new()
         .merge(zones::router())
         .merge(records::router())
+        .merge(recursor::router())
+        .merge(recursion_access::router())
+        .merge(rpz::router())
+        .merge(local_data::router())
         .merge(health::router())
         .merge(leases::router())
         .merge(cluster::router())
@@ -37,6 +46,7 @@ pub fn router() -> Router<AppState> {
         .merge(dhcp_config::router())
         .merge(dns_forwarders::router())
         .merge(lb::router())
+        .merge(lb_topology::router())
         .merge(mdns::router())
         .merge(zone_transfer::router())
         .merge(watch::router())
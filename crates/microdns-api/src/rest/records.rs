@@ -137,6 +137,9 @@ async fn create_record(
         .ok_or((StatusCode::NOT_FOUND, "zone not found".to_string()))?;
 
     validate_dns_name(&req.name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if let Some(hc) = &req.health_check {
+        microdns_lb::probe::validate(hc).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    }
 
     // Dedup: if an identical record (same name + type + data) already exists,
     // return it instead of creating a duplicate.
@@ -254,6 +257,9 @@ async fn update_record(
         record.enabled = enabled;
     }
     if let Some(health_check) = req.health_check {
+        if let Some(hc) = &health_check {
+            microdns_lb::probe::validate(hc).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        }
         record.health_check = health_check;
     }
     record.updated_at = Utc::now();
@@ -0,0 +1,75 @@
+//! Recursion access control: who may use the recursor, and whether everyone
+//! else still gets answers for local zones.
+//!
+//! Stored in the database and applied live, like the zone-transfer settings,
+//! so a `[dns.recursor]` edit on a generated config file is not the only way
+//! to close an open resolver.
+
+use crate::security::internal_error;
+use crate::AppState;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{delete, get, put};
+use axum::{Json, Router};
+use microdns_core::config::RecursionAccessConfig;
+use microdns_core::net::IpNet;
+
+/// Database section the settings live under. Shared with the binary, which
+/// seeds it from the config file on first run.
+pub const CONFIG_SECTION: &str = "recursion_access";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/recursor/access/config", get(get_config))
+        .route("/recursor/access/config", put(put_config))
+        .route("/recursor/access/config", delete(delete_config))
+}
+
+/// The stored settings, or 404 when none have been stored on this instance.
+async fn get_config(
+    State(state): State<AppState>,
+) -> Result<Json<RecursionAccessConfig>, (StatusCode, String)> {
+    match state
+        .db
+        .get_runtime_section::<RecursionAccessConfig>(CONFIG_SECTION)
+        .map_err(internal_error)?
+    {
+        Some(config) => Ok(Json(config)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "recursion access has not been configured on this instance".to_string(),
+        )),
+    }
+}
+
+/// Store the settings. The recursor applies them within seconds, without a
+/// restart.
+async fn put_config(
+    State(state): State<AppState>,
+    Json(config): Json<RecursionAccessConfig>,
+) -> Result<Json<RecursionAccessConfig>, (StatusCode, String)> {
+    for cidr in config.allow_recursion.iter().chain(&config.deny_recursion) {
+        if IpNet::parse(cidr).is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("'{cidr}' is not an address or CIDR block"),
+            ));
+        }
+    }
+
+    state
+        .db
+        .set_runtime_section(CONFIG_SECTION, &config)
+        .map_err(internal_error)?;
+    Ok(Json(config))
+}
+
+/// Forget the settings: the recursor falls back to the defaults — recursion
+/// for private ranges and loopback, local zones for everyone.
+async fn delete_config(State(state): State<AppState>) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .db
+        .delete_runtime_section(CONFIG_SECTION)
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
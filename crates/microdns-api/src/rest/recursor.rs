@@ -0,0 +1,169 @@
+//! Recursive resolver endpoints.
+
+use crate::security::validate_dns_name;
+use crate::AppState;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use hickory_proto::rr::RecordType;
+use microdns_core::types::RecordData;
+use microdns_recursor::cache::{CacheEntryInfo, CacheKey, CacheStats, DnsCache};
+use microdns_recursor::upstream::UpstreamStatus;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Most entries one listing returns.
+const MAX_LIST_LIMIT: usize = 10_000;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/recursor/upstreams", get(list_upstreams))
+        .route("/recursor/cache", get(cache_stats))
+        .route("/recursor/cache", delete(flush_cache))
+        .route("/recursor/cache/entries", get(list_entries))
+        .route("/recursor/cache/entries", delete(flush_entries))
+        .route("/recursor/cache/entries", post(seed_entries))
+}
+
+/// The upstream scoreboard: smoothed RTT and failure state per forwarder, in
+/// the order the recursor currently prefers them. Servers appear once they
+/// have been asked something.
+async fn list_upstreams(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<UpstreamStatus>>, (StatusCode, String)> {
+    let upstreams = state.recursor_upstreams.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the recursor is not running on this instance".to_string(),
+    ))?;
+    Ok(Json(upstreams.snapshot()))
+}
+
+/// Cache occupancy against its entry and byte budgets, hit and miss counts,
+/// and how many entries have been evicted for room or dropped on expiry.
+async fn cache_stats(
+    State(state): State<AppState>,
+) -> Result<Json<CacheStats>, (StatusCode, String)> {
+    let cache = state.recursor_cache.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the recursor is not running on this instance".to_string(),
+    ))?;
+    Ok(Json(cache.stats()))
+}
+
+fn recursor_cache(state: &AppState) -> Result<&Arc<DnsCache>, (StatusCode, String)> {
+    state.recursor_cache.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the recursor is not running on this instance".to_string(),
+    ))
+}
+
+#[derive(Debug, Serialize)]
+struct Flushed {
+    flushed: usize,
+}
+
+/// Empty the whole cache.
+async fn flush_cache(State(state): State<AppState>) -> Result<Json<Flushed>, (StatusCode, String)> {
+    let cache = recursor_cache(&state)?;
+    let flushed = cache.len();
+    cache.clear();
+    Ok(Json(Flushed { flushed }))
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EntryFilter {
+    /// Exactly this name.
+    name: Option<String>,
+    /// This name and everything under it.
+    suffix: Option<String>,
+    /// Names containing this text.
+    search: Option<String>,
+    /// One record type, e.g. `AAAA`.
+    #[serde(rename = "type")]
+    rtype: Option<String>,
+    limit: Option<usize>,
+}
+
+impl EntryFilter {
+    fn matcher(&self) -> Result<impl Fn(&CacheKey) -> bool, (StatusCode, String)> {
+        let rtype = match &self.rtype {
+            Some(t) => Some(u16::from(RecordType::from_str(&t.to_uppercase()).map_err(
+                |_| (StatusCode::BAD_REQUEST, format!("'{t}' is not a record type")),
+            )?)),
+            None => None,
+        };
+        let name = self.name.as_ref().map(|n| n.trim_end_matches('.').to_lowercase());
+        let suffix = self.suffix.clone();
+        let search = self.search.as_ref().map(|s| s.to_lowercase());
+        Ok(move |key: &CacheKey| {
+            rtype.is_none_or(|t| key.rtype == t)
+                && name.as_ref().is_none_or(|n| key.name == *n)
+                && suffix.as_ref().is_none_or(|s| key.is_at_or_under(s))
+                && search.as_ref().is_none_or(|s| key.name.contains(s.as_str()))
+        })
+    }
+}
+
+/// Cached entries with their remaining TTL and records, sorted by name.
+/// Filter with `name`, `suffix`, `search` and `type`; `limit` defaults to 100.
+async fn list_entries(
+    State(state): State<AppState>,
+    Query(filter): Query<EntryFilter>,
+) -> Result<Json<Vec<CacheEntryInfo>>, (StatusCode, String)> {
+    let cache = recursor_cache(&state)?;
+    let limit = filter.limit.unwrap_or(100).min(MAX_LIST_LIMIT);
+    Ok(Json(cache.entries(filter.matcher()?, limit)))
+}
+
+/// Flush the entries for one name (`name`) or a whole domain (`suffix`),
+/// optionally of one `type`. The whole cache is `DELETE /recursor/cache`.
+async fn flush_entries(
+    State(state): State<AppState>,
+    Query(filter): Query<EntryFilter>,
+) -> Result<Json<Flushed>, (StatusCode, String)> {
+    let cache = recursor_cache(&state)?;
+    if filter.name.is_none() && filter.suffix.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "give a name or a suffix to flush".to_string(),
+        ));
+    }
+    Ok(Json(Flushed {
+        flushed: cache.flush(filter.matcher()?),
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+struct SeedRequest {
+    name: String,
+    ttl: u32,
+    records: Vec<RecordData>,
+}
+
+#[derive(Debug, Serialize)]
+struct Seeded {
+    seeded: usize,
+}
+
+/// Put an answer in the cache by hand, as though upstream had sent it — to
+/// warm a name before a cutover. It expires like any other entry. Records use
+/// the same shape as the zone records API.
+async fn seed_entries(
+    State(state): State<AppState>,
+    Json(req): Json<SeedRequest>,
+) -> Result<Json<Seeded>, (StatusCode, String)> {
+    let cache = recursor_cache(&state)?;
+    validate_dns_name(req.name.trim_end_matches('.')).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    if req.ttl == 0 || req.records.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "an entry needs a non-zero ttl and at least one record".to_string(),
+        ));
+    }
+    let seeded = cache
+        .seed(&req.name, req.ttl, &req.records)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(Seeded { seeded }))
+}
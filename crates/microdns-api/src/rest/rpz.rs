@@ -0,0 +1,314 @@
+//! Response policy zones: which zones the recursor reads as block and rewrite
+//! rules, how each is doing, and a blocklist importer that fills one.
+//!
+//! The zone list is stored in the database and applied live, like the other
+//! recursor settings. The zones themselves are ordinary zones, so their rules
+//! can also be edited record by record, or mirrored from a primary by adding
+//! them as secondary zones in the zone-transfer settings.
+
+use crate::security::{internal_error, validate_dns_name};
+use crate::AppState;
+use axum::extract::{DefaultBodyLimit, Query, State};
+use axum::http::StatusCode;
+use axum::routing::{delete, get, post, put};
+use axum::{Json, Router};
+use chrono::Utc;
+use microdns_core::config::RpzConfig;
+use microdns_core::types::{Record, RecordData, RecordSource, SoaData, Zone};
+use microdns_recursor::rpz::{parse_blocklist, PolicyZoneStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Database section the settings live under. Shared with the binary, which
+/// seeds it from the config file on first run.
+pub const CONFIG_SECTION: &str = "rpz";
+
+/// Largest blocklist accepted in one import. Public lists run to a few MB.
+const MAX_IMPORT_SIZE: usize = 64 * 1024 * 1024;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/recursor/rpz", get(get_status))
+        .route("/recursor/rpz/config", get(get_config))
+        .route("/recursor/rpz/config", put(put_config))
+        .route("/recursor/rpz/config", delete(delete_config))
+        .route(
+            "/recursor/rpz/import",
+            post(import_blocklist).layer(DefaultBodyLimit::max(MAX_IMPORT_SIZE)),
+        )
+}
+
+/// Each policy zone in precedence order, with its rule counts and how often
+/// it has matched since it was last loaded.
+async fn get_status(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PolicyZoneStatus>>, (StatusCode, String)> {
+    let rpz = state.recursor_rpz.as_ref().ok_or((
+        StatusCode::SERVICE_UNAVAILABLE,
+        "the recursor is not running on this instance".to_string(),
+    ))?;
+    Ok(Json(rpz.status()))
+}
+
+/// The stored settings, or 404 when none have been stored on this instance.
+async fn get_config(
+    State(state): State<AppState>,
+) -> Result<Json<RpzConfig>, (StatusCode, String)> {
+    match state
+        .db
+        .get_runtime_section::<RpzConfig>(CONFIG_SECTION)
+        .map_err(internal_error)?
+    {
+        Some(config) => Ok(Json(config)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "response policy has not been configured on this instance".to_string(),
+        )),
+    }
+}
+
+/// Store the zone list. A zone need not exist yet — a mirror that has not
+/// transferred applies nothing until it does.
+async fn put_config(
+    State(state): State<AppState>,
+    Json(config): Json<RpzConfig>,
+) -> Result<Json<RpzConfig>, (StatusCode, String)> {
+    let mut seen = HashSet::new();
+    for zone in &config.zones {
+        validate_dns_name(zone).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+        if !seen.insert(zone.trim_end_matches('.').to_lowercase()) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                format!("policy zone '{zone}' is listed twice"),
+            ));
+        }
+    }
+
+    state
+        .db
+        .set_runtime_section(CONFIG_SECTION, &config)
+        .map_err(internal_error)?;
+    Ok(Json(config))
+}
+
+/// Forget the settings: no policy is applied. The zones themselves are kept.
+async fn delete_config(State(state): State<AppState>) -> Result<StatusCode, (StatusCode, String)> {
+    state
+        .db
+        .delete_runtime_section(CONFIG_SECTION)
+        .map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    /// Policy zone to import into; created if it does not exist. An existing
+    /// zone must already be in the policy list.
+    zone: String,
+    #[serde(default)]
+    action: BlockAction,
+    /// Block every subdomain of a listed name too, as ad and malware lists
+    /// intend.
+    #[serde(default = "default_true")]
+    subdomains: bool,
+    /// Replace the zone's rules instead of adding to them.
+    #[serde(default)]
+    replace: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum BlockAction {
+    #[default]
+    Nxdomain,
+    Nodata,
+}
+
+impl BlockAction {
+    /// The RPZ CNAME target that expresses this action.
+    fn target(self) -> &'static str {
+        match self {
+            BlockAction::Nxdomain => ".",
+            BlockAction::Nodata => "*.",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResult {
+    zone: String,
+    /// Distinct domains found in the list.
+    domains: usize,
+    /// Rules added to the zone.
+    added: usize,
+    /// Rules in the zone after the import.
+    rules: usize,
+}
+
+/// Import a hosts file or domain list into a policy zone, and make sure the
+/// zone is in the policy list. The body is the list as-is:
+///
+/// `curl --data-binary @hosts 'http://…/api/v1/recursor/rpz/import?zone=blocklist.rpz'`
+async fn import_blocklist(
+    State(state): State<AppState>,
+    Query(params): Query<ImportParams>,
+    body: String,
+) -> Result<Json<ImportResult>, (StatusCode, String)> {
+    let zone_name = params.zone.trim_end_matches('.').to_lowercase();
+    validate_dns_name(&zone_name).map_err(|e| (StatusCode::BAD_REQUEST, e))?;
+
+    let domains = parse_blocklist(&body);
+    if domains.is_empty() && !params.replace {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "no domains found — expected a hosts file or one domain per line".to_string(),
+        ));
+    }
+
+    let mut config = state
+        .db
+        .get_runtime_section::<RpzConfig>(CONFIG_SECTION)
+        .map_err(internal_error)?
+        .unwrap_or_default();
+    let existing = state
+        .db
+        .get_zone_by_name(&zone_name)
+        .map_err(internal_error)?;
+    check_import_target(&zone_name, existing.is_some(), &config)
+        .map_err(|e| (StatusCode::CONFLICT, e))?;
+
+    let zone = match existing {
+        Some(zone) => zone,
+        None => {
+            let zone = policy_zone(&zone_name);
+            state
+                .db
+                .create_zone(&zone_name, &zone)
+                .map_err(internal_error)?;
+            zone
+        }
+    };
+
+    let mut records = state.db.list_records(&zone.id).map_err(internal_error)?;
+    if params.replace {
+        records.retain(|r| r.name == "@");
+    }
+    let mut owners: HashSet<String> = records.iter().map(|r| r.name.to_lowercase()).collect();
+
+    let now = Utc::now();
+    let before = records.len();
+    for domain in &domains {
+        let wildcard = params.subdomains.then(|| format!("*.{domain}"));
+        for owner in std::iter::once(domain.clone()).chain(wildcard) {
+            if !owners.insert(owner.clone()) {
+                continue;
+            }
+            records.push(Record {
+                id: Uuid::new_v4(),
+                zone_id: zone.id,
+                name: owner,
+                ttl: zone.default_ttl,
+                data: RecordData::CNAME(params.action.target().to_string()),
+                enabled: true,
+                health_check: None,
+                source: RecordSource::Manual,
+                origin: None,
+                created_at: now,
+                updated_at: now,
+            });
+        }
+    }
+    let added = records.len() - before;
+
+    state
+        .db
+        .replace_zone_records(&zone.id, &records)
+        .map_err(internal_error)?;
+    state
+        .db
+        .increment_soa_serial(&zone.id)
+        .map_err(internal_error)?;
+
+    if !is_policy_zone(&config, &zone_name) {
+        config.zones.push(zone_name.clone());
+        state
+            .db
+            .set_runtime_section(CONFIG_SECTION, &config)
+            .map_err(internal_error)?;
+    }
+
+    Ok(Json(ImportResult {
+        zone: zone_name,
+        domains: domains.len(),
+        added,
+        rules: records.iter().filter(|r| r.name != "@").count(),
+    }))
+}
+
+fn is_policy_zone(config: &RpzConfig, zone_name: &str) -> bool {
+    config
+        .zones
+        .iter()
+        .any(|z| z.trim_end_matches('.').eq_ignore_ascii_case(zone_name))
+}
+
+/// An import may fill a zone already in the policy list, or create a new
+/// one. An ordinary zone by that name is refused: the import would turn its
+/// names into block rules, and `replace` would delete its records.
+fn check_import_target(zone_name: &str, exists: bool, config: &RpzConfig) -> Result<(), String> {
+    if exists && !is_policy_zone(config, zone_name) {
+        return Err(format!(
+            "zone '{zone_name}' exists and is not a policy zone; \
+             add it to the policy list first or import into a new zone"
+        ));
+    }
+    Ok(())
+}
+
+/// A new, empty policy zone.
+fn policy_zone(name: &str) -> Zone {
+    Zone {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        soa: SoaData {
+            mname: format!("ns1.{name}"),
+            rname: format!("admin.{name}"),
+            serial: Utc::now()
+                .format("%Y%m%d00")
+                .to_string()
+                .parse()
+                .unwrap_or(1),
+            refresh: 3600,
+            retry: 900,
+            expire: 604800,
+            minimum: 300,
+        },
+        default_ttl: 300,
+        created_at: Utc::now(),
+        updated_at: Utc::now(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imports_only_fill_policy_zones_or_create_new_ones() {
+        let config = RpzConfig {
+            zones: vec!["blocklist.rpz.".into()],
+        };
+        assert!(check_import_target("blocklist.rpz", true, &config).is_ok());
+        assert!(check_import_target("ads.rpz", false, &config).is_ok());
+
+        // An authoritative zone that happens to share the name is left alone.
+        let err = check_import_target("corp.example", true, &config).unwrap_err();
+        assert!(err.contains("not a policy zone"));
+        assert!(check_import_target("corp.example", true, &RpzConfig::default()).is_err());
+    }
+}
@@ -1,24 +1,52 @@
 use hickory_proto::rr::LowerName;
 use microdns_core::db::Db;
+use microdns_core::zone_index::ZoneTree;
+use microdns_core::topology::{Topology, TopologyState};
 use std::str::FromStr;
+use std::sync::Arc;
 
 /// Manages the set of zones this server is authoritative for.
-/// Checks against the database to determine if we should answer queries.
+/// Answers come from the database's compiled zones, load-balanced groups
+/// steered by the topology rules.
 pub struct ZoneCatalog {
     db: Db,
+    topology: TopologyState,
 }
 
 impl ZoneCatalog {
     pub fn new(db: Db) -> Self {
-        Self { db }
+        Self {
+            db,
+            topology: TopologyState::default(),
+        }
+    }
+
+    /// Steer load-balanced answers by rules kept current by whoever holds
+    /// the handle.
+    pub fn with_topology(mut self, topology: TopologyState) -> Self {
+        self.topology = topology;
+        self
     }
 
     /// Check if this server is authoritative for the given name
     pub fn is_authoritative(&self, name: &LowerName) -> bool {
-        let fqdn = name.to_string();
-        let fqdn = fqdn.trim_end_matches('.');
+        self.zone_for(name).is_some()
+    }
+
+    /// The zone that holds the given name, if this server is authoritative
+    /// for it.
+    pub fn zone_for(&self, name: &LowerName) -> Option<String> {
+        self.tree().find_zone(name).map(|z| z.zone().name.clone())
+    }
+
+    /// The zones as compiled for answering, current as of the last write.
+    pub fn tree(&self) -> Arc<ZoneTree> {
+        self.db.zone_tree()
+    }
 
-        matches!(self.db.find_zone_for_fqdn(fqdn), Ok(Some(_)))
+    /// The topology rules in force.
+    pub fn topology(&self) -> Arc<Topology> {
+        self.topology.current()
     }
 
     /// Get zone names from the database
@@ -2,15 +2,18 @@ use crate::catalog::ZoneCatalog;
 use crate::transfer::ZoneTransfer;
 use crate::runtime::TransferState;
 use crate::secondary::NotifyAcceptor;
-use crate::zone;
 use hickory_proto::op::{MessageType, OpCode, ResponseCode};
 use hickory_proto::rr::{LowerName, RecordType};
 use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
 use microdns_core::db::Db;
+use microdns_core::dnstap::{self, Dnstap, Protocol};
+use microdns_core::metrics;
+pub use microdns_core::net::IpNet;
 use microdns_core::query_tracker::QueryTracker;
-use std::net::{IpAddr, SocketAddr};
+use microdns_core::topology::{self, TopologyState};
+use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::Semaphore;
@@ -30,24 +33,25 @@ const AXFR_RECORDS_PER_MESSAGE: usize = 100;
 pub struct AuthServer {
     listen_addr: SocketAddr,
     catalog: Arc<ZoneCatalog>,
-    db: Db,
     tracker: Option<Arc<QueryTracker>>,
     /// Live zone-transfer settings: the AXFR ACL, and whose NOTIFY to believe.
     transfer: TransferState,
     /// Accepts inbound NOTIFY for zones this instance mirrors. Absent when the
     /// instance is not a secondary for anything.
     notify: Option<NotifyAcceptor>,
+    /// Where queries and answers are logged as dnstap, when they are.
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl AuthServer {
     pub fn new(listen_addr: SocketAddr, db: Db) -> Self {
         Self {
             listen_addr,
-            catalog: Arc::new(ZoneCatalog::new(db.clone())),
-            db,
+            catalog: Arc::new(ZoneCatalog::new(db)),
             tracker: None,
             transfer: TransferState::default(),
             notify: None,
+            dnstap: None,
         }
     }
 
@@ -72,6 +76,14 @@ impl AuthServer {
         self.with_transfer_state(TransferState::new(&config))
     }
 
+    /// Answer load-balanced groups with the members each client's topology
+    /// rule prefers. The rules are shared with whatever keeps them current.
+    pub fn with_topology(mut self, topology: TopologyState) -> Self {
+        let db = self.catalog.db().clone();
+        self.catalog = Arc::new(ZoneCatalog::new(db).with_topology(topology));
+        self
+    }
+
     pub fn with_query_tracker(mut self, tracker: Arc<QueryTracker>) -> Self {
         self.tracker = Some(tracker);
         self
@@ -84,6 +96,13 @@ impl AuthServer {
         self
     }
 
+    /// Log queries and their answers to dnstap as `AUTH_QUERY` and
+    /// `AUTH_RESPONSE`. Zone transfers are not logged.
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.dnstap = Some(dnstap);
+        self
+    }
+
     pub async fn run(self, shutdown: tokio::sync::watch::Receiver<bool>) -> anyhow::Result<()> {
         let socket = UdpSocket::bind(self.listen_addr).await?;
         let tcp_listener = TcpListener::bind(self.listen_addr).await?;
@@ -97,7 +116,7 @@ impl AuthServer {
         let mut shutdown_tcp = shutdown;
 
         let catalog_tcp = self.catalog.clone();
-        let db_tcp = self.db.clone();
+        let dnstap_tcp = self.dnstap.clone();
         let tracker_tcp = self.tracker.clone();
         let transfer_tcp = self.transfer.clone();
         let notify_tcp = self.notify.clone();
@@ -119,7 +138,7 @@ impl AuthServer {
                                 };
                                 debug!("TCP connection from {src}");
                                 let catalog = catalog_tcp.clone();
-                                let db = db_tcp.clone();
+                                let dnstap = dnstap_tcp.clone();
                                 let tracker = tracker_tcp.clone();
                                 let transfer = transfer_tcp.clone();
                                 let notify = notify_tcp.clone();
@@ -129,7 +148,7 @@ impl AuthServer {
                                         handle_tcp_connection(
                                             stream,
                                             &catalog,
-                                            &db,
+                                            dnstap.as_deref(),
                                             tracker.as_deref(),
                                             src,
                                             &transfer,
@@ -176,6 +195,7 @@ impl AuthServer {
                     );
                     match response {
                         Ok(resp) => {
+                            tap(self.dnstap.as_deref(), Protocol::Udp, src, &data, &resp);
                             if let Err(e) = socket_ref.send_to(&resp, src).await {
                                 error!("failed to send response to {src}: {e}");
                             }
@@ -240,6 +260,11 @@ impl AuthServer {
         Ok(response.to_bytes()?)
     }
 
+    #[tracing::instrument(
+        name = "dns.query",
+        skip_all,
+        fields(server = "auth", %peer, qtype = metrics::qtype_label(data))
+    )]
     fn handle_query(
         catalog: &ZoneCatalog,
         data: &[u8],
@@ -247,12 +272,27 @@ impl AuthServer {
         peer: SocketAddr,
         notify: Option<&NotifyAcceptor>,
     ) -> anyhow::Result<Vec<u8>> {
+        let started = Instant::now();
+        let (response, zone) = Self::answer(catalog, data, tracker, peer, notify)?;
+        metrics::record_dns_query("auth", zone.as_deref(), data, &response, started.elapsed());
+        Ok(response)
+    }
+
+    /// Build the answer to a query, and name the zone it came from when this
+    /// server is authoritative for the name.
+    fn answer(
+        catalog: &ZoneCatalog,
+        data: &[u8],
+        tracker: Option<&QueryTracker>,
+        peer: SocketAddr,
+        notify: Option<&NotifyAcceptor>,
+    ) -> anyhow::Result<(Vec<u8>, Option<String>)> {
         use hickory_proto::op::Message;
 
         let request = Message::from_bytes(data)?;
 
         if request.op_code() == OpCode::Notify {
-            return Self::handle_notify(&request, peer, notify);
+            return Ok((Self::handle_notify(&request, peer, notify)?, None));
         }
 
         let mut response = Message::new();
@@ -265,13 +305,13 @@ impl AuthServer {
 
         if request.op_code() != OpCode::Query {
             response.set_response_code(ResponseCode::NotImp);
-            return Ok(response.to_bytes()?);
+            return Ok((response.to_bytes()?, None));
         }
 
         let queries = request.queries();
         if queries.is_empty() {
             response.set_response_code(ResponseCode::FormErr);
-            return Ok(response.to_bytes()?);
+            return Ok((response.to_bytes()?, None));
         }
 
         // Copy the query section
@@ -297,35 +337,42 @@ impl AuthServer {
         debug!("query: {} {} from catalog", qname, qtype);
 
         // Check if we're authoritative for this zone
-        if !catalog.is_authoritative(&qname) {
+        let tree = catalog.tree();
+        let Some(zone) = tree.find_zone(&qname) else {
             response.set_response_code(ResponseCode::Refused);
-            return Ok(response.to_bytes()?);
-        }
+            return Ok((response.to_bytes()?, None));
+        };
+        let zone_name = zone.zone().name.clone();
 
         // Handle ANY queries
         if qtype == RecordType::ANY {
-            let records = zone::resolve_query(catalog.db(), &qname, RecordType::SOA);
-            for record in records {
-                response.add_answer(record);
+            if let Some(soa) = zone.soa() {
+                response.add_answer(soa.clone());
             }
             response.set_response_code(ResponseCode::NoError);
-            return Ok(response.to_bytes()?);
+            return Ok((response.to_bytes()?, Some(zone_name)));
         }
 
-        let records = zone::resolve_query(catalog.db(), &qname, qtype);
+        let client = topology::client_address(&request, peer.ip());
+        let steering = catalog.topology();
+        // Set when the answer is a load-balanced group's, picked for the client.
+        let consulted = std::cell::Cell::new(false);
+        let records = zone.lookup_preferring(&qname, qtype, &|member| {
+            consulted.set(true);
+            steering.prefers(client, member)
+        });
+        let steered = consulted.get() && steering.rule_name(client).is_some();
+        echo_client_subnet(&request, &mut response, steered);
 
         if records.is_empty() {
-            if let Some(soa) = zone::get_authority_soa(catalog.db(), &qname) {
-                response.add_name_server(soa);
+            if let Some(soa) = zone.soa() {
+                response.add_name_server(soa.clone());
             }
             // Check if the name exists with other record types.
             // NXDOMAIN = name doesn't exist at all; NOERROR = name exists but
             // no records of the queried type (critical for systemd-resolved
             // which does parallel A+AAAA lookups).
-            let fqdn_str = qname.to_string();
-            let fqdn_str = fqdn_str.trim_end_matches('.');
-            let name_exists = catalog.db().fqdn_exists(fqdn_str).unwrap_or(false);
-            if name_exists {
+            if zone.name_exists(&qname) {
                 response.set_response_code(ResponseCode::NoError);
             } else {
                 response.set_response_code(ResponseCode::NXDomain);
@@ -337,7 +384,40 @@ impl AuthServer {
             response.set_response_code(ResponseCode::NoError);
         }
 
-        Ok(response.to_bytes()?)
+        Ok((response.to_bytes()?, Some(zone_name)))
+    }
+}
+
+/// Answer a query's EDNS Client Subnet option (RFC 7871 §7.2.1). The scope
+/// is the full source prefix when the answer was steered by a topology rule
+/// covering the subnet — it may differ for the next subnet over, so a
+/// resolver must cache it for this one only — and 0 otherwise.
+fn echo_client_subnet(
+    request: &hickory_proto::op::Message,
+    response: &mut hickory_proto::op::Message,
+    steered: bool,
+) {
+    use hickory_proto::op::Edns;
+    use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+
+    let Some((addr, source, _)) = microdns_core::net::client_subnet(request) else {
+        return;
+    };
+    let scope = if steered { source } else { 0 };
+    let edns = response.extensions_mut().get_or_insert_with(|| {
+        let mut edns = Edns::new();
+        edns.set_max_payload(1232);
+        edns
+    });
+    edns.options_mut()
+        .insert(EdnsOption::Subnet(ClientSubnet::new(addr, source, scope)));
+}
+
+/// Log a query and the answer to it, when dnstap is on and samples this one.
+fn tap(dnstap: Option<&Dnstap>, protocol: Protocol, peer: SocketAddr, query: &[u8], response: &[u8]) {
+    if let Some(dnstap) = dnstap.filter(|d| d.sample(query)) {
+        dnstap.log(dnstap::MessageType::AuthQuery, protocol, Some(peer), query);
+        dnstap.log(dnstap::MessageType::AuthResponse, protocol, Some(peer), response);
     }
 }
 
@@ -363,7 +443,7 @@ fn map_hickory_type(t: RecordType) -> Option<microdns_core::types::RecordType> {
 async fn handle_tcp_connection(
     mut stream: tokio::net::TcpStream,
     catalog: &ZoneCatalog,
-    db: &Db,
+    dnstap: Option<&Dnstap>,
     tracker: Option<&QueryTracker>,
     peer: SocketAddr,
     transfer: &TransferState,
@@ -413,7 +493,7 @@ async fn handle_tcp_connection(
             return Ok(());
         }
 
-        let zt = ZoneTransfer::new(db.clone());
+        let zt = ZoneTransfer::new(catalog.db().clone());
         match zt.build_axfr_records(zone_name) {
             Ok(records) => {
                 // RFC 5936 §2.2: a zone is sent as a *sequence* of messages.
@@ -474,6 +554,7 @@ async fn handle_tcp_connection(
     } else {
         // Regular TCP query — reuse UDP handler
         let response = AuthServer::handle_query(catalog, &buf, tracker, peer, notify)?;
+        tap(dnstap, Protocol::Tcp, peer, &buf, &response);
         let len = response.len() as u16;
         stream.write_all(&len.to_be_bytes()).await?;
         stream.write_all(&response).await?;
@@ -483,56 +564,6 @@ async fn handle_tcp_connection(
     Ok(())
 }
 
-/// A CIDR block, kept local so the ACL adds no dependency.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct IpNet {
-    addr: IpAddr,
-    prefix: u8,
-}
-
-impl IpNet {
-    /// Parse `10.0.0.0/8`, or a bare address treated as a single host.
-    pub fn parse(s: &str) -> Option<Self> {
-        let s = s.trim();
-        let (addr_part, prefix_part) = match s.split_once('/') {
-            Some((a, p)) => (a, Some(p)),
-            None => (s, None),
-        };
-        let addr: IpAddr = addr_part.parse().ok()?;
-        let max = if addr.is_ipv4() { 32 } else { 128 };
-        let prefix = match prefix_part {
-            Some(p) => p.parse::<u8>().ok()?,
-            None => max,
-        };
-        if prefix > max {
-            return None;
-        }
-        Some(Self { addr, prefix })
-    }
-
-    pub fn contains(&self, ip: IpAddr) -> bool {
-        match (self.addr, ip) {
-            (IpAddr::V4(net), IpAddr::V4(other)) => {
-                let mask = if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) };
-                (u32::from(net) & mask) == (u32::from(other) & mask)
-            }
-            (IpAddr::V6(net), IpAddr::V6(other)) => {
-                let mask = if self.prefix == 0 {
-                    0u128
-                } else {
-                    u128::MAX << (128 - self.prefix)
-                };
-                (u128::from(net) & mask) == (u128::from(other) & mask)
-            }
-            // An IPv4-mapped IPv6 peer should still match an IPv4 rule.
-            (IpAddr::V4(_), IpAddr::V6(other)) => other
-                .to_ipv4_mapped()
-                .is_some_and(|v4| self.contains(IpAddr::V4(v4))),
-            (IpAddr::V6(_), IpAddr::V4(_)) => false,
-        }
-    }
-}
-
 /// Whether a peer may request a zone transfer. Denies by default.
 fn transfer_allowed(peer: SocketAddr, allow: &[IpNet]) -> bool {
     allow.iter().any(|net| net.contains(peer.ip()))
@@ -558,22 +589,6 @@ fn refusal(
 mod acl_tests {
     use super::*;
 
-    #[test]
-    fn cidr_matching() {
-        let net = IpNet::parse("192.168.0.0/16").unwrap();
-        assert!(net.contains("192.168.1.253".parse().unwrap()));
-        assert!(net.contains("192.168.200.199".parse().unwrap()));
-        assert!(!net.contains("10.0.0.1".parse().unwrap()));
-
-        // A bare address is a single host.
-        let host = IpNet::parse("192.168.1.253").unwrap();
-        assert!(host.contains("192.168.1.253".parse().unwrap()));
-        assert!(!host.contains("192.168.1.254".parse().unwrap()));
-
-        assert!(IpNet::parse("192.168.0.0/33").is_none());
-        assert!(IpNet::parse("not-an-address").is_none());
-    }
-
     #[test]
     fn transfers_are_denied_by_default() {
         let peer: SocketAddr = "192.168.1.253:5000".parse().unwrap();
@@ -596,3 +611,146 @@ mod acl_tests {
         assert!(transfer_allowed(mapped, &allow));
     }
 }
+
+#[cfg(test)]
+mod topology_tests {
+    use super::*;
+    use chrono::Utc;
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+    use hickory_proto::rr::Name;
+    use microdns_core::config::{LbTopologyConfig, TopologyRule};
+    use microdns_core::types::{HealthCheck, Record, RecordData, RecordSource};
+    use microdns_core::types::{SoaData, Zone};
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    fn member(zone: &Zone, ip: &str) -> Record {
+        Record {
+            id: Uuid::new_v4(),
+            zone_id: zone.id,
+            name: "app".into(),
+            ttl: 30,
+            data: RecordData::A(ip.parse().unwrap()),
+            enabled: true,
+            health_check: Some(HealthCheck::default()),
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn ask(catalog: &ZoneCatalog, peer: &str, subnet: Option<(&str, u8)>) -> Message {
+        ask_for(catalog, "app.svc.lo.", peer, subnet)
+    }
+
+    fn ask_for(
+        catalog: &ZoneCatalog,
+        name: &str,
+        peer: &str,
+        subnet: Option<(&str, u8)>,
+    ) -> Message {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        if let Some((addr, prefix)) = subnet {
+            let mut edns = Edns::new();
+            edns.options_mut().insert(EdnsOption::Subnet(ClientSubnet::new(
+                addr.parse().unwrap(),
+                prefix,
+                0,
+            )));
+            request.set_edns(edns);
+        }
+        let peer = SocketAddr::new(peer.parse().unwrap(), 5000);
+        let (wire, _) =
+            AuthServer::answer(catalog, &request.to_bytes().unwrap(), None, peer, None).unwrap();
+        Message::from_bytes(&wire).unwrap()
+    }
+
+    fn addresses(response: &Message) -> Vec<String> {
+        let mut out: Vec<String> = response
+            .answers()
+            .iter()
+            .map(|r| r.data().unwrap().to_string())
+            .collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn clients_get_their_own_subnets_members_while_any_are_healthy() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let zone = Zone {
+            id: Uuid::new_v4(),
+            name: "svc.lo".into(),
+            soa: SoaData {
+                mname: "ns.svc.lo".into(),
+                rname: "admin.svc.lo".into(),
+                serial: 1,
+                refresh: 3600,
+                retry: 900,
+                expire: 604800,
+                minimum: 30,
+            },
+            default_ttl: 30,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        db.create_zone("svc.lo", &zone).unwrap();
+        let g10 = member(&zone, "192.168.10.80");
+        db.create_record(&g10).unwrap();
+        db.create_record(&member(&zone, "192.168.11.80")).unwrap();
+        db.create_record(&member(&zone, "10.1.0.80")).unwrap();
+        db.create_record(&Record {
+            name: "www".into(),
+            health_check: None,
+            ..member(&zone, "10.1.0.81")
+        })
+        .unwrap();
+
+        let topology = TopologyState::new(&LbTopologyConfig {
+            rules: ["192.168.10.0/24", "192.168.11.0/24"]
+                .into_iter()
+                .map(|net| TopologyRule {
+                    name: net.into(),
+                    clients: vec![net.into()],
+                    members: Vec::new(),
+                })
+                .collect(),
+        });
+        let catalog = ZoneCatalog::new(db.clone()).with_topology(topology);
+
+        assert_eq!(addresses(&ask(&catalog, "192.168.10.5", None)), ["192.168.10.80"]);
+        assert_eq!(addresses(&ask(&catalog, "192.168.11.5", None)), ["192.168.11.80"]);
+        // No rule for this client: every healthy member.
+        assert_eq!(addresses(&ask(&catalog, "203.0.113.9", None)).len(), 3);
+
+        // A resolver asking on a g11 client's behalf gets g11's member, with
+        // a scope that keeps the answer in that subnet's cache.
+        let response = ask(&catalog, "10.0.0.53", Some(("192.168.11.0", 24)));
+        assert_eq!(addresses(&response), ["192.168.11.80"]);
+        let echoed = response.extensions().as_ref().unwrap().option(EdnsCode::Subnet);
+        let wire = Vec::<u8>::try_from(echoed.unwrap()).unwrap();
+        assert_eq!((wire[2], wire[3]), (24, 24));
+
+        // A name that is not a load-balanced group is the same for everyone.
+        let response = ask_for(&catalog, "www.svc.lo.", "10.0.0.53", Some(("192.168.11.0", 24)));
+        assert_eq!(addresses(&response), ["10.1.0.81"]);
+        let echoed = response.extensions().as_ref().unwrap().option(EdnsCode::Subnet);
+        let wire = Vec::<u8>::try_from(echoed.unwrap()).unwrap();
+        assert_eq!((wire[2], wire[3]), (24, 0));
+
+        // The local member goes down: fall back to everyone still healthy.
+        db.update_record(&Record {
+            enabled: false,
+            ..g10
+        })
+        .unwrap();
+        assert_eq!(
+            addresses(&ask(&catalog, "192.168.10.5", None)),
+            ["10.1.0.80", "192.168.11.80"]
+        );
+    }
+}
@@ -256,6 +256,14 @@ fn default_allow_transfer() -> Vec<String> {
     ]
 }
 
+/// The private ranges and loopback of both address families: IPv6 LAN
+/// clients (unique-local and link-local) recurse just like IPv4 ones.
+fn default_allow_recursion() -> Vec<String> {
+    let mut allow = default_allow_transfer();
+    allow.extend(["::1/128", "fc00::/7", "fe80::/10"].map(String::from));
+    allow
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DnsRecursorConfig {
     #[serde(default = "default_true")]
@@ -266,6 +274,257 @@ pub struct DnsRecursorConfig {
     pub forward_zones: HashMap<String, Vec<String>>,
     #[serde(default = "default_cache_size")]
     pub cache_size: usize,
+    /// Memory budget for the cache in bytes; the least recently used
+    /// answers are dropped to stay under it.
+    #[serde(default = "default_cache_max_bytes")]
+    pub cache_max_bytes: usize,
+    /// Floor on how long a positive answer is cached, whatever its TTL.
+    #[serde(default)]
+    pub cache_min_ttl: u32,
+    /// Ceiling on how long a positive answer is cached.
+    #[serde(default = "default_cache_max_ttl")]
+    pub cache_max_ttl: u32,
+    /// Ceiling on how long an NXDOMAIN or NODATA answer is cached.
+    #[serde(default = "default_cache_max_negative_ttl")]
+    pub cache_max_negative_ttl: u32,
+    /// Save the cache to the database on shutdown and load it on start, so a
+    /// restart does not send every client to upstream at once.
+    #[serde(default)]
+    pub cache_persist: bool,
+    /// How long to wait for each upstream attempt before retransmitting or
+    /// moving on to the next server (milliseconds).
+    #[serde(default = "default_upstream_timeout_ms")]
+    pub upstream_timeout_ms: u64,
+    /// UDP retransmissions to the same upstream after the first attempt.
+    #[serde(default = "default_upstream_retries")]
+    pub upstream_retries: u32,
+    /// Query upstreams over long-lived TCP connections instead of UDP.
+    /// Truncated UDP answers are retried over TCP either way.
+    #[serde(default)]
+    pub upstream_tcp: bool,
+    /// Randomize the letter case of each plain-DNS query name (the "0x20"
+    /// trick) and accept only answers that echo it exactly — a few more bits
+    /// of entropy against off-path spoofing. Upstreams that do not preserve
+    /// case can be opted out one by one with `?no-0x20`.
+    #[serde(default = "default_true")]
+    pub upstream_randomize_case: bool,
+    /// QNAME minimisation (RFC 9156) toward forward-zone servers: ask for a
+    /// name's ancestors below the zone one label at a time, so a name under a
+    /// missing branch is never sent in full. Costs up to ten sequential round
+    /// trips, one per label below the zone, the first time a name is asked.
+    /// Upstreams that mishandle the minimised queries can be opted out one by
+    /// one with `?no-qmin`.
+    #[serde(default = "default_true")]
+    pub upstream_qname_minimisation: bool,
+    /// Send each query to the two preferred upstreams at once and take the
+    /// first answer. Halves the tail latency when an upstream stalls, at the
+    /// cost of twice the upstream traffic.
+    #[serde(default)]
+    pub upstream_race: bool,
+    /// Clients allowed to recurse, as CIDRs. Private ranges and loopback by
+    /// default, so an instance that ends up reachable from the internet is not
+    /// an open resolver. Empty refuses recursion to everyone.
+    #[serde(default = "default_allow_recursion")]
+    pub allow_recursion: Vec<String>,
+    /// Clients refused recursion even when `allow_recursion` covers them.
+    #[serde(default)]
+    pub deny_recursion: Vec<String>,
+    /// Answer names in local zones to any client, recursion or not — the
+    /// recursor then doubles as the public face of those zones.
+    #[serde(default = "default_true")]
+    pub answer_local_to_all: bool,
+    /// Response policy zones, highest precedence first. Each names a zone in
+    /// the database — local, imported from a blocklist, or mirrored from a
+    /// primary — whose records are read as RPZ rules rather than served.
+    #[serde(default)]
+    pub rpz_zones: Vec<String>,
+    /// Per-name overrides answered before anything is forwarded, without a
+    /// zone to hold them — e.g. `{ name = "printer.corp.example", type = "A",
+    /// data = "192.168.1.40" }`.
+    #[serde(default)]
+    pub local_data: Vec<LocalDataRecord>,
+    /// Files in `/etc/hosts` format whose names are answered the same way,
+    /// with PTR records for their addresses. Reloaded when they change.
+    #[serde(default)]
+    pub hosts_files: Vec<String>,
+    /// Directory hosts files must live in, since the list can be changed
+    /// through the API. Files named in `hosts_files` above are allowed
+    /// wherever they are. Defaults to the directory holding the database.
+    #[serde(default)]
+    pub hosts_dir: Option<PathBuf>,
+    /// DNS64 synthesis for IPv6-only clients behind NAT64. Off when absent.
+    #[serde(default)]
+    pub dns64: Option<Dns64Config>,
+    /// EDNS Client Subnet on upstream queries. Off when absent.
+    #[serde(default)]
+    pub ecs: Option<EcsConfig>,
+}
+
+/// EDNS Client Subnet (RFC 7871): tell upstreams roughly where the client is,
+/// so a CDN-aware server can answer with a nearby address. Answers are then
+/// cached per client subnet for as wide a scope as the upstream says they hold.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EcsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Leading bits of an IPv4 client address sent upstream. 24 at most is
+    /// the RFC's advice; 0 sends nothing for IPv4 clients.
+    #[serde(default = "default_ecs_ipv4_prefix")]
+    pub ipv4_prefix: u8,
+    /// Leading bits of an IPv6 client address sent upstream; 56 at most is
+    /// the RFC's advice.
+    #[serde(default = "default_ecs_ipv6_prefix")]
+    pub ipv6_prefix: u8,
+    /// Send the subnet of clients on private addresses too — RFC 1918, shared
+    /// address space, unique local, loopback and link-local. Off by default:
+    /// such a subnet means nothing to a public upstream and describes the
+    /// inside of the network to it.
+    #[serde(default)]
+    pub allow_private: bool,
+    /// Subnet to send on behalf of clients on private addresses instead of
+    /// their own, typically the site's public prefix (`203.0.113.0/24`).
+    #[serde(default)]
+    pub private_subnet: Option<String>,
+}
+
+impl Default for EcsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            ipv4_prefix: default_ecs_ipv4_prefix(),
+            ipv6_prefix: default_ecs_ipv6_prefix(),
+            allow_private: false,
+            private_subnet: None,
+        }
+    }
+}
+
+/// DNS64 (RFC 6147): when a name has no AAAA record but has an A record, answer
+/// the AAAA query with the IPv4 address embedded in a NAT64 prefix, so an
+/// IPv6-only client can reach it through the translator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Dns64Config {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// The NAT64 prefix, of length 32, 40, 48, 56, 64 or 96 (RFC 6052). The
+    /// well-known prefix is never used for private IPv4 addresses.
+    #[serde(default = "default_dns64_prefix")]
+    pub prefix: String,
+    /// Clients that get synthesized answers, as CIDRs — the IPv6-only
+    /// segments. Empty means every client.
+    #[serde(default)]
+    pub clients: Vec<String>,
+    /// AAAA answers in these ranges count as no AAAA at all, so synthesis
+    /// happens anyway. IPv4-mapped addresses by default (RFC 6147 §5.1.4).
+    #[serde(default = "default_dns64_exclude_aaaa")]
+    pub exclude_aaaa: Vec<String>,
+    /// IPv4 addresses never synthesized, as CIDRs — destinations the
+    /// IPv6-only segment reaches some other way.
+    #[serde(default)]
+    pub exclude_a: Vec<String>,
+    /// Domains, with everything under them, that never get synthesis.
+    #[serde(default)]
+    pub exclude_names: Vec<String>,
+}
+
+impl Default for Dns64Config {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            prefix: default_dns64_prefix(),
+            clients: Vec::new(),
+            exclude_aaaa: default_dns64_exclude_aaaa(),
+            exclude_a: Vec::new(),
+            exclude_names: Vec::new(),
+        }
+    }
+}
+
+/// Response policy zone settings, stored in the database and managed through
+/// the API. `[dns.recursor] rpz_zones` seeds them once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RpzConfig {
+    /// Policy zones, highest precedence first. Empty applies no policy.
+    #[serde(default)]
+    pub zones: Vec<String>,
+}
+
+impl RpzConfig {
+    /// The settings a `[dns.recursor]` block implies, used to seed the stored value.
+    pub fn from_recursor(recursor: &DnsRecursorConfig) -> Self {
+        Self {
+            zones: recursor.rpz_zones.clone(),
+        }
+    }
+}
+
+/// Local-data overrides and hosts files, stored in the database and managed
+/// through the API. `[dns.recursor] local_data` and `hosts_files` seed them
+/// once.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LocalDataConfig {
+    #[serde(default)]
+    pub records: Vec<LocalDataRecord>,
+    #[serde(default)]
+    pub hosts_files: Vec<String>,
+}
+
+impl LocalDataConfig {
+    /// The settings a `[dns.recursor]` block implies, used to seed the stored value.
+    pub fn from_recursor(recursor: &DnsRecursorConfig) -> Self {
+        Self {
+            records: recursor.local_data.clone(),
+            hosts_files: recursor.hosts_files.clone(),
+        }
+    }
+}
+
+/// One local-data record. A name with local data is answered from it alone:
+/// a query for a type it has no record of gets an empty answer, not whatever
+/// upstream would have said.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LocalDataRecord {
+    pub name: String,
+    #[serde(default = "default_local_data_ttl")]
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub data: crate::types::RecordData,
+}
+
+/// Recursion access control, stored in the database and managed through the
+/// API. The `[dns.recursor]` fields seed it once, like [`ZoneTransferConfig`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecursionAccessConfig {
+    /// CIDRs permitted to recurse. Empty refuses recursion to everyone.
+    #[serde(default = "default_allow_recursion")]
+    pub allow_recursion: Vec<String>,
+    /// CIDRs refused recursion; takes precedence over `allow_recursion`.
+    #[serde(default)]
+    pub deny_recursion: Vec<String>,
+    /// Answer names in local zones to clients refused recursion.
+    #[serde(default = "default_true")]
+    pub answer_local_to_all: bool,
+}
+
+impl Default for RecursionAccessConfig {
+    fn default() -> Self {
+        Self {
+            allow_recursion: default_allow_recursion(),
+            deny_recursion: Vec::new(),
+            answer_local_to_all: true,
+        }
+    }
+}
+
+impl RecursionAccessConfig {
+    /// The settings a `[dns.recursor]` block implies, used to seed the stored value.
+    pub fn from_recursor(recursor: &DnsRecursorConfig) -> Self {
+        Self {
+            allow_recursion: recursor.allow_recursion.clone(),
+            deny_recursion: recursor.deny_recursion.clone(),
+            answer_local_to_all: recursor.answer_local_to_all,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -288,6 +547,43 @@ pub struct DnsLbConfig {
     /// one explicitly.
     #[serde(default = "default_probe_timeout")]
     pub default_timeout_secs: u32,
+    /// Topology rules; seed the stored [`LbTopologyConfig`] on first run.
+    #[serde(default)]
+    pub topology: Vec<TopologyRule>,
+}
+
+/// Topology steering for load-balanced groups, stored in the database and
+/// managed through the API. `[dns.loadbalancer] topology` seeds it once.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LbTopologyConfig {
+    #[serde(default)]
+    pub rules: Vec<TopologyRule>,
+}
+
+impl LbTopologyConfig {
+    /// The settings a `[dns.loadbalancer]` block implies, used to seed the stored value.
+    pub fn from_lb(lb: &DnsLbConfig) -> Self {
+        Self {
+            rules: lb.topology.clone(),
+        }
+    }
+}
+
+/// Clients in `clients` are answered with the group members in `members`
+/// while any of those are healthy. Where several rules match a client, the
+/// one with the most specific `clients` entry applies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TopologyRule {
+    /// A label for logs and the API, such as the site name.
+    #[serde(default)]
+    pub name: String,
+    /// CIDRs of the clients this rule steers. A query's EDNS Client Subnet,
+    /// when it carries one, stands in for the address it came from.
+    pub clients: Vec<String>,
+    /// CIDRs of the members those clients prefer. Empty means the same
+    /// networks as `clients`: stay in your own subnet.
+    #[serde(default)]
+    pub members: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -499,6 +795,12 @@ pub struct LoggingConfig {
     pub level: String,
     #[serde(default = "default_log_format")]
     pub format: String,
+    /// Per-query dnstap telemetry. Off when absent.
+    #[serde(default)]
+    pub dnstap: Option<DnstapConfig>,
+    /// OpenTelemetry traces exported over OTLP. Off when absent.
+    #[serde(default)]
+    pub otlp: Option<OtlpConfig>,
 }
 
 impl Default for LoggingConfig {
@@ -506,6 +808,93 @@ impl Default for LoggingConfig {
         Self {
             level: default_log_level(),
             format: default_log_format(),
+            dnstap: None,
+            otlp: None,
+        }
+    }
+}
+
+/// dnstap output: protobuf messages in Frame Streams, for collectors and tools
+/// built for BIND, Unbound and the like.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnstapConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Unix socket of a dnstap collector (`fstrm_capture`, dnscollector, …).
+    /// Takes precedence over `file`.
+    #[serde(default)]
+    pub socket: Option<String>,
+    /// File to write to instead, rotated by size.
+    #[serde(default)]
+    pub file: Option<String>,
+    /// Size at which the file is rotated.
+    #[serde(default = "default_dnstap_max_file_bytes")]
+    pub max_file_bytes: u64,
+    /// Rotated files kept, as `<file>.1` (newest) to `<file>.N`.
+    #[serde(default = "default_dnstap_max_files")]
+    pub max_files: u32,
+    /// Identity sent with every message; the instance ID when unset.
+    #[serde(default)]
+    pub identity: Option<String>,
+    /// Fraction of queries logged, from 0.0 to 1.0. A sampled query has its
+    /// query and response logged together.
+    #[serde(default = "default_dnstap_sample_rate")]
+    pub sample_rate: f64,
+    /// Message types to log: `auth_query`, `auth_response`, `client_query`,
+    /// `client_response`, `forwarder_query`, `forwarder_response`. Empty logs
+    /// them all.
+    #[serde(default)]
+    pub message_types: Vec<String>,
+    /// DNS query types to log (`A`, `AAAA`, …). Empty logs them all.
+    #[serde(default)]
+    pub query_types: Vec<String>,
+    /// Messages waiting for the writer before new ones are dropped.
+    #[serde(default = "default_dnstap_queue_size")]
+    pub queue_size: usize,
+}
+
+impl Default for DnstapConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            socket: None,
+            file: None,
+            max_file_bytes: default_dnstap_max_file_bytes(),
+            max_files: default_dnstap_max_files(),
+            identity: None,
+            sample_rate: default_dnstap_sample_rate(),
+            message_types: Vec::new(),
+            query_types: Vec::new(),
+            queue_size: default_dnstap_queue_size(),
+        }
+    }
+}
+
+/// Traces sent to an OpenTelemetry collector (or Jaeger, Tempo, …) over
+/// OTLP/gRPC.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtlpConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Collector endpoint.
+    #[serde(default = "default_otlp_endpoint")]
+    pub endpoint: String,
+    /// `service.name` on every span.
+    #[serde(default = "default_otlp_service_name")]
+    pub service_name: String,
+    /// Fraction of traces started here that are kept, 0.0–1.0. Traces started
+    /// by a caller follow the caller's decision.
+    #[serde(default = "default_otlp_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            endpoint: default_otlp_endpoint(),
+            service_name: default_otlp_service_name(),
+            sample_ratio: default_otlp_sample_ratio(),
         }
     }
 }
@@ -568,9 +957,60 @@ fn default_log_level() -> String {
 fn default_log_format() -> String {
     "json".to_string()
 }
+fn default_dnstap_max_file_bytes() -> u64 {
+    100 * 1024 * 1024
+}
+fn default_dnstap_max_files() -> u32 {
+    5
+}
+fn default_dnstap_sample_rate() -> f64 {
+    1.0
+}
+fn default_dnstap_queue_size() -> usize {
+    10_000
+}
+fn default_otlp_endpoint() -> String {
+    "http://127.0.0.1:4317".to_string()
+}
+fn default_otlp_service_name() -> String {
+    "microdns".to_string()
+}
+fn default_otlp_sample_ratio() -> f64 {
+    1.0
+}
 fn default_cache_size() -> usize {
     10000
 }
+fn default_cache_max_bytes() -> usize {
+    64 * 1024 * 1024
+}
+fn default_cache_max_ttl() -> u32 {
+    86_400
+}
+fn default_cache_max_negative_ttl() -> u32 {
+    3_600
+}
+fn default_upstream_timeout_ms() -> u64 {
+    2000
+}
+fn default_upstream_retries() -> u32 {
+    1
+}
+fn default_local_data_ttl() -> u32 {
+    300
+}
+fn default_ecs_ipv4_prefix() -> u8 {
+    24
+}
+fn default_ecs_ipv6_prefix() -> u8 {
+    56
+}
+fn default_dns64_prefix() -> String {
+    "64:ff9b::/96".to_string()
+}
+fn default_dns64_exclude_aaaa() -> Vec<String> {
+    vec!["::ffff:0:0/96".to_string()]
+}
 fn default_cluster_domain() -> String {
     "cluster.local".to_string()
 }
@@ -741,6 +1181,12 @@ enabled = true
 listen = "0.0.0.0:5353"
 cache_size = 10000
 
+local_data = [
+    { name = "printer.corp.example", type = "A", data = "192.168.1.40" },
+    { name = "wiki.corp.example", ttl = 60, type = "CNAME", data = "docs.corp.example." },
+]
+hosts_files = ["/etc/hosts"]
+
 [dns.recursor.forward_zones]
 "corp.local" = ["10.0.1.1:53"]
 
@@ -751,6 +1197,10 @@ default_probe = "ping"
 probe_concurrency = 32
 ping_packet_count = 3
 default_timeout_secs = 5
+topology = [
+    { name = "g10", clients = ["192.168.10.0/24"] },
+    { name = "gw", clients = ["10.0.0.0/8"], members = ["10.1.0.0/16"] },
+]
 
 [api.rest]
 enabled = true
@@ -775,6 +1225,35 @@ format = "json"
         assert_eq!(auth.zones.len(), 2);
         let recursor = config.dns.recursor.unwrap();
         assert!(recursor.forward_zones.contains_key("corp.local"));
+        assert_eq!(recursor.upstream_timeout_ms, 2000);
+        assert_eq!(recursor.cache_max_bytes, 64 * 1024 * 1024);
+        assert_eq!((recursor.cache_min_ttl, recursor.cache_max_ttl), (0, 86_400));
+        assert_eq!(recursor.cache_max_negative_ttl, 3_600);
+        assert!(!recursor.cache_persist);
+        assert_eq!(recursor.upstream_retries, 1);
+        assert!(!recursor.upstream_tcp);
+        assert!(recursor.upstream_randomize_case);
+        assert!(recursor.upstream_qname_minimisation);
+        // Recursion stays on private networks unless opened up explicitly.
+        assert_eq!(recursor.allow_recursion.len(), 7);
+        assert!(recursor.allow_recursion.contains(&"::1/128".to_string()));
+        assert!(recursor.deny_recursion.is_empty());
+        assert!(recursor.answer_local_to_all);
+        assert!(recursor.dns64.is_none());
+        assert!(recursor.ecs.is_none());
+        assert_eq!(recursor.local_data.len(), 2);
+        assert_eq!(recursor.local_data[0].ttl, 300);
+        assert_eq!(
+            recursor.local_data[0].data,
+            crate::types::RecordData::A("192.168.1.40".parse().unwrap())
+        );
+        assert_eq!(recursor.local_data[1].ttl, 60);
+        assert_eq!(recursor.hosts_files, vec!["/etc/hosts".to_string()]);
+
+        let topology = LbTopologyConfig::from_lb(config.dns.loadbalancer.as_ref().unwrap());
+        assert_eq!(topology.rules.len(), 2);
+        assert!(topology.rules[0].members.is_empty());
+        assert_eq!(topology.rules[1].members, vec!["10.1.0.0/16".to_string()]);
     }
 
     #[test]
@@ -1,9 +1,10 @@
 use crate::error::{Error, Result};
 use crate::types::{
-    DbInstanceConfig, DhcpDbReservation, DhcpPool, DnsForwarder, IpamAllocation, PersistedHealth,
-    QueryStat, Record, RecordType, ReplicationMeta, Zone,
+    CachedResponse, DbInstanceConfig, DhcpDbReservation, DhcpPool, DnsForwarder, IpamAllocation,
+    PersistedHealth, QueryStat, Record, RecordType, ReplicationMeta, Zone,
 };
-use chrono::Utc;
+use crate::zone_index::{ZoneIndex, ZoneTree};
+use chrono::{DateTime, Utc};
 use redb::{Database, ReadableTable, ReadableTableMetadata, TableDefinition};
 use std::path::Path;
 use std::sync::Arc;
@@ -64,6 +65,12 @@ const RUNTIME_CONFIG_TABLE: TableDefinition<&str, &str> =
 const QUERY_STATS_TABLE: TableDefinition<&str, &str> =
     TableDefinition::new("query_stats");
 
+/// Recursor cache snapshot: "{name}:{rtype}:{rclass}" -> saved_at and
+/// expires_at (Unix seconds, big-endian i64 each), then the response bytes.
+/// Replaced wholesale on shutdown, read on start.
+const RECURSOR_CACHE_TABLE: TableDefinition<&str, &[u8]> =
+    TableDefinition::new("recursor_cache");
+
 /// Called with a zone name whenever that zone's contents change.
 ///
 /// Every writer — the REST API, DHCP registration, the Kubernetes and mDNS
@@ -80,6 +87,10 @@ pub type ZoneChangeHook = Arc<dyn Fn(&str) + Send + Sync>;
 pub struct Db {
     inner: Arc<Database>,
     on_zone_change: Option<ZoneChangeHook>,
+    /// Zones compiled for the DNS servers. Every write to a zone or its
+    /// records marks it for reloading — creating and deleting zones and
+    /// replicated copies included, which do not bump a serial.
+    zone_index: Arc<ZoneIndex>,
 }
 
 impl Db {
@@ -106,13 +117,17 @@ impl Db {
             let _ = write_txn.open_table(LB_RECORD_HEALTH_TABLE)?;
             let _ = write_txn.open_table(QUERY_STATS_TABLE)?;
             let _ = write_txn.open_table(RUNTIME_CONFIG_TABLE)?;
+            let _ = write_txn.open_table(RECURSOR_CACHE_TABLE)?;
         }
         write_txn.commit()?;
 
-        Ok(Self {
+        let db = Self {
             inner: Arc::new(db),
             on_zone_change: None,
-        })
+            zone_index: Arc::new(ZoneIndex::new()),
+        };
+        db.zone_index.refresh(&db);
+        Ok(db)
     }
 
     /// Install the zone-change hook. Set once at startup, before the handle is
@@ -122,6 +137,13 @@ impl Db {
         self
     }
 
+    /// Every zone, compiled for answering queries and current as of the last
+    /// write to have returned. Never waits: writers reload what they change
+    /// before returning, and a query during a reload gets the tree before it.
+    pub fn zone_tree(&self) -> Arc<ZoneTree> {
+        self.zone_index.current(self)
+    }
+
     /// Access the underlying redb Database for custom table operations.
     pub fn raw(&self) -> &Database {
         &self.inner
@@ -145,6 +167,7 @@ impl Db {
             name_idx.insert(name, id_str.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, zone.id);
         Ok(())
     }
 
@@ -251,6 +274,7 @@ impl Db {
             }
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, *id);
         Ok(())
     }
 
@@ -290,6 +314,7 @@ impl Db {
             }
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, *zone_id);
         Ok(count)
     }
 
@@ -320,6 +345,7 @@ impl Db {
             by_zone.insert(index_key.as_str(), new_val.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, record.zone_id);
         Ok(())
     }
 
@@ -420,17 +446,18 @@ impl Db {
         let prefix = format!("{zone_id}:");
         let mut result = Vec::new();
 
-        let iter = by_zone.iter()?;
+        // Keys sort by zone ID first, so the zone's records are one range.
+        let iter = by_zone.range(prefix.as_str()..)?;
         for entry in iter {
             let entry = entry.map_err(|e| Error::Database(e.to_string()))?;
-            let key = entry.0.value().to_string();
-            if key.starts_with(&prefix) {
-                let record_ids = entry.1.value().to_string();
-                for rid in record_ids.split(',') {
-                    if let Some(v) = records.get(rid)? {
-                        let record: Record = serde_json::from_str(v.value())?;
-                        result.push(record);
-                    }
+            if !entry.0.value().starts_with(&prefix) {
+                break;
+            }
+            let record_ids = entry.1.value().to_string();
+            for rid in record_ids.split(',') {
+                if let Some(v) = records.get(rid)? {
+                    let record: Record = serde_json::from_str(v.value())?;
+                    result.push(record);
                 }
             }
         }
@@ -438,6 +465,18 @@ impl Db {
         Ok(result)
     }
 
+    /// Every record in every zone, in one pass over the records table.
+    pub(crate) fn list_all_records(&self) -> Result<Vec<Record>> {
+        let read_txn = self.inner.begin_read()?;
+        let records = read_txn.open_table(RECORDS_TABLE)?;
+        let mut result = Vec::new();
+        for entry in records.iter()? {
+            let (_, v) = entry?;
+            result.push(serde_json::from_str(v.value())?);
+        }
+        Ok(result)
+    }
+
     pub fn update_record(&self, record: &Record) -> Result<()> {
         let write_txn = self.inner.begin_write()?;
         {
@@ -451,10 +490,12 @@ impl Db {
             records.insert(id_str.as_str(), json.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, record.zone_id);
         Ok(())
     }
 
     pub fn delete_record(&self, id: &Uuid) -> Result<()> {
+        let zone_id;
         let write_txn = self.inner.begin_write()?;
         {
             let id_str = id.to_string();
@@ -468,6 +509,7 @@ impl Db {
             drop(record_json);
 
             records.remove(id_str.as_str())?;
+            zone_id = record.zone_id;
 
             // Update zone index
             let index_key = format!(
@@ -500,6 +542,7 @@ impl Db {
             lb_health.remove(id_str.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, zone_id);
         Ok(())
     }
 
@@ -544,6 +587,7 @@ impl Db {
             zones.insert(id_str.as_str(), json.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, *zone_id);
 
         // Announce only after the commit: a listener that reacts by serving or
         // transferring the zone must never see a serial that could still be
@@ -633,6 +677,7 @@ impl Db {
             name_idx.insert(zone.name.as_str(), id_str.as_str())?;
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, zone.id);
         Ok(())
     }
 
@@ -689,6 +734,7 @@ impl Db {
             }
         }
         write_txn.commit()?;
+        self.zone_index.invalidate(self, *zone_id);
         Ok(())
     }
 
@@ -1001,18 +1047,24 @@ impl Db {
     /// Find the most-specific forward zone matching a FQDN and return its servers.
     /// Used by the recursor to decide whether to forward a query.
     pub fn find_forward_servers(&self, qname: &str) -> Option<Vec<String>> {
+        self.find_forward_zone(qname).map(|(_, servers)| servers)
+    }
+
+    /// The most specific forwarder covering `qname`: its zone (lowercase, no
+    /// trailing dot) and servers.
+    pub fn find_forward_zone(&self, qname: &str) -> Option<(String, Vec<String>)> {
         let forwarders = self.list_dns_forwarders().ok()?;
         let qname = qname.trim_end_matches('.').to_lowercase();
-        let mut best: Option<(Vec<String>, usize)> = None;
+        let mut best: Option<(String, Vec<String>)> = None;
         for fwd in &forwarders {
             let zone = fwd.zone.trim_end_matches('.').to_lowercase();
             if qname == zone || qname.ends_with(&format!(".{}", zone)) {
-                if best.as_ref().map_or(true, |(_, s)| zone.len() > *s) {
-                    best = Some((fwd.servers.clone(), zone.len()));
+                if best.as_ref().map_or(true, |(z, _)| zone.len() > z.len()) {
+                    best = Some((zone, fwd.servers.clone()));
                 }
             }
         }
-        best.map(|(servers, _)| servers)
+        best
     }
 
     pub fn delete_dns_forwarder(&self, zone: &str) -> Result<()> {
@@ -1211,6 +1263,61 @@ impl Db {
         write_txn.commit()?;
         Ok(())
     }
+
+    // ─── Recursor cache ──────────────────────────────────────────────────────
+
+    /// Replace the saved recursor cache with `entries`, in one transaction.
+    pub fn replace_recursor_cache(&self, entries: &[CachedResponse]) -> Result<()> {
+        let write_txn = self.inner.begin_write()?;
+        {
+            let mut table = write_txn.open_table(RECURSOR_CACHE_TABLE)?;
+            table.retain(|_, _| false)?;
+            for entry in entries {
+                let key = format!("{}:{}:{}", entry.name, entry.rtype, entry.rclass);
+                let mut value = Vec::with_capacity(16 + entry.response.len());
+                value.extend_from_slice(&entry.saved_at.timestamp().to_be_bytes());
+                value.extend_from_slice(&entry.expires_at.timestamp().to_be_bytes());
+                value.extend_from_slice(&entry.response);
+                table.insert(key.as_str(), value.as_slice())?;
+            }
+        }
+        write_txn.commit()?;
+        Ok(())
+    }
+
+    /// The saved recursor cache. Rows that do not decode are skipped.
+    pub fn list_recursor_cache(&self) -> Result<Vec<CachedResponse>> {
+        let read_txn = self.inner.begin_read()?;
+        let table = read_txn.open_table(RECURSOR_CACHE_TABLE)?;
+        let mut out = Vec::new();
+        for entry in table.iter()? {
+            let (k, v) = entry?;
+            if let Some(row) = decode_cached_response(k.value(), v.value()) {
+                out.push(row);
+            }
+        }
+        Ok(out)
+    }
+}
+
+fn decode_cached_response(key: &str, value: &[u8]) -> Option<CachedResponse> {
+    let mut parts = key.rsplitn(3, ':');
+    let rclass = parts.next()?.parse().ok()?;
+    let rtype = parts.next()?.parse().ok()?;
+    let name = parts.next()?.to_string();
+    if value.len() < 16 {
+        return None;
+    }
+    let saved_at = i64::from_be_bytes(value[..8].try_into().ok()?);
+    let expires_at = i64::from_be_bytes(value[8..16].try_into().ok()?);
+    Some(CachedResponse {
+        name,
+        rtype,
+        rclass,
+        saved_at: DateTime::from_timestamp(saved_at, 0)?,
+        expires_at: DateTime::from_timestamp(expires_at, 0)?,
+        response: value[16..].to_vec(),
+    })
 }
 
 /// The zone that owns `fqdn` — the longest suffix match, which is the zone a
@@ -0,0 +1,768 @@
+//! dnstap: a protobuf record of each DNS message sent or received, written
+//! as Frame Streams to a collector's Unix socket or to a file.
+//!
+//! Servers hand messages to a [`Dnstap`] handle, which encodes them on the
+//! spot and queues them for a writer thread. The queue is bounded: when the
+//! output cannot keep up, messages are dropped and counted rather than
+//! slowing down the answer path.
+//!
+//! The wire format follows <https://dnstap.info>: the `dnstap.Dnstap`
+//! protobuf (only the fields written here are declared) inside Frame Streams
+//! data frames, after a START control frame with the content type
+//! `protobuf:dnstap.Dnstap`.
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hickory_proto::rr::RecordType;
+use tracing::{info, warn};
+
+use crate::config::DnstapConfig;
+
+/// Frame Streams content type of dnstap.
+pub const CONTENT_TYPE: &str = "protobuf:dnstap.Dnstap";
+
+const CONTROL_ACCEPT: u32 = 0x01;
+const CONTROL_START: u32 = 0x02;
+const CONTROL_STOP: u32 = 0x03;
+const CONTROL_READY: u32 = 0x04;
+const CONTROL_FIELD_CONTENT_TYPE: u32 = 0x01;
+
+/// How long to wait before trying an output that failed again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `dnstap.Dnstap`: the envelope every message is written in.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct Frame {
+    #[prost(bytes = "vec", optional, tag = "1")]
+    pub identity: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub version: Option<Vec<u8>>,
+    #[prost(message, optional, tag = "14")]
+    pub message: Option<TapMessage>,
+    /// Always `MESSAGE` (1).
+    #[prost(int32, required, tag = "15")]
+    pub r#type: i32,
+}
+
+/// `dnstap.Message`: one DNS message and who it passed between.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct TapMessage {
+    /// A [`MessageType`].
+    #[prost(int32, required, tag = "1")]
+    pub r#type: i32,
+    /// `INET` (1) or `INET6` (2).
+    #[prost(int32, optional, tag = "2")]
+    pub socket_family: Option<i32>,
+    /// A [`Protocol`].
+    #[prost(int32, optional, tag = "3")]
+    pub socket_protocol: Option<i32>,
+    #[prost(bytes = "vec", optional, tag = "4")]
+    pub query_address: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "5")]
+    pub response_address: Option<Vec<u8>>,
+    #[prost(uint32, optional, tag = "6")]
+    pub query_port: Option<u32>,
+    #[prost(uint32, optional, tag = "7")]
+    pub response_port: Option<u32>,
+    #[prost(uint64, optional, tag = "8")]
+    pub query_time_sec: Option<u64>,
+    #[prost(fixed32, optional, tag = "9")]
+    pub query_time_nsec: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "10")]
+    pub query_message: Option<Vec<u8>>,
+    #[prost(uint64, optional, tag = "12")]
+    pub response_time_sec: Option<u64>,
+    #[prost(fixed32, optional, tag = "13")]
+    pub response_time_nsec: Option<u32>,
+    #[prost(bytes = "vec", optional, tag = "14")]
+    pub response_message: Option<Vec<u8>>,
+}
+
+/// The kinds of message logged, numbered as in `dnstap.Message.Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum MessageType {
+    /// A query to the authoritative server.
+    AuthQuery = 1,
+    /// The authoritative server's answer.
+    AuthResponse = 2,
+    /// A query to the recursor.
+    ClientQuery = 5,
+    /// The recursor's answer.
+    ClientResponse = 6,
+    /// A query the recursor forwarded upstream.
+    ForwarderQuery = 7,
+    /// The upstream's answer to it.
+    ForwarderResponse = 8,
+}
+
+impl MessageType {
+    const ALL: [Self; 6] = [
+        Self::AuthQuery,
+        Self::AuthResponse,
+        Self::ClientQuery,
+        Self::ClientResponse,
+        Self::ForwarderQuery,
+        Self::ForwarderResponse,
+    ];
+
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AuthQuery => "auth_query",
+            Self::AuthResponse => "auth_response",
+            Self::ClientQuery => "client_query",
+            Self::ClientResponse => "client_response",
+            Self::ForwarderQuery => "forwarder_query",
+            Self::ForwarderResponse => "forwarder_response",
+        }
+    }
+
+    fn is_response(self) -> bool {
+        matches!(
+            self,
+            Self::AuthResponse | Self::ClientResponse | Self::ForwarderResponse
+        )
+    }
+
+    /// Whether this instance sent the query, so the peer is the responder.
+    fn is_outgoing(self) -> bool {
+        matches!(self, Self::ForwarderQuery | Self::ForwarderResponse)
+    }
+
+    fn bit(self) -> u16 {
+        1 << (self as i32)
+    }
+}
+
+/// The transport a message travelled over, numbered as in
+/// `dnstap.SocketProtocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i32)]
+pub enum Protocol {
+    Udp = 1,
+    Tcp = 2,
+    Dot = 3,
+    Doh = 4,
+}
+
+/// Handle the servers log through. Cheap to share; the writer thread stops
+/// once the last handle is dropped.
+pub struct Dnstap {
+    tx: SyncSender<Vec<u8>>,
+    identity: Vec<u8>,
+    version: Vec<u8>,
+    /// Bit per [`MessageType`] to log.
+    types: u16,
+    /// Query types to log; empty for all.
+    qtypes: Vec<u16>,
+    sample_rate: f64,
+    sampled: AtomicU64,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Dnstap {
+    /// Open the output and start the writer, or `None` when dnstap is off.
+    pub fn start(config: &DnstapConfig, instance_id: &str) -> anyhow::Result<Option<Arc<Self>>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if !(0.0..=1.0).contains(&config.sample_rate) {
+            anyhow::bail!(
+                "dnstap sample_rate must be between 0.0 and 1.0, not {}",
+                config.sample_rate
+            );
+        }
+        let mut types = 0u16;
+        for name in &config.message_types {
+            let kind = MessageType::ALL
+                .into_iter()
+                .find(|kind| kind.name() == name.to_lowercase())
+                .ok_or_else(|| anyhow::anyhow!("unknown dnstap message type '{name}'"))?;
+            types |= kind.bit();
+        }
+        if types == 0 {
+            types = MessageType::ALL
+                .iter()
+                .fold(0, |bits, kind| bits | kind.bit());
+        }
+        let qtypes = config
+            .query_types
+            .iter()
+            .map(|name| {
+                RecordType::from_str(&name.to_uppercase())
+                    .map(u16::from)
+                    .map_err(|_| anyhow::anyhow!("unknown dnstap query type '{name}'"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let (sink, description) = match (&config.socket, &config.file) {
+            (Some(socket), _) => (Sink::socket(socket), format!("socket {socket}")),
+            (None, Some(file)) => (
+                Sink::file(file, config.max_file_bytes, config.max_files),
+                format!("file {file}"),
+            ),
+            (None, None) => anyhow::bail!("dnstap needs a socket or a file to write to"),
+        };
+
+        let (tx, rx) = sync_channel(config.queue_size.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        let writer_dropped = dropped.clone();
+        std::thread::Builder::new()
+            .name("dnstap".into())
+            .spawn(move || run_writer(sink, rx, writer_dropped))?;
+        info!(
+            "dnstap: writing to {description} (sample rate {})",
+            config.sample_rate
+        );
+
+        Ok(Some(Arc::new(Self {
+            tx,
+            identity: config
+                .identity
+                .clone()
+                .unwrap_or_else(|| instance_id.to_string())
+                .into_bytes(),
+            version: format!("microdns {}", env!("CARGO_PKG_VERSION")).into_bytes(),
+            types,
+            qtypes,
+            sample_rate: config.sample_rate,
+            sampled: AtomicU64::new(0),
+            dropped,
+        })))
+    }
+
+    /// Whether the exchange that starts with `query` is logged. Deciding once
+    /// per exchange keeps a logged query's response in the log with it.
+    pub fn sample(&self, query: &[u8]) -> bool {
+        if !self.qtypes.is_empty()
+            && !question_type(query).is_some_and(|t| self.qtypes.contains(&t))
+        {
+            return false;
+        }
+        if self.sample_rate >= 1.0 {
+            return true;
+        }
+        // Every query moves the count along by the rate; one is logged each
+        // time it passes a whole number, which spaces them out evenly.
+        let n = self.sampled.fetch_add(1, Ordering::Relaxed) as f64;
+        ((n + 1.0) * self.sample_rate).floor() > (n * self.sample_rate).floor()
+    }
+
+    /// Whether messages of this kind are logged at all.
+    pub fn wants(&self, kind: MessageType) -> bool {
+        self.types & kind.bit() != 0
+    }
+
+    /// Log one message. `peer` is the other end: the client for a query
+    /// received, the upstream for one sent. It is `None` when there is no
+    /// single address, as with DNS over HTTPS.
+    pub fn log(
+        &self,
+        kind: MessageType,
+        protocol: Protocol,
+        peer: Option<SocketAddr>,
+        message: &[u8],
+    ) {
+        if !self.wants(kind) {
+            return;
+        }
+        let frame = self.encode(kind, protocol, peer, message, SystemTime::now());
+        match self.tx.try_send(frame) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Messages dropped because the output fell behind or failed.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The data frame for one message.
+    fn encode(
+        &self,
+        kind: MessageType,
+        protocol: Protocol,
+        peer: Option<SocketAddr>,
+        message: &[u8],
+        at: SystemTime,
+    ) -> Vec<u8> {
+        let at = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut tap = TapMessage {
+            r#type: kind as i32,
+            socket_protocol: Some(protocol as i32),
+            ..Default::default()
+        };
+        if let Some(peer) = peer {
+            tap.socket_family = Some(if peer.is_ipv4() { 1 } else { 2 });
+            let addr = match peer.ip() {
+                IpAddr::V4(v4) => v4.octets().to_vec(),
+                IpAddr::V6(v6) => v6.octets().to_vec(),
+            };
+            if kind.is_outgoing() {
+                tap.response_address = Some(addr);
+                tap.response_port = Some(peer.port().into());
+            } else {
+                tap.query_address = Some(addr);
+                tap.query_port = Some(peer.port().into());
+            }
+        }
+        if kind.is_response() {
+            tap.response_time_sec = Some(at.as_secs());
+            tap.response_time_nsec = Some(at.subsec_nanos());
+            tap.response_message = Some(message.to_vec());
+        } else {
+            tap.query_time_sec = Some(at.as_secs());
+            tap.query_time_nsec = Some(at.subsec_nanos());
+            tap.query_message = Some(message.to_vec());
+        }
+        let frame = Frame {
+            identity: Some(self.identity.clone()),
+            version: Some(self.version.clone()),
+            message: Some(tap),
+            r#type: 1,
+        };
+        let payload = prost::Message::encode_to_vec(&frame);
+        let mut data = Vec::with_capacity(4 + payload.len());
+        data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        data.extend_from_slice(&payload);
+        data
+    }
+}
+
+/// The type of the first question in a query, read straight off the wire.
+pub(crate) fn question_type(query: &[u8]) -> Option<u16> {
+    if query.len() < 12 || u16::from_be_bytes([query[4], query[5]]) == 0 {
+        return None;
+    }
+    let mut pos = 12;
+    loop {
+        let len = *query.get(pos)? as usize;
+        if len == 0 {
+            pos += 1;
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            pos += 2;
+            break;
+        }
+        pos += 1 + len;
+    }
+    Some(u16::from_be_bytes([*query.get(pos)?, *query.get(pos + 1)?]))
+}
+
+/// A Frame Streams control frame, with the dnstap content type where the
+/// frame carries one.
+fn control_frame(kind: u32) -> Vec<u8> {
+    let mut body = kind.to_be_bytes().to_vec();
+    if matches!(kind, CONTROL_READY | CONTROL_ACCEPT | CONTROL_START) {
+        body.extend_from_slice(&CONTROL_FIELD_CONTENT_TYPE.to_be_bytes());
+        body.extend_from_slice(&(CONTENT_TYPE.len() as u32).to_be_bytes());
+        body.extend_from_slice(CONTENT_TYPE.as_bytes());
+    }
+    let mut frame = 0u32.to_be_bytes().to_vec();
+    frame.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&body);
+    frame
+}
+
+/// Read one control frame and return its type.
+fn read_control(stream: &mut impl Read) -> std::io::Result<u32> {
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header)?;
+    let escape = u32::from_be_bytes(header[..4].try_into().unwrap());
+    let len = u32::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+    if escape != 0 || !(4..=512).contains(&len) {
+        return Err(std::io::Error::other("not a Frame Streams control frame"));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body)?;
+    Ok(u32::from_be_bytes(body[..4].try_into().unwrap()))
+}
+
+/// Where frames end up.
+enum Sink {
+    /// A collector listening on a Unix socket, spoken to bidirectionally.
+    Socket {
+        path: String,
+        stream: Option<BufWriter<UnixStream>>,
+        retry_at: Instant,
+    },
+    /// A file, moved aside to `<path>.1` once it reaches `max_bytes`.
+    File {
+        path: PathBuf,
+        max_bytes: u64,
+        max_files: u32,
+        out: Option<BufWriter<File>>,
+        written: u64,
+        retry_at: Instant,
+    },
+}
+
+impl Sink {
+    fn socket(path: &str) -> Self {
+        Self::Socket {
+            path: path.to_string(),
+            stream: None,
+            retry_at: Instant::now(),
+        }
+    }
+
+    fn file(path: &str, max_bytes: u64, max_files: u32) -> Self {
+        Self::File {
+            path: PathBuf::from(path),
+            max_bytes,
+            max_files,
+            out: None,
+            written: 0,
+            retry_at: Instant::now(),
+        }
+    }
+
+    /// Write one data frame. Returns false when it was dropped because the
+    /// output is unavailable.
+    fn write(&mut self, frame: &[u8]) -> bool {
+        match self {
+            Self::Socket {
+                path,
+                stream,
+                retry_at,
+            } => {
+                if stream.is_none() {
+                    if Instant::now() < *retry_at {
+                        return false;
+                    }
+                    match connect(path) {
+                        Ok(connected) => {
+                            info!("dnstap: connected to {path}");
+                            *stream = Some(connected);
+                        }
+                        Err(e) => {
+                            warn!("dnstap: could not connect to {path}: {e}");
+                            *retry_at = Instant::now() + RETRY_INTERVAL;
+                            return false;
+                        }
+                    }
+                }
+                let Some(out) = stream.as_mut() else {
+                    return false;
+                };
+                if let Err(e) = out.write_all(frame) {
+                    warn!("dnstap: lost connection to {path}: {e}");
+                    *stream = None;
+                    *retry_at = Instant::now() + RETRY_INTERVAL;
+                    return false;
+                }
+                true
+            }
+            Self::File {
+                path,
+                max_bytes,
+                max_files,
+                out,
+                written,
+                retry_at,
+            } => {
+                if out.is_none() {
+                    if Instant::now() < *retry_at {
+                        return false;
+                    }
+                    match open_file(path, *max_files) {
+                        Ok(opened) => {
+                            *out = Some(opened);
+                            *written = control_frame(CONTROL_START).len() as u64;
+                        }
+                        Err(e) => {
+                            warn!("dnstap: could not open {}: {e}", path.display());
+                            *retry_at = Instant::now() + RETRY_INTERVAL;
+                            return false;
+                        }
+                    }
+                }
+                let Some(file) = out.as_mut() else {
+                    return false;
+                };
+                if let Err(e) = file.write_all(frame) {
+                    warn!("dnstap: could not write to {}: {e}", path.display());
+                    *out = None;
+                    *retry_at = Instant::now() + RETRY_INTERVAL;
+                    return false;
+                }
+                *written += frame.len() as u64;
+                if *written >= *max_bytes {
+                    // Close this file properly; the next frame opens a new one.
+                    if let Some(mut file) = out.take() {
+                        let _ = file.write_all(&control_frame(CONTROL_STOP));
+                        let _ = file.flush();
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        match self {
+            Self::Socket { path, stream, .. } => {
+                if let Some(Err(e)) = stream.as_mut().map(|s| s.flush()) {
+                    warn!("dnstap: lost connection to {path}: {e}");
+                    *stream = None;
+                }
+            }
+            Self::File { out, .. } => {
+                if let Some(file) = out.as_mut() {
+                    let _ = file.flush();
+                }
+            }
+        }
+    }
+
+    /// End the stream with a STOP frame.
+    fn finish(&mut self) {
+        let stop = control_frame(CONTROL_STOP);
+        match self {
+            Self::Socket { stream, .. } => {
+                if let Some(mut out) = stream.take() {
+                    let _ = out.write_all(&stop);
+                    let _ = out.flush();
+                }
+            }
+            Self::File { out, .. } => {
+                if let Some(mut file) = out.take() {
+                    let _ = file.write_all(&stop);
+                    let _ = file.flush();
+                }
+            }
+        }
+    }
+}
+
+/// Connect to a collector and agree on the content type: READY, ACCEPT, START.
+fn connect(path: &str) -> std::io::Result<BufWriter<UnixStream>> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    stream.set_write_timeout(Some(Duration::from_secs(5)))?;
+    stream.write_all(&control_frame(CONTROL_READY))?;
+    if read_control(&mut stream)? != CONTROL_ACCEPT {
+        return Err(std::io::Error::other("the collector did not accept dnstap"));
+    }
+    stream.write_all(&control_frame(CONTROL_START))?;
+    Ok(BufWriter::new(stream))
+}
+
+/// Start a new file at `path`, moving any earlier one aside first so that a
+/// restart never appends to a stream that was already STOPped.
+fn open_file(path: &Path, max_files: u32) -> std::io::Result<BufWriter<File>> {
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() > 0) {
+        rotate(path, max_files)?;
+    }
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&control_frame(CONTROL_START))?;
+    Ok(file)
+}
+
+/// `path` becomes `path.1`, `path.1` becomes `path.2`, and so on; whatever
+/// would become `path.<max_files + 1>` is gone.
+fn rotate(path: &Path, max_files: u32) -> std::io::Result<()> {
+    if max_files == 0 {
+        return std::fs::remove_file(path);
+    }
+    let numbered = |n: u32| PathBuf::from(format!("{}.{n}", path.display()));
+    for n in (1..max_files).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+fn run_writer(mut sink: Sink, rx: Receiver<Vec<u8>>, dropped: Arc<AtomicU64>) {
+    let mut reported = 0;
+    while let Ok(frame) = rx.recv() {
+        let mut lost = 0u64;
+        lost += u64::from(!sink.write(&frame));
+        while let Ok(frame) = rx.try_recv() {
+            lost += u64::from(!sink.write(&frame));
+        }
+        sink.flush();
+
+        let total = dropped.fetch_add(lost, Ordering::Relaxed) + lost;
+        if total > reported {
+            warn!("dnstap: {} message(s) dropped so far", total);
+            reported = total;
+        }
+    }
+    sink.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use hickory_proto::serialize::binary::BinEncodable;
+    use prost::Message as _;
+    use std::os::unix::net::UnixListener;
+
+    fn query(name: &str, rtype: RecordType) -> Vec<u8> {
+        let mut message = Message::new();
+        message.add_query(Query::query(Name::from_str(name).unwrap(), rtype));
+        message.to_bytes().unwrap()
+    }
+
+    /// Split a Frame Streams byte stream into control frame types and decoded
+    /// data frames.
+    fn read_stream(mut bytes: &[u8]) -> (Vec<u32>, Vec<Frame>) {
+        let (mut controls, mut frames) = (Vec::new(), Vec::new());
+        while bytes.len() >= 4 {
+            let len = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+            if len == 0 {
+                let len = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as usize;
+                controls.push(u32::from_be_bytes(bytes[8..12].try_into().unwrap()));
+                bytes = &bytes[8 + len..];
+            } else {
+                frames.push(Frame::decode(&bytes[4..4 + len]).unwrap());
+                bytes = &bytes[4 + len..];
+            }
+        }
+        (controls, frames)
+    }
+
+    #[test]
+    fn files_hold_decodable_messages_and_rotate_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DnstapConfig {
+            file: Some(dir.path().join("unused").to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let tap = Dnstap::start(&config, "node-1").unwrap().unwrap();
+        let client: SocketAddr = "198.51.100.7:5353".parse().unwrap();
+        let q = query("www.example.test.", RecordType::A);
+        let frame = tap.encode(
+            MessageType::ClientQuery,
+            Protocol::Udp,
+            Some(client),
+            &q,
+            SystemTime::now(),
+        );
+
+        let path = dir.path().join("dnstap.fstrm");
+        let mut sink = Sink::file(&path.to_string_lossy(), 200, 2);
+        for _ in 0..12 {
+            assert!(sink.write(&frame));
+        }
+        sink.finish();
+        assert!(dir.path().join("dnstap.fstrm.2").exists());
+        assert!(!dir.path().join("dnstap.fstrm.3").exists());
+
+        for file in [path.clone(), dir.path().join("dnstap.fstrm.1")] {
+            let (controls, frames) = read_stream(&std::fs::read(&file).unwrap());
+            assert_eq!(controls, vec![CONTROL_START, CONTROL_STOP]);
+            assert!(!frames.is_empty());
+        }
+        let (_, frames) = read_stream(&std::fs::read(&path).unwrap());
+        let frame = &frames[0];
+        assert_eq!(frame.identity.as_deref(), Some(&b"node-1"[..]));
+        let message = frame.message.as_ref().unwrap();
+        assert_eq!(message.r#type, MessageType::ClientQuery as i32);
+        assert_eq!(message.socket_protocol, Some(Protocol::Udp as i32));
+        assert_eq!(
+            message.query_address.as_deref(),
+            Some(&[198, 51, 100, 7][..])
+        );
+        assert_eq!(message.query_port, Some(5353));
+        assert_eq!(message.query_message.as_deref(), Some(&q[..]));
+    }
+
+    #[test]
+    fn sockets_get_the_frame_streams_handshake() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dnstap.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let collector = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            assert_eq!(read_control(&mut stream).unwrap(), CONTROL_READY);
+            stream.write_all(&control_frame(CONTROL_ACCEPT)).unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            read_stream(&rest)
+        });
+
+        let config = DnstapConfig {
+            socket: Some(path.to_string_lossy().to_string()),
+            message_types: vec!["forwarder_response".into()],
+            ..Default::default()
+        };
+        let tap = Dnstap::start(&config, "node-1").unwrap().unwrap();
+        let upstream: SocketAddr = "[2001:db8::53]:53".parse().unwrap();
+        let q = query("www.example.test.", RecordType::AAAA);
+        tap.log(
+            MessageType::ForwarderQuery,
+            Protocol::Tcp,
+            Some(upstream),
+            &q,
+        );
+        tap.log(
+            MessageType::ForwarderResponse,
+            Protocol::Tcp,
+            Some(upstream),
+            &q,
+        );
+        drop(tap);
+
+        let (controls, frames) = collector.join().unwrap();
+        assert_eq!(controls, vec![CONTROL_START, CONTROL_STOP]);
+        assert_eq!(frames.len(), 1, "only the wanted type");
+        let message = frames[0].message.as_ref().unwrap();
+        assert_eq!(message.r#type, MessageType::ForwarderResponse as i32);
+        assert_eq!(message.socket_family, Some(2));
+        assert_eq!(message.response_port, Some(53));
+        assert_eq!(message.response_message.as_deref(), Some(&q[..]));
+    }
+
+    #[test]
+    fn sampling_keeps_the_rate_and_the_query_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = DnstapConfig {
+            file: Some(dir.path().join("t").to_string_lossy().to_string()),
+            sample_rate: 0.25,
+            query_types: vec!["aaaa".into()],
+            ..Default::default()
+        };
+        let tap = Dnstap::start(&config, "node-1").unwrap().unwrap();
+        let aaaa = query("www.example.test.", RecordType::AAAA);
+        let a = query("www.example.test.", RecordType::A);
+        assert_eq!((0..100).filter(|_| tap.sample(&aaaa)).count(), 25);
+        assert!(!(0..100).any(|_| tap.sample(&a)));
+
+        for bad in [
+            DnstapConfig {
+                message_types: vec!["client_chatter".into()],
+                ..config.clone()
+            },
+            DnstapConfig {
+                sample_rate: 1.5,
+                ..config.clone()
+            },
+            DnstapConfig {
+                file: None,
+                ..config.clone()
+            },
+        ] {
+            assert!(Dnstap::start(&bad, "node-1").is_err());
+        }
+    }
+}
@@ -1,7 +1,13 @@
 pub mod config;
 pub mod db;
+pub mod dnstap;
 pub mod error;
 pub mod log_buffer;
+pub mod metrics;
+pub mod net;
 pub mod query_tracker;
 pub mod reverse;
+pub mod selection;
+pub mod topology;
 pub mod types;
+pub mod zone_index;
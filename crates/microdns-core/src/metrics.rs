@@ -0,0 +1,98 @@
+//! Metrics shared by the DNS servers. Everything is recorded through the
+//! `metrics` facade, so it costs next to nothing until the binary installs a
+//! recorder; the REST API renders what was recorded at `/metrics`.
+
+use std::time::Duration;
+
+use hickory_proto::rr::RecordType;
+
+use crate::dnstap::question_type;
+
+/// Count one answered query and how long it took. `server` is `auth` or
+/// `recursor`; `zone` is the zone that answered, when there is one.
+pub fn record_dns_query(
+    server: &'static str,
+    zone: Option<&str>,
+    query: &[u8],
+    response: &[u8],
+    elapsed: Duration,
+) {
+    let qtype = qtype_label(query);
+    let rcode = rcode_label(response);
+    match zone {
+        Some(zone) => metrics::counter!(
+            "microdns_dns_queries_total",
+            "server" => server,
+            "qtype" => qtype,
+            "rcode" => rcode,
+            "zone" => zone.trim_end_matches('.').to_lowercase(),
+        )
+        .increment(1),
+        None => metrics::counter!(
+            "microdns_dns_queries_total",
+            "server" => server,
+            "qtype" => qtype,
+            "rcode" => rcode,
+        )
+        .increment(1),
+    }
+    metrics::histogram!("microdns_dns_query_duration_seconds", "server" => server)
+        .record(elapsed.as_secs_f64());
+}
+
+/// The query type asked, by name. Types hickory does not know are lumped
+/// together so that odd queries cannot grow the label set without bound.
+pub fn qtype_label(query: &[u8]) -> &'static str {
+    let Some(qtype) = question_type(query) else {
+        return "NONE";
+    };
+    match RecordType::from(qtype) {
+        RecordType::Unknown(_) => "OTHER",
+        known => known.into(),
+    }
+}
+
+/// The response code of an answer, by name. Extended codes carried in EDNS
+/// are not looked at.
+pub fn rcode_label(response: &[u8]) -> &'static str {
+    match response.get(3).map(|b| b & 0x0f) {
+        Some(0) => "NOERROR",
+        Some(1) => "FORMERR",
+        Some(2) => "SERVFAIL",
+        Some(3) => "NXDOMAIN",
+        Some(4) => "NOTIMP",
+        Some(5) => "REFUSED",
+        Some(_) => "OTHER",
+        None => "NONE",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, Query, ResponseCode};
+    use hickory_proto::rr::Name;
+    use hickory_proto::serialize::binary::BinEncodable;
+    use std::str::FromStr;
+
+    #[test]
+    fn labels_come_straight_off_the_wire() {
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            Name::from_str("www.example.test.").unwrap(),
+            RecordType::AAAA,
+        ));
+        message.set_response_code(ResponseCode::NXDomain);
+        let bytes = message.to_bytes().unwrap();
+        assert_eq!(qtype_label(&bytes), "AAAA");
+        assert_eq!(rcode_label(&bytes), "NXDOMAIN");
+
+        let mut odd = Message::new();
+        odd.add_query(Query::query(
+            Name::from_str("www.example.test.").unwrap(),
+            RecordType::Unknown(65300),
+        ));
+        assert_eq!(qtype_label(&odd.to_bytes().unwrap()), "OTHER");
+        assert_eq!(qtype_label(&[]), "NONE");
+    }
+}
@@ -0,0 +1,131 @@
+use hickory_proto::op::Message;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use std::net::IpAddr;
+
+/// A CIDR block, as used by the transfer and recursion ACLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    /// Parse `10.0.0.0/8`, or a bare address treated as a single host.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        let (addr_part, prefix_part) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr_part.parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix_part {
+            Some(p) => p.parse::<u8>().ok()?,
+            None => max,
+        };
+        if prefix > max {
+            return None;
+        }
+        Some(Self { addr, prefix })
+    }
+
+    /// The prefix length; longer is more specific.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(other)) => {
+                let mask = if self.prefix == 0 {
+                    0
+                } else {
+                    u32::MAX << (32 - self.prefix)
+                };
+                (u32::from(net) & mask) == (u32::from(other) & mask)
+            }
+            (IpAddr::V6(net), IpAddr::V6(other)) => {
+                let mask = if self.prefix == 0 {
+                    0u128
+                } else {
+                    u128::MAX << (128 - self.prefix)
+                };
+                (u128::from(net) & mask) == (u128::from(other) & mask)
+            }
+            // An IPv4-mapped IPv6 peer should still match an IPv4 rule.
+            (IpAddr::V4(_), IpAddr::V6(other)) => other
+                .to_ipv4_mapped()
+                .is_some_and(|v4| self.contains(IpAddr::V4(v4))),
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+/// The EDNS Client Subnet option (RFC 7871) in `message`: its address, source
+/// prefix and scope prefix.
+pub fn client_subnet(message: &Message) -> Option<(IpAddr, u8, u8)> {
+    let edns = message.extensions().as_ref()?;
+    let EdnsOption::Subnet(option) = edns.option(EdnsCode::Subnet)? else {
+        return None;
+    };
+    // hickory keeps the fields private; its wire form has them all.
+    let data = Vec::<u8>::try_from(option).ok()?;
+    let (&source, &scope) = (data.get(2)?, data.get(3)?);
+    let addr = match u16::from_be_bytes([data[0], data[1]]) {
+        1 => {
+            let mut octets = [0u8; 4];
+            let bytes = &data[4..];
+            octets[..bytes.len().min(4)].copy_from_slice(&bytes[..bytes.len().min(4)]);
+            IpAddr::V4(octets.into())
+        }
+        2 => {
+            let mut octets = [0u8; 16];
+            let bytes = &data[4..];
+            octets[..bytes.len().min(16)].copy_from_slice(&bytes[..bytes.len().min(16)]);
+            IpAddr::V6(octets.into())
+        }
+        _ => return None,
+    };
+    Some((addr, source, scope))
+}
+
+/// Frame a DNS message for TCP transport (prepend 2-byte length).
+pub fn frame_dns_tcp(msg: &[u8]) -> Vec<u8> {
+    let len = msg.len() as u16;
+    let mut framed = Vec::with_capacity(2 + msg.len());
+    framed.extend_from_slice(&len.to_be_bytes());
+    framed.extend_from_slice(msg);
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cidr_matching() {
+        let net = IpNet::parse("192.168.0.0/16").unwrap();
+        assert!(net.contains("192.168.1.253".parse().unwrap()));
+        assert!(net.contains("192.168.200.199".parse().unwrap()));
+        assert!(!net.contains("10.0.0.1".parse().unwrap()));
+
+        // A bare address is a single host.
+        let host = IpNet::parse("192.168.1.253").unwrap();
+        assert!(host.contains("192.168.1.253".parse().unwrap()));
+        assert!(!host.contains("192.168.1.254".parse().unwrap()));
+
+        assert!(IpNet::parse("192.168.0.0/33").is_none());
+        assert!(IpNet::parse("not-an-address").is_none());
+    }
+
+    #[test]
+    fn test_frame_dns_tcp() {
+        let msg = vec![0x12, 0x34, 0x01, 0x00];
+        let framed = frame_dns_tcp(&msg);
+        assert_eq!(framed.len(), 6);
+        // 2-byte length prefix = 4
+        assert_eq!(framed[0], 0x00);
+        assert_eq!(framed[1], 0x04);
+        assert_eq!(&framed[2..], &msg);
+    }
+}
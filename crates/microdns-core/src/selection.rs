@@ -0,0 +1,241 @@
+//! Which healthy members of a load-balanced RRset go into an answer, and in
+//! what order.
+//!
+//! The LB monitor decides who is healthy by enabling and disabling records;
+//! this decides, per response, which of the enabled ones a client is given
+//! and which comes first. It runs on the compiled zone, so the authoritative
+//! server and the recursor's local zones answer the same way.
+
+use crate::types::{AnswerOrder, HealthCheck, Selection};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A group's policy, compiled against its healthy members' weights.
+pub struct Selector {
+    mode: Selection,
+    count: usize,
+    order: AnswerOrder,
+    max_answers: Option<usize>,
+    weights: Vec<u32>,
+    next: AtomicU64,
+    /// Responses given so far, for `round_robin`.
+    rotation: AtomicU64,
+}
+
+impl Selector {
+    pub fn new(policy: &HealthCheck, weights: Vec<u32>) -> Self {
+        Self {
+            mode: policy.selection,
+            count: policy.answer_count.max(1) as usize,
+            order: policy.order,
+            max_answers: policy.max_answers.map(|n| n.max(1) as usize),
+            weights,
+            next: AtomicU64::new(0),
+            rotation: AtomicU64::new(0),
+        }
+    }
+
+    /// Indices of the members to answer with, in answer order, chosen from
+    /// `eligible`. With no weight among them to share traffic by, every
+    /// eligible member is selected.
+    pub fn pick(&self, eligible: &[usize]) -> Vec<usize> {
+        let total: u64 = eligible.iter().map(|&i| u64::from(self.weights[i])).sum();
+        let mut picked = match self.mode {
+            _ if total == 0 => eligible.to_vec(),
+            Selection::All => eligible.to_vec(),
+            Selection::WeightedRandom => self.pick_random(eligible, total, &mut rand::thread_rng()),
+            Selection::Ratio => {
+                let n = self.next.fetch_add(1, Ordering::Relaxed);
+                let slot = n.wrapping_mul(stride(total)) % total;
+                vec![self.member_at(eligible, slot)]
+            }
+        };
+        match self.order {
+            AnswerOrder::Fixed => {}
+            AnswerOrder::RoundRobin if !picked.is_empty() => {
+                let n = self.rotation.fetch_add(1, Ordering::Relaxed);
+                let len = picked.len() as u64;
+                picked.rotate_left((n % len) as usize);
+            }
+            AnswerOrder::RoundRobin => {}
+            AnswerOrder::Random => picked.shuffle(&mut rand::thread_rng()),
+        }
+        if let Some(max) = self.max_answers {
+            picked.truncate(max);
+        }
+        picked
+    }
+
+    /// Draw without replacement, each remaining member in proportion to its
+    /// weight. Zero-weight members are never drawn.
+    fn pick_random(&self, eligible: &[usize], total: u64, rng: &mut impl Rng) -> Vec<usize> {
+        let mut remaining: Vec<(usize, u64)> = eligible
+            .iter()
+            .map(|&i| (i, u64::from(self.weights[i])))
+            .filter(|&(_, w)| w > 0)
+            .collect();
+        let mut left = total;
+        let mut picked = Vec::with_capacity(self.count.min(remaining.len()));
+        while picked.len() < self.count && !remaining.is_empty() {
+            let mut roll = rng.gen_range(0..left);
+            let at = remaining
+                .iter()
+                .position(|&(_, w)| {
+                    if roll < w {
+                        true
+                    } else {
+                        roll -= w;
+                        false
+                    }
+                })
+                .unwrap_or(remaining.len() - 1);
+            let (index, weight) = remaining.remove(at);
+            left -= weight;
+            picked.push(index);
+        }
+        picked
+    }
+
+    /// The member owning `slot` when the slots `0..total` are laid out as
+    /// consecutive runs, one run of `weight` slots per eligible member.
+    fn member_at(&self, eligible: &[usize], mut slot: u64) -> usize {
+        for &index in eligible {
+            let weight = u64::from(self.weights[index]);
+            if slot < weight {
+                return index;
+            }
+            slot -= weight;
+        }
+        eligible[eligible.len() - 1]
+    }
+}
+
+/// The step between ratio slots: coprime to `total`, so a cycle visits every
+/// slot once, and near `total / φ`, so consecutive answers interleave members
+/// instead of running through one member's slots back to back.
+fn stride(total: u64) -> u64 {
+    if total <= 2 {
+        return 1;
+    }
+    let start = ((total as f64) * 0.618_033_988_75).round() as u64;
+    (start..total)
+        .chain(1..start)
+        .find(|&s| gcd(s, total) == 1)
+        .unwrap_or(1)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn policy(selection: Selection, answer_count: u32) -> HealthCheck {
+        HealthCheck {
+            selection,
+            answer_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn ratio_gives_each_member_its_exact_share_per_cycle() {
+        // A 10% canary.
+        let selector = Selector::new(&policy(Selection::Ratio, 1), vec![9, 1]);
+        let mut counts = [0; 2];
+        let mut canary_at = Vec::new();
+        for n in 0..20 {
+            let picked = selector.pick(&[0, 1]);
+            assert_eq!(picked.len(), 1);
+            counts[picked[0]] += 1;
+            if picked[0] == 1 {
+                canary_at.push(n);
+            }
+        }
+        assert_eq!(counts, [18, 2]);
+        // Exactly once in each cycle of ten.
+        assert!(canary_at[0] < 10 && canary_at[1] >= 10);
+
+        // Interleaved, not one member's slots back to back.
+        let selector = Selector::new(&policy(Selection::Ratio, 1), vec![3, 3]);
+        let order: Vec<usize> = (0..6).map(|_| selector.pick(&[0, 1])[0]).collect();
+        assert_ne!(order, [0, 0, 0, 1, 1, 1]);
+        assert_eq!(order.iter().filter(|&&i| i == 1).count(), 3);
+    }
+
+    #[test]
+    fn weighted_random_draws_distinct_members_in_proportion() {
+        let selector = Selector::new(&policy(Selection::WeightedRandom, 2), vec![1, 0, 8, 1]);
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut first = [0; 4];
+        for _ in 0..10_000 {
+            let picked = selector.pick_random(&[0, 1, 2, 3], 10, &mut rng);
+            assert_eq!(picked.len(), 2);
+            assert_ne!(picked[0], picked[1]);
+            assert!(!picked.contains(&1), "zero weight is never drawn");
+            first[picked[0]] += 1;
+        }
+        assert!((7_600..8_400).contains(&first[2]), "{first:?}");
+
+        // Never more than the members there are to give.
+        let selector = Selector::new(&policy(Selection::WeightedRandom, 5), vec![1, 0, 1]);
+        assert_eq!(selector.pick(&[0, 1, 2]).len(), 2);
+    }
+
+    #[test]
+    fn round_robin_rotates_and_max_answers_cuts_after_ordering() {
+        let rotating = HealthCheck {
+            order: AnswerOrder::RoundRobin,
+            max_answers: Some(2),
+            ..policy(Selection::All, 1)
+        };
+        let selector = Selector::new(&rotating, vec![1, 1, 1]);
+        let answers: Vec<Vec<usize>> = (0..4).map(|_| selector.pick(&[0, 1, 2])).collect();
+        assert_eq!(answers, [vec![0, 1], vec![1, 2], vec![2, 0], vec![0, 1]]);
+
+        // Shuffled, every member still comes first now and then.
+        let shuffled = HealthCheck {
+            order: AnswerOrder::Random,
+            ..policy(Selection::All, 1)
+        };
+        let selector = Selector::new(&shuffled, vec![1, 1, 1]);
+        let mut first = [false; 3];
+        for _ in 0..200 {
+            let picked = selector.pick(&[0, 1, 2]);
+            assert_eq!(picked.len(), 3);
+            first[picked[0]] = true;
+        }
+        assert_eq!(first, [true; 3]);
+    }
+
+    #[test]
+    fn only_eligible_members_are_picked_and_no_weight_means_all_of_them() {
+        let selector = Selector::new(&policy(Selection::Ratio, 1), vec![5, 1, 1, 0]);
+        let mut counts = [0; 4];
+        for _ in 0..20 {
+            counts[selector.pick(&[1, 2])[0]] += 1;
+        }
+        assert_eq!(counts, [0, 10, 10, 0]);
+
+        // No weight among the eligible falls back to all of them, still cut
+        // to `max_answers`.
+        assert_eq!(selector.pick(&[3]), [3]);
+        let capped = HealthCheck {
+            max_answers: Some(1),
+            ..policy(Selection::Ratio, 1)
+        };
+        let selector = Selector::new(&capped, vec![0, 0]);
+        assert_eq!(selector.pick(&[0, 1]), [0]);
+        for total in 1..50 {
+            assert_eq!(gcd(stride(total), total), 1, "total {total}");
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! Topology steering: clients are answered with the members of a
+//! load-balanced group in their own network first.
+//!
+//! The same service runs in several subnets, and a client is better served by
+//! the backend next to it. Rules map client networks to the member networks
+//! they prefer; per query, the answer is drawn from the preferred members that
+//! are healthy, or from every healthy member when none of those are. Health is
+//! still one global `enabled` flag per record — steering only narrows what a
+//! given client is offered, so it never takes a member away from anyone else.
+//!
+//! The rules live in the database and a watcher swaps the parsed copy here
+//! when they change, like the recursion ACL.
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use hickory_proto::op::Message;
+use tracing::{info, warn};
+
+use crate::config::LbTopologyConfig;
+use crate::net::{self, IpNet};
+
+/// One parsed [`TopologyRule`](crate::config::TopologyRule).
+#[derive(Debug, Clone, PartialEq)]
+struct Rule {
+    name: String,
+    clients: Vec<IpNet>,
+    members: Vec<IpNet>,
+}
+
+/// The parsed form of [`LbTopologyConfig`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Topology {
+    rules: Vec<Rule>,
+}
+
+impl Topology {
+    /// Parse the stored rules. Entries that do not parse are dropped with a
+    /// warning; a rule left with no client network is dropped whole.
+    pub fn parse(config: &LbTopologyConfig) -> Self {
+        let rules = config
+            .rules
+            .iter()
+            .filter_map(|rule| {
+                let clients = parse_nets(&rule.clients, &rule.name, "clients");
+                if clients.is_empty() {
+                    warn!(
+                        "topology: ignoring rule '{}' with no valid clients",
+                        rule.name
+                    );
+                    return None;
+                }
+                let members = if rule.members.is_empty() {
+                    clients.clone()
+                } else {
+                    parse_nets(&rule.members, &rule.name, "members")
+                };
+                Some(Rule {
+                    name: rule.name.clone(),
+                    clients,
+                    members,
+                })
+            })
+            .collect();
+        Self { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The rule for `client`: the one with its most specific matching client
+    /// network, the first such on a tie.
+    fn rule_for(&self, client: IpAddr) -> Option<&Rule> {
+        let mut best: Option<(&Rule, u8)> = None;
+        for rule in &self.rules {
+            let prefix = rule
+                .clients
+                .iter()
+                .filter(|net| net.contains(client))
+                .map(IpNet::prefix_len)
+                .max();
+            if let Some(prefix) = prefix {
+                if best.is_none_or(|(_, p)| prefix > p) {
+                    best = Some((rule, prefix));
+                }
+            }
+        }
+        best.map(|(rule, _)| rule)
+    }
+
+    /// The name of the rule steering `client`, if one does.
+    pub fn rule_name(&self, client: IpAddr) -> Option<&str> {
+        self.rule_for(client).map(|rule| rule.name.as_str())
+    }
+
+    /// Whether `client` prefers a member at `member`. Nothing is preferred
+    /// for a client no rule covers, which leaves every healthy member in.
+    pub fn prefers(&self, client: IpAddr, member: IpAddr) -> bool {
+        self.rule_for(client)
+            .is_some_and(|rule| rule.members.iter().any(|net| net.contains(member)))
+    }
+
+    /// One line describing what is in force, for the log on startup or change.
+    pub fn summary(&self) -> String {
+        if self.rules.is_empty() {
+            return "no rules, every client gets every healthy member".to_string();
+        }
+        let names: Vec<&str> = self
+            .rules
+            .iter()
+            .map(|rule| {
+                if rule.name.is_empty() {
+                    "-"
+                } else {
+                    rule.name.as_str()
+                }
+            })
+            .collect();
+        format!("{} rule(s): {}", self.rules.len(), names.join(", "))
+    }
+}
+
+fn parse_nets(entries: &[String], rule: &str, field: &str) -> Vec<IpNet> {
+    entries
+        .iter()
+        .filter_map(|c| match IpNet::parse(c) {
+            Some(net) => Some(net),
+            None => {
+                warn!("topology: ignoring invalid {field} entry '{c}' in rule '{rule}'");
+                None
+            }
+        })
+        .collect()
+}
+
+/// The address a query is steered by: its EDNS Client Subnet when it carries
+/// one, so a resolver asking for someone else gets their answer, or else the
+/// address it came from. A source prefix of 0 asks that the client's network
+/// not be used (RFC 7871 §7.1.2), which leaves the sender's.
+pub fn client_address(request: &Message, peer: IpAddr) -> IpAddr {
+    match net::client_subnet(request) {
+        Some((addr, source, _)) if source > 0 => addr.to_canonical(),
+        _ => peer.to_canonical(),
+    }
+}
+
+/// Shared handle to the rules in force.
+#[derive(Clone, Default)]
+pub struct TopologyState {
+    inner: Arc<Mutex<Arc<Topology>>>,
+}
+
+impl TopologyState {
+    pub fn new(config: &LbTopologyConfig) -> Self {
+        let topology = Topology::parse(config);
+        info!("lb topology: {}", topology.summary());
+        Self {
+            inner: Arc::new(Mutex::new(Arc::new(topology))),
+        }
+    }
+
+    /// Swap in new rules. Returns whether anything changed, so the caller
+    /// can log a change and not a heartbeat.
+    pub fn replace(&self, config: &LbTopologyConfig) -> bool {
+        let next = Topology::parse(config);
+        let mut current = self.inner.lock().unwrap();
+        let changed = **current != next;
+        *current = Arc::new(next);
+        changed
+    }
+
+    /// The rules in force, for a single query.
+    pub fn current(&self) -> Arc<Topology> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    pub fn summary(&self) -> String {
+        self.inner.lock().unwrap().summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, Query};
+    use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+    use hickory_proto::rr::{Name, RecordType};
+
+    use crate::config::TopologyRule;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn rule(name: &str, clients: &[&str], members: &[&str]) -> TopologyRule {
+        TopologyRule {
+            name: name.into(),
+            clients: clients.iter().map(|s| s.to_string()).collect(),
+            members: members.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn clients_prefer_members_in_their_own_network() {
+        let topology = Topology::parse(&LbTopologyConfig {
+            rules: vec![
+                rule("g10", &["192.168.10.0/24"], &[]),
+                rule("g11", &["192.168.11.0/24"], &[]),
+                rule("gw", &["10.0.0.0/8"], &["10.1.0.0/16"]),
+                rule("gw-lab", &["10.9.0.0/16"], &["10.9.0.0/16"]),
+            ],
+        });
+        assert!(topology.prefers(ip("192.168.10.5"), ip("192.168.10.80")));
+        assert!(!topology.prefers(ip("192.168.10.5"), ip("192.168.11.80")));
+        assert!(topology.prefers(ip("::ffff:192.168.11.5"), ip("192.168.11.80")));
+        assert!(topology.prefers(ip("10.2.3.4"), ip("10.1.0.80")));
+        // The most specific client network wins, wherever it is listed.
+        assert_eq!(topology.rule_name(ip("10.9.3.4")), Some("gw-lab"));
+        assert!(!topology.prefers(ip("10.9.3.4"), ip("10.1.0.80")));
+        // Nobody's rule, nobody preferred.
+        assert!(!topology.prefers(ip("203.0.113.9"), ip("192.168.10.80")));
+    }
+
+    #[test]
+    fn bad_entries_are_dropped_and_changes_are_reported() {
+        let state = TopologyState::new(&LbTopologyConfig::default());
+        assert!(state.current().is_empty());
+        let config = LbTopologyConfig {
+            rules: vec![
+                rule("broken", &["not-a-cidr"], &[]),
+                rule("g10", &["192.168.10.0/24", "nope"], &[]),
+            ],
+        };
+        assert!(state.replace(&config));
+        assert!(!state.replace(&config));
+        assert_eq!(state.summary(), "1 rule(s): g10");
+    }
+
+    #[test]
+    fn a_client_subnet_stands_in_for_the_sender() {
+        let mut request = Message::new();
+        request.add_query(Query::query(
+            Name::from_ascii("app.lo.").unwrap(),
+            RecordType::A,
+        ));
+        let resolver = ip("10.0.0.53");
+        assert_eq!(client_address(&request, resolver), resolver);
+
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                ip("192.168.11.0"),
+                24,
+                0,
+            )));
+        request.set_edns(edns.clone());
+        assert_eq!(client_address(&request, resolver), ip("192.168.11.0"));
+
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                ip("192.168.11.0"),
+                0,
+                0,
+            )));
+        request.set_edns(edns);
+        assert_eq!(client_address(&request, resolver), resolver);
+    }
+}
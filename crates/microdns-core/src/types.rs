@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::net::{Ipv4Addr, Ipv6Addr};
 use uuid::Uuid;
 
@@ -151,6 +152,9 @@ pub enum RecordSource {
     Mdns,
     /// Generated from Kubernetes API objects by the cluster DNS source.
     K8s,
+    /// Local data the recursor answers without a zone: an override, or an
+    /// entry read from a hosts file (named in `origin`).
+    Local,
 }
 
 impl std::fmt::Display for RecordSource {
@@ -160,6 +164,7 @@ impl std::fmt::Display for RecordSource {
             RecordSource::Dhcp => write!(f, "dhcp"),
             RecordSource::Mdns => write!(f, "mdns"),
             RecordSource::K8s => write!(f, "k8s"),
+            RecordSource::Local => write!(f, "local"),
         }
     }
 }
@@ -209,6 +214,145 @@ pub struct HealthCheck {
     pub healthy_threshold: u32,
     /// Optional: specific port/path for HTTP checks
     pub endpoint: Option<String>,
+    /// This member's share of traffic under `weighted_random` and `ratio`.
+    /// Unset means 1; 0 keeps a healthy member out of weighted answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub weight: Option<u32>,
+    /// How the group's healthy members are put into an answer. Members of
+    /// one RRset should agree on this, `answer_count`, `order` and
+    /// `max_answers`; if they don't, the earliest-created member with a
+    /// health check decides for the group.
+    #[serde(default)]
+    pub selection: Selection,
+    /// How many members a `weighted_random` answer carries.
+    #[serde(default = "default_answer_count")]
+    pub answer_count: u32,
+    /// The order the selected members are answered in.
+    #[serde(default)]
+    pub order: AnswerOrder,
+    /// Answer with at most this many members, after ordering.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_answers: Option<u32>,
+    /// This member's tier: 0 (the default) is primary, 1 its backup, and so
+    /// on. Only the best tier with a healthy member is served.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u32>,
+    /// How long a better tier must have been healthy before traffic fails
+    /// back to it from the tier covering for it. 0 fails back at once.
+    #[serde(default)]
+    pub preempt_delay_secs: u32,
+    /// The query a `dns` probe sends and the reply it expects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsProbe>,
+    /// The request an `http`/`https` probe makes and the reply it expects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpProbe>,
+    /// The handshake a `tls` probe makes and the certificate it accepts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsProbe>,
+    /// The service a `grpc` probe asks about, and how it connects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcProbe>,
+}
+
+fn default_answer_count() -> u32 {
+    1
+}
+
+impl HealthCheck {
+    /// The weight used for selection, with the default applied.
+    pub fn weight(&self) -> u32 {
+        self.weight.unwrap_or(1)
+    }
+
+    /// The tier, with the default applied.
+    pub fn priority(&self) -> u32 {
+        self.priority.unwrap_or(0)
+    }
+
+    /// Whether `other` answers a group the same way: same selection, answer
+    /// count, order and cap.
+    pub fn same_group_policy(&self, other: &HealthCheck) -> bool {
+        self.selection == other.selection
+            && self.answer_count == other.answer_count
+            && self.order == other.order
+            && self.max_answers == other.max_answers
+    }
+}
+
+/// A TCP check every 5 seconds with a 2-second timeout, down after 3
+/// failures and up after 2 successes, answering every healthy member.
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            probe_type: ProbeType::Tcp,
+            interval_secs: 5,
+            timeout_secs: 2,
+            unhealthy_threshold: 3,
+            healthy_threshold: 2,
+            endpoint: None,
+            weight: None,
+            selection: Selection::default(),
+            answer_count: default_answer_count(),
+            order: AnswerOrder::default(),
+            max_answers: None,
+            priority: None,
+            preempt_delay_secs: 0,
+            dns: None,
+            http: None,
+            tls: None,
+            grpc: None,
+        }
+    }
+}
+
+/// How the healthy members of a load-balanced RRset are answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Selection {
+    /// Every healthy member, in stored order.
+    #[default]
+    All,
+    /// `answer_count` members drawn at random without replacement, each
+    /// with probability proportional to its weight.
+    WeightedRandom,
+    /// One member per answer, handed out in exact proportion to weight:
+    /// over every `sum(weights)` answers each member appears `weight` times.
+    Ratio,
+}
+
+/// The order a load-balanced group's answer is given in. Clients mostly use
+/// the first address, so this is what spreads them over the members.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerOrder {
+    /// As selected: stored order, or draw order for `weighted_random`.
+    #[default]
+    Fixed,
+    /// Rotated by one position per response.
+    RoundRobin,
+    /// Shuffled per response.
+    Random,
+}
+
+impl std::fmt::Display for AnswerOrder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fixed => write!(f, "fixed"),
+            Self::RoundRobin => write!(f, "round_robin"),
+            Self::Random => write!(f, "random"),
+        }
+    }
+}
+
+impl std::fmt::Display for Selection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::All => write!(f, "all"),
+            Self::WeightedRandom => write!(f, "weighted_random"),
+            Self::Ratio => write!(f, "ratio"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -231,6 +375,179 @@ pub enum ProbeType {
     /// `Healthy`. Failure-detection latency is governed by keepalive
     /// tunables, not the probe interval.
     TcpHalfOpen,
+    /// Send a DNS query to the target (port 53, or the endpoint's) and
+    /// check the reply against [`HealthCheck::dns`].
+    Dns,
+    /// Complete a TLS handshake with the target (port 443, or the
+    /// endpoint's) and check its certificate against [`HealthCheck::tls`].
+    Tls,
+    /// Call `grpc.health.v1.Health/Check` on the target (port 50051, or
+    /// the endpoint's); healthy when it answers `SERVING`.
+    Grpc,
+}
+
+/// What a `dns` probe asks, and what counts as a correct reply.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsProbe {
+    /// Name to query; the root by default.
+    #[serde(default = "default_dns_probe_name")]
+    pub name: String,
+    /// Record type to query, e.g. `A` or `SOA`.
+    #[serde(default = "default_dns_probe_type")]
+    pub record_type: String,
+    #[serde(default)]
+    pub transport: DnsTransport,
+    /// Response code the reply must carry, e.g. `NOERROR` or `NXDOMAIN`.
+    /// Unset accepts any reply but SERVFAIL and REFUSED.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_rcode: Option<String>,
+    /// An address that must be among the reply's A/AAAA answers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_answer: Option<std::net::IpAddr>,
+}
+
+fn default_dns_probe_name() -> String {
+    ".".to_string()
+}
+
+fn default_dns_probe_type() -> String {
+    "SOA".to_string()
+}
+
+impl Default for DnsProbe {
+    fn default() -> Self {
+        Self {
+            name: default_dns_probe_name(),
+            record_type: default_dns_probe_type(),
+            transport: DnsTransport::default(),
+            expect_rcode: None,
+            expect_answer: None,
+        }
+    }
+}
+
+/// What an `http`/`https` probe requests, and what counts as healthy.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpProbe {
+    #[serde(default = "default_http_probe_method")]
+    pub method: String,
+    /// Status codes that pass. Empty accepts any 2xx.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expect_status: Vec<u16>,
+    /// A substring the response body must contain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_body: Option<String>,
+    /// A regular expression the response body must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expect_body_regex: Option<String>,
+    /// Virtual host to ask for: sent as the Host header and, over HTTPS,
+    /// as the TLS server name. The connection still goes to the member.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// Extra request headers.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Validate the member's certificate (against `host` when set). Off by
+    /// default: a health check is about liveness, not identity.
+    #[serde(default)]
+    pub verify_tls: bool,
+    /// PEM file of CA certificates to validate against instead of the
+    /// built-in roots. Setting it implies `verify_tls`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+}
+
+fn default_http_probe_method() -> String {
+    "GET".to_string()
+}
+
+impl Default for HttpProbe {
+    fn default() -> Self {
+        Self {
+            method: default_http_probe_method(),
+            expect_status: Vec::new(),
+            expect_body: None,
+            expect_body_regex: None,
+            host: None,
+            headers: BTreeMap::new(),
+            verify_tls: false,
+            ca_bundle: None,
+        }
+    }
+}
+
+/// What a `tls` probe asks for, and which certificates pass.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TlsProbe {
+    /// Server name sent as SNI and that the certificate must be valid for.
+    /// Unset sends none and checks the certificate against the address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// Validate the chain and name. Off, only expiry is checked.
+    #[serde(default = "default_true")]
+    pub verify: bool,
+    /// PEM file of CA certificates to validate against instead of the
+    /// built-in roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    /// Fail when the certificate expires in fewer days than this.
+    #[serde(default = "default_tls_min_days_left")]
+    pub min_days_left: u32,
+    /// Pass, but warn, when the certificate expires in fewer days than this.
+    #[serde(default = "default_tls_warn_days_left")]
+    pub warn_days_left: u32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_tls_min_days_left() -> u32 {
+    7
+}
+
+fn default_tls_warn_days_left() -> u32 {
+    30
+}
+
+impl Default for TlsProbe {
+    fn default() -> Self {
+        Self {
+            server_name: None,
+            verify: true,
+            ca_bundle: None,
+            min_days_left: default_tls_min_days_left(),
+            warn_days_left: default_tls_warn_days_left(),
+        }
+    }
+}
+
+/// What a `grpc` probe checks, and how it connects.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GrpcProbe {
+    /// Service name to check; empty asks about the server as a whole.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub service: String,
+    /// Connect over TLS rather than plaintext HTTP/2.
+    #[serde(default)]
+    pub tls: bool,
+    /// Name the server's certificate must be valid for, also sent as SNI.
+    /// Unset checks the certificate against the member's address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_name: Option<String>,
+    /// PEM file of CA certificates to validate against instead of the
+    /// built-in roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+}
+
+/// Transport a `dns` probe queries over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsTransport {
+    #[default]
+    Udp,
+    Tcp,
 }
 
 impl std::fmt::Display for ProbeType {
@@ -241,6 +558,9 @@ impl std::fmt::Display for ProbeType {
             ProbeType::Https => write!(f, "https"),
             ProbeType::Tcp => write!(f, "tcp"),
             ProbeType::TcpHalfOpen => write!(f, "tcp_half_open"),
+            ProbeType::Dns => write!(f, "dns"),
+            ProbeType::Tls => write!(f, "tls"),
+            ProbeType::Grpc => write!(f, "grpc"),
         }
     }
 }
@@ -267,6 +587,44 @@ impl std::fmt::Display for HealthStatus {
     }
 }
 
+/// Operator-set state of a load-balanced record, independent of its health.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AdminState {
+    /// In rotation whenever healthy.
+    #[default]
+    Active,
+    /// Out of rotation but still probed; used by failsafe as a last resort.
+    Draining,
+    /// Out of rotation, not probed and never used by failsafe.
+    Maintenance,
+}
+
+impl std::fmt::Display for AdminState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Active => write!(f, "active"),
+            Self::Draining => write!(f, "draining"),
+            Self::Maintenance => write!(f, "maintenance"),
+        }
+    }
+}
+
+impl std::str::FromStr for AdminState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "active" => Ok(Self::Active),
+            "draining" => Ok(Self::Draining),
+            "maintenance" => Ok(Self::Maintenance),
+            other => Err(format!(
+                "unknown admin state {other:?} (expected active, draining or maintenance)"
+            )),
+        }
+    }
+}
+
 /// Per-query observability stats. Tracks when each `(fqdn, type)` pair
 /// was last asked about by a real DNS client and how many times in
 /// total. Persisted via the `query_stats` redb table; hydrated on
@@ -283,6 +641,21 @@ pub struct QueryStat {
     pub total_count: u64,
 }
 
+/// A recursor cache entry saved on shutdown and loaded on start, so a
+/// restart does not send every client to upstream at once. Persisted via the
+/// `recursor_cache` redb table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+    /// Lower-cased query name without trailing dot.
+    pub name: String,
+    pub rtype: u16,
+    pub rclass: u16,
+    pub saved_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    /// The wire-format response, TTLs as they stood at `saved_at`.
+    pub response: Vec<u8>,
+}
+
 /// Persisted health-state row. Written at the end of each LB probe cycle so
 /// a quick container restart does not lose the most recent view of each
 /// record's health. Callers should consult `last_checked_at` to decide
@@ -298,6 +671,9 @@ pub struct PersistedHealth {
     pub last_probe_type: ProbeType,
     pub consecutive_successes: u32,
     pub consecutive_failures: u32,
+    /// Operator-set state; rows written before it existed are active.
+    #[serde(default)]
+    pub admin_state: AdminState,
 }
 
 /// DHCP lease record
@@ -0,0 +1,751 @@
+//! Zones compiled for answering queries.
+//!
+//! The database stores zones and records as JSON, indexed for editing. Reading
+//! them per query means deserializing every zone to find the one that owns the
+//! name, then the records. This is the same data, held in memory as a map of
+//! zones to owner names to RRsets, already converted to hickory records: a
+//! query is a handful of hash lookups and clones.
+//!
+//! The tree is immutable and swapped whole, so readers never lock. Every write
+//! to a zone marks it stale, and the writer reloads the stale zones from the
+//! database before the write returns, so an answer never predates a write its
+//! caller has seen complete. Writes that land during a reload are picked up by
+//! the next one, so a burst of them costs few. Queries never wait on a reload:
+//! they are answered from the tree it replaces until it is swapped in.
+
+use crate::db::Db;
+use crate::error::Result;
+use crate::selection::Selector;
+use crate::types::{HealthCheck, Record, RecordData, Zone};
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use hickory_proto::rr::rdata::{CAA, CNAME, MX, NS, PTR, SOA, SRV, TXT};
+use hickory_proto::rr::{LowerName, Name, RData, Record as DnsRecord, RecordType};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use uuid::Uuid;
+
+/// The compiled view of a database's zones, kept current as they change.
+pub(crate) struct ZoneIndex {
+    tree: ArcSwap<ZoneTree>,
+    /// Set whenever `pending` holds something; the only thing a reader checks
+    /// when nothing has changed.
+    stale: AtomicBool,
+    pending: Mutex<Pending>,
+    /// Held for a whole reload, so reloads cannot finish out of order.
+    reload: Mutex<()>,
+}
+
+/// Zones written since the last reload.
+#[derive(Default)]
+struct Pending {
+    everything: bool,
+    zones: HashSet<Uuid>,
+}
+
+impl ZoneIndex {
+    /// An index that loads everything on the first [`Self::refresh`].
+    pub(crate) fn new() -> Self {
+        Self {
+            tree: ArcSwap::from_pointee(ZoneTree::default()),
+            stale: AtomicBool::new(true),
+            pending: Mutex::new(Pending {
+                everything: true,
+                zones: HashSet::new(),
+            }),
+            reload: Mutex::new(()),
+        }
+    }
+
+    /// Mark a zone as changed and reload it. Called by the writer after the
+    /// write is committed; waits for any reload already under way.
+    pub(crate) fn invalidate(&self, db: &Db, zone_id: Uuid) {
+        {
+            let mut pending = self.pending.lock().unwrap();
+            pending.zones.insert(zone_id);
+            self.stale.store(true, Ordering::Release);
+        }
+        self.refresh(db);
+    }
+
+    /// Reload whatever is stale, waiting for any reload already under way.
+    pub(crate) fn refresh(&self, db: &Db) {
+        if self.stale.load(Ordering::Acquire) {
+            self.reload(db, self.reload.lock().unwrap());
+        }
+    }
+
+    /// The tree as of the last completed reload. A reload left pending by a
+    /// failed one is retried here, but only if no other is running: a query
+    /// never waits for one.
+    pub(crate) fn current(&self, db: &Db) -> Arc<ZoneTree> {
+        if self.stale.load(Ordering::Acquire) {
+            if let Ok(held) = self.reload.try_lock() {
+                self.reload(db, held);
+            }
+        }
+        self.tree.load_full()
+    }
+
+    fn reload(&self, db: &Db, _held: MutexGuard<'_, ()>) {
+        let pending = {
+            let mut pending = self.pending.lock().unwrap();
+            if !self.stale.load(Ordering::Acquire) {
+                // Someone else reloaded while this reader waited.
+                return;
+            }
+            self.stale.store(false, Ordering::Release);
+            std::mem::take(&mut *pending)
+        };
+
+        let result = if pending.everything {
+            load_all(db)
+        } else {
+            load_changed(db, &self.tree.load(), &pending.zones)
+        };
+        match result {
+            Ok(tree) => self.tree.store(Arc::new(tree)),
+            Err(e) => {
+                // Serve what there is and try again on the next write or query.
+                tracing::error!("zone index: reload failed: {e}");
+                let mut again = self.pending.lock().unwrap();
+                again.everything |= pending.everything;
+                again.zones.extend(pending.zones);
+                self.stale.store(true, Ordering::Release);
+            }
+        }
+    }
+}
+
+fn load_all(db: &Db) -> Result<ZoneTree> {
+    let mut records: HashMap<Uuid, Vec<Record>> = HashMap::new();
+    for record in db.list_all_records()? {
+        records.entry(record.zone_id).or_default().push(record);
+    }
+    let mut tree = ZoneTree::default();
+    for zone in db.list_zones()? {
+        let records = records.remove(&zone.id).unwrap_or_default();
+        tree.insert(CompiledZone::compile(zone, &records));
+    }
+    Ok(tree)
+}
+
+fn load_changed(db: &Db, current: &ZoneTree, changed: &HashSet<Uuid>) -> Result<ZoneTree> {
+    // By ID, so that a renamed zone drops its old name and a deleted one goes.
+    let mut zones = current.zones.clone();
+    zones.retain(|_, zone| !changed.contains(&zone.zone.id));
+    for id in changed {
+        if let Some(zone) = db.get_zone(id)? {
+            let records = db.list_records(&zone.id)?;
+            let compiled = CompiledZone::compile(zone, &records);
+            zones.insert(compiled.key.clone(), Arc::new(compiled));
+        }
+    }
+    Ok(ZoneTree { zones })
+}
+
+/// Every zone, by lowercase name.
+#[derive(Default)]
+pub struct ZoneTree {
+    zones: HashMap<String, Arc<CompiledZone>>,
+}
+
+impl ZoneTree {
+    fn insert(&mut self, zone: CompiledZone) {
+        self.zones.insert(zone.key.clone(), Arc::new(zone));
+    }
+
+    /// The most specific zone that holds `name`: `host.mdns.g9.lo` belongs to
+    /// `mdns.g9.lo` when both it and `g9.lo` exist.
+    pub fn find_zone(&self, name: &LowerName) -> Option<&Arc<CompiledZone>> {
+        self.find_zone_for_fqdn(&name.to_string())
+    }
+
+    /// [`Self::find_zone`] for a name in text form.
+    pub fn find_zone_for_fqdn(&self, fqdn: &str) -> Option<&Arc<CompiledZone>> {
+        let fqdn = key(fqdn);
+        let mut suffix = fqdn.as_ref();
+        loop {
+            if let Some(zone) = self.zones.get(suffix) {
+                return Some(zone);
+            }
+            suffix = suffix.split_once('.')?.1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+}
+
+/// One zone's records, by lowercase owner name.
+pub struct CompiledZone {
+    zone: Zone,
+    key: String,
+    soa: Option<DnsRecord>,
+    nodes: HashMap<String, Node>,
+}
+
+/// The RRsets at one owner name. A node whose records are all disabled is
+/// still there: the name exists, it just has nothing to answer with.
+#[derive(Default)]
+struct Node {
+    rrsets: Vec<Rrset>,
+}
+
+impl Node {
+    fn rrset(&self, rtype: RecordType) -> Option<&Rrset> {
+        self.rrsets.iter().find(|rrset| rrset.rtype == rtype)
+    }
+}
+
+/// The enabled records of one type at one name, and how to answer from them
+/// when they are a load-balanced group.
+struct Rrset {
+    rtype: RecordType,
+    records: Vec<DnsRecord>,
+    /// Each record's weight, while compiling.
+    weights: Vec<u32>,
+    /// While compiling, the policy of the earliest-created member with a
+    /// health check, and when and as what it was created.
+    policy: Option<((DateTime<Utc>, Uuid), HealthCheck)>,
+    selector: Option<Selector>,
+}
+
+impl Rrset {
+    /// The records for one response. A group answers from its members at
+    /// addresses `prefer` accepts when there are any, and from all of them
+    /// when there are none.
+    fn answer(&self, prefer: &dyn Fn(IpAddr) -> bool) -> Vec<DnsRecord> {
+        let Some(selector) = &self.selector else {
+            return self.records.clone();
+        };
+        let preferred: Vec<usize> = (0..self.records.len())
+            .filter(|&i| address(&self.records[i]).is_some_and(prefer))
+            .collect();
+        let eligible = if preferred.is_empty() {
+            (0..self.records.len()).collect()
+        } else {
+            preferred
+        };
+        selector
+            .pick(&eligible)
+            .into_iter()
+            .map(|i| self.records[i].clone())
+            .collect()
+    }
+}
+
+fn address(record: &DnsRecord) -> Option<IpAddr> {
+    match record.data()? {
+        RData::A(a) => Some(IpAddr::V4(a.0)),
+        RData::AAAA(a) => Some(IpAddr::V6(a.0)),
+        _ => None,
+    }
+}
+
+impl CompiledZone {
+    fn compile(zone: Zone, records: &[Record]) -> Self {
+        let zone_key = key(&zone.name).into_owned();
+        let origin = Name::from_str(&ensure_fqdn(&zone.name)).ok();
+        let soa = origin.clone().and_then(|origin| {
+            let rdata = to_rdata(&RecordData::SOA(zone.soa.clone()))?;
+            Some(DnsRecord::from_rdata(origin, zone.default_ttl, rdata))
+        });
+
+        let mut nodes: HashMap<String, Node> = HashMap::new();
+        for record in records {
+            let owner = if record.name == "@" {
+                zone.name.trim_end_matches('.').to_string()
+            } else {
+                format!("{}.{}", record.name, zone.name.trim_end_matches('.'))
+            };
+            let node = nodes.entry(key(&owner).into_owned()).or_default();
+            if !record.enabled {
+                continue;
+            }
+            let (Ok(name), Some(rdata)) =
+                (Name::from_str(&format!("{owner}.")), to_rdata(&record.data))
+            else {
+                continue;
+            };
+            let rtype = rdata.record_type();
+            let dns_record = DnsRecord::from_rdata(name, record.ttl, rdata);
+            let rrset = match node.rrsets.iter().position(|r| r.rtype == rtype) {
+                Some(at) => &mut node.rrsets[at],
+                None => {
+                    node.rrsets.push(Rrset {
+                        rtype,
+                        records: Vec::new(),
+                        weights: Vec::new(),
+                        policy: None,
+                        selector: None,
+                    });
+                    node.rrsets.last_mut().unwrap()
+                }
+            };
+            rrset.records.push(dns_record);
+            rrset
+                .weights
+                .push(record.health_check.as_ref().map_or(1, HealthCheck::weight));
+            // Members that disagree on the group's policy cannot all be
+            // followed: the earliest-created one's applies, whatever order
+            // the records were stored or replicated in.
+            if let Some(check) = &record.health_check {
+                let created = (record.created_at, record.id);
+                match &rrset.policy {
+                    Some((first, policy)) => {
+                        if !policy.same_group_policy(check) {
+                            tracing::warn!(
+                                "{owner} {rtype}: members disagree on how the group is \
+                                 answered; the earliest-created member's policy applies"
+                            );
+                        }
+                        if created < *first {
+                            rrset.policy = Some((created, check.clone()));
+                        }
+                    }
+                    None => rrset.policy = Some((created, check.clone())),
+                }
+            }
+        }
+        for rrset in nodes.values_mut().flat_map(|node| &mut node.rrsets) {
+            if let Some((_, policy)) = rrset.policy.take() {
+                let weights = std::mem::take(&mut rrset.weights);
+                rrset.selector = Some(Selector::new(&policy, weights));
+            }
+        }
+
+        Self {
+            zone,
+            key: zone_key,
+            soa,
+            nodes,
+        }
+    }
+
+    /// The zone as stored.
+    pub fn zone(&self) -> &Zone {
+        &self.zone
+    }
+
+    /// The zone's SOA record, for answers and the authority section.
+    pub fn soa(&self) -> Option<&DnsRecord> {
+        self.soa.as_ref()
+    }
+
+    /// Whether anything at all is recorded at `name` — the line between
+    /// NXDOMAIN and an empty NOERROR.
+    pub fn name_exists(&self, name: &LowerName) -> bool {
+        self.nodes.contains_key(key(&name.to_string()).as_ref())
+    }
+
+    /// The records answering `name`/`rtype`:
+    ///
+    /// - SOA gives the zone's SOA, whatever the name;
+    /// - a name with `*` labels is a CoreDNS-style pattern, answered with every
+    ///   record it matches under the record's own name;
+    /// - otherwise the exact RRset, or failing that the closest wildcard's
+    ///   (RFC 4592), under the name asked for.
+    ///
+    /// A load-balanced group answers with the members its
+    /// [`Selection`](crate::types::Selection) picks for this response, in its
+    /// [`AnswerOrder`](crate::types::AnswerOrder).
+    pub fn lookup(&self, name: &LowerName, rtype: RecordType) -> Vec<DnsRecord> {
+        self.lookup_preferring(name, rtype, &|_| false)
+    }
+
+    /// [`Self::lookup`] for a client steered to some members: a group answers
+    /// from its healthy members at addresses `prefer` accepts, or from all of
+    /// its healthy members when none of those are left.
+    pub fn lookup_preferring(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        prefer: &dyn Fn(IpAddr) -> bool,
+    ) -> Vec<DnsRecord> {
+        if rtype == RecordType::SOA {
+            return self.soa.iter().cloned().collect();
+        }
+        let fqdn = name.to_string();
+        let owner = key(&fqdn);
+        if owner.split('.').any(|label| label == "*") {
+            return self.lookup_pattern(&owner, rtype, prefer);
+        }
+        if let Some(rrset) = self.nodes.get(owner.as_ref()).and_then(|n| n.rrset(rtype)) {
+            return rrset.answer(prefer);
+        }
+
+        // `a.b.zone` falls back to `*.b.zone`, then `*.zone`.
+        let Some(relative) = owner
+            .strip_suffix(self.key.as_str())
+            .and_then(|p| p.strip_suffix('.'))
+        else {
+            return Vec::new();
+        };
+        let mut remaining = relative;
+        loop {
+            let parent = remaining.split_once('.').map(|(_, parent)| parent);
+            let wildcard = match parent {
+                Some(parent) => format!("*.{parent}.{}", self.key),
+                None => format!("*.{}", self.key),
+            };
+            if let Some(rrset) = self.nodes.get(&wildcard).and_then(|n| n.rrset(rtype)) {
+                let name = Name::from(name.clone());
+                return rrset
+                    .answer(prefer)
+                    .into_iter()
+                    .map(|mut record| {
+                        record.set_name(name.clone());
+                        record
+                    })
+                    .collect();
+            }
+            match parent {
+                Some(parent) => remaining = parent,
+                None => break,
+            }
+        }
+        Vec::new()
+    }
+
+    /// Every record whose owner matches `pattern` label for label, `*`
+    /// standing for any one label.
+    fn lookup_pattern(
+        &self,
+        pattern: &str,
+        rtype: RecordType,
+        prefer: &dyn Fn(IpAddr) -> bool,
+    ) -> Vec<DnsRecord> {
+        let pattern: Vec<&str> = pattern.split('.').collect();
+        let mut out = Vec::new();
+        for (owner, node) in &self.nodes {
+            let labels: Vec<&str> = owner.split('.').collect();
+            let matches = labels.len() == pattern.len()
+                && pattern.iter().zip(&labels).all(|(p, l)| *p == "*" || p == l);
+            if matches {
+                if let Some(rrset) = node.rrset(rtype) {
+                    out.extend(rrset.answer(prefer));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// Names as the index keys them: lowercase, without the trailing dot.
+fn key(name: &str) -> Cow<'_, str> {
+    let name = name.trim_end_matches('.');
+    if name.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(name.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
+fn ensure_fqdn(name: &str) -> String {
+    if name.ends_with('.') {
+        name.to_string()
+    } else {
+        format!("{name}.")
+    }
+}
+
+/// Convert record data to hickory RData. `None` when a name in it does not
+/// parse.
+pub fn to_rdata(data: &RecordData) -> Option<RData> {
+    match data {
+        RecordData::A(addr) => Some(RData::A((*addr).into())),
+        RecordData::AAAA(addr) => Some(RData::AAAA((*addr).into())),
+        RecordData::CNAME(name) => Name::from_str(&ensure_fqdn(name))
+            .ok()
+            .map(|n| RData::CNAME(CNAME(n))),
+        RecordData::MX {
+            preference,
+            exchange,
+        } => Name::from_str(&ensure_fqdn(exchange))
+            .ok()
+            .map(|name| RData::MX(MX::new(*preference, name))),
+        RecordData::NS(name) => Name::from_str(&ensure_fqdn(name))
+            .ok()
+            .map(|n| RData::NS(NS(n))),
+        RecordData::PTR(name) => Name::from_str(&ensure_fqdn(name))
+            .ok()
+            .map(|n| RData::PTR(PTR(n))),
+        RecordData::SOA(soa) => {
+            let mname = Name::from_str(&ensure_fqdn(&soa.mname)).ok()?;
+            let rname = Name::from_str(&ensure_fqdn(&soa.rname)).ok()?;
+            Some(RData::SOA(SOA::new(
+                mname,
+                rname,
+                soa.serial,
+                soa.refresh as i32,
+                soa.retry as i32,
+                soa.expire as i32,
+                soa.minimum,
+            )))
+        }
+        RecordData::SRV(srv) => {
+            let target = Name::from_str(&ensure_fqdn(&srv.target)).ok()?;
+            Some(RData::SRV(SRV::new(
+                srv.priority,
+                srv.weight,
+                srv.port,
+                target,
+            )))
+        }
+        RecordData::TXT(text) => Some(RData::TXT(TXT::new(vec![text.clone()]))),
+        RecordData::CAA(caa) => Some(RData::CAA(CAA::new_issue(
+            caa.flags & 0x80 != 0,
+            Name::from_str(&caa.value).ok(),
+            vec![],
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{RecordSource, SoaData};
+    use chrono::Utc;
+    use tempfile::TempDir;
+
+    fn zone(name: &str) -> Zone {
+        Zone {
+            id: Uuid::new_v4(),
+            name: name.into(),
+            soa: SoaData {
+                mname: format!("ns.{name}"),
+                rname: format!("admin.{name}"),
+                serial: 1,
+                refresh: 3600,
+                retry: 900,
+                expire: 604800,
+                minimum: 30,
+            },
+            default_ttl: 30,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn add_a(db: &Db, zone: &Uuid, name: &str, ip: &str) -> Record {
+        let record = Record {
+            id: Uuid::new_v4(),
+            zone_id: *zone,
+            name: name.into(),
+            ttl: 30,
+            data: RecordData::A(ip.parse().unwrap()),
+            enabled: true,
+            health_check: None,
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        db.create_record(&record).unwrap();
+        record
+    }
+
+    fn name(s: &str) -> LowerName {
+        LowerName::from(Name::from_str(s).unwrap())
+    }
+
+    fn a(db: &Db, qname: &str) -> Vec<String> {
+        let tree = db.zone_tree();
+        let Some(zone) = tree.find_zone(&name(qname)) else {
+            return Vec::new();
+        };
+        zone.lookup(&name(qname), RecordType::A)
+            .iter()
+            .map(|r| format!("{} {}", r.name(), r.data().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn wildcard_query_returns_all_matching_and_leaves_normal_queries_intact() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let cluster = zone("cluster.local");
+        db.create_zone("cluster.local", &cluster).unwrap();
+        add_a(&db, &cluster.id, "a.default.svc", "10.0.0.1");
+        add_a(&db, &cluster.id, "b.default.svc", "10.0.0.2");
+        add_a(&db, &cluster.id, "c.other.svc", "10.0.0.3");
+
+        // CoreDNS-style wildcard: `*.default.svc` → a + b (not c in `other`).
+        assert_eq!(a(&db, "*.default.svc.cluster.local.").len(), 2);
+        // A normal (non-`*`) query still resolves exactly one record.
+        assert_eq!(a(&db, "a.default.svc.cluster.local.").len(), 1);
+        // A non-existent normal name still returns nothing (NXDOMAIN path).
+        assert!(a(&db, "nope.default.svc.cluster.local.").is_empty());
+    }
+
+    #[test]
+    fn the_most_specific_zone_answers_and_wildcards_take_the_query_name() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let parent = zone("g9.lo");
+        let child = zone("mdns.g9.lo");
+        db.create_zone("g9.lo", &parent).unwrap();
+        db.create_zone("mdns.g9.lo", &child).unwrap();
+        add_a(&db, &parent.id, "*", "10.0.0.1");
+        add_a(&db, &child.id, "tracker", "10.0.1.1");
+        let disabled = add_a(&db, &child.id, "gone", "10.0.1.2");
+        db.update_record(&Record {
+            enabled: false,
+            ..disabled
+        })
+        .unwrap();
+
+        let tree = db.zone_tree();
+        let owner = tree.find_zone(&name("Tracker.MDNS.g9.lo.")).unwrap();
+        assert_eq!(owner.zone().name, "mdns.g9.lo");
+        assert_eq!(a(&db, "tracker.mdns.g9.lo."), ["tracker.mdns.g9.lo. 10.0.1.1"]);
+        assert_eq!(a(&db, "printer.g9.lo."), ["printer.g9.lo. 10.0.0.1"]);
+        assert!(tree.find_zone(&name("example.com.")).is_none());
+
+        // A name whose only record is disabled exists, with nothing in it.
+        assert!(a(&db, "gone.mdns.g9.lo.").is_empty());
+        assert!(owner.name_exists(&name("gone.mdns.g9.lo.")));
+        assert!(!owner.name_exists(&name("never.mdns.g9.lo.")));
+        assert_eq!(
+            owner.lookup(&name("never.mdns.g9.lo."), RecordType::SOA).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn a_ratio_group_answers_one_member_per_query_by_weight() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let web = zone("web.lo");
+        db.create_zone("web.lo", &web).unwrap();
+        for (ip, weight) in [("10.0.0.1", 9), ("10.0.0.2", 1)] {
+            let record = add_a(&db, &web.id, "app", ip);
+            db.update_record(&Record {
+                health_check: Some(HealthCheck {
+                    weight: Some(weight),
+                    selection: crate::types::Selection::Ratio,
+                    ..Default::default()
+                }),
+                ..record
+            })
+            .unwrap();
+        }
+        add_a(&db, &web.id, "plain", "10.0.1.1");
+        add_a(&db, &web.id, "plain", "10.0.1.2");
+
+        let mut canary = 0;
+        for _ in 0..100 {
+            let answer = a(&db, "app.web.lo.");
+            assert_eq!(answer.len(), 1);
+            if answer[0].ends_with("10.0.0.2") {
+                canary += 1;
+            }
+        }
+        assert_eq!(canary, 10);
+        // Records without a health check are answered whole, as before.
+        assert_eq!(a(&db, "plain.web.lo.").len(), 2);
+    }
+
+    #[test]
+    fn the_earliest_created_member_decides_a_disputed_group_policy() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let web = zone("web.lo");
+        db.create_zone("web.lo", &web).unwrap();
+        // Stored first, but created after the member it disagrees with.
+        let all = add_a(&db, &web.id, "app", "10.0.0.1");
+        db.update_record(&Record {
+            health_check: Some(HealthCheck::default()),
+            ..all
+        })
+        .unwrap();
+        let capped = add_a(&db, &web.id, "app", "10.0.0.2");
+        db.update_record(&Record {
+            health_check: Some(HealthCheck {
+                max_answers: Some(1),
+                ..Default::default()
+            }),
+            created_at: Utc::now() - chrono::Duration::hours(1),
+            ..capped
+        })
+        .unwrap();
+        add_a(&db, &web.id, "app", "10.0.0.3");
+
+        assert_eq!(a(&db, "app.web.lo."), ["app.web.lo. 10.0.0.1"]);
+    }
+
+    #[test]
+    fn writes_are_seen_by_the_next_lookup() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let gw = zone("gw.lo");
+        db.create_zone("gw.lo", &gw).unwrap();
+        let before = db.zone_tree();
+
+        let record = add_a(&db, &gw.id, "boot", "10.0.0.5");
+        assert_eq!(a(&db, "boot.gw.lo."), ["boot.gw.lo. 10.0.0.5"]);
+        // A tree already handed out does not change under its reader.
+        assert!(before
+            .find_zone(&name("gw.lo."))
+            .unwrap()
+            .lookup(&name("boot.gw.lo."), RecordType::A)
+            .is_empty());
+
+        db.delete_record(&record.id).unwrap();
+        assert!(a(&db, "boot.gw.lo.").is_empty());
+
+        // Replication writes zones and records without bumping a serial.
+        let renamed = Zone {
+            name: "gw2.lo".into(),
+            ..gw.clone()
+        };
+        db.upsert_zone(&renamed).unwrap();
+        let tree = db.zone_tree();
+        assert!(tree.find_zone(&name("gw.lo.")).is_none());
+        assert!(tree.find_zone(&name("gw2.lo.")).is_some());
+
+        db.delete_zone(&gw.id).unwrap();
+        assert!(db.zone_tree().is_empty());
+    }
+
+    #[test]
+    fn queries_do_not_wait_for_a_reload() {
+        let dir = TempDir::new().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let gw = zone("gw.lo");
+        db.create_zone("gw.lo", &gw).unwrap();
+        add_a(&db, &gw.id, "boot", "10.0.0.5");
+
+        let index = ZoneIndex::new();
+        index.refresh(&db);
+        let before = index.current(&db);
+        assert!(before.find_zone(&name("gw.lo.")).is_some());
+
+        // A writer is mid-reload: readers get the tree it will replace.
+        let held = index.reload.lock().unwrap();
+        index.pending.lock().unwrap().zones.insert(gw.id);
+        index.stale.store(true, Ordering::Release);
+        let other = db.get_zone(&gw.id).unwrap().map(|z| Zone {
+            name: "gw2.lo".into(),
+            ..z
+        });
+        db.upsert_zone(&other.unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&index.current(&db), &before));
+
+        // With the reload lock free again, a reader picks up what is left.
+        drop(held);
+        let after = index.current(&db);
+        assert!(after.find_zone(&name("gw.lo.")).is_none());
+        assert!(after.find_zone(&name("gw2.lo.")).is_some());
+    }
+}
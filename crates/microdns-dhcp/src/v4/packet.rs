@@ -28,6 +28,20 @@ impl DhcpMessageType {
             _ => None,
         }
     }
+
+    /// Lowercase name, as used in metric labels.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Discover => "discover",
+            Self::Offer => "offer",
+            Self::Request => "request",
+            Self::Decline => "decline",
+            Self::Ack => "ack",
+            Self::Nak => "nak",
+            Self::Release => "release",
+            Self::Inform => "inform",
+        }
+    }
 }
 
 /// DHCP option codes
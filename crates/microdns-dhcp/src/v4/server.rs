@@ -369,6 +369,7 @@ impl Dhcpv4Server {
         Ok(())
     }
 
+    #[tracing::instrument(name = "dhcp.packet", skip_all, fields(xid = request.xid))]
     async fn handle_packet(
         &self,
         request: &DhcpPacket,
@@ -380,8 +381,9 @@ impl Dhcpv4Server {
 
         let mac = request.mac_address();
         info!("DHCP {msg_type:?} from {mac} (xid: {:08x})", request.xid);
+        metrics::counter!("microdns_dhcp_received_total", "type" => msg_type.name()).increment(1);
 
-        match msg_type {
+        let reply = match msg_type {
             DhcpMessageType::Discover => self.handle_discover(request).await,
             DhcpMessageType::Request => self.handle_request(request).await,
             DhcpMessageType::Release => {
@@ -389,7 +391,15 @@ impl Dhcpv4Server {
                 Ok(None) // No response for Release
             }
             _ => Ok(None),
+        };
+
+        if let Ok(Some(ref packet)) = reply {
+            if let Some(sent) = packet.message_type() {
+                metrics::counter!("microdns_dhcp_sent_total", "type" => sent.name()).increment(1);
+            }
         }
+        record_pool_metrics(&self.pools.lock().await);
+        reply
     }
 
     /// Handle DHCP DISCOVER: allocate an IP and send OFFER.
@@ -1062,6 +1072,7 @@ impl Dhcpv4Server {
         }
 
         let avail: u32 = new_pools.iter().map(|p| p.available_count()).sum();
+        record_pool_metrics(&new_pools);
 
         // Swap in rebuilt pools and pxe_configs
         {
@@ -1089,6 +1100,19 @@ impl Dhcpv4Server {
 }
 
 /// Create a fresh recv socket bound to the given port with SO_REUSEADDR.
+/// Size and use of each pool, labelled by its range.
+fn record_pool_metrics(pools: &[Ipv4Pool]) {
+    for pool in pools {
+        let range = format!("{}-{}", pool.range_start, pool.range_end);
+        let total = pool.total_count();
+        let allocated = total - pool.available_count();
+        metrics::gauge!("microdns_dhcp_pool_size", "pool" => range.clone()).set(total);
+        metrics::gauge!("microdns_dhcp_pool_allocated", "pool" => range.clone()).set(allocated);
+        metrics::gauge!("microdns_dhcp_pool_utilization", "pool" => range)
+            .set(f64::from(allocated) / f64::from(total.max(1)));
+    }
+}
+
 fn bind_recv_socket(port: u16) -> anyhow::Result<UdpSocket> {
     let sock = socket2::Socket::new(
         socket2::Domain::IPV4,
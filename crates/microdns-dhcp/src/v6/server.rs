@@ -86,6 +86,7 @@ impl Dhcpv6Server {
         Ok(())
     }
 
+    #[tracing::instrument(name = "dhcpv6.packet", skip_all, fields(%src))]
     async fn handle_packet(
         &self,
         request: &Dhcpv6Packet,
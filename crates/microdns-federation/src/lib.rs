@@ -3,6 +3,7 @@ pub mod heartbeat;
 pub mod leaf;
 pub mod replication;
 pub mod sync;
+pub mod trace_context;
 
 pub(crate) mod proto {
     tonic::include_proto!("microdns");
@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use crate::proto;
+use crate::trace_context;
 use microdns_core::config::{PeerConfig, ReplicationConfig};
 use microdns_core::db::Db;
 use microdns_core::types::{Record, RecordData, RecordSource, ReplicationMeta, SoaData, Zone};
@@ -90,6 +91,7 @@ impl ReplicationAgent {
         }
     }
 
+    #[tracing::instrument(name = "replication.sync_peer", skip_all, fields(peer = %peer.id))]
     async fn sync_peer(&self, peer: &PeerConfig) -> anyhow::Result<()> {
         let endpoint = format!("http://{}:{}", peer.addr, peer.grpc_port);
         let channel = Channel::from_shared(endpoint.clone())?
@@ -104,7 +106,7 @@ impl ReplicationAgent {
         let mut zone_client =
             proto::zone_service_client::ZoneServiceClient::new(channel.clone());
         let zones_resp = zone_client
-            .list_zones(proto::ListZonesRequest {})
+            .list_zones(trace_context::request(proto::ListZonesRequest {}))
             .await?
             .into_inner();
 
@@ -160,9 +162,9 @@ impl ReplicationAgent {
             let mut record_client =
                 proto::record_service_client::RecordServiceClient::new(channel.clone());
             let records_resp = record_client
-                .list_records(proto::ListRecordsRequest {
+                .list_records(trace_context::request(proto::ListRecordsRequest {
                     zone_id: proto_zone.id.clone(),
-                })
+                }))
                 .await?
                 .into_inner();
 
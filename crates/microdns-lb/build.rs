@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tonic_build::configure()
+        .build_server(false)
+        .build_client(true)
+        .compile_protos(&["../../proto/grpc/health/v1/health.proto"], &["../../proto/"])?;
+    Ok(())
+}
@@ -0,0 +1,220 @@
+//! Operator control over load-balanced records: take a member out of
+//! rotation without deleting it or waiting for its probes to fail.
+//!
+//! A `draining` member stops being handed out but is still probed, so it
+//! can be watched while connections wind down; `maintenance` also stops its
+//! probes and keeps it out of failsafe. The state lives on the member's
+//! [`RecordHealth`](crate::RecordHealth), is persisted with it, and every
+//! change is announced on the monitor's `StateChange` broadcast.
+
+use crate::monitor::StateChange;
+use crate::state::HealthState;
+use chrono::Utc;
+use microdns_core::db::Db;
+use microdns_core::types::{AdminState, RecordData};
+use tokio::sync::{broadcast, Mutex};
+use tracing::info;
+use uuid::Uuid;
+
+/// Set `record_id`'s administrative state, persist it and announce the
+/// change. A member leaving rotation is disabled in DNS at once; one coming
+/// back is re-enabled by the next probe cycle once it is healthy.
+///
+/// A member the monitor has not picked up yet — one just created, say — is
+/// registered here, so it can be staged before its first probe.
+///
+/// Returns `Ok(None)` if the record does not exist or has no health check.
+pub async fn set_admin_state(
+    db: &Db,
+    state: &Mutex<HealthState>,
+    events: &broadcast::Sender<StateChange>,
+    record_id: Uuid,
+    admin: AdminState,
+) -> anyhow::Result<Option<StateChange>> {
+    let Some(mut record) = db.get_record(&record_id)? else {
+        return Ok(None);
+    };
+    let Some(hc) = record.health_check.clone() else {
+        return Ok(None);
+    };
+    let (previous, health) = {
+        let mut state = state.lock().await;
+        state.register(
+            record_id,
+            hc.healthy_threshold.max(1),
+            hc.unhealthy_threshold.max(1),
+            record.zone_id,
+            record.name.clone(),
+            record.data.record_type().to_string(),
+        );
+        let Some(previous) = state.set_admin_state(&record_id, admin) else {
+            return Ok(None);
+        };
+        let Some(health) = state.get(&record_id).cloned() else {
+            return Ok(None);
+        };
+        (previous, health)
+    };
+    if let Some(row) = health.to_persisted(record_id) {
+        db.upsert_lb_health_batch(&[row])?;
+    }
+
+    if admin != AdminState::Active && record.enabled {
+        record.enabled = false;
+        db.update_record(&record)?;
+    }
+    let ip = match &record.data {
+        RecordData::A(a) => a.to_string(),
+        RecordData::AAAA(a) => a.to_string(),
+        _ => String::new(),
+    };
+    let zone_name = db
+        .get_zone(&health.zone_id)?
+        .map(|z| z.name)
+        .unwrap_or_default();
+
+    let change = StateChange {
+        record_id,
+        zone_id: health.zone_id,
+        zone_name,
+        name: health.record_name.clone(),
+        ip,
+        record_type: health.record_type.clone(),
+        status: health.status,
+        // Not a status transition.
+        previous_status: None,
+        failsafe: false,
+        probe_type: health.last_probe_type,
+        detail: format!("admin state {previous} → {admin}"),
+        cert_days_left: health.cert_days_left,
+        admin_state: Some(admin),
+        at: Utc::now(),
+    };
+    if previous != admin {
+        info!(
+            "{}.{} {} admin state {previous} → {admin}",
+            change.name, change.zone_name, change.ip
+        );
+        let _ = events.send(change.clone());
+    }
+    Ok(Some(change))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdns_core::types::{
+        HealthCheck, HealthStatus, ProbeType, Record, RecordSource, SoaData, Zone,
+    };
+
+    fn db_with_zone() -> (Db, Uuid, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let zone = Zone {
+            id: Uuid::new_v4(),
+            name: "svc.lo".into(),
+            soa: SoaData {
+                mname: "ns.svc.lo".into(),
+                rname: "admin.svc.lo".into(),
+                serial: 1,
+                refresh: 3600,
+                retry: 900,
+                expire: 604800,
+                minimum: 30,
+            },
+            default_ttl: 30,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        db.create_zone("svc.lo", &zone).unwrap();
+        (db, zone.id, dir)
+    }
+
+    fn member(zone: Uuid, health_check: Option<HealthCheck>) -> Record {
+        Record {
+            id: Uuid::new_v4(),
+            zone_id: zone,
+            name: "api".into(),
+            ttl: 30,
+            data: RecordData::A("10.0.0.1".parse().unwrap()),
+            enabled: true,
+            health_check,
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_state_persists_and_is_announced() {
+        let (db, zone, _dir) = db_with_zone();
+        let record = member(zone, Some(HealthCheck::default()));
+        db.create_record(&record).unwrap();
+        let id = record.id;
+        let state = Mutex::new(HealthState::new());
+        let (events, mut rx) = broadcast::channel(8);
+        {
+            let mut state = state.lock().await;
+            state.register(id, 1, 1, zone, "api".into(), "A".into());
+            state.record_probe_result(&id, true, Utc::now(), ProbeType::Tcp, "ok".into());
+        }
+
+        let change = set_admin_state(&db, &state, &events, id, AdminState::Draining)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.admin_state, Some(AdminState::Draining));
+        assert_eq!(change.status, HealthStatus::Healthy);
+        assert_eq!(change.previous_status, None);
+        assert_eq!(
+            rx.try_recv().unwrap().detail,
+            "admin state active → draining"
+        );
+        let row = db.get_lb_health(&id).unwrap().unwrap();
+        assert_eq!(row.admin_state, AdminState::Draining);
+        assert!(!db.get_record(&id).unwrap().unwrap().enabled);
+
+        // Setting the same state again is quiet.
+        set_admin_state(&db, &state, &events, id, AdminState::Draining)
+            .await
+            .unwrap();
+        assert!(rx.try_recv().is_err());
+
+        let unknown = set_admin_state(&db, &state, &events, Uuid::new_v4(), AdminState::Active)
+            .await
+            .unwrap();
+        assert!(unknown.is_none());
+    }
+
+    #[tokio::test]
+    async fn new_members_can_be_staged_before_their_first_probe() {
+        let (db, zone, _dir) = db_with_zone();
+        let staged = member(zone, Some(HealthCheck::default()));
+        let plain = member(zone, None);
+        db.create_record(&staged).unwrap();
+        db.create_record(&plain).unwrap();
+        let state = Mutex::new(HealthState::new());
+        let (events, _rx) = broadcast::channel(8);
+
+        let change = set_admin_state(&db, &state, &events, staged.id, AdminState::Maintenance)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(change.status, HealthStatus::Unknown);
+        let health = state.lock().await.get(&staged.id).cloned().unwrap();
+        assert_eq!(health.admin_state, AdminState::Maintenance);
+        assert_eq!(health.record_name, "api");
+        assert_eq!(
+            db.get_lb_health(&staged.id).unwrap().unwrap().admin_state,
+            AdminState::Maintenance
+        );
+
+        // Not load-balanced: nothing to stage.
+        let none = set_admin_state(&db, &state, &events, plain.id, AdminState::Maintenance)
+            .await
+            .unwrap();
+        assert!(none.is_none());
+        assert!(state.lock().await.get(&plain.id).is_none());
+    }
+}
@@ -0,0 +1,167 @@
+//! gRPC health probe: the standard `grpc.health.v1.Health/Check` call, over
+//! plaintext HTTP/2 or TLS. The member is healthy when it answers `SERVING`
+//! for the configured service.
+
+use crate::memo::{bundle_version, BundleVersion, Memo};
+use crate::probe::endpoint_port;
+use microdns_core::types::GrpcProbe;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+
+pub mod proto {
+    tonic::include_proto!("grpc.health.v1");
+}
+
+use proto::health_check_response::ServingStatus;
+use proto::health_client::HealthClient;
+use proto::HealthCheckRequest;
+
+/// What a probe's channel is built from. A connected channel is kept per
+/// member and setting, and dropped when a check on it fails.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct ChannelKey {
+    addr: SocketAddr,
+    tls: bool,
+    server_name: Option<String>,
+    ca_bundle: Option<BundleVersion>,
+    timeout: Duration,
+}
+
+static CHANNELS: Memo<ChannelKey, Channel> = Memo::new();
+
+/// Ask `target` whether `probe.service` is serving.
+pub async fn probe(
+    target: IpAddr,
+    timeout: Duration,
+    endpoint: Option<&str>,
+    probe: &GrpcProbe,
+) -> Result<String, String> {
+    let port = endpoint_port(endpoint, 50051)?;
+    let key = ChannelKey {
+        addr: SocketAddr::new(target, port),
+        tls: probe.tls,
+        server_name: probe.server_name.clone(),
+        ca_bundle: match &probe.ca_bundle {
+            Some(path) if probe.tls => Some(bundle_version(path)?),
+            _ => None,
+        },
+        timeout,
+    };
+
+    let check = async {
+        let channel = match CHANNELS.get(&key) {
+            Some(channel) => channel,
+            None => {
+                let channel = endpoint_for(&key)?
+                    .connect()
+                    .await
+                    .map_err(|e| error_chain(&e))?;
+                CHANNELS.insert(key.clone(), channel.clone());
+                channel
+            }
+        };
+        let request = HealthCheckRequest {
+            service: probe.service.clone(),
+        };
+        HealthClient::new(channel)
+            .check(request)
+            .await
+            .map_err(|status| format!("{}: {}", status.code(), status.message()))
+    };
+    let response = match tokio::time::timeout(timeout, check).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(e)) => {
+            CHANNELS.remove(&key);
+            return Err(format!("grpc/{port}: {e}"));
+        }
+        Err(_) => {
+            CHANNELS.remove(&key);
+            return Err(format!("grpc/{port}: timeout"));
+        }
+    };
+    match response.into_inner().status() {
+        ServingStatus::Serving => Ok(format!("grpc/{port}: SERVING")),
+        other => Err(format!("grpc/{port}: {}", other.as_str_name())),
+    }
+}
+
+fn endpoint_for(key: &ChannelKey) -> Result<Endpoint, String> {
+    let scheme = if key.tls { "https" } else { "http" };
+    let endpoint = Endpoint::from_shared(format!("{scheme}://{}", key.addr))
+        .map_err(|e| e.to_string())?
+        .connect_timeout(key.timeout)
+        .timeout(key.timeout);
+    if !key.tls {
+        return Ok(endpoint);
+    }
+    let mut tls = ClientTlsConfig::new();
+    if let Some(name) = &key.server_name {
+        tls = tls.domain_name(name.clone());
+    }
+    tls = match &key.ca_bundle {
+        Some((path, _)) => {
+            let pem = std::fs::read(path).map_err(|e| format!("ca_bundle {path}: {e}"))?;
+            tls.ca_certificate(Certificate::from_pem(pem))
+        }
+        None => tls.with_webpki_roots(),
+    };
+    endpoint.tls_config(tls).map_err(|e| e.to_string())
+}
+
+/// A transport error with its causes; tonic's own message is only
+/// "transport error".
+fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut out = e.to_string();
+    let mut source = e.source();
+    while let Some(cause) = source {
+        out.push_str(": ");
+        out.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probe_reports_serving_status() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // The overall service ("") starts out serving.
+        let (mut reporter, health) = tonic_health::server::health_reporter();
+        reporter
+            .set_service_status("orders", tonic_health::ServingStatus::NotServing)
+            .await;
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(health)
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let endpoint = format!(":{port}");
+        let timeout = Duration::from_secs(2);
+        let check = |service: &str| GrpcProbe {
+            service: service.to_string(),
+            ..GrpcProbe::default()
+        };
+
+        let result = probe(local, timeout, Some(&endpoint), &check("")).await;
+        assert!(result.is_ok(), "{result:?}");
+        let result = probe(local, timeout, Some(&endpoint), &check("orders")).await;
+        assert!(result.is_err_and(|e| e.ends_with("NOT_SERVING")));
+        let result = probe(local, timeout, Some(&endpoint), &check("billing")).await;
+        assert!(result.is_err_and(|e| e.contains("not registered")));
+
+        // Nothing listening: a transport error, not a hang.
+        let closed = {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap().port()
+        };
+        let result = probe(local, timeout, Some(&format!(":{closed}")), &check("")).await;
+        assert!(result.is_err());
+    }
+}
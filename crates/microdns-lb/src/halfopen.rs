@@ -387,6 +387,8 @@ async fn publish_status(
             failsafe: false,
             probe_type: ProbeType::TcpHalfOpen,
             detail,
+            cert_days_left: None,
+            admin_state: None,
             at: now,
         };
         info!(
@@ -1,8 +1,12 @@
+pub mod admin;
+pub mod grpc;
 pub mod halfopen;
 pub mod icmp;
+mod memo;
 pub mod monitor;
 pub mod probe;
 pub mod state;
+pub mod tls;
 
 pub use monitor::{HealthMonitor, MonitorConfig, StateChange};
 pub use state::{HealthAggregate, HealthState, RecordHealth};
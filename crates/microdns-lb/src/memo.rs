@@ -0,0 +1,82 @@
+//! Process-wide tables for what probes would otherwise rebuild on every
+//! cycle: compiled body patterns, HTTP clients, TLS verifiers and gRPC
+//! channels.
+//!
+//! Entries are keyed by the settings they were built from, so editing a
+//! health check simply builds a new one. A CA bundle is keyed by its path
+//! and modification time ([`bundle_version`]) and is re-read only when the
+//! file changes.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Entries kept before a table starts over. Keys only go stale when health
+/// checks are edited or members removed.
+const MAX_ENTRIES: usize = 4096;
+
+pub(crate) struct Memo<K, V> {
+    entries: LazyLock<Mutex<HashMap<K, V>>>,
+}
+
+impl<K: Eq + Hash, V: Clone> Memo<K, V> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            entries: LazyLock::new(Default::default),
+        }
+    }
+
+    pub(crate) fn get<Q>(&self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    pub(crate) fn insert(&self, key: K, value: V) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_ENTRIES {
+            entries.clear();
+        }
+        entries.insert(key, value);
+    }
+
+    pub(crate) fn remove<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    /// The entry for `key`, built with `make` the first time.
+    pub(crate) fn get_or_try_insert<Q>(
+        &self,
+        key: &Q,
+        make: impl FnOnce() -> Result<V, String>,
+    ) -> Result<V, String>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ToOwned<Owned = K> + ?Sized,
+    {
+        if let Some(value) = self.get(key) {
+            return Ok(value);
+        }
+        let value = make()?;
+        self.insert(key.to_owned(), value.clone());
+        Ok(value)
+    }
+}
+
+/// A CA bundle as a cache key: its path and when it was last written.
+pub(crate) type BundleVersion = (String, SystemTime);
+
+pub(crate) fn bundle_version(path: &str) -> Result<BundleVersion, String> {
+    let modified = std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(|e| format!("ca_bundle {path}: {e}"))?;
+    Ok((path.to_string(), modified))
+}
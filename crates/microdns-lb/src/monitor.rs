@@ -4,7 +4,7 @@ use crate::state::{HealthState, RecordHealth};
 use chrono::Utc;
 use futures::stream::{FuturesUnordered, StreamExt};
 use microdns_core::db::Db;
-use microdns_core::types::{HealthStatus, ProbeType, RecordData};
+use microdns_core::types::{AdminState, HealthCheck, HealthStatus, ProbeType, RecordData};
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
@@ -49,12 +49,20 @@ pub struct StateChange {
     /// New status the record transitioned *to*.
     pub status: HealthStatus,
     /// Status the record transitioned *from*. None on the very first
-    /// observation (Unknown → first probe result).
+    /// observation (Unknown → first probe result) and for admin state
+    /// changes, which leave the status as it was.
     #[serde(default)]
     pub previous_status: Option<HealthStatus>,
     pub failsafe: bool,
     pub probe_type: ProbeType,
     pub detail: String,
+    /// Days left on the record's certificate, for `tls` probes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cert_days_left: Option<i64>,
+    /// Set when the change is an operator setting the record's
+    /// administrative state rather than a probe result.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_state: Option<AdminState>,
     pub at: chrono::DateTime<Utc>,
 }
 
@@ -283,7 +291,7 @@ impl HealthMonitor {
     /// One end-to-end probe cycle:
     ///   1. Collect every record with a HealthCheck configured.
     ///   2. Probe everything in parallel (capped concurrency).
-    ///   3. Apply state transitions in one decision pass.
+    ///   3. Apply state transitions, then serve each group's active tier.
     ///   4. Apply last-alive failsafe.
     ///   5. Persist a snapshot in a single batched txn.
     async fn run_check_cycle(&self) -> anyhow::Result<usize> {
@@ -296,6 +304,11 @@ impl HealthMonitor {
         let mut targets: Vec<ProbeTarget> = Vec::new();
         let mut halfopen_ids: std::collections::HashSet<Uuid> =
             std::collections::HashSet::new();
+        // Half-open records and whether each is in DNS: their watchers keep
+        // their health, but which of them to serve is decided here.
+        let mut watched: Vec<(Uuid, bool)> = Vec::new();
+        // Records in maintenance: kept, not probed, taken out of DNS.
+        let mut parked: Vec<(Uuid, bool)> = Vec::new();
 
         for zone in &zones {
             zone_names.insert(zone.id, zone.name.clone());
@@ -311,7 +324,7 @@ impl HealthMonitor {
                 };
 
                 // Register in HealthState if not already present.
-                {
+                let admin = {
                     let mut state = self.state.lock().await;
                     state.register(
                         record.id,
@@ -321,6 +334,12 @@ impl HealthMonitor {
                         record.name.clone(),
                         record.data.record_type().to_string(),
                     );
+                    state.set_tier(&record.id, hc.priority(), hc.preempt_delay_secs);
+                    state.get(&record.id).map(|h| h.admin_state)
+                };
+                if admin == Some(AdminState::Maintenance) {
+                    parked.push((record.id, record.enabled));
+                    continue;
                 }
 
                 let probe_type = hc.probe_type;
@@ -329,13 +348,12 @@ impl HealthMonitor {
                 } else {
                     self.config.default_timeout
                 };
-                let endpoint = hc.endpoint.clone();
 
                 // Half-open records are handled by long-lived per-record
                 // tasks, not by the probe cycle. Make sure a watcher is
                 // running and skip adding it to `targets`.
                 if probe_type == ProbeType::TcpHalfOpen {
-                    let port = parse_port(endpoint.as_deref()).unwrap_or(80);
+                    let port = parse_port(hc.endpoint.as_deref()).unwrap_or(80);
                     let socket_addr = SocketAddr::new(target_ip, port);
                     self.halfopen
                         .ensure(WatcherSpec {
@@ -350,6 +368,7 @@ impl HealthMonitor {
                         })
                         .await;
                     halfopen_ids.insert(record.id);
+                    watched.push((record.id, record.enabled));
                     continue;
                 }
 
@@ -361,7 +380,7 @@ impl HealthMonitor {
                     target_ip,
                     probe_type,
                     timeout,
-                    endpoint,
+                    health_check: hc,
                     enabled_in_db: record.enabled,
                 });
             }
@@ -373,6 +392,9 @@ impl HealthMonitor {
         let mut live: std::collections::HashSet<Uuid> =
             targets.iter().map(|t| t.record_id).collect();
         live.extend(halfopen_ids.iter().copied());
+        // Tear down any half-open watchers whose record is gone or parked.
+        let _ = self.halfopen.retain_only(&live).await;
+        live.extend(parked.iter().map(|(id, _)| *id));
         let dropped = {
             let mut state = self.state.lock().await;
             state.retain_only(&live)
@@ -382,12 +404,10 @@ impl HealthMonitor {
                 warn!("cleanup: delete_lb_health({id}) failed: {e}");
             }
         }
-        // Tear down any half-open watchers whose record is gone.
-        let _ = self.halfopen.retain_only(&live).await;
 
-        if targets.is_empty() {
-            // No probe targets this cycle — but half-open watchers may have
-            // updated state. Persist and emit zero-probed status.
+        if targets.is_empty() && parked.is_empty() && watched.is_empty() {
+            // Nothing to probe or serve this cycle. Persist and emit
+            // zero-probed status.
             let snapshot = {
                 let state = self.state.lock().await;
                 state.snapshot_persisted()
@@ -408,10 +428,9 @@ impl HealthMonitor {
             futs.push(async move {
                 let _permit = sem.acquire_owned().await.expect("semaphore closed");
                 let result = probe::run_probe(
-                    target.probe_type,
+                    &target.health_check,
                     target.target_ip,
                     target.timeout,
-                    target.endpoint.as_deref(),
                     ping_count,
                 )
                 .await;
@@ -432,6 +451,17 @@ impl HealthMonitor {
         {
             let mut state = self.state.lock().await;
             for (target, result) in &results {
+                let probe = target.probe_type.to_string();
+                let outcome = if result.success { "success" } else { "failure" };
+                metrics::counter!(
+                    "microdns_lb_probes_total",
+                    "probe" => probe.clone(),
+                    "outcome" => outcome,
+                )
+                .increment(1);
+                metrics::histogram!("microdns_lb_probe_duration_seconds", "probe" => probe)
+                    .record(result.latency.as_secs_f64());
+
                 let result_with_prev = state.record_probe_result_with_prev(
                     &target.record_id,
                     result.success,
@@ -439,14 +469,7 @@ impl HealthMonitor {
                     target.probe_type,
                     result.detail.clone(),
                 );
-
-                let should_be_enabled = state
-                    .get(&target.record_id)
-                    .map(|h| h.should_be_enabled())
-                    .unwrap_or(true);
-                if should_be_enabled != target.enabled_in_db {
-                    to_update_in_db.push((target.record_id, should_be_enabled));
-                }
+                state.set_cert(&target.record_id, result.cert_days_left, result.cert_expiring);
 
                 if let Some((prev, Some(new_status))) = result_with_prev {
                     let zone_name = zone_names
@@ -465,10 +488,32 @@ impl HealthMonitor {
                         failsafe: false,
                         probe_type: target.probe_type,
                         detail: result.detail.clone(),
+                        cert_days_left: result.cert_days_left,
+                        admin_state: None,
                         at: now,
                     });
                 }
             }
+
+            // Serve only each group's active priority tier, so decide once
+            // every result of the cycle is in — for every member, half-open
+            // and draining ones included, not only those probed just now.
+            let tiers = state.active_tiers(now);
+            let members = results
+                .iter()
+                .map(|(target, _)| (target.record_id, target.enabled_in_db))
+                .chain(watched.iter().copied());
+            for (id, enabled_in_db) in members {
+                let should_serve = state.should_serve(&id, &tiers);
+                if should_serve != enabled_in_db {
+                    to_update_in_db.push((id, should_serve));
+                }
+            }
+            for (id, enabled_in_db) in &parked {
+                if *enabled_in_db {
+                    to_update_in_db.push((*id, false));
+                }
+            }
         }
 
         // ── 4. Failsafe ────────────────────────────────────────────────────
@@ -494,10 +539,12 @@ impl HealthMonitor {
                             h.record_type.clone(),
                             h.last_probe_type,
                             h.last_probe_detail.clone(),
+                            h.cert_days_left,
                         )
                     })
                 };
-                if let Some((zone_id, name, rtype, ptype, detail)) = info_for_event {
+                if let Some((zone_id, name, rtype, ptype, detail, cert_days_left)) = info_for_event
+                {
                     let zone_name = zone_names.get(&zone_id).cloned().unwrap_or_default();
                     // Find the matching record IP (we don't track it on
                     // RecordHealth; resolve via DB).
@@ -521,6 +568,8 @@ impl HealthMonitor {
                         failsafe: true,
                         probe_type: ptype,
                         detail,
+                        cert_days_left,
+                        admin_state: None,
                         at: now,
                     });
                     info!(
@@ -551,6 +600,12 @@ impl HealthMonitor {
         }
 
         for change in &transitions {
+            metrics::counter!(
+                "microdns_lb_state_changes_total",
+                "status" => change.status.to_string(),
+                "failsafe" => if change.failsafe { "true" } else { "false" },
+            )
+            .increment(1);
             // record state-change in the log even if no subscribers.
             if change.failsafe {
                 info!(
@@ -582,13 +637,7 @@ impl HealthMonitor {
 /// Parse `:port` or `:port/path` style endpoints into a port. Returns
 /// `None` if the endpoint is empty or unparseable.
 fn parse_port(endpoint: Option<&str>) -> Option<u16> {
-    let ep = endpoint?;
-    let rest = ep.strip_prefix(':').unwrap_or(ep);
-    let port_str = match rest.find('/') {
-        Some(i) => &rest[..i],
-        None => rest,
-    };
-    port_str.parse().ok()
+    crate::probe::split_endpoint(endpoint).ok()?.0
 }
 
 #[derive(Debug, Clone)]
@@ -600,6 +649,86 @@ struct ProbeTarget {
     target_ip: IpAddr,
     probe_type: ProbeType,
     timeout: Duration,
-    endpoint: Option<String>,
+    health_check: HealthCheck,
     enabled_in_db: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdns_core::types::{Record, RecordSource, SoaData, Zone};
+
+    fn member(zone: Uuid, ip: &str, port: u16, probe_type: ProbeType, priority: u32) -> Record {
+        Record {
+            id: Uuid::new_v4(),
+            zone_id: zone,
+            name: "app".into(),
+            ttl: 30,
+            data: RecordData::A(ip.parse().unwrap()),
+            enabled: true,
+            health_check: Some(HealthCheck {
+                probe_type,
+                endpoint: Some(port.to_string()),
+                healthy_threshold: 1,
+                priority: Some(priority),
+                ..Default::default()
+            }),
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn tiers_are_decided_over_half_open_and_draining_members_too() {
+        let listener = tokio::net::TcpListener::bind("0.0.0.0:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        let zone = Zone {
+            id: Uuid::new_v4(),
+            name: "svc.lo".into(),
+            soa: SoaData {
+                mname: "ns.svc.lo".into(),
+                rname: "admin.svc.lo".into(),
+                serial: 1,
+                refresh: 3600,
+                retry: 900,
+                expire: 604800,
+                minimum: 30,
+            },
+            default_ttl: 30,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        };
+        db.create_zone("svc.lo", &zone).unwrap();
+        let primary = member(zone.id, "127.0.0.1", port, ProbeType::Tcp, 0);
+        let backup = member(zone.id, "127.0.0.2", port, ProbeType::TcpHalfOpen, 1);
+        let draining = member(zone.id, "127.0.0.3", port, ProbeType::TcpHalfOpen, 0);
+        for record in [&primary, &backup, &draining] {
+            db.create_record(record).unwrap();
+        }
+
+        let monitor = HealthMonitor::new(db.clone(), Duration::from_secs(60), ProbeType::Tcp);
+        monitor.run_check_cycle().await.unwrap();
+        monitor
+            .state()
+            .lock()
+            .await
+            .set_admin_state(&draining.id, AdminState::Draining);
+        monitor.run_check_cycle().await.unwrap();
+
+        let enabled = |id| db.get_record(&id).unwrap().unwrap().enabled;
+        assert!(enabled(primary.id));
+        assert!(!enabled(backup.id), "a half-open backup stays out while tier 0 is up");
+        assert!(!enabled(draining.id), "a draining member is not served");
+    }
+}
@@ -1,37 +1,176 @@
-use microdns_core::types::ProbeType;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{Name, RData, RecordType};
+use microdns_core::net::frame_dns_tcp;
+use microdns_core::types::{
+    DnsProbe, DnsTransport, GrpcProbe, HealthCheck, HttpProbe, ProbeType, TlsProbe,
+};
+use crate::memo::{bundle_version, BundleVersion, Memo};
 use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
 use tracing::{debug, warn};
 
+/// How much of a response body `expect_body` and `expect_body_regex` look
+/// at; the rest is not read.
+const MAX_BODY: usize = 64 * 1024;
+
+/// Check that `hc` can be run as written: its endpoint, its HTTP method,
+/// headers and body pattern, and its DNS query and response code parse. Called when a health check is stored, so a probe never
+/// finds out.
+pub fn validate(hc: &HealthCheck) -> Result<(), String> {
+    split_endpoint(hc.endpoint.as_deref())?;
+    if let Some(http) = &hc.http {
+        http_method(http)?;
+        for (name, value) in &http.headers {
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("bad header name {name:?}"))?;
+            reqwest::header::HeaderValue::from_str(value)
+                .map_err(|_| format!("bad value for header {name:?}"))?;
+        }
+        if let Some(pattern) = &http.expect_body_regex {
+            body_regex(pattern)?;
+        }
+    }
+    if let Some(dns) = &hc.dns {
+        dns_query(dns)?;
+        if let Some(expected) = &dns.expect_rcode {
+            parse_rcode(expected).ok_or_else(|| format!("unknown expect_rcode {expected:?}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Split a health check's `endpoint` — `:port`, `:port/path`, `port` or
+/// `/path` — into the port, when it names one, and the path (`/` if not).
+pub(crate) fn split_endpoint(endpoint: Option<&str>) -> Result<(Option<u16>, &str), String> {
+    let Some(ep) = endpoint.filter(|ep| !ep.is_empty()) else {
+        return Ok((None, "/"));
+    };
+    let rest = match ep.strip_prefix(':') {
+        Some(rest) => rest,
+        None if ep.starts_with('/') => return Ok((None, ep)),
+        None => ep,
+    };
+    let (port, path) = match rest.find('/') {
+        Some(slash) => rest.split_at(slash),
+        None => (rest, "/"),
+    };
+    if port.is_empty() {
+        return Ok((None, path));
+    }
+    let port = port.parse().map_err(|_| format!("bad port {port:?}"))?;
+    Ok((Some(port), path))
+}
+
+/// The port `endpoint` names, or `default`.
+pub(crate) fn endpoint_port(endpoint: Option<&str>, default: u16) -> Result<u16, String> {
+    Ok(split_endpoint(endpoint)?.0.unwrap_or(default))
+}
+
+fn http_method(probe: &HttpProbe) -> Result<reqwest::Method, String> {
+    reqwest::Method::from_bytes(probe.method.to_uppercase().as_bytes())
+        .map_err(|_| format!("bad method {:?}", probe.method))
+}
+
+/// `pattern` compiled, once per pattern rather than once per probe.
+fn body_regex(pattern: &str) -> Result<Arc<regex::Regex>, String> {
+    static COMPILED: Memo<String, Arc<regex::Regex>> = Memo::new();
+    COMPILED.get_or_try_insert(pattern, || {
+        regex::Regex::new(pattern)
+            .map(Arc::new)
+            .map_err(|e| format!("bad expect_body_regex: {e}"))
+    })
+}
+
+/// What an HTTP probe's client is built from. Clients, and their connection
+/// pools, are kept per member and setting.
+#[derive(PartialEq, Eq, Hash)]
+struct HttpClientKey {
+    addr: SocketAddr,
+    host: Option<String>,
+    verify: bool,
+    ca_bundle: Option<BundleVersion>,
+}
+
+fn http_client(key: &HttpClientKey) -> Result<reqwest::Client, String> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(host) = &key.host {
+        builder = builder.resolve(host, key.addr);
+    }
+    if !key.verify {
+        builder = builder.danger_accept_invalid_certs(true);
+    } else if let Some((path, _)) = &key.ca_bundle {
+        let pem = std::fs::read(path).map_err(|e| format!("ca_bundle {path}: {e}"))?;
+        let certs = reqwest::Certificate::from_pem_bundle(&pem)
+            .map_err(|e| format!("ca_bundle {path}: {e}"))?;
+        builder = builder.tls_built_in_root_certs(false);
+        for cert in certs {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
 /// Result of a single probe execution.
 #[derive(Debug, Clone)]
 pub struct ProbeResult {
     pub success: bool,
     pub latency: Duration,
     pub detail: String,
+    /// Days until the member's certificate expires, for `tls` probes that
+    /// got one.
+    pub cert_days_left: Option<i64>,
+    /// The probe passed with a certificate inside `warn_days_left` of
+    /// expiry.
+    pub cert_expiring: bool,
 }
 
-/// Execute a health check probe against a target IP. `ping_count` is the
-/// number of ICMP echos to send for ping probes (ignored otherwise).
+/// Execute `hc`'s health check probe against a target IP. `ping_count` is
+/// the number of ICMP echos to send for ping probes (ignored otherwise).
 pub async fn run_probe(
-    probe_type: ProbeType,
+    hc: &HealthCheck,
     target: IpAddr,
     timeout: Duration,
-    endpoint: Option<&str>,
     ping_count: u8,
 ) -> ProbeResult {
     let start = std::time::Instant::now();
+    let probe_type = hc.probe_type;
+    let endpoint = hc.endpoint.as_deref();
+    let mut cert_days_left = None;
+    let mut cert_expiring = false;
 
     let result = match probe_type {
         ProbeType::Ping => ping_probe(target, timeout, ping_count).await,
-        ProbeType::Http => http_probe(target, false, timeout, endpoint).await,
-        ProbeType::Https => http_probe(target, true, timeout, endpoint).await,
+        ProbeType::Http | ProbeType::Https => {
+            let default = HttpProbe::default();
+            let http = hc.http.as_ref().unwrap_or(&default);
+            http_probe(target, probe_type == ProbeType::Https, timeout, endpoint, http).await
+        }
         ProbeType::Tcp => tcp_probe(target, timeout, endpoint).await,
         // TcpHalfOpen is handled out-of-band by the half-open manager,
         // not by the probe cycle. If we ever land here, treat it as a
         // best-effort connect to surface a probe error in the dashboard.
         ProbeType::TcpHalfOpen => tcp_probe(target, timeout, endpoint).await,
+        ProbeType::Dns => {
+            let default = DnsProbe::default();
+            dns_probe(target, timeout, endpoint, hc.dns.as_ref().unwrap_or(&default)).await
+        }
+        ProbeType::Tls => {
+            let default = TlsProbe::default();
+            let tls = hc.tls.as_ref().unwrap_or(&default);
+            let (days, result) = crate::tls::probe(target, timeout, endpoint, tls).await;
+            cert_days_left = days;
+            cert_expiring = result.is_ok() && crate::tls::expiring(tls, days);
+            result
+        }
+        ProbeType::Grpc => {
+            let default = GrpcProbe::default();
+            let grpc = hc.grpc.as_ref().unwrap_or(&default);
+            crate::grpc::probe(target, timeout, endpoint, grpc).await
+        }
     };
 
     let latency = start.elapsed();
@@ -43,6 +182,8 @@ pub async fn run_probe(
                 success: true,
                 latency,
                 detail,
+                cert_days_left,
+                cert_expiring,
             }
         }
         Err(e) => {
@@ -51,6 +192,8 @@ pub async fn run_probe(
                 success: false,
                 latency,
                 detail: e.to_string(),
+                cert_days_left,
+                cert_expiring: false,
             }
         }
     }
@@ -90,56 +233,87 @@ async fn ping_probe(target: IpAddr, timeout: Duration, count: u8) -> Result<Stri
     }
 }
 
-/// HTTP/HTTPS probe - makes a GET request and checks for 2xx status.
+/// HTTP/HTTPS probe - makes the configured request and checks its status
+/// (any 2xx unless `expect_status` is set) and, if asked, its body.
 async fn http_probe(
     target: IpAddr,
     https: bool,
     timeout: Duration,
     endpoint: Option<&str>,
+    probe: &HttpProbe,
 ) -> Result<String, String> {
     let scheme = if https { "https" } else { "http" };
-    // Parse endpoint for port if provided (e.g., ":80/health")
-    let (port_str, actual_path) = if let Some(ep) = endpoint {
-        if let Some(rest) = ep.strip_prefix(':') {
-            if let Some(slash_pos) = rest.find('/') {
-                (
-                    &rest[..slash_pos],
-                    &rest[slash_pos..],
-                )
-            } else {
-                (rest, "/")
-            }
-        } else {
-            ("", ep)
-        }
-    } else {
-        ("", "/")
-    };
+    let (port, actual_path) = split_endpoint(endpoint)?;
+    let port = port.unwrap_or(if https { 443 } else { 80 });
+    let addr = SocketAddr::new(target, port);
 
-    let url = if port_str.is_empty() {
-        format!("{scheme}://{target}{actual_path}")
-    } else {
-        format!("{scheme}://{target}:{port_str}{actual_path}")
+    // Name the virtual host in the URL and pin it to the member (in the
+    // client), so it goes out as the Host header and the TLS server name.
+    let url = match &probe.host {
+        Some(host) => format!("{scheme}://{host}:{port}{actual_path}"),
+        None => format!("{scheme}://{addr}{actual_path}"),
+    };
+    let key = HttpClientKey {
+        addr,
+        host: probe.host.clone(),
+        // A CA bundle is only there to verify against, so it turns
+        // verification on.
+        verify: probe.verify_tls || probe.ca_bundle.is_some(),
+        ca_bundle: probe.ca_bundle.as_deref().map(bundle_version).transpose()?,
+    };
+    static CLIENTS: Memo<HttpClientKey, reqwest::Client> = Memo::new();
+    let client = match CLIENTS.get(&key) {
+        Some(client) => client,
+        None => {
+            let client = http_client(&key)?;
+            CLIENTS.insert(key, client.clone());
+            client
+        }
     };
 
-    let client = reqwest::Client::builder()
-        .timeout(timeout)
-        .danger_accept_invalid_certs(true) // Health checks don't validate certs
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    let resp = client
-        .get(&url)
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+    let mut request = client.request(http_method(probe)?, &url).timeout(timeout);
+    for (name, value) in &probe.headers {
+        request = request.header(name, value);
+    }
+    let mut resp = request.send().await.map_err(|e| e.to_string())?;
 
     let status = resp.status();
-    if status.is_success() {
-        Ok(format!("HTTP {status}"))
+    let status_ok = if probe.expect_status.is_empty() {
+        status.is_success()
     } else {
-        Err(format!("HTTP {status}"))
+        probe.expect_status.contains(&status.as_u16())
+    };
+    if !status_ok {
+        return Err(format!("HTTP {status}"));
+    }
+    if probe.expect_body.is_some() || probe.expect_body_regex.is_some() {
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await.map_err(|e| e.to_string())? {
+            let room = MAX_BODY - body.len();
+            body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+            if body.len() == MAX_BODY {
+                break;
+            }
+        }
+        check_body(&String::from_utf8_lossy(&body), probe)
+            .map_err(|e| format!("HTTP {status}, {e}"))?;
+    }
+    Ok(format!("HTTP {status}"))
+}
+
+/// Whether a response body satisfies `expect_body` and `expect_body_regex`.
+fn check_body(body: &str, probe: &HttpProbe) -> Result<(), String> {
+    if let Some(needle) = &probe.expect_body {
+        if !body.contains(needle.as_str()) {
+            return Err(format!("body lacks {needle:?}"));
+        }
+    }
+    if let Some(pattern) = &probe.expect_body_regex {
+        if !body_regex(pattern)?.is_match(body) {
+            return Err(format!("body does not match {pattern:?}"));
+        }
     }
+    Ok(())
 }
 
 /// TCP connect probe — checks if a TCP connection can be established and
@@ -150,9 +324,7 @@ async fn tcp_probe(
     timeout: Duration,
     endpoint: Option<&str>,
 ) -> Result<String, String> {
-    let port: u16 = endpoint
-        .and_then(|ep| ep.trim_start_matches(':').parse().ok())
-        .unwrap_or(80);
+    let port = endpoint_port(endpoint, 80)?;
 
     let addr = SocketAddr::new(target, port);
     match tokio::time::timeout(timeout, TcpStream::connect(addr)).await {
@@ -162,6 +334,115 @@ async fn tcp_probe(
     }
 }
 
+/// DNS probe — sends the configured query over UDP or TCP and checks the
+/// reply's response code and, if asked, that it answers with an address.
+async fn dns_probe(
+    target: IpAddr,
+    timeout: Duration,
+    endpoint: Option<&str>,
+    probe: &DnsProbe,
+) -> Result<String, String> {
+    let port = endpoint_port(endpoint, 53)?;
+    let addr = SocketAddr::new(target, port);
+    let query = dns_query(probe)?;
+    let wire = query.to_vec().map_err(|e| e.to_string())?;
+
+    let exchange = async {
+        match probe.transport {
+            DnsTransport::Udp => {
+                let bind = if target.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                let socket = UdpSocket::bind(bind).await?;
+                socket.connect(addr).await?;
+                socket.send(&wire).await?;
+                let mut buf = vec![0u8; 4096];
+                let len = socket.recv(&mut buf).await?;
+                buf.truncate(len);
+                Ok::<_, std::io::Error>(buf)
+            }
+            DnsTransport::Tcp => {
+                let mut stream = TcpStream::connect(addr).await?;
+                stream.write_all(&frame_dns_tcp(&wire)).await?;
+                let len = stream.read_u16().await? as usize;
+                let mut buf = vec![0u8; len];
+                stream.read_exact(&mut buf).await?;
+                Ok(buf)
+            }
+        }
+    };
+    let reply = match tokio::time::timeout(timeout, exchange).await {
+        Ok(Ok(reply)) => reply,
+        Ok(Err(e)) => return Err(format!("dns/{port}: {e}")),
+        Err(_) => return Err(format!("dns/{port}: timeout")),
+    };
+    let reply = Message::from_vec(&reply).map_err(|e| format!("dns/{port}: {e}"))?;
+    check_dns_reply(&query, &reply, probe).map(|detail| format!("dns/{port}: {detail}"))
+}
+
+fn dns_query(probe: &DnsProbe) -> Result<Message, String> {
+    let name = Name::from_ascii(&probe.name)
+        .map_err(|e| format!("bad query name {:?}: {e}", probe.name))?;
+    let rtype = RecordType::from_str(&probe.record_type.to_uppercase())
+        .map_err(|_| format!("bad query type {:?}", probe.record_type))?;
+    let mut query = Message::new();
+    query
+        .set_id(rand::random())
+        .set_message_type(MessageType::Query)
+        .set_op_code(OpCode::Query)
+        .set_recursion_desired(true)
+        .add_query(Query::query(name, rtype));
+    Ok(query)
+}
+
+/// Whether `reply` correctly answers `query` under `probe`'s expectations.
+fn check_dns_reply(query: &Message, reply: &Message, probe: &DnsProbe) -> Result<String, String> {
+    if reply.id() != query.id()
+        || reply.message_type() != MessageType::Response
+        || reply.queries() != query.queries()
+    {
+        return Err("reply does not match the query".to_string());
+    }
+    let rcode = reply.response_code();
+    match &probe.expect_rcode {
+        Some(expected) => {
+            let expected = parse_rcode(expected)
+                .ok_or_else(|| format!("unknown expect_rcode {expected:?}"))?;
+            if rcode != expected {
+                return Err(format!("{rcode:?}, expected {expected:?}"));
+            }
+        }
+        None => {
+            if matches!(rcode, ResponseCode::ServFail | ResponseCode::Refused) {
+                return Err(format!("{rcode:?}"));
+            }
+        }
+    }
+    if let Some(expected) = probe.expect_answer {
+        let found = reply.answers().iter().any(|rr| match rr.data() {
+            Some(RData::A(a)) => IpAddr::V4(a.0) == expected,
+            Some(RData::AAAA(a)) => IpAddr::V6(a.0) == expected,
+            _ => false,
+        });
+        if !found {
+            return Err(format!("{rcode:?}, {expected} not in answer"));
+        }
+    }
+    Ok(format!("{rcode:?}, {} answers", reply.answers().len()))
+}
+
+/// `NOERROR`, `NXDOMAIN`, … or a numeric code.
+fn parse_rcode(s: &str) -> Option<ResponseCode> {
+    let code = match s.trim().to_uppercase().as_str() {
+        "NOERROR" => ResponseCode::NoError,
+        "FORMERR" => ResponseCode::FormErr,
+        "SERVFAIL" => ResponseCode::ServFail,
+        "NXDOMAIN" => ResponseCode::NXDomain,
+        "NOTIMP" => ResponseCode::NotImp,
+        "REFUSED" => ResponseCode::Refused,
+        other => other.parse::<u16>().ok()?.into(),
+    };
+    Some(code)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -178,4 +459,269 @@ mod tests {
         // Should fail (connection refused)
         assert!(result.is_err());
     }
+
+    fn reply_to(query: &Message, rcode: ResponseCode, answer: Option<IpAddr>) -> Message {
+        let mut reply = Message::new();
+        reply
+            .set_id(query.id())
+            .set_message_type(MessageType::Response)
+            .set_response_code(rcode)
+            .add_queries(query.queries().to_vec());
+        if let Some(IpAddr::V4(ip)) = answer {
+            let name = query.queries()[0].name().clone();
+            reply.add_answer(hickory_proto::rr::Record::from_rdata(
+                name,
+                60,
+                RData::A(ip.into()),
+            ));
+        }
+        reply
+    }
+
+    #[test]
+    fn dns_reply_is_checked_against_the_expectations() {
+        let probe = DnsProbe {
+            name: "www.example.com.".into(),
+            record_type: "a".into(),
+            ..DnsProbe::default()
+        };
+        let query = dns_query(&probe).unwrap();
+        let ip: IpAddr = "192.0.2.7".parse().unwrap();
+
+        let ok = reply_to(&query, ResponseCode::NXDomain, None);
+        assert!(check_dns_reply(&query, &ok, &probe).is_ok());
+        let servfail = reply_to(&query, ResponseCode::ServFail, None);
+        assert!(check_dns_reply(&query, &servfail, &probe).is_err());
+
+        let expect = DnsProbe {
+            expect_rcode: Some("noerror".into()),
+            expect_answer: Some(ip),
+            ..probe.clone()
+        };
+        let answered = reply_to(&query, ResponseCode::NoError, Some(ip));
+        assert!(check_dns_reply(&query, &answered, &expect).is_ok());
+        let other = reply_to(&query, ResponseCode::NoError, Some("192.0.2.8".parse().unwrap()));
+        assert!(check_dns_reply(&query, &other, &expect).is_err());
+        assert!(check_dns_reply(&query, &ok, &expect).is_err());
+
+        let mut stale = answered.clone();
+        stale.set_id(query.id().wrapping_add(1));
+        assert!(check_dns_reply(&query, &stale, &expect).is_err());
+
+        // Same ID, another question: not the reply to this query.
+        let other_query = dns_query(&DnsProbe {
+            name: "mail.example.com.".into(),
+            ..probe.clone()
+        })
+        .unwrap();
+        let mut misdirected = reply_to(&other_query, ResponseCode::NoError, Some(ip));
+        misdirected.set_id(query.id());
+        assert!(check_dns_reply(&query, &misdirected, &expect).is_err());
+    }
+
+    #[test]
+    fn unrunnable_dns_checks_are_refused_up_front() {
+        let check = |dns: DnsProbe| {
+            validate(&HealthCheck {
+                probe_type: ProbeType::Dns,
+                dns: Some(dns),
+                ..Default::default()
+            })
+        };
+        assert!(check(DnsProbe::default()).is_ok());
+        assert!(check(DnsProbe {
+            name: "bad..name".into(),
+            ..DnsProbe::default()
+        })
+        .is_err());
+        assert!(check(DnsProbe {
+            record_type: "BOGUS".into(),
+            ..DnsProbe::default()
+        })
+        .is_err());
+        assert!(check(DnsProbe {
+            expect_rcode: Some("MAYBE".into()),
+            ..DnsProbe::default()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_dns_probe_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            let (len, peer) = server.recv_from(&mut buf).await.unwrap();
+            let query = Message::from_vec(&buf[..len]).unwrap();
+            let ip = "192.0.2.7".parse().ok();
+            let reply = reply_to(&query, ResponseCode::NoError, ip);
+            server.send_to(&reply.to_vec().unwrap(), peer).await.unwrap();
+        });
+
+        let probe = DnsProbe {
+            name: "www.example.com.".into(),
+            record_type: "A".into(),
+            expect_answer: "192.0.2.7".parse().ok(),
+            ..DnsProbe::default()
+        };
+        let endpoint = format!(":{port}");
+        let result = dns_probe(
+            "127.0.0.1".parse().unwrap(),
+            Duration::from_secs(2),
+            Some(&endpoint),
+            &probe,
+        )
+        .await;
+        assert!(result.is_ok(), "{result:?}");
+    }
+
+    /// Serve one HTTP request on a loopback port: status 200, with the
+    /// request line and Host header echoed in the body.
+    async fn one_shot_http_server() -> u16 {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let len = stream.read(&mut buf).await.unwrap();
+            let request = String::from_utf8_lossy(&buf[..len]).to_string();
+            let mut lines = request.lines();
+            let line = lines.next().unwrap_or_default().to_string();
+            let host = lines
+                .find_map(|l| l.strip_prefix("host: ").map(str::to_string))
+                .unwrap_or_default();
+            let body = format!("{line}\nhost={host}\n");
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(resp.as_bytes()).await.unwrap();
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_http_probe_expectations() {
+        let probe = HttpProbe {
+            method: "head".into(),
+            ..HttpProbe::default()
+        };
+        let port = one_shot_http_server().await;
+        let endpoint = format!(":{port}/healthz");
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let timeout = Duration::from_secs(2);
+        let result = http_probe(local, false, timeout, Some(&endpoint), &probe).await;
+        assert_eq!(result.as_deref(), Ok("HTTP 200 OK"));
+
+        // The virtual host goes out as Host; the body must name it.
+        let probe = HttpProbe {
+            host: Some("app.example.com".into()),
+            expect_status: vec![200, 204],
+            expect_body_regex: Some(r"^GET /healthz .*\nhost=app\.example\.com:\d+".into()),
+            ..HttpProbe::default()
+        };
+        let port = one_shot_http_server().await;
+        let endpoint = format!(":{port}/healthz");
+        let result = http_probe(local, false, timeout, Some(&endpoint), &probe).await;
+        assert!(result.is_ok(), "{result:?}");
+
+        let probe = HttpProbe {
+            expect_status: vec![204],
+            ..HttpProbe::default()
+        };
+        let port = one_shot_http_server().await;
+        let endpoint = format!(":{port}/");
+        let result = http_probe(local, false, timeout, Some(&endpoint), &probe).await;
+        assert_eq!(result, Err("HTTP 200 OK".to_string()));
+    }
+
+    #[test]
+    fn body_expectations() {
+        let probe = HttpProbe {
+            expect_body: Some("\"status\":\"ok\"".into()),
+            expect_body_regex: Some(r"version\W+2\.".into()),
+            ..HttpProbe::default()
+        };
+        assert!(check_body(r#"{"status":"ok","version":"2.4"}"#, &probe).is_ok());
+        assert!(check_body(r#"{"status":"degraded","version":"2.4"}"#, &probe).is_err());
+        assert!(check_body(r#"{"status":"ok","version":"1.9"}"#, &probe).is_err());
+    }
+
+    #[test]
+    fn endpoints_name_a_port_a_path_or_both() {
+        assert_eq!(split_endpoint(None), Ok((None, "/")));
+        assert_eq!(split_endpoint(Some(":8080")), Ok((Some(8080), "/")));
+        assert_eq!(split_endpoint(Some(":8080/healthz")), Ok((Some(8080), "/healthz")));
+        assert_eq!(split_endpoint(Some("5432")), Ok((Some(5432), "/")));
+        assert_eq!(split_endpoint(Some("/healthz")), Ok((None, "/healthz")));
+        assert!(split_endpoint(Some(":http")).is_err());
+        assert_eq!(endpoint_port(Some(":853"), 53), Ok(853));
+        assert_eq!(endpoint_port(None, 53), Ok(53));
+    }
+
+    #[test]
+    fn unrunnable_http_checks_are_refused_up_front() {
+        let check = |http: HttpProbe| {
+            validate(&HealthCheck {
+                probe_type: ProbeType::Http,
+                http: Some(http),
+                ..Default::default()
+            })
+        };
+        assert!(check(HttpProbe::default()).is_ok());
+        assert!(check(HttpProbe {
+            method: "get it".into(),
+            ..HttpProbe::default()
+        })
+        .is_err());
+        assert!(check(HttpProbe {
+            expect_body_regex: Some("(unclosed".into()),
+            ..HttpProbe::default()
+        })
+        .is_err());        for (name, value) in [("x token", "1"), ("x-token", "line\nbreak")] {
+            assert!(check(HttpProbe {
+                headers: [(name.to_string(), value.to_string())].into(),
+                ..HttpProbe::default()
+            })
+            .is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_start_of_a_long_body_is_read() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0u8; 4096];
+            let _ = stream.read(&mut buf).await.unwrap();
+            let body = format!("{}ready", "x".repeat(MAX_BODY));
+            let resp = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(resp.as_bytes()).await;
+        });
+        let probe = HttpProbe {
+            expect_body: Some("ready".into()),
+            ..HttpProbe::default()
+        };
+        let endpoint = format!(":{port}/");
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let timeout = Duration::from_secs(2);
+        let result = http_probe(local, false, timeout, Some(&endpoint), &probe).await;
+        assert_eq!(result, Err("HTTP 200 OK, body lacks \"ready\"".to_string()));
+    }
+
+    #[tokio::test]
+    async fn a_ca_bundle_is_used_without_verify_tls() {
+        let probe = HttpProbe {
+            ca_bundle: Some("/nonexistent/ca.pem".into()),
+            ..HttpProbe::default()
+        };
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let result = http_probe(local, true, Duration::from_secs(1), Some(":1/"), &probe).await;
+        assert!(result.unwrap_err().starts_with("ca_bundle /nonexistent/ca.pem"));
+    }
 }
@@ -1,5 +1,5 @@
 use chrono::{DateTime, Utc};
-use microdns_core::types::{HealthStatus, PersistedHealth, ProbeType};
+use microdns_core::types::{AdminState, HealthStatus, PersistedHealth, ProbeType};
 use std::collections::HashMap;
 use uuid::Uuid;
 
@@ -34,6 +34,17 @@ pub struct RecordHealth {
     pub last_probe_detail: String,
     /// Probe type used on the most recent probe.
     pub last_probe_type: ProbeType,
+    /// Tier within the group; 0 is primary. Lower tiers are served while
+    /// they have a healthy member.
+    pub priority: u32,
+    /// Seconds a better tier must have been healthy before failing back.
+    pub preempt_delay_secs: u32,
+    /// Days left on the certificate seen by the last `tls` probe.
+    pub cert_days_left: Option<i64>,
+    /// The last `tls` probe passed inside `warn_days_left` of expiry.
+    pub cert_expiring: bool,
+    /// Operator-set state: draining and maintenance records are not served.
+    pub admin_state: AdminState,
 }
 
 impl RecordHealth {
@@ -58,6 +69,11 @@ impl RecordHealth {
             last_healthy_at: None,
             last_probe_detail: String::new(),
             last_probe_type: ProbeType::Ping,
+            priority: 0,
+            preempt_delay_secs: 0,
+            cert_days_left: None,
+            cert_expiring: false,
+            admin_state: AdminState::Active,
         }
     }
 
@@ -85,6 +101,11 @@ impl RecordHealth {
             last_healthy_at: persisted.last_healthy_at,
             last_probe_detail: persisted.last_probe_detail.clone(),
             last_probe_type: persisted.last_probe_type,
+            priority: 0,
+            preempt_delay_secs: 0,
+            cert_days_left: None,
+            cert_expiring: false,
+            admin_state: persisted.admin_state,
         }
     }
 
@@ -148,11 +169,30 @@ impl RecordHealth {
         }
     }
 
+    /// Whether this record is in rotation: active and up.
+    fn serving(&self) -> bool {
+        self.admin_state == AdminState::Active && self.should_be_enabled()
+    }
+
+    /// Whether this record has been Healthy for at least `delay`: long
+    /// enough for its tier to take traffic back from a tier covering for it.
+    fn settled(&self, now: DateTime<Utc>, delay: chrono::Duration) -> bool {
+        self.status == HealthStatus::Healthy
+            && self
+                .last_state_change_at
+                .is_none_or(|since| now - since >= delay)
+    }
+
     /// Serialize current state for persistence.
     pub fn to_persisted(&self, record_id: Uuid) -> Option<PersistedHealth> {
         // Don't persist a row that has never been probed — there's nothing
         // useful to write. Hydration code already treats "no row" as Unknown.
-        let last_checked_at = self.last_checked_at?;
+        // An operator state is the exception: it must survive a restart.
+        let last_checked_at = match self.last_checked_at {
+            Some(at) => at,
+            None if self.admin_state != AdminState::Active => Utc::now(),
+            None => return None,
+        };
         Some(PersistedHealth {
             record_id,
             status: self.status,
@@ -163,6 +203,7 @@ impl RecordHealth {
             last_probe_type: self.last_probe_type,
             consecutive_successes: self.success_count,
             consecutive_failures: self.failure_count,
+            admin_state: self.admin_state,
         })
     }
 }
@@ -200,6 +241,30 @@ impl HealthState {
         });
     }
 
+    /// Set a record's tier and fail-back delay from its current health check.
+    pub fn set_tier(&mut self, record_id: &Uuid, priority: u32, preempt_delay_secs: u32) {
+        if let Some(h) = self.records.get_mut(record_id) {
+            h.priority = priority;
+            h.preempt_delay_secs = preempt_delay_secs;
+        }
+    }
+
+    /// Note the days left on a record's certificate from its latest probe,
+    /// and whether they are inside the probe's warning window.
+    pub fn set_cert(&mut self, record_id: &Uuid, days: Option<i64>, expiring: bool) {
+        if let Some(h) = self.records.get_mut(record_id) {
+            h.cert_days_left = days;
+            h.cert_expiring = expiring;
+        }
+    }
+
+    /// Set a record's administrative state. Returns the previous one, or
+    /// `None` if the record is not under monitoring.
+    pub fn set_admin_state(&mut self, record_id: &Uuid, admin: AdminState) -> Option<AdminState> {
+        let h = self.records.get_mut(record_id)?;
+        Some(std::mem::replace(&mut h.admin_state, admin))
+    }
+
     /// Insert (or overwrite) a record's health using a persisted row.
     pub fn hydrate(&mut self, record_id: Uuid, health: RecordHealth) {
         self.records.insert(record_id, health);
@@ -278,10 +343,11 @@ impl HealthState {
     }
 
     /// Failsafe grouping: for every `(zone_id, name, type)` group with 2+
-    /// members where every member is Unhealthy, return the record_id with
-    /// the most recent `last_healthy_at` (deterministic "last alive"). If
-    /// none of the members has ever been seen healthy, the group does not
-    /// trigger a failsafe.
+    /// members none of which is in rotation, return the record_id with the
+    /// most recent `last_healthy_at` (deterministic "last alive"). Members
+    /// in maintenance are never picked; a draining one can be, as a last
+    /// resort. If none of the candidates has ever been seen healthy, the
+    /// group does not trigger a failsafe.
     pub fn failsafe_records(&self) -> Vec<Uuid> {
         type Key<'a> = (Uuid, &'a str, &'a str);
         let mut groups: HashMap<Key<'_>, Vec<(Uuid, &RecordHealth)>> = HashMap::new();
@@ -295,12 +361,13 @@ impl HealthState {
             if members.len() < 2 {
                 continue;
             }
-            if !members.iter().all(|(_, h)| h.status == HealthStatus::Unhealthy) {
+            if members.iter().any(|(_, h)| h.serving()) {
                 continue;
             }
             // Pick the member with the most recent last_healthy_at.
             let pick = members
                 .iter()
+                .filter(|(_, h)| h.admin_state != AdminState::Maintenance)
                 .max_by_key(|(_, h)| h.last_healthy_at)
                 .and_then(|(id, h)| h.last_healthy_at.map(|_| *id));
             if let Some(id) = pick {
@@ -310,6 +377,50 @@ impl HealthState {
         out
     }
 
+    /// The tier each `(zone_id, name, type)` group is served from: the best
+    /// tier with a member that has been healthy for the group's preemption
+    /// delay, or failing that the best tier with a member up at all. Groups
+    /// with nothing up are absent and left to the failsafe.
+    ///
+    /// The first rule keeps a backup tier in service while a recovered
+    /// primary proves itself; the second covers a start with nothing settled
+    /// yet, and failing over — a tier that is down is never chosen.
+    pub fn active_tiers(&self, now: DateTime<Utc>) -> ActiveTiers {
+        let mut groups: HashMap<GroupKey, Vec<&RecordHealth>> = HashMap::new();
+        for h in self.records.values() {
+            groups.entry(group_key(h)).or_default().push(h);
+        }
+
+        let mut tiers = HashMap::new();
+        for (key, members) in groups {
+            let delay = members.iter().map(|h| h.preempt_delay_secs).max().unwrap_or(0);
+            let delay = chrono::Duration::seconds(i64::from(delay));
+            let settled = members
+                .iter()
+                .filter(|h| h.admin_state == AdminState::Active && h.settled(now, delay))
+                .map(|h| h.priority)
+                .min();
+            let up = members
+                .iter()
+                .filter(|h| h.serving())
+                .map(|h| h.priority)
+                .min();
+            if let Some(tier) = settled.or(up) {
+                tiers.insert(key, tier);
+            }
+        }
+        ActiveTiers(tiers)
+    }
+
+    /// Whether a record should be answered: it is active, up, and in its
+    /// group's active tier.
+    pub fn should_serve(&self, record_id: &Uuid, tiers: &ActiveTiers) -> bool {
+        let Some(h) = self.records.get(record_id) else {
+            return true;
+        };
+        h.serving() && tiers.0.get(&group_key(h)).is_none_or(|&t| t == h.priority)
+    }
+
     /// Snapshot every record's persistable state. Returns rows for records
     /// that have been probed at least once.
     pub fn snapshot_persisted(&self) -> Vec<PersistedHealth> {
@@ -346,6 +457,25 @@ impl HealthState {
     }
 }
 
+type GroupKey = (Uuid, String, String);
+
+fn group_key(h: &RecordHealth) -> GroupKey {
+    (h.zone_id, h.record_name.clone(), h.record_type.clone())
+}
+
+/// Each group's serving tier, from [`HealthState::active_tiers`].
+#[derive(Debug, Clone, Default)]
+pub struct ActiveTiers(HashMap<GroupKey, u32>);
+
+impl ActiveTiers {
+    /// The tier `(zone_id, name, type)` is served from, if any is up.
+    pub fn get(&self, zone_id: Uuid, name: &str, rtype: &str) -> Option<u32> {
+        self.0
+            .get(&(zone_id, name.to_string(), rtype.to_string()))
+            .copied()
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct HealthAggregate {
     pub total: usize,
@@ -424,6 +554,85 @@ mod tests {
         assert!(state.failsafe_records().is_empty());
     }
 
+    #[test]
+    fn backups_serve_only_while_the_primary_tier_is_down() {
+        let mut state = HealthState::new();
+        let zone = Uuid::new_v4();
+        let (primary, backup) = (Uuid::new_v4(), Uuid::new_v4());
+        state.register(primary, 1, 1, zone, "api".into(), "A".into());
+        state.register(backup, 1, 1, zone, "api".into(), "A".into());
+        state.set_tier(&backup, 1, 60);
+
+        // Nothing probed yet: the primary tier, optimistically.
+        let t0 = "2026-01-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let tiers = state.active_tiers(t0);
+        assert!(state.should_serve(&primary, &tiers));
+        assert!(!state.should_serve(&backup, &tiers));
+
+        for id in [primary, backup] {
+            state.record_probe_result(&id, true, t0, ProbeType::Tcp, "ok".into());
+        }
+        let t1 = t0 + chrono::Duration::seconds(120);
+        assert_eq!(state.active_tiers(t1).get(zone, "api", "A"), Some(0));
+
+        // The primary fails: the backup takes over at once.
+        state.record_probe_result(&primary, false, t1, ProbeType::Tcp, "x".into());
+        let tiers = state.active_tiers(t1);
+        assert!(!state.should_serve(&primary, &tiers));
+        assert!(state.should_serve(&backup, &tiers));
+
+        // Back, but not for long enough to take traffic back.
+        let t2 = t1 + chrono::Duration::seconds(10);
+        state.record_probe_result(&primary, true, t2, ProbeType::Tcp, "ok".into());
+        let at = |secs| state.active_tiers(t2 + chrono::Duration::seconds(secs));
+        assert_eq!(at(59).get(zone, "api", "A"), Some(1));
+        assert_eq!(at(60).get(zone, "api", "A"), Some(0));
+
+        // Everything down: no tier, and the failsafe decides.
+        let t3 = t2 + chrono::Duration::seconds(120);
+        for id in [primary, backup] {
+            state.record_probe_result(&id, false, t3, ProbeType::Tcp, "x".into());
+        }
+        assert_eq!(state.active_tiers(t3).get(zone, "api", "A"), None);
+        assert_eq!(state.failsafe_records().len(), 1);
+    }
+
+    #[test]
+    fn drained_and_maintained_members_leave_rotation() {
+        let mut state = HealthState::new();
+        let zone = Uuid::new_v4();
+        let (primary, backup) = (Uuid::new_v4(), Uuid::new_v4());
+        state.register(primary, 1, 1, zone, "api".into(), "A".into());
+        state.register(backup, 1, 1, zone, "api".into(), "A".into());
+        state.set_tier(&backup, 1, 0);
+        let t0 = now();
+        state.record_probe_result(&backup, true, t0, ProbeType::Tcp, "ok".into());
+        state.record_probe_result(&primary, true, t0, ProbeType::Tcp, "ok".into());
+        let t1 = t0 + chrono::Duration::seconds(1);
+        state.record_probe_result(&primary, true, t1, ProbeType::Tcp, "ok".into());
+
+        // Draining the primary hands its traffic to the backup tier.
+        let previous = state.set_admin_state(&primary, AdminState::Draining);
+        assert_eq!(previous, Some(AdminState::Active));
+        let tiers = state.active_tiers(t1);
+        assert_eq!(tiers.get(zone, "api", "A"), Some(1));
+        assert!(!state.should_serve(&primary, &tiers));
+        assert!(state.should_serve(&backup, &tiers));
+
+        // With the backup down, a draining member is the last resort;
+        // one in maintenance never is.
+        state.record_probe_result(&backup, false, t1, ProbeType::Tcp, "x".into());
+        assert_eq!(state.failsafe_records(), vec![primary]);
+        state.set_admin_state(&primary, AdminState::Maintenance);
+        assert_eq!(state.failsafe_records(), vec![backup]);
+
+        // The state survives a restart.
+        let row = state.get(&primary).unwrap().to_persisted(primary).unwrap();
+        let back = RecordHealth::from_persisted(&row, 1, 1, zone, "api".into(), "A".into());
+        assert_eq!(back.admin_state, AdminState::Maintenance);
+        assert_eq!(state.set_admin_state(&Uuid::new_v4(), AdminState::Active), None);
+    }
+
     #[test]
     fn snapshot_skips_never_probed() {
         let mut state = HealthState::new();
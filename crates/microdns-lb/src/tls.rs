@@ -0,0 +1,330 @@
+//! TLS probe. Completes a handshake with the member and then judges the
+//! certificate it presented: its chain and name (unless `verify` is off)
+//! and how many days it has left before it expires.
+//!
+//! The handshake itself accepts any certificate so that expiry can be read
+//! and reported even from a certificate that fails validation; validation
+//! is run on the presented chain afterwards.
+
+use crate::memo::{bundle_version, BundleVersion, Memo};
+use chrono::{DateTime, Utc};
+use microdns_core::types::TlsProbe;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tracing::warn;
+
+static PROVIDER: LazyLock<Arc<CryptoProvider>> =
+    LazyLock::new(|| Arc::new(rustls::crypto::ring::default_provider()));
+
+/// The handshake's client configuration, the same for every probe. Sessions
+/// are not resumed: every probe sees a full handshake and the certificate.
+static ACCEPT_ANY: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    let mut config = ClientConfig::builder_with_provider(PROVIDER.clone())
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAny {
+            provider: PROVIDER.clone(),
+        }))
+        .with_no_client_auth();
+    config.resumption = rustls::client::Resumption::disabled();
+    Arc::new(config)
+});
+
+/// Handshake with `target` and judge its certificate. Returns the days the
+/// certificate has left, when one was presented and readable, alongside
+/// the verdict. A pass inside `warn_days_left` is still a pass; see
+/// [`expiring`].
+pub async fn probe(
+    target: IpAddr,
+    timeout: Duration,
+    endpoint: Option<&str>,
+    probe: &TlsProbe,
+) -> (Option<i64>, Result<String, String>) {
+    let port = match crate::probe::endpoint_port(endpoint, 443) {
+        Ok(port) => port,
+        Err(e) => return (None, Err(e)),
+    };
+    let server_name = match &probe.server_name {
+        Some(name) => match ServerName::try_from(name.clone()) {
+            Ok(name) => name,
+            Err(_) => return (None, Err(format!("bad server_name {name:?}"))),
+        },
+        None => ServerName::IpAddress(target.into()),
+    };
+
+    let addr = SocketAddr::new(target, port);
+    let handshake = async {
+        let tcp = TcpStream::connect(addr).await?;
+        TlsConnector::from(ACCEPT_ANY.clone())
+            .connect(server_name.clone(), tcp)
+            .await
+    };
+    let stream = match tokio::time::timeout(timeout, handshake).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => return (None, Err(format!("tls/{port}: {e}"))),
+        Err(_) => return (None, Err(format!("tls/{port}: timeout"))),
+    };
+    let chain = stream.get_ref().1.peer_certificates().unwrap_or_default();
+    let Some((leaf, intermediates)) = chain.split_first() else {
+        return (None, Err(format!("tls/{port}: no certificate")));
+    };
+
+    let days_left = not_after(leaf).map(|at| (at - Utc::now()).num_days());
+    let verdict = if probe.verify {
+        verify(leaf, intermediates, &server_name, probe)
+    } else {
+        Ok(())
+    };
+    let verdict = verdict.and_then(|()| match days_left {
+        None => Err("unreadable certificate expiry".to_string()),
+        Some(days) if days < i64::from(probe.min_days_left) => {
+            Err(format!("certificate expires in {days} days"))
+        }
+        Some(days) if expiring(probe, Some(days)) => {
+            warn!("tls probe to {addr}: certificate expires in {days} days");
+            Ok(format!("certificate expires in {days} days, renew soon"))
+        }
+        Some(days) => Ok(format!("certificate valid for {days} days")),
+    });
+    (days_left, verdict.map_err(|e| format!("tls/{port}: {e}")))
+}
+
+/// Whether a certificate with `days_left` is inside `probe`'s warning window.
+pub fn expiring(probe: &TlsProbe, days_left: Option<i64>) -> bool {
+    days_left.is_some_and(|days| days < i64::from(probe.warn_days_left))
+}
+
+/// Validate the presented chain for `server_name` against the configured
+/// roots.
+fn verify(
+    leaf: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    server_name: &ServerName<'_>,
+    probe: &TlsProbe,
+) -> Result<(), String> {
+    static VERIFIERS: Memo<Option<BundleVersion>, Arc<WebPkiServerVerifier>> = Memo::new();
+    let bundle = probe.ca_bundle.as_deref().map(bundle_version).transpose()?;
+    let verifier = VERIFIERS.get_or_try_insert(&bundle, || verifier(&bundle))?;
+    verifier
+        .verify_server_cert(leaf, intermediates, server_name, &[], UnixTime::now())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// A verifier for `bundle`'s CA certificates, or for the built-in roots.
+fn verifier(bundle: &Option<BundleVersion>) -> Result<Arc<WebPkiServerVerifier>, String> {
+    let roots = match bundle {
+        Some((path, _)) => {
+            let mut roots = RootCertStore::empty();
+            let certs = CertificateDer::pem_file_iter(path)
+                .map_err(|e| format!("ca_bundle {path}: {e}"))?;
+            for cert in certs {
+                let cert = cert.map_err(|e| format!("ca_bundle {path}: {e}"))?;
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("ca_bundle {path}: {e}"))?;
+            }
+            roots
+        }
+        None => RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        },
+    };
+    WebPkiServerVerifier::builder_with_provider(Arc::new(roots), PROVIDER.clone())
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// The `notAfter` time of a DER-encoded X.509 certificate.
+fn not_after(cert: &[u8]) -> Option<DateTime<Utc>> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+}
+
+/// Lets any certificate through the handshake, still requiring the server
+/// to prove it holds the certificate's key; [`verify`] judges it after.
+#[derive(Debug)]
+struct AcceptAny {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for AcceptAny {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::probe::run_probe;
+    use chrono::Datelike;
+    use microdns_core::types::{HealthCheck, ProbeType};
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+    /// A CA, and a TLS server on loopback presenting a certificate for
+    /// `svc.test` it issued, expiring `days` from now. Returns the server's
+    /// port and the CA's PEM.
+    async fn tls_server(days: i64) -> (u16, String) {
+        let mut ca_params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let expiry = Utc::now() + chrono::Duration::days(days);
+        let mut params = rcgen::CertificateParams::new(vec!["svc.test".to_string()]).unwrap();
+        params.not_after =
+            rcgen::date_time_ymd(expiry.year(), expiry.month() as u8, expiry.day() as u8);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![cert.der().clone()],
+            PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+        )
+        .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let _ = acceptor.accept(tcp).await;
+                });
+            }
+        });
+        (port, ca.pem())
+    }
+
+    #[tokio::test]
+    async fn probe_checks_chain_name_and_expiry() {
+        let (port, ca) = tls_server(400).await;
+        let bundle = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(bundle.path(), ca).unwrap();
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let timeout = Duration::from_secs(2);
+        let endpoint = format!(":{port}");
+        let valid = TlsProbe {
+            server_name: Some("svc.test".into()),
+            ca_bundle: Some(bundle.path().display().to_string()),
+            ..TlsProbe::default()
+        };
+
+        let (days, result) = probe(local, timeout, Some(&endpoint), &valid).await;
+        assert!(result.is_ok(), "{result:?}");
+        assert!(days.is_some_and(|d| (398..=400).contains(&d)), "{days:?}");
+
+        // Wrong name, then an issuer the built-in roots don't know.
+        let wrong_name = TlsProbe {
+            server_name: Some("other.test".into()),
+            ..valid.clone()
+        };
+        let (days, result) = probe(local, timeout, Some(&endpoint), &wrong_name).await;
+        assert!(result.is_err() && days.is_some());
+        let public_roots = TlsProbe {
+            ca_bundle: None,
+            ..valid.clone()
+        };
+        let (_, result) = probe(local, timeout, Some(&endpoint), &public_roots).await;
+        assert!(result.is_err());
+
+        // Unverified, expiry alone decides.
+        let (port, _) = tls_server(3).await;
+        let endpoint = format!(":{port}");
+        let unverified = TlsProbe {
+            verify: false,
+            ..TlsProbe::default()
+        };
+        let (days, result) = probe(local, timeout, Some(&endpoint), &unverified).await;
+        assert!(days.is_some_and(|d| d < 7), "{days:?}");
+        assert!(result.is_err());
+        let lenient = TlsProbe {
+            min_days_left: 0,
+            ..unverified
+        };
+        let (_, result) = probe(local, timeout, Some(&endpoint), &lenient).await;
+        assert!(result.is_ok_and(|detail| detail.contains("renew soon")));
+    }
+
+    #[tokio::test]
+    async fn a_pass_near_expiry_is_flagged() {
+        let local: IpAddr = "127.0.0.1".parse().unwrap();
+        let check = |port: u16| HealthCheck {
+            probe_type: ProbeType::Tls,
+            endpoint: Some(format!(":{port}")),
+            tls: Some(TlsProbe {
+                verify: false,
+                min_days_left: 0,
+                ..TlsProbe::default()
+            }),
+            ..HealthCheck::default()
+        };
+
+        let (port, _) = tls_server(3).await;
+        let result = run_probe(&check(port), local, Duration::from_secs(2), 1).await;
+        assert!(result.success && result.cert_expiring, "{result:?}");
+
+        let (port, _) = tls_server(400).await;
+        let result = run_probe(&check(port), local, Duration::from_secs(2), 1).await;
+        assert!(result.success && !result.cert_expiring, "{result:?}");
+    }
+}
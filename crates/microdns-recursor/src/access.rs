@@ -0,0 +1,166 @@
+//! Live recursion access control.
+//!
+//! A recursor that answers anyone is an open resolver: a reflector for
+//! amplification attacks and a free cache for strangers. The ACL decides who
+//! may recurse; everyone else is refused, optionally still answered for names
+//! in local zones.
+//!
+//! Like the zone-transfer settings, the list lives in the database and a
+//! watcher swaps the parsed copy here when it changes. Entries that do not
+//! parse are dropped with a warning: a bad allow entry then refuses, and a bad
+//! deny entry is reported rather than silently taking recursion down.
+
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use microdns_core::config::RecursionAccessConfig;
+use microdns_core::net::IpNet;
+use tracing::{info, warn};
+
+/// The parsed form of [`RecursionAccessConfig`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccessSettings {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+    pub answer_local_to_all: bool,
+}
+
+impl AccessSettings {
+    pub fn parse(config: &RecursionAccessConfig) -> Self {
+        Self {
+            allow: parse_nets(&config.allow_recursion, "allow_recursion"),
+            deny: parse_nets(&config.deny_recursion, "deny_recursion"),
+            answer_local_to_all: config.answer_local_to_all,
+        }
+    }
+
+    /// Whether `client` may have names outside local zones resolved for it.
+    /// A deny entry wins over an allow entry.
+    pub fn may_recurse(&self, client: IpAddr) -> bool {
+        !self.deny.iter().any(|net| net.contains(client))
+            && self.allow.iter().any(|net| net.contains(client))
+    }
+
+    /// One line describing what is in force, for the log on startup or change.
+    pub fn summary(&self) -> String {
+        let recursion = if self.allow.is_empty() {
+            "recursion refused to all".to_string()
+        } else {
+            format!("recursion allowed from {} CIDR(s)", self.allow.len())
+        };
+        let local = if self.answer_local_to_all {
+            "local zones answered to all"
+        } else {
+            "local zones answered to allowed clients only"
+        };
+        format!("{recursion}, {} denied, {local}", self.deny.len())
+    }
+}
+
+impl Default for AccessSettings {
+    fn default() -> Self {
+        Self::parse(&RecursionAccessConfig::default())
+    }
+}
+
+fn parse_nets(entries: &[String], field: &str) -> Vec<IpNet> {
+    entries
+        .iter()
+        .filter_map(|c| match IpNet::parse(c) {
+            Some(net) => Some(net),
+            None => {
+                warn!("ignoring invalid {field} entry '{c}'");
+                None
+            }
+        })
+        .collect()
+}
+
+/// Shared handle to the settings in force.
+#[derive(Clone, Default)]
+pub struct AccessState {
+    inner: Arc<Mutex<AccessSettings>>,
+}
+
+impl AccessState {
+    pub fn new(config: &RecursionAccessConfig) -> Self {
+        let settings = AccessSettings::parse(config);
+        info!("recursion access: {}", settings.summary());
+        Self {
+            inner: Arc::new(Mutex::new(settings)),
+        }
+    }
+
+    /// Swap in new settings. Returns whether anything changed, so the caller
+    /// can log a change and not a heartbeat.
+    pub fn replace(&self, config: &RecursionAccessConfig) -> bool {
+        let next = AccessSettings::parse(config);
+        let mut current = self.inner.lock().unwrap();
+        let changed = *current != next;
+        *current = next;
+        changed
+    }
+
+    /// The settings in force, for a single query's decision.
+    pub fn settings(&self) -> AccessSettings {
+        self.inner.lock().unwrap().clone()
+    }
+
+    pub fn summary(&self) -> String {
+        self.inner.lock().unwrap().summary()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn defaults_allow_private_ranges_only() {
+        let settings = AccessSettings::default();
+        assert!(settings.may_recurse(ip("192.168.1.10")));
+        assert!(settings.may_recurse(ip("10.1.2.3")));
+        assert!(settings.may_recurse(ip("127.0.0.1")));
+        assert!(!settings.may_recurse(ip("203.0.113.5")));
+        assert!(settings.answer_local_to_all);
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let settings = AccessSettings::parse(&RecursionAccessConfig {
+            allow_recursion: vec!["192.168.0.0/16".into()],
+            deny_recursion: vec!["192.168.99.0/24".into()],
+            answer_local_to_all: true,
+        });
+        assert!(settings.may_recurse(ip("192.168.1.10")));
+        assert!(!settings.may_recurse(ip("192.168.99.10")));
+    }
+
+    #[test]
+    fn empty_allow_refuses_everyone() {
+        let settings = AccessSettings::parse(&RecursionAccessConfig {
+            allow_recursion: vec![],
+            deny_recursion: vec![],
+            answer_local_to_all: false,
+        });
+        assert!(!settings.may_recurse(ip("127.0.0.1")));
+        assert!(settings.summary().contains("refused to all"));
+    }
+
+    #[test]
+    fn invalid_entries_are_dropped_and_changes_reported() {
+        let mut config = RecursionAccessConfig::default();
+        config.allow_recursion.push("not-a-cidr".into());
+        let state = AccessState::new(&config);
+        assert_eq!(state.settings().allow.len(), 7);
+
+        assert!(!state.replace(&config), "identical config is not a change");
+        config.deny_recursion.push("10.9.0.0/16".into());
+        assert!(state.replace(&config));
+        assert!(!state.settings().may_recurse(ip("10.9.1.1")));
+    }
+}
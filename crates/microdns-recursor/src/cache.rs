@@ -1,7 +1,70 @@
-use dashmap::DashMap;
-use hickory_proto::op::Message;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use crate::ecs::Subnet;
+use chrono::Utc;
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use microdns_core::config::DnsRecursorConfig;
+use microdns_core::db::Db;
+use microdns_core::types::{CachedResponse, RecordData};
+use serde::Serialize;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::BuildHasher;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Most shards a cache is split into. Each has its own lock, so concurrent
+/// lookups rarely wait on each other.
+const MAX_SHARDS: usize = 16;
+
+/// Entries per shard below which splitting further is not worth it.
+const MIN_SHARD_ENTRIES: usize = 64;
+
+/// Bookkeeping charged per entry on top of the response itself: the key,
+/// map slots and LRU index.
+const ENTRY_OVERHEAD: usize = 96;
+
+/// Size and TTL policy for the cache.
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Most entries held.
+    pub max_entries: usize,
+    /// Most bytes held, responses and bookkeeping together.
+    pub max_bytes: usize,
+    /// Positive answers are kept at least this long, whatever their TTL says.
+    pub min_ttl: u32,
+    /// Positive answers are kept at most this long.
+    pub max_ttl: u32,
+    /// NXDOMAIN and NODATA answers are kept at most this long.
+    pub max_negative_ttl: u32,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_bytes: 64 * 1024 * 1024,
+            min_ttl: 0,
+            max_ttl: 86_400,
+            max_negative_ttl: 3_600,
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn from_config(config: &DnsRecursorConfig) -> Self {
+        Self {
+            max_entries: config.cache_size,
+            max_bytes: config.cache_max_bytes,
+            min_ttl: config.cache_min_ttl,
+            max_ttl: config.cache_max_ttl.max(config.cache_min_ttl),
+            max_negative_ttl: config.cache_max_negative_ttl,
+        }
+    }
+}
 
 /// A cached DNS response with expiry tracking.
 struct CacheEntry {
@@ -9,30 +72,81 @@ struct CacheEntry {
     response_bytes: Vec<u8>,
     /// When this entry was inserted.
     inserted_at: Instant,
-    /// TTL from the response records (minimum across all answer records).
+    /// How long the entry lives: the smallest TTL in the response, clamped.
     ttl: Duration,
+    /// Bytes charged against the budget.
+    size: usize,
+    /// Position in the shard's LRU order; higher is more recent.
+    tick: u64,
 }
 
 impl CacheEntry {
     fn is_expired(&self) -> bool {
         self.inserted_at.elapsed() >= self.ttl
     }
+
+    fn remaining(&self) -> Duration {
+        self.ttl.saturating_sub(self.inserted_at.elapsed())
+    }
+}
+
+/// One lock's worth of the cache, with its own share of the budget.
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys by last use, least recent first.
+    lru: BTreeMap<u64, CacheKey>,
+    bytes: usize,
+}
+
+impl Shard {
+    fn remove(&mut self, key: &CacheKey) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.lru.remove(&entry.tick);
+        self.bytes -= entry.size;
+        Some(entry)
+    }
+
+    /// Drop the least recently used entry. Returns whether it had expired.
+    fn pop_lru(&mut self) -> Option<bool> {
+        let (_, key) = self.lru.pop_first()?;
+        let entry = self.entries.remove(&key)?;
+        self.bytes -= entry.size;
+        Some(entry.is_expired())
+    }
 }
 
-/// Thread-safe DNS response cache with TTL expiration and size limits.
+/// Thread-safe DNS response cache: sharded, bounded by entry count and by
+/// bytes, least recently used out first.
+///
+/// Answers are replayed with their TTLs counted down by the time they have
+/// spent in the cache, so a client never holds a record longer than its owner
+/// allowed. Negative answers (NXDOMAIN, NODATA) are cached for their SOA
+/// minimum, capped separately.
 pub struct DnsCache {
-    entries: DashMap<CacheKey, CacheEntry>,
-    max_size: usize,
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+    config: CacheConfig,
+    shard_entries: usize,
+    shard_bytes: usize,
+    tick: AtomicU64,
     hit_count: AtomicUsize,
     miss_count: AtomicUsize,
+    insert_count: AtomicUsize,
+    eviction_count: AtomicUsize,
+    expiration_count: AtomicUsize,
 }
 
-/// Cache key: (lowercased qname, qtype, qclass)
+/// Cache key: (lowercased qname, qtype, qclass), plus the client subnet for
+/// an answer that holds only there
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct CacheKey {
     pub name: String,
     pub rtype: u16,
     pub rclass: u16,
+    /// The EDNS Client Subnet the answer was asked for; `None` for an answer
+    /// that holds for everyone.
+    pub subnet: Option<Subnet>,
 }
 
 impl CacheKey {
@@ -41,79 +155,385 @@ impl CacheKey {
             name: name.to_lowercase(),
             rtype,
             rclass,
+            subnet: None,
         }
     }
+
+    /// The same key, scoped to a client subnet.
+    pub fn with_subnet(mut self, subnet: Option<Subnet>) -> Self {
+        self.subnet = subnet;
+        self
+    }
+
+    /// Whether the key's name is `domain` or a name under it.
+    pub fn is_at_or_under(&self, domain: &str) -> bool {
+        let domain = domain.trim_end_matches('.').to_lowercase();
+        domain.is_empty()
+            || self.name == domain
+            || self
+                .name
+                .strip_suffix(&domain)
+                .is_some_and(|rest| rest.ends_with('.'))
+    }
+}
+
+/// One cache entry as shown over the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheEntryInfo {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub rtype: String,
+    /// The client subnet the answer is cached for, when it is not for everyone.
+    pub subnet: Option<String>,
+    pub rcode: String,
+    /// Seconds until the entry expires.
+    pub ttl_remaining: u64,
+    /// Seconds the entry was cached for.
+    pub ttl: u64,
+    pub bytes: usize,
+    /// Answer and authority records in zone-file form, with the TTLs a
+    /// client would be served now.
+    pub records: Vec<String>,
+}
+
+/// Counters and occupancy, as reported over the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: usize,
+    pub max_entries: usize,
+    pub max_bytes: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub inserts: usize,
+    /// Live entries pushed out to make room.
+    pub evictions: usize,
+    /// Entries dropped because their TTL ran out.
+    pub expirations: usize,
 }
 
 impl DnsCache {
+    /// A cache of at most `max_size` entries, with the default byte budget
+    /// and TTL clamps.
     pub fn new(max_size: usize) -> Self {
+        Self::with_config(CacheConfig {
+            max_entries: max_size,
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: CacheConfig) -> Self {
+        let shard_count = (config.max_entries / MIN_SHARD_ENTRIES).clamp(1, MAX_SHARDS);
         Self {
-            entries: DashMap::with_capacity(max_size.min(4096)),
-            max_size,
+            shards: (0..shard_count).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+            shard_entries: (config.max_entries / shard_count).max(1),
+            shard_bytes: config.max_bytes / shard_count,
+            config,
+            tick: AtomicU64::new(0),
             hit_count: AtomicUsize::new(0),
             miss_count: AtomicUsize::new(0),
+            insert_count: AtomicUsize::new(0),
+            eviction_count: AtomicUsize::new(0),
+            expiration_count: AtomicUsize::new(0),
         }
     }
 
-    /// Look up a cached response. Returns the response bytes if found and not expired.
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    fn shard(&self, key: &CacheKey) -> &Mutex<Shard> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        &self.shards[index]
+    }
+
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Look up a cached response. Returns the response bytes, TTLs counted
+    /// down by the time spent in the cache, if found and not expired.
     pub fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
-        let entry = match self.entries.get(key) {
-            Some(e) => e,
-            None => {
+        let (bytes, age) = {
+            let mut shard = self.shard(key).lock().unwrap();
+            let Some(entry) = shard.entries.get(key) else {
+                self.miss_count.fetch_add(1, Ordering::Relaxed);
+                return None;
+            };
+            if entry.is_expired() {
+                shard.remove(key);
+                self.expiration_count.fetch_add(1, Ordering::Relaxed);
                 self.miss_count.fetch_add(1, Ordering::Relaxed);
                 return None;
             }
-        };
 
-        if entry.is_expired() {
-            drop(entry);
-            self.entries.remove(key);
-            self.miss_count.fetch_add(1, Ordering::Relaxed);
-            return None;
-        }
+            let tick = self.next_tick();
+            let shard = &mut *shard;
+            let entry = shard.entries.get_mut(key)?;
+            shard.lru.remove(&entry.tick);
+            shard.lru.insert(tick, key.clone());
+            entry.tick = tick;
+            (entry.response_bytes.clone(), entry.inserted_at.elapsed())
+        };
 
         self.hit_count.fetch_add(1, Ordering::Relaxed);
-        Some(entry.response_bytes.clone())
+        Some(age_response(&bytes, age.as_secs() as u32))
     }
 
-    /// Insert a response into the cache.
-    /// The `min_ttl` is the minimum TTL across all answer records.
+    /// Insert a response into the cache to live `ttl_secs`, clamped to the
+    /// configured bounds. Zero is not cached.
     pub fn insert(&self, key: CacheKey, response_bytes: Vec<u8>, ttl_secs: u32) {
         if ttl_secs == 0 {
             return;
         }
+        let ttl = ttl_secs.clamp(self.config.min_ttl, self.config.max_ttl);
+        self.store(key, response_bytes, ttl);
+    }
 
-        // Evict expired entries if we're at capacity
-        if self.entries.len() >= self.max_size {
-            self.evict_expired();
+    /// Cache an upstream answer for as long as it may be: positive answers
+    /// for their smallest TTL, NXDOMAIN and NODATA for their SOA minimum
+    /// (RFC 2308), each clamped. The TTLs in the stored copy are clamped to
+    /// match. Returns whether anything was cached.
+    pub fn insert_response(&self, key: CacheKey, response: &Message) -> bool {
+        let Some(ttl) = self.cacheable_ttl(response) else {
+            return false;
+        };
+
+        let mut stored = response.clone();
+        if response.answers().is_empty() {
+            map_ttls(&mut stored, |t| t.min(ttl));
+        } else {
+            map_ttls(&mut stored, |t| t.clamp(self.config.min_ttl, self.config.max_ttl));
         }
+        let Ok(bytes) = stored.to_bytes() else {
+            return false;
+        };
+        self.store(key, bytes, ttl);
+        true
+    }
 
-        // If still at capacity, skip insertion (simple eviction policy)
-        if self.entries.len() >= self.max_size {
+    /// How long `response` may be cached, or `None` if it may not be.
+    fn cacheable_ttl(&self, response: &Message) -> Option<u32> {
+        let negative = match response.response_code() {
+            ResponseCode::NoError => response.answers().is_empty(),
+            ResponseCode::NXDomain => true,
+            _ => return None,
+        };
+        let ttl = if negative {
+            let soa_ttl = response.name_servers().iter().find_map(|r| match r.data() {
+                Some(RData::SOA(soa)) => Some(soa.minimum().min(r.ttl())),
+                _ => None,
+            })?;
+            soa_ttl.min(self.config.max_negative_ttl)
+        } else {
+            min_ttl_from_response(response).clamp(self.config.min_ttl, self.config.max_ttl)
+        };
+        (ttl > 0).then_some(ttl)
+    }
+
+    fn store(&self, key: CacheKey, response_bytes: Vec<u8>, ttl_secs: u32) {
+        let size = response_bytes.len() + key.name.len() + ENTRY_OVERHEAD;
+        if size > self.shard_bytes {
             return;
         }
 
-        self.entries.insert(
+        let mut shard = self.shard(&key).lock().unwrap();
+        shard.remove(&key);
+        while shard.entries.len() >= self.shard_entries || shard.bytes + size > self.shard_bytes {
+            match shard.pop_lru() {
+                Some(true) => self.expiration_count.fetch_add(1, Ordering::Relaxed),
+                Some(false) => self.eviction_count.fetch_add(1, Ordering::Relaxed),
+                None => break,
+            };
+        }
+
+        let tick = self.next_tick();
+        shard.lru.insert(tick, key.clone());
+        shard.bytes += size;
+        shard.entries.insert(
             key,
             CacheEntry {
                 response_bytes,
                 inserted_at: Instant::now(),
                 ttl: Duration::from_secs(ttl_secs as u64),
+                size,
+                tick,
             },
         );
+        self.insert_count.fetch_add(1, Ordering::Relaxed);
     }
 
-    /// Remove expired entries.
-    fn evict_expired(&self) {
-        self.entries.retain(|_, entry| !entry.is_expired());
+    /// Entries whose key passes `filter`, sorted by name then type, at most
+    /// `limit` of them. Looking does not count as use: hit counts and LRU
+    /// order are left alone.
+    pub fn entries(&self, filter: impl Fn(&CacheKey) -> bool, limit: usize) -> Vec<CacheEntryInfo> {
+        let mut matches: Vec<(CacheKey, Vec<u8>, Duration, Duration, usize)> = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            for (key, entry) in &shard.entries {
+                if entry.is_expired() || !filter(key) {
+                    continue;
+                }
+                matches.push((
+                    key.clone(),
+                    entry.response_bytes.clone(),
+                    entry.inserted_at.elapsed(),
+                    entry.ttl,
+                    entry.size,
+                ));
+            }
+        }
+        matches.sort_by(|a, b| (&a.0.name, a.0.rtype).cmp(&(&b.0.name, b.0.rtype)));
+        matches.truncate(limit);
+
+        matches
+            .into_iter()
+            .map(|(key, bytes, age, ttl, size)| {
+                let aged = Message::from_bytes(&age_response(&bytes, age.as_secs() as u32)).ok();
+                CacheEntryInfo {
+                    rtype: RecordType::from(key.rtype).to_string(),
+                    subnet: key.subnet.map(|s| s.to_string()),
+                    rcode: aged
+                        .as_ref()
+                        .map_or_else(String::new, |m| m.response_code().to_string()),
+                    ttl_remaining: ttl.saturating_sub(age).as_secs(),
+                    ttl: ttl.as_secs(),
+                    bytes: size,
+                    records: aged
+                        .iter()
+                        .flat_map(|m| m.answers().iter().chain(m.name_servers()))
+                        .map(|r| r.to_string())
+                        .collect(),
+                    name: key.name,
+                }
+            })
+            .collect()
+    }
+
+    /// Drop every entry whose key passes `filter`. Returns how many went.
+    pub fn flush(&self, filter: impl Fn(&CacheKey) -> bool) -> usize {
+        let mut flushed = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap();
+            let keys: Vec<CacheKey> = shard.entries.keys().filter(|k| filter(k)).cloned().collect();
+            for key in keys {
+                shard.remove(&key);
+                flushed += 1;
+            }
+        }
+        flushed
+    }
+
+    /// Put an answer for `name` in the cache by hand, one entry per record
+    /// type, as though upstream had sent it. Returns the number of entries.
+    pub fn seed(&self, name: &str, ttl: u32, records: &[RecordData]) -> Result<usize, String> {
+        let name = name.trim_end_matches('.').to_lowercase();
+        let qname = Name::from_str(&format!("{name}."))
+            .map_err(|e| format!("'{name}' is not a valid name: {e}"))?;
+
+        let mut by_type: BTreeMap<u16, Vec<RData>> = BTreeMap::new();
+        for data in records {
+            let rdata = crate::resolver::record_data_to_rdata(data).ok_or_else(|| {
+                format!("{} records cannot be cached", data.record_type())
+            })?;
+            by_type
+                .entry(rdata.record_type().into())
+                .or_default()
+                .push(rdata);
+        }
+
+        for (rtype, rdatas) in &by_type {
+            let mut msg = Message::new();
+            msg.set_message_type(MessageType::Response);
+            msg.set_op_code(OpCode::Query);
+            msg.set_recursion_desired(true);
+            msg.set_recursion_available(true);
+            msg.add_query(Query::query(qname.clone(), RecordType::from(*rtype)));
+            for rdata in rdatas {
+                msg.add_answer(Record::from_rdata(qname.clone(), ttl, rdata.clone()));
+            }
+            let bytes = msg.to_bytes().map_err(|e| e.to_string())?;
+            self.insert(
+                CacheKey::from_query(&name, *rtype, DNSClass::IN.into()),
+                bytes,
+                ttl,
+            );
+        }
+        Ok(by_type.len())
+    }
+
+    /// Save every live entry to the database, replacing what was saved
+    /// before. Returns the number saved.
+    pub fn save(&self, db: &Db) -> anyhow::Result<usize> {
+        let now = Utc::now();
+        let mut rows = Vec::new();
+        for shard in &self.shards {
+            let shard = shard.lock().unwrap();
+            for (key, entry) in &shard.entries {
+                let remaining = entry.remaining();
+                // Per-subnet answers are short-lived and say where clients
+                // are; they are not written to disk.
+                if remaining.is_zero() || key.subnet.is_some() {
+                    continue;
+                }
+                let age = entry.inserted_at.elapsed().as_secs() as u32;
+                rows.push(CachedResponse {
+                    name: key.name.clone(),
+                    rtype: key.rtype,
+                    rclass: key.rclass,
+                    saved_at: now,
+                    expires_at: now + chrono::Duration::seconds(remaining.as_secs() as i64),
+                    response: age_response(&entry.response_bytes, age),
+                });
+            }
+        }
+        db.replace_recursor_cache(&rows)?;
+        Ok(rows.len())
+    }
+
+    /// Load what [`Self::save`] stored, minus whatever expired in between,
+    /// TTLs counted down by the time spent saved. Returns the number loaded.
+    pub fn hydrate(&self, db: &Db) -> usize {
+        let rows = match db.list_recursor_cache() {
+            Ok(rows) => rows,
+            Err(e) => {
+                warn!("recursor cache hydrate failed: {e}");
+                return 0;
+            }
+        };
+        let now = Utc::now();
+        let mut loaded = 0;
+        for row in rows {
+            let remaining = (row.expires_at - now).num_seconds();
+            if remaining <= 0 {
+                continue;
+            }
+            let downtime = (now - row.saved_at).num_seconds().max(0) as u32;
+            self.store(
+                CacheKey::from_query(&row.name, row.rtype, row.rclass),
+                age_response(&row.response, downtime),
+                remaining as u32,
+            );
+            loaded += 1;
+        }
+        loaded
     }
 
     pub fn len(&self) -> usize {
-        self.entries.len()
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().entries.len())
+            .sum()
     }
 
     pub fn is_empty(&self) -> bool {
-        self.entries.is_empty()
+        self.len() == 0
+    }
+
+    /// Bytes held, responses and bookkeeping together.
+    pub fn bytes(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().bytes).sum()
     }
 
     pub fn hit_count(&self) -> usize {
@@ -124,10 +544,55 @@ impl DnsCache {
         self.miss_count.load(Ordering::Relaxed)
     }
 
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.len(),
+            bytes: self.bytes(),
+            max_entries: self.config.max_entries,
+            max_bytes: self.config.max_bytes,
+            hits: self.hit_count(),
+            misses: self.miss_count(),
+            inserts: self.insert_count.load(Ordering::Relaxed),
+            evictions: self.eviction_count.load(Ordering::Relaxed),
+            expirations: self.expiration_count.load(Ordering::Relaxed),
+        }
+    }
+
     pub fn clear(&self) {
-        self.entries.clear();
+        for shard in &self.shards {
+            *shard.lock().unwrap() = Shard::default();
+        }
         self.hit_count.store(0, Ordering::Relaxed);
         self.miss_count.store(0, Ordering::Relaxed);
+        self.insert_count.store(0, Ordering::Relaxed);
+        self.eviction_count.store(0, Ordering::Relaxed);
+        self.expiration_count.store(0, Ordering::Relaxed);
+    }
+}
+
+/// `response` with every record's TTL reduced by `age` seconds. Bytes that do
+/// not parse as a message are returned as they are.
+fn age_response(response: &[u8], age: u32) -> Vec<u8> {
+    if age == 0 {
+        return response.to_vec();
+    }
+    let Ok(mut msg) = Message::from_bytes(response) else {
+        return response.to_vec();
+    };
+    map_ttls(&mut msg, |t| t.saturating_sub(age));
+    msg.to_bytes().unwrap_or_else(|_| response.to_vec())
+}
+
+/// Apply `f` to the TTL of every record in `msg`.
+fn map_ttls(msg: &mut Message, f: impl Fn(u32) -> u32) {
+    for record in msg.answers_mut() {
+        record.set_ttl(f(record.ttl()));
+    }
+    for record in msg.name_servers_mut() {
+        record.set_ttl(f(record.ttl()));
+    }
+    for record in msg.additionals_mut() {
+        record.set_ttl(f(record.ttl()));
     }
 }
 
@@ -187,7 +652,7 @@ mod tests {
             vec![2],
             300,
         );
-        // At capacity - this should be silently dropped
+        // At capacity - the least recently used entry makes room
         cache.insert(
             CacheKey::from_query("c.com", 1, 1),
             vec![3],
@@ -208,4 +673,162 @@ mod tests {
         assert_eq!(cache.len(), 0);
         assert_eq!(cache.hit_count(), 0);
     }
+
+    fn key(name: &str) -> CacheKey {
+        CacheKey::from_query(name, 1, 1)
+    }
+
+    #[test]
+    fn least_recently_used_goes_first() {
+        let cache = DnsCache::new(2);
+        cache.insert(key("a.com"), vec![1], 300);
+        cache.insert(key("b.com"), vec![2], 300);
+        assert!(cache.get(&key("a.com")).is_some());
+        cache.insert(key("c.com"), vec![3], 300);
+
+        assert!(cache.get(&key("a.com")).is_some(), "recently used, kept");
+        assert!(cache.get(&key("b.com")).is_none(), "least recently used, evicted");
+        assert!(cache.get(&key("c.com")).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn byte_budget_is_enforced() {
+        let cache = DnsCache::with_config(CacheConfig {
+            max_entries: 100,
+            max_bytes: 3 * (1000 + ENTRY_OVERHEAD + 5),
+            ..Default::default()
+        });
+        for name in ["a.com", "b.com", "c.com", "d.com"] {
+            cache.insert(key(name), vec![0; 1000], 300);
+        }
+        assert_eq!(cache.len(), 3);
+        assert!(cache.bytes() <= cache.config().max_bytes);
+        assert!(cache.get(&key("a.com")).is_none());
+
+        // Something bigger than the whole budget is not cached at all.
+        cache.insert(key("huge.com"), vec![0; 10_000], 300);
+        assert!(cache.get(&key("huge.com")).is_none());
+        assert_eq!(cache.len(), 3);
+    }
+
+    fn answer(name: &str, ttl: u32) -> Message {
+        use hickory_proto::op::{MessageType, Query};
+        use hickory_proto::rr::{Name, Record, RecordType};
+        use std::str::FromStr;
+
+        let name = Name::from_str(name).unwrap();
+        let mut msg = Message::new();
+        msg.set_message_type(MessageType::Response);
+        msg.add_query(Query::query(name.clone(), RecordType::A));
+        msg.add_answer(Record::from_rdata(
+            name,
+            ttl,
+            RData::A("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap().into()),
+        ));
+        msg
+    }
+
+    fn cached_ttl(cache: &DnsCache, name: &str) -> u32 {
+        let bytes = cache.get(&key(name)).unwrap();
+        Message::from_bytes(&bytes).unwrap().answers()[0].ttl()
+    }
+
+    #[test]
+    fn ttls_are_clamped_into_the_configured_range() {
+        let cache = DnsCache::with_config(CacheConfig {
+            min_ttl: 60,
+            max_ttl: 3600,
+            ..Default::default()
+        });
+        assert!(cache.insert_response(key("short.test"), &answer("short.test.", 5)));
+        assert!(cache.insert_response(key("long.test"), &answer("long.test.", 604_800)));
+        assert_eq!(cached_ttl(&cache, "short.test"), 60);
+        assert_eq!(cached_ttl(&cache, "long.test"), 3600);
+    }
+
+    #[test]
+    fn negative_answers_are_cached_for_the_soa_minimum_capped() {
+        use hickory_proto::rr::rdata::SOA;
+        use hickory_proto::rr::{Name, Record};
+        use std::str::FromStr;
+
+        let mut nxdomain = answer("gone.test.", 300);
+        nxdomain.take_answers();
+        nxdomain.set_response_code(ResponseCode::NXDomain);
+        let zone = Name::from_str("test.").unwrap();
+        let soa = SOA::new(zone.clone(), zone.clone(), 1, 3600, 900, 604_800, 86_400);
+        nxdomain.add_name_server(Record::from_rdata(zone, 86_400, RData::SOA(soa)));
+
+        let cache = DnsCache::with_config(CacheConfig {
+            max_negative_ttl: 900,
+            ..Default::default()
+        });
+        assert!(cache.insert_response(key("gone.test"), &nxdomain));
+        let cached = Message::from_bytes(&cache.get(&key("gone.test")).unwrap()).unwrap();
+        assert_eq!(cached.response_code(), ResponseCode::NXDomain);
+        assert_eq!(cached.name_servers()[0].ttl(), 900);
+
+        // Without an SOA there is nothing to say how long the name is gone.
+        nxdomain.take_name_servers();
+        assert!(!cache.insert_response(key("other.test"), &nxdomain));
+
+        let mut servfail = answer("broken.test.", 300);
+        servfail.set_response_code(ResponseCode::ServFail);
+        assert!(!cache.insert_response(key("broken.test"), &servfail));
+    }
+
+    #[test]
+    fn entries_can_be_listed_seeded_and_flushed_by_name() {
+        let cache = DnsCache::new(100);
+        let a = RecordData::A("192.0.2.10".parse().unwrap());
+        assert_eq!(cache.seed("App.Example.test.", 300, std::slice::from_ref(&a)), Ok(1));
+        assert_eq!(
+            cache.seed("db.example.test", 300, &[a, RecordData::TXT("v=1".into())]),
+            Ok(2)
+        );
+        cache.insert_response(key("other.test"), &answer("other.test.", 300));
+
+        let listed = cache.entries(|k| k.is_at_or_under("example.test"), 100);
+        let names: Vec<_> = listed.iter().map(|e| (e.name.as_str(), e.rtype.as_str())).collect();
+        assert_eq!(
+            names,
+            vec![("app.example.test", "A"), ("db.example.test", "A"), ("db.example.test", "TXT")]
+        );
+        assert!(listed[0].records[0].contains("192.0.2.10"));
+        assert!(listed[0].ttl_remaining <= 300);
+        assert_eq!(cache.hit_count(), 0, "looking is not a hit");
+
+        assert_eq!(cache.flush(|k| k.name == "db.example.test"), 2);
+        assert_eq!(cache.flush(|k| k.is_at_or_under("example.test")), 1);
+        assert_eq!(cache.len(), 1);
+        assert!(!key("notexample.test").is_at_or_under("example.test"));
+    }
+
+    #[test]
+    fn saved_entries_come_back_after_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+
+        let cache = DnsCache::new(100);
+        cache.insert_response(key("kept.test"), &answer("kept.test.", 300));
+        assert_eq!(cache.save(&db).unwrap(), 1);
+
+        let restarted = DnsCache::new(100);
+        assert_eq!(restarted.hydrate(&db), 1);
+        assert!(cached_ttl(&restarted, "kept.test") <= 300);
+
+        // Saving again replaces the snapshot rather than adding to it.
+        DnsCache::new(100).save(&db).unwrap();
+        assert_eq!(DnsCache::new(100).hydrate(&db), 0);
+    }
+
+    #[test]
+    fn replayed_answers_count_their_ttl_down() {
+        let msg = answer("aged.test.", 300);
+        let aged = age_response(&msg.to_bytes().unwrap(), 120);
+        assert_eq!(Message::from_bytes(&aged).unwrap().answers()[0].ttl(), 180);
+        let expired = age_response(&msg.to_bytes().unwrap(), 400);
+        assert_eq!(Message::from_bytes(&expired).unwrap().answers()[0].ttl(), 0);
+    }
 }
@@ -0,0 +1,263 @@
+//! DNS64 (RFC 6147): AAAA answers synthesized from A records, so clients on an
+//! IPv6-only segment can reach IPv4-only names through a NAT64 translator.
+//!
+//! When an AAAA query comes back without a usable AAAA record, the resolver
+//! asks for the A records instead and embeds each address in the NAT64 prefix
+//! the way RFC 6052 lays it out for that prefix length.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use hickory_proto::op::Message;
+use hickory_proto::rr::{RData, RecordType};
+use microdns_core::config::Dns64Config;
+use microdns_core::net::IpNet;
+use tracing::warn;
+
+/// The well-known NAT64 prefix, 64:ff9b::/96.
+const WELL_KNOWN_PREFIX: Ipv6Addr = Ipv6Addr::new(0x64, 0xff9b, 0, 0, 0, 0, 0, 0);
+
+/// Parsed DNS64 settings.
+#[derive(Debug, Clone)]
+pub struct Dns64 {
+    prefix: Ipv6Addr,
+    prefix_len: u8,
+    clients: Vec<IpNet>,
+    exclude_aaaa: Vec<IpNet>,
+    exclude_a: Vec<IpNet>,
+    exclude_names: Vec<String>,
+}
+
+impl Dns64 {
+    /// The settings to apply, or `None` when DNS64 is off or the prefix is
+    /// unusable (which is logged).
+    pub fn from_config(config: &Dns64Config) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let Some((prefix, prefix_len)) = parse_prefix(&config.prefix) else {
+            warn!(
+                "dns64: '{}' is not a NAT64 prefix of length 32, 40, 48, 56, 64 or 96; DNS64 disabled",
+                config.prefix
+            );
+            return None;
+        };
+        Some(Self {
+            prefix,
+            prefix_len,
+            clients: parse_nets(&config.clients, "clients"),
+            exclude_aaaa: parse_nets(&config.exclude_aaaa, "exclude_aaaa"),
+            exclude_a: parse_nets(&config.exclude_a, "exclude_a"),
+            exclude_names: config
+                .exclude_names
+                .iter()
+                .map(|n| n.trim_end_matches('.').to_lowercase())
+                .collect(),
+        })
+    }
+
+    /// Whether an AAAA query for `qname` (lowercase, no trailing dot) from
+    /// `client` is a candidate for synthesis.
+    pub fn applies_to(&self, client: IpAddr, qname: &str) -> bool {
+        if !self.clients.is_empty() && !self.clients.iter().any(|net| net.contains(client)) {
+            return false;
+        }
+        !self
+            .exclude_names
+            .iter()
+            .any(|n| qname == n || qname.ends_with(&format!(".{n}")))
+    }
+
+    /// Whether `response` to an AAAA query calls for synthesis: NOERROR with
+    /// no AAAA record outside the excluded ranges. NXDOMAIN is passed on as
+    /// it is — if there is no name there is no A record either.
+    pub fn wants_synthesis(&self, response: &Message) -> bool {
+        response.response_code() == hickory_proto::op::ResponseCode::NoError
+            && !response.answers().iter().any(|r| match r.data() {
+                Some(RData::AAAA(aaaa)) => !self
+                    .exclude_aaaa
+                    .iter()
+                    .any(|net| net.contains(IpAddr::V6(aaaa.0))),
+                _ => false,
+            })
+    }
+
+    /// `v4` embedded in the prefix (RFC 6052 §2.2), or `None` when it is
+    /// excluded — by `exclude_a`, or because the well-known prefix must not
+    /// carry non-global addresses (RFC 6052 §3.1).
+    pub fn synthesize(&self, v4: Ipv4Addr) -> Option<Ipv6Addr> {
+        if self
+            .exclude_a
+            .iter()
+            .any(|net| net.contains(IpAddr::V4(v4)))
+        {
+            return None;
+        }
+        if self.prefix == WELL_KNOWN_PREFIX && !is_global(v4) {
+            return None;
+        }
+
+        let mut octets = self.prefix.octets();
+        let mut pos = usize::from(self.prefix_len / 8);
+        octets[pos..].fill(0);
+        for byte in v4.octets() {
+            // Bits 64 to 71 are the "u" octet and must stay zero.
+            if pos == 8 {
+                pos += 1;
+            }
+            octets[pos] = byte;
+            pos += 1;
+        }
+        Some(Ipv6Addr::from(octets))
+    }
+
+    /// Turn the A answers in `a_response` into AAAA records. CNAMEs on the
+    /// way are kept; the TTL of each synthesized record is capped by
+    /// `negative_ttl`, how long the missing AAAA may be cached. `None` when
+    /// nothing could be synthesized.
+    pub fn synthesize_answers(
+        &self,
+        a_response: &Message,
+        negative_ttl: Option<u32>,
+    ) -> Option<Vec<hickory_proto::rr::Record>> {
+        let mut answers = Vec::new();
+        let mut synthesized = false;
+        for record in a_response.answers() {
+            match record.data() {
+                Some(RData::A(a)) => {
+                    let Some(v6) = self.synthesize(a.0) else {
+                        continue;
+                    };
+                    let ttl = negative_ttl.map_or(record.ttl(), |n| n.min(record.ttl()));
+                    answers.push(hickory_proto::rr::Record::from_rdata(
+                        record.name().clone(),
+                        ttl,
+                        RData::AAAA(v6.into()),
+                    ));
+                    synthesized = true;
+                }
+                _ if record.record_type() == RecordType::CNAME => answers.push(record.clone()),
+                _ => {}
+            }
+        }
+        synthesized.then_some(answers)
+    }
+
+    /// One line describing what is in force, for the log on startup.
+    pub fn summary(&self) -> String {
+        let clients = if self.clients.is_empty() {
+            "all clients".to_string()
+        } else {
+            format!("{} client CIDR(s)", self.clients.len())
+        };
+        format!(
+            "prefix {}/{} for {clients}, {} excluded name(s)",
+            self.prefix,
+            self.prefix_len,
+            self.exclude_names.len()
+        )
+    }
+}
+
+/// A NAT64 prefix: an IPv6 network of one of the lengths RFC 6052 defines.
+fn parse_prefix(s: &str) -> Option<(Ipv6Addr, u8)> {
+    let (addr, len) = s.trim().split_once('/')?;
+    let addr: Ipv6Addr = addr.parse().ok()?;
+    let len: u8 = len.parse().ok()?;
+    matches!(len, 32 | 40 | 48 | 56 | 64 | 96).then_some((addr, len))
+}
+
+/// Whether `v4` may be reached through the well-known prefix.
+fn is_global(v4: Ipv4Addr) -> bool {
+    let shared = v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64;
+    !(v4.is_private()
+        || v4.is_loopback()
+        || v4.is_link_local()
+        || v4.is_unspecified()
+        || v4.is_broadcast()
+        || shared)
+}
+
+fn parse_nets(entries: &[String], field: &str) -> Vec<IpNet> {
+    entries
+        .iter()
+        .filter_map(|c| match IpNet::parse(c) {
+            Some(net) => Some(net),
+            None => {
+                warn!("dns64: ignoring invalid {field} entry '{c}'");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dns64(prefix: &str) -> Dns64 {
+        Dns64::from_config(&Dns64Config {
+            prefix: prefix.into(),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn embeds_addresses_as_rfc_6052_lays_them_out() {
+        // RFC 6052 §2.4, for 192.0.2.33.
+        let v4: Ipv4Addr = "192.0.2.33".parse().unwrap();
+        for (prefix, expected) in [
+            ("2001:db8::/32", "2001:db8:c000:221::"),
+            ("2001:db8:100::/40", "2001:db8:1c0:2:21::"),
+            ("2001:db8:122::/48", "2001:db8:122:c000:2:2100::"),
+            ("2001:db8:122:300::/56", "2001:db8:122:3c0:0:221::"),
+            ("2001:db8:122:344::/64", "2001:db8:122:344:c0:2:2100:0"),
+            ("2001:db8:122:344::/96", "2001:db8:122:344::192.0.2.33"),
+        ] {
+            let expected: Ipv6Addr = expected.parse().unwrap();
+            assert_eq!(dns64(prefix).synthesize(v4), Some(expected), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn well_known_prefix_skips_private_addresses() {
+        let wkp = dns64("64:ff9b::/96");
+        assert_eq!(
+            wkp.synthesize("198.51.100.7".parse().unwrap()),
+            Some("64:ff9b::198.51.100.7".parse().unwrap())
+        );
+        assert_eq!(wkp.synthesize("10.1.2.3".parse().unwrap()), None);
+        assert_eq!(wkp.synthesize("100.64.0.1".parse().unwrap()), None);
+
+        // A network-specific prefix is the operator's to route as they like.
+        assert!(dns64("2001:db8:64::/96")
+            .synthesize("10.1.2.3".parse().unwrap())
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_prefixes_rfc_6052_does_not_define() {
+        assert!(parse_prefix("64:ff9b::/80").is_none());
+        assert!(parse_prefix("64:ff9b::").is_none());
+        assert!(Dns64::from_config(&Dns64Config {
+            prefix: "10.0.0.0/8".into(),
+            ..Default::default()
+        })
+        .is_none());
+    }
+
+    #[test]
+    fn clients_and_names_limit_where_it_applies() {
+        let dns64 = Dns64::from_config(&Dns64Config {
+            clients: vec!["2001:db8:10::/48".into()],
+            exclude_names: vec!["legacy.test.".into()],
+            ..Default::default()
+        })
+        .unwrap();
+        let v6only: IpAddr = "2001:db8:10::5".parse().unwrap();
+        assert!(dns64.applies_to(v6only, "www.example.test"));
+        assert!(!dns64.applies_to("192.168.1.5".parse().unwrap(), "www.example.test"));
+        assert!(!dns64.applies_to(v6only, "legacy.test"));
+        assert!(!dns64.applies_to(v6only, "app.legacy.test"));
+    }
+}
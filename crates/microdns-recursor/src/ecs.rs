@@ -0,0 +1,332 @@
+//! EDNS Client Subnet (RFC 7871): the leading bits of the client's address,
+//! sent upstream so a CDN-aware server can answer with something nearby.
+//!
+//! The upstream says in its answer how much of the subnet it looked at (the
+//! scope). An answer with scope 0 — or with no option at all — is the same
+//! for everyone and is cached as usual; any other is cached for the subnet it
+//! was asked for, and served only to clients in that subnet.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use hickory_proto::op::{Edns, Message};
+use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
+use microdns_core::config::EcsConfig;
+use tracing::warn;
+
+/// EDNS buffer size advertised when a query gains an OPT record only to carry
+/// the subnet (the DNS flag day 2020 value).
+const MAX_PAYLOAD: u16 = 1232;
+
+/// An address truncated to its leading `prefix` bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Subnet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    /// `addr` cut down to `prefix` bits, which is clamped to the family's width.
+    pub fn new(addr: IpAddr, prefix: u8) -> Self {
+        match addr {
+            IpAddr::V4(v4) => {
+                let prefix = prefix.min(32);
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                Self {
+                    addr: IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask)),
+                    prefix,
+                }
+            }
+            IpAddr::V6(v6) => {
+                let prefix = prefix.min(128);
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                Self {
+                    addr: IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask)),
+                    prefix,
+                }
+            }
+        }
+    }
+
+    /// Parse `203.0.113.0/24`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = s.trim().split_once('/')?;
+        let addr: IpAddr = addr.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        (prefix <= max).then(|| Self::new(addr, prefix))
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Parsed ECS settings.
+#[derive(Debug, Clone)]
+pub struct Ecs {
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    allow_private: bool,
+    private_subnet: Option<Subnet>,
+}
+
+impl Ecs {
+    /// The settings to apply, or `None` when ECS is off.
+    pub fn from_config(config: &EcsConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        let private_subnet = config.private_subnet.as_deref().and_then(|s| {
+            let subnet = Subnet::parse(s);
+            if subnet.is_none() {
+                warn!("ecs: ignoring invalid private_subnet '{s}'");
+            }
+            subnet
+        });
+        Some(Self {
+            ipv4_prefix: config.ipv4_prefix.min(32),
+            ipv6_prefix: config.ipv6_prefix.min(128),
+            allow_private: config.allow_private,
+            private_subnet,
+        })
+    }
+
+    /// The subnet to send upstream on behalf of `client`, or `None` when
+    /// nothing should be.
+    ///
+    /// A client that sent a subnet of its own — a forwarder speaking for
+    /// someone else — is taken at its word, but never passed on with more
+    /// bits than configured; a source prefix of 0 is its way of asking that
+    /// nothing be sent (RFC 7871 §7.1.2).
+    pub fn subnet_for(&self, request: &Message, client: IpAddr) -> Option<Subnet> {
+        let (addr, prefix) = match read(request) {
+            Some((subnet, _)) if subnet.prefix == 0 => return None,
+            Some((subnet, _)) => (subnet.addr, subnet.prefix),
+            None => (client.to_canonical(), u8::MAX),
+        };
+        if is_private(addr) {
+            if self.private_subnet.is_some() {
+                return self.private_subnet;
+            }
+            if !self.allow_private {
+                return None;
+            }
+        }
+        let max = match addr {
+            IpAddr::V4(_) => self.ipv4_prefix,
+            IpAddr::V6(_) => self.ipv6_prefix,
+        };
+        (max > 0).then(|| Subnet::new(addr, prefix.min(max)))
+    }
+
+    /// One line describing what is in force, for the log on startup.
+    pub fn summary(&self) -> String {
+        let private = match (self.private_subnet, self.allow_private) {
+            (Some(subnet), _) => format!("private clients as {subnet}"),
+            (None, true) => "private clients included".to_string(),
+            (None, false) => "private clients excluded".to_string(),
+        };
+        format!(
+            "/{} for IPv4, /{} for IPv6, {private}",
+            self.ipv4_prefix, self.ipv6_prefix
+        )
+    }
+}
+
+/// Addresses whose subnet is not sent unless configured: RFC 1918, shared
+/// address space, unique local, loopback, link-local and unspecified.
+fn is_private(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => {
+            let shared = v4.octets()[0] == 100 && (v4.octets()[1] & 0xc0) == 64;
+            v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || shared
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+/// The client subnet option in `message`, and its scope prefix.
+pub fn read(message: &Message) -> Option<(Subnet, u8)> {
+    let (addr, source, scope) = microdns_core::net::client_subnet(message)?;
+    Some((Subnet::new(addr, source), scope))
+}
+
+/// `query` carrying `subnet` as its client subnet, in place of any the client
+/// sent. A query without EDNS gains an OPT record for it.
+pub fn with_subnet(query: &[u8], subnet: Subnet) -> anyhow::Result<Vec<u8>> {
+    let mut message = Message::from_bytes(query)?;
+    let edns = message.extensions_mut().get_or_insert_with(|| {
+        let mut edns = Edns::new();
+        edns.set_max_payload(MAX_PAYLOAD);
+        edns
+    });
+    edns.options_mut()
+        .insert(EdnsOption::Subnet(ClientSubnet::new(
+            subnet.addr,
+            subnet.prefix,
+            0,
+        )));
+    Ok(message.to_bytes()?)
+}
+
+/// `query` without any client subnet option, for a client whose subnet is
+/// not to be passed on.
+pub fn without_subnet(query: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut message = Message::from_bytes(query)?;
+    if let Some(edns) = message.extensions_mut().as_mut() {
+        edns.options_mut().remove(EdnsCode::Subnet);
+    }
+    Ok(message.to_bytes()?)
+}
+
+/// `response` echoing the subnet option the client sent, `asked`, with the
+/// scope the answer holds for (RFC 7871 §7.2.2). Never wider than asked.
+pub fn echo(response: &[u8], asked: Subnet, scope: u8) -> anyhow::Result<Vec<u8>> {
+    let mut message = Message::from_bytes(response)?;
+    let edns = message.extensions_mut().get_or_insert_with(|| {
+        let mut edns = Edns::new();
+        edns.set_max_payload(MAX_PAYLOAD);
+        edns
+    });
+    edns.options_mut()
+        .insert(EdnsOption::Subnet(ClientSubnet::new(
+            asked.addr,
+            asked.prefix,
+            scope.min(asked.prefix),
+        )));
+    Ok(message.to_bytes()?)
+}
+
+/// Take the client subnet option out of an upstream answer to a query that
+/// carried `sent`, and return the scope the answer holds for: 0 when it holds
+/// for everyone, including when the upstream sent no option back.
+///
+/// `None` when the option does not echo what was sent (RFC 7871 §7.3) — such
+/// an answer must not be cached for anyone.
+pub fn take_scope(response: &mut Message, sent: Subnet) -> Option<u8> {
+    let option = read(response);
+    if let Some(edns) = response.extensions_mut().as_mut() {
+        edns.options_mut().remove(EdnsCode::Subnet);
+    }
+    match option {
+        None => Some(0),
+        Some((echoed, scope)) if echoed == sent => Some(scope.min(sent.prefix)),
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RecordType};
+    use std::str::FromStr;
+
+    fn query(client_subnet: Option<Subnet>) -> Message {
+        let mut message = Message::new();
+        message.add_query(Query::query(
+            Name::from_str("www.example.test.").unwrap(),
+            RecordType::A,
+        ));
+        if let Some(subnet) = client_subnet {
+            message =
+                Message::from_bytes(&with_subnet(&message.to_bytes().unwrap(), subnet).unwrap())
+                    .unwrap();
+        }
+        message
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn sends_the_configured_leading_bits_and_keeps_private_clients_private() {
+        let ecs = Ecs::from_config(&EcsConfig::default()).unwrap();
+        let plain = query(None);
+
+        let subnet = ecs.subnet_for(&plain, ip("198.51.100.77")).unwrap();
+        assert_eq!(subnet.to_string(), "198.51.100.0/24");
+        let subnet = ecs.subnet_for(&plain, ip("2001:db8:1234:5678::1")).unwrap();
+        assert_eq!(subnet.to_string(), "2001:db8:1234:5600::/56");
+
+        for private in ["192.168.1.10", "10.0.0.1", "100.64.1.1", "fd00::1", "::1"] {
+            assert_eq!(ecs.subnet_for(&plain, ip(private)), None, "{private}");
+        }
+
+        let ecs = Ecs::from_config(&EcsConfig {
+            private_subnet: Some("203.0.113.0/24".into()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(
+            ecs.subnet_for(&plain, ip("192.168.1.10"))
+                .unwrap()
+                .to_string(),
+            "203.0.113.0/24"
+        );
+    }
+
+    #[test]
+    fn a_client_subnet_is_narrowed_and_an_opt_out_is_honoured() {
+        let ecs = Ecs::from_config(&EcsConfig::default()).unwrap();
+        let forwarded = query(Some(Subnet::new(ip("198.51.100.77"), 32)));
+        assert_eq!(read(&forwarded).unwrap().0.to_string(), "198.51.100.77/32");
+        assert_eq!(
+            ecs.subnet_for(&forwarded, ip("10.0.0.1"))
+                .unwrap()
+                .to_string(),
+            "198.51.100.0/24"
+        );
+
+        let opted_out = query(Some(Subnet::new(ip("0.0.0.0"), 0)));
+        assert_eq!(ecs.subnet_for(&opted_out, ip("198.51.100.77")), None);
+    }
+
+    #[test]
+    fn scope_comes_from_an_echo_of_what_was_sent() {
+        let sent = Subnet::new(ip("198.51.100.0"), 24);
+        let mut answer = query(Some(sent));
+        assert_eq!(take_scope(&mut answer, sent), Some(0));
+        assert!(read(&answer).is_none(), "the option is stripped");
+
+        let mut answer = query(None);
+        assert_eq!(take_scope(&mut answer, sent), Some(0), "no option: global");
+
+        let mut scoped = query(None);
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                ip("198.51.100.0"),
+                24,
+                20,
+            )));
+        scoped.set_edns(edns);
+        assert_eq!(take_scope(&mut scoped, sent), Some(20));
+
+        let mut other = query(Some(Subnet::new(ip("203.0.113.0"), 24)));
+        assert_eq!(take_scope(&mut other, sent), None);
+    }
+}
@@ -0,0 +1,49 @@
+//! Extended DNS Errors (RFC 8914): a reason attached to an error response, so a
+//! client sees why it was refused rather than just that it was.
+
+use hickory_proto::op::{Edns, Message};
+use hickory_proto::rr::rdata::opt::EdnsOption;
+
+/// EDNS option code for an Extended DNS Error.
+pub const OPTION_CODE: u16 = 15;
+
+/// INFO-CODE: the answer was replaced by local policy.
+pub const FORGED_ANSWER: u16 = 4;
+
+/// INFO-CODE: the name is on a blocklist.
+pub const BLOCKED: u16 = 15;
+
+/// INFO-CODE: the server is configured to refuse this client.
+pub const PROHIBITED: u16 = 18;
+
+/// Attach an Extended DNS Error to `response`.
+///
+/// Only when the request carried EDNS: a client that did not send an OPT
+/// record must not get one back (RFC 6891 §7).
+pub fn attach(response: &mut Message, request: &Message, info_code: u16, text: &str) {
+    let Some(req_edns) = request.extensions() else {
+        return;
+    };
+    let mut data = info_code.to_be_bytes().to_vec();
+    data.extend_from_slice(text.as_bytes());
+
+    let mut edns = Edns::new();
+    edns.set_max_payload(req_edns.max_payload().max(512));
+    edns.options_mut()
+        .insert(EdnsOption::Unknown(OPTION_CODE, data));
+    response.set_edns(edns);
+}
+
+/// The INFO-CODE and text of the Extended DNS Error in `message`, if any.
+pub fn extract(message: &Message) -> Option<(u16, String)> {
+    let edns = message.extensions().as_ref()?;
+    let option = edns.option(OPTION_CODE.into())?;
+    let EdnsOption::Unknown(_, data) = option else {
+        return None;
+    };
+    if data.len() < 2 {
+        return None;
+    }
+    let code = u16::from_be_bytes([data[0], data[1]]);
+    Some((code, String::from_utf8_lossy(&data[2..]).into_owned()))
+}
@@ -1,6 +1,6 @@
+use crate::upstream::Upstream;
 use microdns_core::types::DnsForwarder;
 use std::collections::HashMap;
-use std::net::SocketAddr;
 
 /// Manages forward zone configuration.
 /// Maps zone names to lists of upstream DNS servers.
@@ -12,26 +12,18 @@ struct ForwardZone {
     /// Zone name (lowercase, no trailing dot)
     name: String,
     /// Upstream servers to forward to
-    servers: Vec<SocketAddr>,
+    servers: Vec<Upstream>,
 }
 
 impl ForwardTable {
-    /// Build from config map: zone_name -> [addr:port, ...]
+    /// Build from config map: zone_name -> [server, ...], each server in any
+    /// form [`Upstream::parse`] accepts.
     pub fn from_config(config: &HashMap<String, Vec<String>>) -> Self {
         let mut zones: Vec<ForwardZone> = config
             .iter()
             .filter_map(|(name, addrs)| {
-                let servers: Vec<SocketAddr> = addrs
-                    .iter()
-                    .filter_map(|a| {
-                        // Accept "host:port" or just "host" (default port 53)
-                        if a.contains(':') {
-                            a.parse().ok()
-                        } else {
-                            format!("{a}:53").parse().ok()
-                        }
-                    })
-                    .collect();
+                let servers: Vec<Upstream> =
+                    addrs.iter().filter_map(|a| Upstream::parse(a)).collect();
 
                 if servers.is_empty() {
                     tracing::warn!("forward zone {name}: no valid upstream servers");
@@ -56,16 +48,10 @@ impl ForwardTable {
         let mut zones: Vec<ForwardZone> = forwarders
             .iter()
             .filter_map(|fwd| {
-                let servers: Vec<SocketAddr> = fwd
+                let servers: Vec<Upstream> = fwd
                     .servers
                     .iter()
-                    .filter_map(|a| {
-                        if a.contains(':') {
-                            a.parse().ok()
-                        } else {
-                            format!("{a}:53").parse().ok()
-                        }
-                    })
+                    .filter_map(|a| Upstream::parse(a))
                     .collect();
                 if servers.is_empty() {
                     tracing::warn!(
@@ -86,12 +72,17 @@ impl ForwardTable {
 
     /// Find the forward servers for a given FQDN.
     /// Returns the most specific matching zone's servers.
-    pub fn lookup(&self, fqdn: &str) -> Option<&[SocketAddr]> {
+    pub fn lookup(&self, fqdn: &str) -> Option<&[Upstream]> {
+        self.find(fqdn).map(|(_, servers)| servers)
+    }
+
+    /// The most specific matching zone's name and servers.
+    pub fn find(&self, fqdn: &str) -> Option<(&str, &[Upstream])> {
         let fqdn = fqdn.trim_end_matches('.').to_lowercase();
 
         for fz in &self.zones {
             if fqdn == fz.name || fqdn.ends_with(&format!(".{}", fz.name)) {
-                return Some(&fz.servers);
+                return Some((&fz.name, &fz.servers));
             }
         }
 
@@ -147,6 +138,24 @@ mod tests {
         assert_eq!(servers[0].to_string(), "10.0.1.1:53");
     }
 
+    #[test]
+    fn test_forward_encrypted_servers() {
+        let mut config = HashMap::new();
+        config.insert(
+            "corp.local".to_string(),
+            vec![
+                "tls://10.0.1.1#dns.corp.local".to_string(),
+                "https://dns.corp.local/dns-query".to_string(),
+                "bogus://10.0.1.2".to_string(),
+            ],
+        );
+
+        let table = ForwardTable::from_config(&config);
+        let servers = table.lookup("host.corp.local").unwrap();
+        assert_eq!(servers.len(), 2);
+        assert!(servers.iter().all(|s| s.is_encrypted()));
+    }
+
     #[test]
     fn test_forward_empty() {
         let config = HashMap::new();
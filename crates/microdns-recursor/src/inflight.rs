@@ -0,0 +1,155 @@
+use crate::cache::CacheKey;
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+
+/// Deduplicates concurrent upstream lookups for the same question.
+///
+/// The first query for a key becomes the leader and goes upstream; anyone
+/// asking the same thing while it is outstanding waits for the leader's answer
+/// instead of sending their own. When every pod in a deployment restarts at
+/// once, that is one upstream query per name rather than one per pod.
+pub struct InFlight {
+    pending: DashMap<CacheKey, broadcast::Sender<Arc<Vec<u8>>>>,
+    coalesced: AtomicUsize,
+}
+
+/// What a caller got when it joined a lookup.
+pub enum Join<'a> {
+    /// Nobody was asking yet: do the lookup and hand the answer to `complete`.
+    Leader(Lead<'a>),
+    /// Someone else is already asking: wait for their answer.
+    Follower(broadcast::Receiver<Arc<Vec<u8>>>),
+}
+
+/// The leader's obligation to publish an answer. Dropping it without calling
+/// [`Lead::complete`] (the task was cancelled, or the lookup errored out)
+/// releases the key, and waiters see the channel close and ask for themselves.
+pub struct Lead<'a> {
+    inflight: &'a InFlight,
+    key: CacheKey,
+    tx: broadcast::Sender<Arc<Vec<u8>>>,
+    done: bool,
+}
+
+impl InFlight {
+    pub fn new() -> Self {
+        Self {
+            pending: DashMap::new(),
+            coalesced: AtomicUsize::new(0),
+        }
+    }
+
+    /// Join the lookup for `key`, becoming its leader if there is none.
+    pub fn join(&self, key: &CacheKey) -> Join<'_> {
+        match self.pending.entry(key.clone()) {
+            Entry::Occupied(e) => {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                Join::Follower(e.get().subscribe())
+            }
+            Entry::Vacant(e) => {
+                let (tx, _) = broadcast::channel(1);
+                e.insert(tx.clone());
+                Join::Leader(Lead {
+                    inflight: self,
+                    key: key.clone(),
+                    tx,
+                    done: false,
+                })
+            }
+        }
+    }
+
+    /// Number of lookups currently outstanding upstream.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Queries answered by piggybacking on another query's upstream lookup.
+    pub fn coalesced_count(&self) -> usize {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for InFlight {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lead<'_> {
+    /// Publish the upstream response to everyone waiting on this key.
+    pub fn complete(mut self, response: &[u8]) {
+        self.done = true;
+        // Remove first, so a query arriving after this point starts a fresh
+        // lookup (or, more likely, hits the cache) instead of subscribing to a
+        // channel that has already fired.
+        self.inflight.pending.remove(&self.key);
+        let _ = self.tx.send(Arc::new(response.to_vec()));
+    }
+}
+
+impl Drop for Lead<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            self.inflight.pending.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> CacheKey {
+        CacheKey::from_query(name, 1, 1)
+    }
+
+    #[tokio::test]
+    async fn followers_receive_the_leaders_answer() {
+        let inflight = InFlight::new();
+        let lead = match inflight.join(&key("example.com")) {
+            Join::Leader(l) => l,
+            Join::Follower(_) => panic!("first caller must lead"),
+        };
+        let mut rx = match inflight.join(&key("EXAMPLE.com")) {
+            Join::Follower(rx) => rx,
+            Join::Leader(_) => panic!("second caller must follow"),
+        };
+        assert_eq!(inflight.coalesced_count(), 1);
+
+        lead.complete(&[1, 2, 3]);
+        assert_eq!(*rx.recv().await.unwrap(), vec![1, 2, 3]);
+        assert!(inflight.is_empty());
+    }
+
+    #[tokio::test]
+    async fn an_abandoned_lead_releases_its_followers() {
+        let inflight = InFlight::new();
+        let lead = inflight.join(&key("example.com"));
+        let mut rx = match inflight.join(&key("example.com")) {
+            Join::Follower(rx) => rx,
+            Join::Leader(_) => panic!("second caller must follow"),
+        };
+
+        drop(lead);
+        assert!(rx.recv().await.is_err());
+        assert!(matches!(inflight.join(&key("example.com")), Join::Leader(_)));
+    }
+
+    #[test]
+    fn different_questions_do_not_coalesce() {
+        let inflight = InFlight::new();
+        let _a = inflight.join(&key("a.example.com"));
+        let b = inflight.join(&key("b.example.com"));
+        assert!(matches!(b, Join::Leader(_)));
+        assert_eq!(inflight.len(), 2);
+        assert_eq!(inflight.coalesced_count(), 0);
+    }
+}
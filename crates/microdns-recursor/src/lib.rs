@@ -1,15 +1,31 @@
+pub mod access;
 pub mod cache;
+pub mod dns64;
+pub mod ecs;
+pub mod ede;
 pub mod forward;
+pub mod inflight;
+pub mod local_data;
 pub mod resolver;
+pub mod rpz;
+pub mod tls;
+pub mod transport;
+pub mod upstream;
 
-use cache::DnsCache;
+use access::AccessState;
+use cache::{CacheConfig, DnsCache};
+use dns64::Dns64;
+use ecs::Ecs;
 use forward::ForwardTable;
 use microdns_core::config::DnsRecursorConfig;
 use microdns_core::db::Db;
+use microdns_core::dnstap::{self, Dnstap, Protocol};
+use microdns_core::metrics::record_dns_query;
 use resolver::Resolver;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use transport::TransportConfig;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, UdpSocket};
 use tokio::sync::{watch, Semaphore};
@@ -26,21 +42,40 @@ const TCP_TIMEOUT: Duration = Duration::from_secs(30);
 
 pub struct RecursorServer {
     listen_addr: SocketAddr,
-    resolver: Arc<Resolver>,
+    resolver: Resolver,
+    /// Where client queries and answers are logged as dnstap, when they are.
+    dnstap: Option<Arc<Dnstap>>,
 }
 
 impl RecursorServer {
     pub fn new(config: &DnsRecursorConfig, db: Option<Db>) -> anyhow::Result<Self> {
         let listen_addr: SocketAddr = config.listen.parse()?;
 
-        let cache = Arc::new(DnsCache::new(config.cache_size));
+        let cache = Arc::new(DnsCache::with_config(CacheConfig::from_config(config)));
         let forward_table = Arc::new(ForwardTable::from_config(&config.forward_zones));
 
-        let resolver = Arc::new(Resolver::new(cache, forward_table, db));
+        let resolver = Resolver::new(cache, forward_table, db)
+                .with_transport(TransportConfig::from_config(config))
+                .with_upstream_race(config.upstream_race)
+                .with_qname_minimisation(config.upstream_qname_minimisation)
+                .with_access(AccessState::new(
+                    &microdns_core::config::RecursionAccessConfig::from_recursor(config),
+                ))
+                .with_dns64(config.dns64.as_ref().and_then(|c| {
+                    let dns64 = Dns64::from_config(c)?;
+                    info!("dns64: {}", dns64.summary());
+                    Some(dns64)
+                }))
+                .with_ecs(config.ecs.as_ref().and_then(|c| {
+                    let ecs = Ecs::from_config(c)?;
+                    info!("ecs: {}", ecs.summary());
+                    Some(ecs)
+                }));
 
         Ok(Self {
             listen_addr,
             resolver,
+            dnstap: None,
         })
     }
 
@@ -51,14 +86,29 @@ impl RecursorServer {
         // Empty static table — resolver reads forwarders from DB directly
         let forward_table = Arc::new(ForwardTable::from_config(&Default::default()));
 
-        let resolver = Arc::new(Resolver::new(cache, forward_table, Some(db)));
+        let resolver = Resolver::new(cache, forward_table, Some(db));
 
         Ok(Self {
             listen_addr,
             resolver,
+            dnstap: None,
         })
     }
 
+    /// Steer load-balanced groups in local zones by the shared topology rules.
+    pub fn with_topology(mut self, topology: microdns_core::topology::TopologyState) -> Self {
+        self.resolver = self.resolver.with_topology(topology);
+        self
+    }
+
+    /// Log client queries and answers as `CLIENT_QUERY` and `CLIENT_RESPONSE`,
+    /// and what is sent upstream as `FORWARDER_QUERY` and `FORWARDER_RESPONSE`.
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.resolver = self.resolver.with_dnstap(dnstap.clone());
+        self.dnstap = Some(dnstap);
+        self
+    }
+
     pub async fn run(self, shutdown: watch::Receiver<bool>) -> anyhow::Result<()> {
         let socket = Arc::new(UdpSocket::bind(self.listen_addr).await?);
         let tcp_listener = TcpListener::bind(self.listen_addr).await?;
@@ -71,7 +121,9 @@ impl RecursorServer {
         let mut shutdown_udp = shutdown.clone();
         let mut shutdown_tcp = shutdown;
 
-        let resolver_tcp = self.resolver.clone();
+        let resolver = Arc::new(self.resolver);
+        let resolver_tcp = resolver.clone();
+        let dnstap_tcp = self.dnstap.clone();
 
         // TCP accept loop with connection limit
         let tcp_semaphore = Arc::new(Semaphore::new(MAX_TCP_CONNECTIONS));
@@ -90,10 +142,11 @@ impl RecursorServer {
                                 };
                                 debug!("recursor TCP connection from {src}");
                                 let resolver = resolver_tcp.clone();
+                                let dnstap = dnstap_tcp.clone();
                                 tokio::spawn(async move {
                                     let result = tokio::time::timeout(
                                         TCP_TIMEOUT,
-                                        handle_tcp_query(stream, &resolver),
+                                        handle_tcp_query(stream, src, &resolver, dnstap.as_deref()),
                                     ).await;
                                     match result {
                                         Ok(Err(e)) => warn!("recursor TCP handler error from {src}: {e}"),
@@ -124,8 +177,9 @@ impl RecursorServer {
                 result = socket.recv_from(&mut buf) => {
                     let (len, src) = result?;
                     let data = buf[..len].to_vec();
-                    let resolver = self.resolver.clone();
+                    let resolver = resolver.clone();
                     let socket = socket.clone();
+                    let dnstap = self.dnstap.clone();
 
                     let permit = match udp_semaphore.clone().try_acquire_owned() {
                         Ok(p) => p,
@@ -137,8 +191,19 @@ impl RecursorServer {
 
                     // Spawn a task per query for concurrency
                     tokio::spawn(async move {
-                        match resolver.resolve(&data).await {
+                        let started = Instant::now();
+                        let tapped = dnstap.as_deref().filter(|d| d.sample(&data));
+                        if let Some(dnstap) = tapped {
+                            let kind = dnstap::MessageType::ClientQuery;
+                            dnstap.log(kind, Protocol::Udp, Some(src), &data);
+                        }
+                        match resolver.resolve(&data, src.ip()).await {
                             Ok(response) => {
+                                record_dns_query("recursor", None, &data, &response, started.elapsed());
+                                if let Some(dnstap) = tapped {
+                                    let kind = dnstap::MessageType::ClientResponse;
+                                    dnstap.log(kind, Protocol::Udp, Some(src), &response);
+                                }
                                 if let Err(e) = socket.send_to(&response, src).await {
                                     error!("failed to send response to {src}: {e}");
                                 }
@@ -170,7 +235,9 @@ impl RecursorServer {
 
 async fn handle_tcp_query(
     mut stream: tokio::net::TcpStream,
+    src: SocketAddr,
     resolver: &Resolver,
+    dnstap: Option<&Dnstap>,
 ) -> anyhow::Result<()> {
     // DNS over TCP: 2-byte length prefix, then DNS message
     let msg_len = stream.read_u16().await? as usize;
@@ -181,7 +248,16 @@ async fn handle_tcp_query(
     let mut buf = vec![0u8; msg_len];
     stream.read_exact(&mut buf).await?;
 
-    let response = resolver.resolve(&buf).await?;
+    let started = Instant::now();
+    let tapped = dnstap.filter(|d| d.sample(&buf));
+    if let Some(dnstap) = tapped {
+        dnstap.log(dnstap::MessageType::ClientQuery, Protocol::Tcp, Some(src), &buf);
+    }
+    let response = resolver.resolve(&buf, src.ip()).await?;
+    record_dns_query("recursor", None, &buf, &response, started.elapsed());
+    if let Some(dnstap) = tapped {
+        dnstap.log(dnstap::MessageType::ClientResponse, Protocol::Tcp, Some(src), &response);
+    }
     let len = response.len() as u16;
     stream.write_all(&len.to_be_bytes()).await?;
     stream.write_all(&response).await?;
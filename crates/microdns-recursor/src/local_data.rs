@@ -0,0 +1,466 @@
+//! Local data: names the recursor answers itself, without a zone to hold them.
+//!
+//! Two sources feed it — per-name overrides stored with the recursor settings,
+//! and files in `/etc/hosts` format — and both are compiled into one table that
+//! a watcher swaps in whole, like the response policy. An override wins over a
+//! hosts file entry for the same name; among hosts files, the first listed
+//! that has a name wins.
+//!
+//! A hosts file is re-read when its modification time or size changes, so
+//! editing it takes effect on the next refresh without touching the API.
+//! Which files may be read is limited by a [`HostsPolicy`], since the list can
+//! be written through the API.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use microdns_core::config::LocalDataConfig;
+use microdns_core::types::{RecordData, RecordSource};
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// TTL of the records read from a hosts file.
+pub const HOSTS_TTL: u32 = 300;
+
+/// One record served from local data.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LocalRecord {
+    pub name: String,
+    pub ttl: u32,
+    #[serde(flatten)]
+    pub data: RecordData,
+    /// Always [`RecordSource::Local`], so these read like any other record
+    /// in the API.
+    pub source: RecordSource,
+    /// The hosts file the record was read from; `None` for an override.
+    pub origin: Option<String>,
+}
+
+/// Every local name, compiled.
+#[derive(Debug, Default)]
+pub struct LocalData {
+    /// Lowercase names without the trailing dot.
+    names: HashMap<String, Vec<LocalRecord>>,
+}
+
+impl LocalData {
+    /// Whether there is nothing to answer, so the answer path can skip it.
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// The records at `qname` (lowercase, no trailing dot), if it is a local
+    /// name at all.
+    pub fn lookup(&self, qname: &str) -> Option<&[LocalRecord]> {
+        self.names.get(qname).map(Vec::as_slice)
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.values().map(Vec::len).sum()
+    }
+
+    /// Every record, sorted by name, for the API.
+    pub fn records(&self) -> Vec<LocalRecord> {
+        let mut records: Vec<_> = self.names.values().flatten().cloned().collect();
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        records
+    }
+
+    fn compile(config: &LocalDataConfig, files: &[(String, Vec<(String, RecordData)>)]) -> Self {
+        let mut names: HashMap<String, Vec<LocalRecord>> = HashMap::new();
+        for record in &config.records {
+            let name = normalize(&record.name);
+            names.entry(name.clone()).or_default().push(LocalRecord {
+                name,
+                ttl: record.ttl,
+                data: record.data.clone(),
+                source: RecordSource::Local,
+                origin: None,
+            });
+        }
+
+        let overridden: std::collections::HashSet<String> = names.keys().cloned().collect();
+        let mut claimed: HashMap<String, &str> = HashMap::new();
+        for (path, entries) in files {
+            for (name, data) in entries {
+                if overridden.contains(name) {
+                    continue;
+                }
+                if *claimed.entry(name.clone()).or_insert(path.as_str()) != path.as_str() {
+                    continue;
+                }
+                let records = names.entry(name.clone()).or_default();
+                if records.iter().any(|r| r.data == *data) {
+                    continue;
+                }
+                records.push(LocalRecord {
+                    name: name.clone(),
+                    ttl: HOSTS_TTL,
+                    data: data.clone(),
+                    source: RecordSource::Local,
+                    origin: Some(path.clone()),
+                });
+            }
+        }
+        Self { names }
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// The records a hosts file implies: an A or AAAA record for each name on a
+/// line, and a PTR record from each address to the first name given for it.
+///
+/// Comments, malformed lines and link-local addresses with a zone (`fe80::1%eth0`)
+/// are skipped.
+pub fn parse_hosts(text: &str) -> Vec<(String, RecordData)> {
+    let mut records = Vec::new();
+    let mut reversed = std::collections::HashSet::new();
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        let mut tokens = line.split_whitespace();
+        let Some(Ok(addr)) = tokens.next().map(str::parse::<IpAddr>) else {
+            continue;
+        };
+        let mut first = true;
+        for token in tokens {
+            let name = normalize(token);
+            if !is_hostname(&name) {
+                continue;
+            }
+            records.push((
+                name.clone(),
+                match addr {
+                    IpAddr::V4(v4) => RecordData::A(v4),
+                    IpAddr::V6(v6) => RecordData::AAAA(v6),
+                },
+            ));
+            if first && reversed.insert(addr) {
+                records.push((reverse_name(addr), RecordData::PTR(format!("{name}."))));
+            }
+            first = false;
+        }
+    }
+    records
+}
+
+fn is_hostname(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+/// The `in-addr.arpa` or `ip6.arpa` name of an address, without the trailing dot.
+fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{d}.{c}.{b}.{a}.in-addr.arpa")
+        }
+        IpAddr::V6(v6) => {
+            let mut labels: Vec<String> = v6
+                .octets()
+                .iter()
+                .flat_map(|byte| [byte >> 4, byte & 0x0f])
+                .map(|nibble| format!("{nibble:x}"))
+                .collect();
+            labels.reverse();
+            format!("{}.ip6.arpa", labels.join("."))
+        }
+    }
+}
+
+/// Which hosts files may be read: those under one directory, and those the
+/// config file names itself.
+#[derive(Debug, Clone, Default)]
+pub struct HostsPolicy {
+    dir: PathBuf,
+    listed: Vec<String>,
+}
+
+impl HostsPolicy {
+    pub fn new(dir: PathBuf, listed: Vec<String>) -> Self {
+        Self { dir, listed }
+    }
+
+    /// Whether `path` may be read, and if not, why.
+    pub fn check(&self, path: &str) -> Result<(), String> {
+        if self.listed.iter().any(|listed| listed == path) {
+            return Ok(());
+        }
+        let file = Path::new(path);
+        if !file.is_absolute() {
+            return Err(format!("hosts file '{path}' must be an absolute path"));
+        }
+        let outside = || {
+            Err(format!(
+                "hosts file '{path}' must be under {}",
+                self.dir.display()
+            ))
+        };
+        if file.components().any(|c| c == Component::ParentDir) || !file.starts_with(&self.dir) {
+            return outside();
+        }
+        // A symlink inside the directory must not lead out of it.
+        if let (Ok(real), Ok(dir)) = (file.canonicalize(), self.dir.canonicalize()) {
+            if !real.starts_with(dir) {
+                return outside();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What was last read from one hosts file.
+struct HostsFile {
+    /// Modification time and size when read; `None` when it could not be.
+    stamp: Option<(SystemTime, u64)>,
+    entries: Vec<(String, RecordData)>,
+    /// Left unread because the [`HostsPolicy`] does not allow it.
+    refused: bool,
+}
+
+fn stamp(path: &str) -> Option<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+/// Shared handle to the local data in force.
+#[derive(Clone, Default)]
+pub struct LocalDataState {
+    inner: Arc<Mutex<Arc<LocalData>>>,
+    config: Arc<Mutex<LocalDataConfig>>,
+    files: Arc<Mutex<HashMap<String, HostsFile>>>,
+    policy: Arc<Mutex<Option<HostsPolicy>>>,
+}
+
+impl LocalDataState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read only the hosts files `policy` allows from now on. Without one,
+    /// any file listed is read.
+    pub fn set_hosts_policy(&self, policy: HostsPolicy) {
+        *self.policy.lock().unwrap() = Some(policy);
+    }
+
+    /// The policy hosts files are held to, if one is set.
+    pub fn hosts_policy(&self) -> Option<HostsPolicy> {
+        self.policy.lock().unwrap().clone()
+    }
+
+    /// The local data to answer one query from.
+    pub fn current(&self) -> Arc<LocalData> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// Recompile if the settings changed or a hosts file did. Returns whether
+    /// anything changed.
+    pub fn refresh(&self, config: &LocalDataConfig) -> bool {
+        let mut changed = *self.config.lock().unwrap() != *config;
+
+        let policy = self.hosts_policy();
+        let mut files = self.files.lock().unwrap();
+        files.retain(|path, _| config.hosts_files.contains(path));
+        for path in &config.hosts_files {
+            if let Some(Err(e)) = policy.as_ref().map(|p| p.check(path)) {
+                if !files.get(path).is_some_and(|f| f.refused) {
+                    warn!("local data: not reading {e}");
+                    files.insert(
+                        path.clone(),
+                        HostsFile {
+                            stamp: None,
+                            entries: Vec::new(),
+                            refused: true,
+                        },
+                    );
+                    changed = true;
+                }
+                continue;
+            }
+            let now = stamp(path);
+            if files.get(path).is_some_and(|f| !f.refused && f.stamp == now) {
+                continue;
+            }
+            let entries = match std::fs::read_to_string(path) {
+                Ok(text) => {
+                    let entries = parse_hosts(&text);
+                    info!("local data: read {} record(s) from {path}", entries.len());
+                    entries
+                }
+                Err(e) => {
+                    warn!("local data: could not read hosts file {path}: {e}");
+                    Vec::new()
+                }
+            };
+            files.insert(
+                path.clone(),
+                HostsFile {
+                    stamp: now,
+                    entries,
+                    refused: false,
+                },
+            );
+            changed = true;
+        }
+
+        if changed {
+            let ordered: Vec<_> = config
+                .hosts_files
+                .iter()
+                .filter_map(|p| files.get(p).map(|f| (p.clone(), f.entries.clone())))
+                .collect();
+            *self.inner.lock().unwrap() = Arc::new(LocalData::compile(config, &ordered));
+            *self.config.lock().unwrap() = config.clone();
+        }
+        changed
+    }
+
+    /// One line describing what is in force, for the log on startup or change.
+    pub fn summary(&self) -> String {
+        let data = self.current();
+        let config = self.config.lock().unwrap();
+        format!(
+            "{} record(s) from {} override(s) and {} hosts file(s)",
+            data.len(),
+            config.records.len(),
+            config.hosts_files.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use microdns_core::config::LocalDataRecord;
+
+    #[test]
+    fn hosts_files_give_addresses_and_pointers_back() {
+        let records = parse_hosts(
+            "# static hosts\n\
+             127.0.0.1   localhost\n\
+             192.168.1.40  printer.corp.example printer   # office\n\
+             2001:db8::40  printer.corp.example\n\
+             fe80::1%eth0  router\n\
+             not-an-address  nothing\n",
+        );
+        assert!(records.contains(&(
+            "printer.corp.example".into(),
+            RecordData::A("192.168.1.40".parse().unwrap())
+        )));
+        assert!(records.contains(&(
+            "printer".into(),
+            RecordData::A("192.168.1.40".parse().unwrap())
+        )));
+        assert!(records.contains(&(
+            "40.1.168.192.in-addr.arpa".into(),
+            RecordData::PTR("printer.corp.example.".into())
+        )));
+        assert!(records.contains(&(
+            "0.4.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa".into(),
+            RecordData::PTR("printer.corp.example.".into())
+        )));
+        assert!(!records
+            .iter()
+            .any(|(name, _)| name == "router" || name == "nothing"));
+    }
+
+    #[test]
+    fn overrides_win_and_hosts_files_reload_when_edited() {
+        let dir = tempfile::tempdir().unwrap();
+        let hosts = dir.path().join("hosts");
+        std::fs::write(
+            &hosts,
+            "10.0.0.5 nas.corp.example\n10.0.0.6 printer.corp.example\n",
+        )
+        .unwrap();
+        let hosts = hosts.to_string_lossy().to_string();
+
+        let config = LocalDataConfig {
+            records: vec![LocalDataRecord {
+                name: "Printer.Corp.Example.".into(),
+                ttl: 60,
+                data: RecordData::A("192.168.1.40".parse().unwrap()),
+            }],
+            hosts_files: vec![hosts.clone()],
+        };
+        let state = LocalDataState::new();
+        assert!(state.refresh(&config));
+        assert!(!state.refresh(&config), "nothing changed");
+
+        let data = state.current();
+        let printer = data.lookup("printer.corp.example").unwrap();
+        assert_eq!(printer.len(), 1);
+        assert_eq!(
+            printer[0].data,
+            RecordData::A("192.168.1.40".parse().unwrap())
+        );
+        assert_eq!(printer[0].origin, None);
+        let nas = data.lookup("nas.corp.example").unwrap();
+        assert_eq!(nas[0].origin.as_deref(), Some(hosts.as_str()));
+        assert_eq!(nas[0].source, RecordSource::Local);
+
+        std::fs::write(&hosts, "10.0.0.7 nas.corp.example backup.corp.example\n").unwrap();
+        assert!(state.refresh(&config));
+        let data = state.current();
+        assert_eq!(
+            data.lookup("nas.corp.example").unwrap()[0].data,
+            RecordData::A("10.0.0.7".parse().unwrap())
+        );
+        assert!(data.lookup("backup.corp.example").is_some());
+    }
+
+    #[test]
+    fn hosts_files_outside_the_allowed_directory_are_not_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let allowed = dir.path().join("hosts.d");
+        std::fs::create_dir(&allowed).unwrap();
+        let inside = allowed.join("lab");
+        std::fs::write(&inside, "10.0.0.5 nas.corp.example\n").unwrap();
+        let outside = dir.path().join("secret");
+        std::fs::write(&outside, "10.0.0.9 secret.corp.example\n").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&outside, allowed.join("link")).unwrap();
+
+        let path = |p: &Path| p.to_string_lossy().to_string();
+        let policy = HostsPolicy::new(allowed.clone(), vec!["/etc/hosts".into()]);
+        assert!(policy.check(&path(&inside)).is_ok());
+        assert!(policy.check("/etc/hosts").is_ok(), "named in the config file");
+        assert!(policy.check(&path(&allowed.join("new"))).is_ok(), "need not exist yet");
+        for refused in [
+            path(&outside),
+            path(&allowed.join("..").join("secret")),
+            "hosts.d/lab".into(),
+            "/etc/shadow".into(),
+        ] {
+            assert!(policy.check(&refused).is_err(), "{refused}");
+        }
+        #[cfg(unix)]
+        assert!(policy.check(&path(&allowed.join("link"))).is_err());
+
+        let state = LocalDataState::new();
+        state.set_hosts_policy(policy);
+        let config = LocalDataConfig {
+            records: Vec::new(),
+            hosts_files: vec![path(&inside), path(&outside)],
+        };
+        assert!(state.refresh(&config));
+        assert!(!state.refresh(&config), "a refused file is not retried");
+        let data = state.current();
+        assert!(data.lookup("nas.corp.example").is_some());
+        assert!(data.lookup("secret.corp.example").is_none());
+    }
+}
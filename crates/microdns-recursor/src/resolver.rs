@@ -1,16 +1,28 @@
-use crate::cache::{self, CacheKey, DnsCache};
+use crate::access::AccessState;
+use crate::cache::{CacheKey, DnsCache};
+use crate::dns64::Dns64;
+use crate::ecs::{self, Ecs};
+use crate::ede;
 use crate::forward::ForwardTable;
-use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
-use hickory_proto::rr::{LowerName, Name, RecordType};
+use crate::inflight::{InFlight, Join};
+use crate::local_data::{LocalDataState, LocalRecord, HOSTS_TTL};
+use crate::rpz::{self, RpzState};
+use crate::transport::{Transport, TransportConfig};
+use crate::upstream::{Upstream, UpstreamTracker};
+use hickory_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::rr::{DNSClass, LowerName, Name, RData, Record, RecordType};
 use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
 use microdns_core::db::Db;
-use std::net::SocketAddr;
+use microdns_core::dnstap::{self, Dnstap, Protocol};
+use microdns_core::zone_index::CompiledZone;
+use microdns_core::topology::{self, TopologyState};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use tokio::net::UdpSocket;
+use std::time::Instant;
 use tracing::{debug, warn};
 
 /// The recursive resolver. Handles incoming queries by:
-/// 1. Checking local authoritative zones (if db is provided)
+/// 1. Checking local data, then local authoritative zones (if db is provided)
 /// 2. Checking the cache
 /// 3. Forwarding to upstream servers (forward zones or general recursion)
 pub struct Resolver {
@@ -19,9 +31,41 @@ pub struct Resolver {
     forward_table: Arc<ForwardTable>,
     db: Option<Db>,
     /// Upstream resolvers for general recursion (e.g., 8.8.8.8, 1.1.1.1)
-    upstream: Vec<SocketAddr>,
+    upstream: Vec<Upstream>,
+    /// Upstream lookups in progress, so identical concurrent queries share one
+    inflight: InFlight,
+    /// How queries reach upstream servers
+    transport: Transport,
+    /// Latency and failure history per upstream, used to pick which to ask
+    upstreams: Arc<UpstreamTracker>,
+    /// Ask the two best upstreams at once and take whichever answers first
+    race_upstreams: bool,
+    /// Walk forward zones one label at a time (RFC 9156 QNAME minimisation)
+    qname_minimisation: bool,
+    /// Which clients may recurse, and whether the rest still get local zones
+    access: AccessState,
+    /// Response policy zones: names and answers to block or rewrite
+    rpz: RpzState,
+    /// AAAA synthesis for IPv6-only clients behind NAT64
+    dns64: Option<Dns64>,
+    /// Per-name overrides and hosts file entries, answered before anything else
+    local_data: LocalDataState,
+    /// EDNS Client Subnet to send upstream, and cache answers by
+    ecs: Option<Ecs>,
+    /// Which members of load-balanced groups in local zones each client prefers
+    topology: TopologyState,
+    /// Where upstream queries and answers are logged as dnstap, when they are
+    dnstap: Option<Arc<Dnstap>>,
 }
 
+/// How many policy rewrites one query may go through — a local-data CNAME
+/// whose target is itself rewritten, and so on — before the chain is cut.
+const MAX_POLICY_DEPTH: u8 = 8;
+
+/// Most ancestors of a name asked for one at a time before the name itself
+/// goes out (RFC 9156 §2.3's MAX_MINIMISE_COUNT). Deeper names skip the rest.
+const MAX_MINIMISE_COUNT: usize = 10;
+
 impl Resolver {
     pub fn new(
         cache: Arc<DnsCache>,
@@ -29,47 +73,145 @@ impl Resolver {
         db: Option<Db>,
     ) -> Self {
         // Default upstream resolvers
-        let upstream = vec![
-            "8.8.8.8:53".parse().unwrap(),
-            "8.8.4.4:53".parse().unwrap(),
-            "1.1.1.1:53".parse().unwrap(),
-        ];
+        let upstream = ["8.8.8.8:53", "8.8.4.4:53", "1.1.1.1:53"]
+            .iter()
+            .filter_map(|s| Upstream::parse(s))
+            .collect();
 
         Self {
             cache,
             forward_table,
             db,
             upstream,
+            inflight: InFlight::new(),
+            transport: Transport::new(TransportConfig::default()),
+            upstreams: Arc::new(UpstreamTracker::new()),
+            race_upstreams: false,
+            qname_minimisation: true,
+            access: AccessState::default(),
+            rpz: RpzState::new(),
+            dns64: None,
+            local_data: LocalDataState::new(),
+            ecs: None,
+            topology: TopologyState::default(),
+            dnstap: None,
         }
     }
 
-    /// Find forward servers for a FQDN. Checks database first (live data),
-    /// falls back to static config forward table.
-    fn find_forward_servers(&self, qname: &str) -> Option<Vec<SocketAddr>> {
+    /// Use a different upstream transport (timeouts, retries, TCP).
+    pub fn with_transport(mut self, config: TransportConfig) -> Self {
+        self.transport = Transport::new(config);
+        self
+    }
+
+    /// Send each query to the two preferred upstreams in parallel.
+    pub fn with_upstream_race(mut self, race: bool) -> Self {
+        self.race_upstreams = race;
+        self
+    }
+
+    /// Send forward-zone servers minimised query names (RFC 9156). On by
+    /// default; single servers opt out with `?no-qmin`.
+    pub fn with_qname_minimisation(mut self, minimise: bool) -> Self {
+        self.qname_minimisation = minimise;
+        self
+    }
+
+    /// Enforce a recursion ACL shared with whatever keeps it current.
+    pub fn with_access(mut self, access: AccessState) -> Self {
+        self.access = access;
+        self
+    }
+
+    /// Apply response policy zones kept current by whoever holds the handle.
+    pub fn with_rpz(mut self, rpz: RpzState) -> Self {
+        self.rpz = rpz;
+        self
+    }
+
+    /// Answer names from local data kept current by whoever holds the handle.
+    pub fn with_local_data(mut self, local_data: LocalDataState) -> Self {
+        self.local_data = local_data;
+        self
+    }
+
+    /// Send the client's subnet upstream, and cache answers per subnet when
+    /// the upstream says they differ by it.
+    pub fn with_ecs(mut self, ecs: Option<Ecs>) -> Self {
+        self.ecs = ecs;
+        self
+    }
+
+    /// Steer load-balanced groups in local zones by topology rules kept
+    /// current by whoever holds the handle.
+    pub fn with_topology(mut self, topology: TopologyState) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    /// Synthesize AAAA answers from A records for IPv6-only clients.
+    pub fn with_dns64(mut self, dns64: Option<Dns64>) -> Self {
+        self.dns64 = dns64;
+        self
+    }
+
+    /// Log queries sent upstream and the answers to them as dnstap.
+    pub fn with_dnstap(mut self, dnstap: Arc<Dnstap>) -> Self {
+        self.dnstap = Some(dnstap);
+        self
+    }
+
+    /// Find the forward zone for a FQDN, and its servers. Checks database
+    /// first (live data), falls back to static config forward table.
+    fn find_forward_zone(&self, qname: &str) -> Option<(String, Vec<Upstream>)> {
         // Database forwarders take priority (always live, no reload needed)
         if let Some(ref db) = self.db {
-            if let Some(servers) = db.find_forward_servers(qname) {
-                let addrs: Vec<SocketAddr> = servers
-                    .iter()
-                    .filter_map(|a| {
-                        if a.contains(':') {
-                            a.parse().ok()
-                        } else {
-                            format!("{a}:53").parse().ok()
-                        }
-                    })
-                    .collect();
+            if let Some((zone, servers)) = db.find_forward_zone(qname) {
+                let addrs: Vec<Upstream> =
+                    servers.iter().filter_map(|a| Upstream::parse(a)).collect();
                 if !addrs.is_empty() {
-                    return Some(addrs);
+                    return Some((zone, addrs));
                 }
             }
         }
         // Fall back to static forward table from config
-        self.forward_table.lookup(qname).map(|s| s.to_vec())
+        self.forward_table
+            .find(qname)
+            .map(|(zone, servers)| (zone.to_string(), servers.to_vec()))
+    }
+
+    /// Resolve a DNS query from raw bytes sent by `client`. Returns the
+    /// response bytes.
+    #[tracing::instrument(
+        name = "recursor.resolve",
+        skip_all,
+        fields(%client, qtype = microdns_core::metrics::qtype_label(data))
+    )]
+    pub async fn resolve(&self, data: &[u8], client: IpAddr) -> anyhow::Result<Vec<u8>> {
+        self.resolve_at_depth(data, client, 0).await
     }
 
-    /// Resolve a DNS query from raw bytes. Returns the response bytes.
-    pub async fn resolve(&self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    /// [`Self::resolve`], `depth` policy rewrites deep.
+    async fn resolve_at_depth(
+        &self,
+        data: &[u8],
+        client: IpAddr,
+        depth: u8,
+    ) -> anyhow::Result<Vec<u8>> {
+        let response = self.answer_at_depth(data, client, depth).await?;
+        match self.dns64 {
+            Some(ref dns64) => self.apply_dns64(dns64, data, response, client, depth).await,
+            None => Ok(response),
+        }
+    }
+
+    /// The answer to a query, before DNS64.
+    async fn answer_at_depth(
+        &self,
+        data: &[u8],
+        client: IpAddr,
+        depth: u8,
+    ) -> anyhow::Result<Vec<u8>> {
         let request = Message::from_bytes(data)?;
 
         if request.op_code() != OpCode::Query {
@@ -87,64 +229,450 @@ impl Resolver {
         let qname_str = qname.to_string();
         let qname_lower = qname_str.trim_end_matches('.').to_lowercase();
 
-        debug!("recursor query: {} {}", qname, qtype);
+        debug!("recursor query: {} {} from {}", qname, qtype, client);
 
-        // Step 1: Check local authoritative zones FIRST (always prefer local data)
-        if let Some(ref db) = self.db {
-            let lower = LowerName::from(qname.clone());
-            if let Ok(Some(_zone)) = db.find_zone_for_fqdn(&qname_lower) {
-                debug!("resolving {} {} from local auth zone", qname, qtype);
-                return self.resolve_from_local(db, &request, &lower, qtype, true);
+        let access = self.access.settings();
+        let may_recurse = access.may_recurse(client);
+
+        // Step 1: Local data overrides, then local authoritative zones (always
+        // prefer local data)
+        if may_recurse || access.answer_local_to_all {
+            let local = self.local_data.current();
+            if let Some(records) = local.lookup(&qname_lower) {
+                debug!("answering {} {} from local data", qname, qtype);
+                return self
+                    .answer_from_local_data(&request, records, client, depth)
+                    .await;
+            }
+            if let Some(ref db) = self.db {
+                let lower = LowerName::from(qname.clone());
+                if let Some(zone) = db.zone_tree().find_zone(&lower) {
+                    debug!("resolving {} {} from local auth zone", qname, qtype);
+                    return self.resolve_from_local(zone, &request, &lower, qtype, client, true);
+                }
             }
         }
 
-        // Step 2: Check cache
-        let cache_key = CacheKey::from_query(
-            &qname_lower,
+        // Everything past this point is recursion, cache included: a cached
+        // answer is still one this resolver fetched on someone's behalf.
+        if !may_recurse {
+            debug!("refusing recursion for {} {} to {}", qname, qtype, client);
+            return Ok(self.make_refusal(&request));
+        }
+
+        // Step 2: Response policy on the name asked for. Local zones are ours
+        // and never filtered; everything fetched from elsewhere is.
+        let policy = self.rpz.current();
+        let mut passthru = false;
+        if let Some(hit) = policy.check_qname(&qname_lower) {
+            match self.apply_policy(&request, client, &hit, depth).await? {
+                Some(response) => return Ok(response),
+                None => passthru = true,
+            }
+        }
+
+        let response = self.recurse(data, &request, client, &qname_lower).await?;
+
+        // Step 6: Response policy on what came back — CNAME targets, addresses
+        // and name servers.
+        if passthru || policy.is_empty() {
+            return Ok(response);
+        }
+        let Ok(parsed) = Message::from_bytes(&response) else {
+            return Ok(response);
+        };
+        match policy.check_response(&parsed) {
+            Some(hit) => Ok(self
+                .apply_policy(&request, client, &hit, depth)
+                .await?
+                .unwrap_or(response)),
+            None => Ok(response),
+        }
+    }
+
+    /// Answer from the cache or upstream. A client that sent a subnet
+    /// option gets it back, with the scope the answer holds for
+    /// (RFC 7871 §7.2.2).
+    async fn recurse(
+        &self,
+        data: &[u8],
+        request: &Message,
+        client: IpAddr,
+        qname_lower: &str,
+    ) -> anyhow::Result<Vec<u8>> {
+        let (response, scope) = self.recurse_scoped(data, request, client, qname_lower).await?;
+        match ecs::read(request) {
+            Some((asked, _)) => ecs::echo(&response, asked, scope),
+            None => Ok(response),
+        }
+    }
+
+    /// [`Self::recurse`], along with the client subnet prefix the answer is
+    /// specific to: 0 when it is everyone's.
+    async fn recurse_scoped(
+        &self,
+        data: &[u8],
+        request: &Message,
+        client: IpAddr,
+        qname_lower: &str,
+    ) -> anyhow::Result<(Vec<u8>, u8)> {
+        let query = &request.queries()[0];
+        let qname = query.name();
+        let qtype = query.query_type();
+
+        // Step 3: Check cache — an answer for the client's subnet, then one
+        // for everyone
+        let global_key = CacheKey::from_query(
+            qname_lower,
             qtype.into(),
             query.query_class().into(),
         );
+        let subnet = self
+            .ecs
+            .as_ref()
+            .and_then(|ecs| ecs.subnet_for(request, client));
+        let cache_key = global_key.clone().with_subnet(subnet);
+        let scope = subnet.map_or(0, |subnet| subnet.prefix());
 
-        if let Some(cached_bytes) = self.cache.get(&cache_key) {
+        let cached = subnet
+            .and_then(|_| self.cache.get(&cache_key).map(|bytes| (bytes, scope)))
+            .or_else(|| self.cache.get(&global_key).map(|bytes| (bytes, 0)));
+        if let Some((cached_bytes, scope)) = cached {
             debug!("cache hit for {} {}", qname, qtype);
             // Rewrite the response ID to match the request
-            return Ok(self.rewrite_response_id(&cached_bytes, request.id()));
+            return Ok((self.rewrite_response_id(&cached_bytes, request.id()), scope));
         }
 
-        // Step 3: Check forward zones (reads from database, falls back to static config)
-        if let Some(servers) = self.find_forward_servers(&qname_lower) {
+        // The client's own subnet option goes upstream only as `subnet`: an
+        // answer to a query that carried one is not everyone's to share.
+        let rewritten;
+        let data = match subnet {
+            Some(subnet) => {
+                rewritten = ecs::with_subnet(data, subnet)?;
+                rewritten.as_slice()
+            }
+            None if ecs::read(request).is_some() => {
+                rewritten = ecs::without_subnet(data)?;
+                rewritten.as_slice()
+            }
+            None => data,
+        };
+
+        // Step 4: Check forward zones (reads from database, falls back to static config)
+        if let Some((zone, servers)) = self.find_forward_zone(qname_lower) {
+            if let Some(response) = self.minimised_nxdomain(request, &zone, &servers).await {
+                debug!("{} {}: an ancestor does not exist", qname, qtype);
+                // Nothing went upstream with a client subnet: the answer is
+                // everyone's.
+                self.cache_response(&global_key, &response);
+                return Ok((response, 0));
+            }
             debug!("forwarding {} {} to forward zone servers", qname, qtype);
-            let result = self.forward_query(data, &request, &servers, &cache_key).await?;
+            let result = self.forward_query(data, request, &servers, &cache_key).await?;
 
             // If forward failed (SERVFAIL), try local fallback
             if is_servfail(&result) {
                 if let Some(ref db) = self.db {
-                    if let Ok(Some(_zone)) = db.find_zone_for_fqdn(&qname_lower) {
+                    let lower = LowerName::from(qname.clone());
+                    if let Some(zone) = db.zone_tree().find_zone(&lower) {
                         warn!("forward failed for {} {}, using local fallback", qname, qtype);
-                        let lower = LowerName::from(qname.clone());
-                        return self.resolve_from_local(db, &request, &lower, qtype, false);
+                        return self
+                            .resolve_from_local(zone, request, &lower, qtype, client, false)
+                            .map(|response| (response, 0));
                     }
                 }
             }
-            return Ok(result);
+            return Ok((result, scope));
         }
 
-        // Step 4: Forward to upstream resolvers
+        // Step 5: Forward to upstream resolvers
         debug!("forwarding {} {} to upstream resolvers", qname, qtype);
-        self.forward_query(data, &request, &self.upstream, &cache_key).await
+        let response = self.forward_query(data, request, &self.upstream, &cache_key).await?;
+        Ok((response, scope))
+    }
+
+    /// QNAME minimisation (RFC 9156) toward a forward zone's servers, in
+    /// relaxed mode. The name's ancestors below the zone apex are asked for
+    /// one label at a time, as type A. If one of them does not exist, neither
+    /// does the name (RFC 8020), which then never leaves this resolver: the
+    /// NXDOMAIN for it is returned. An NXDOMAIN only counts as that proof with
+    /// no answer and an SOA at or above the ancestor; behind a CNAME or DNAME
+    /// it is about the alias target. Anything else ends the walk, and the
+    /// caller sends the full query as usual.
+    ///
+    /// Ancestor answers are cached like any other, so a walk mostly costs
+    /// round trips the first time a branch of the zone is seen.
+    async fn minimised_nxdomain(
+        &self,
+        request: &Message,
+        zone: &str,
+        servers: &[Upstream],
+    ) -> Option<Vec<u8>> {
+        if !self.qname_minimisation {
+            return None;
+        }
+        let servers: Vec<Upstream> = servers.iter().filter(|s| s.minimise()).cloned().collect();
+        if servers.is_empty() {
+            return None;
+        }
+
+        let qname = request.queries()[0].name();
+        let apex_labels = zone.split('.').filter(|l| !l.is_empty()).count();
+        let labels = usize::from(qname.num_labels());
+        for depth in (apex_labels + 1..labels).take(MAX_MINIMISE_COUNT) {
+            let ancestor = qname.trim_to(depth);
+            let mut step = Message::new();
+            step.set_id(request.id());
+            step.set_recursion_desired(request.recursion_desired());
+            step.add_query(Query::query(ancestor.clone(), RecordType::A));
+
+            let lower = ancestor.to_string().trim_end_matches('.').to_lowercase();
+            let key = CacheKey::from_query(&lower, RecordType::A.into(), DNSClass::IN.into());
+            let response = match self.cache.get(&key) {
+                Some(cached) => cached,
+                None => self
+                    .forward_query(&step.to_bytes().ok()?, &step, &servers, &key)
+                    .await
+                    .ok()?,
+            };
+            let response = Message::from_bytes(&response).ok()?;
+            match response.response_code() {
+                ResponseCode::NXDomain if denies(&response, &ancestor) => {
+                    debug!("qname minimisation: {lower} does not exist");
+                    return self.nxdomain_from(request, &response).ok();
+                }
+                ResponseCode::NoError if is_referral(&response) => return None,
+                ResponseCode::NoError => continue,
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// NXDOMAIN for `request`, with the authority section of `proof` — the
+    /// NXDOMAIN for one of its ancestors — so it is cached for as long.
+    fn nxdomain_from(&self, request: &Message, proof: &Message) -> anyhow::Result<Vec<u8>> {
+        let mut response = Message::new();
+        response.set_id(request.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(OpCode::Query);
+        response.set_recursion_desired(request.recursion_desired());
+        response.set_recursion_available(true);
+        response.set_response_code(ResponseCode::NXDomain);
+        for query in request.queries() {
+            response.add_query(query.clone());
+        }
+        for record in proof.name_servers() {
+            response.add_name_server(record.clone());
+        }
+        Ok(response.to_bytes()?)
+    }
+
+    /// The answer a policy rule calls for, or `None` for a passthru.
+    ///
+    /// A local-data CNAME is followed, so a client redirected to a walled
+    /// garden gets its address and not just a name.
+    async fn apply_policy(
+        &self,
+        request: &Message,
+        client: IpAddr,
+        hit: &rpz::Hit<'_>,
+        depth: u8,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let query = &request.queries()[0];
+        debug!(
+            "rpz {}: {:?} rule matched {} {}",
+            hit.zone,
+            hit.trigger,
+            query.name(),
+            query.query_type()
+        );
+
+        let mut response = Message::new();
+        response.set_id(request.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(OpCode::Query);
+        response.set_recursion_desired(request.recursion_desired());
+        response.set_recursion_available(true);
+        for query in request.queries() {
+            response.add_query(query.clone());
+        }
+        let blocked = format!("blocked by policy zone {}", hit.zone);
+        let rewritten = format!("rewritten by policy zone {}", hit.zone);
+
+        match hit.action {
+            rpz::Action::Passthru => return Ok(None),
+            rpz::Action::NxDomain => {
+                response.set_response_code(ResponseCode::NXDomain);
+                ede::attach(&mut response, request, ede::BLOCKED, &blocked);
+            }
+            rpz::Action::NoData => {
+                response.set_response_code(ResponseCode::NoError);
+                ede::attach(&mut response, request, ede::BLOCKED, &blocked);
+            }
+            rpz::Action::LocalData(records) => {
+                let records = records.iter().map(|(ttl, data)| (*ttl, data));
+                self.answer_with_records(&mut response, records, client, depth)
+                    .await?;
+                ede::attach(&mut response, request, ede::FORGED_ANSWER, &rewritten);
+            }
+        }
+        Ok(Some(response.to_bytes()?))
+    }
+
+    /// Answer the query in `response` from records owned by its name — local
+    /// data or a policy rewrite. Records of other types are left out, and a
+    /// CNAME is followed through the whole resolver, `depth` rewrites deep.
+    async fn answer_with_records<'a>(
+        &self,
+        response: &mut Message,
+        records: impl Iterator<Item = (u32, &'a microdns_core::types::RecordData)>,
+        client: IpAddr,
+        depth: u8,
+    ) -> anyhow::Result<()> {
+        response.set_response_code(ResponseCode::NoError);
+        let query = response.queries()[0].clone();
+        let qtype = query.query_type();
+        for (ttl, data) in records {
+            let Some(rdata) = record_data_to_rdata(data) else {
+                continue;
+            };
+            let is_cname = rdata.record_type() == RecordType::CNAME;
+            if !is_cname && qtype != RecordType::ANY && rdata.record_type() != qtype {
+                continue;
+            }
+            let target = match &rdata {
+                hickory_proto::rr::RData::CNAME(c) => Some(c.0.clone()),
+                _ => None,
+            };
+            response.add_answer(hickory_proto::rr::Record::from_rdata(
+                query.name().clone(),
+                ttl,
+                rdata,
+            ));
+
+            if let Some(target) = target {
+                if qtype == RecordType::CNAME || depth >= MAX_POLICY_DEPTH {
+                    continue;
+                }
+                let mut follow = Message::new();
+                follow.set_recursion_desired(true);
+                follow.add_query(hickory_proto::op::Query::query(target, qtype));
+                let followed =
+                    Box::pin(self.resolve_at_depth(&follow.to_bytes()?, client, depth + 1))
+                        .await?;
+                let followed = Message::from_bytes(&followed)?;
+                response.set_response_code(followed.response_code());
+                for answer in followed.answers() {
+                    response.add_answer(answer.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Answer a name that has local data, from that data alone.
+    async fn answer_from_local_data(
+        &self,
+        request: &Message,
+        records: &[LocalRecord],
+        client: IpAddr,
+        depth: u8,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut response = Message::new();
+        response.set_id(request.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(OpCode::Query);
+        response.set_authoritative(true);
+        response.set_recursion_desired(request.recursion_desired());
+        response.set_recursion_available(true);
+        for query in request.queries() {
+            response.add_query(query.clone());
+        }
+        let ttl = records.iter().map(|r| r.ttl).min().unwrap_or(HOSTS_TTL);
+        let records = records.iter().map(|r| (r.ttl, &r.data));
+        self.answer_with_records(&mut response, records, client, depth)
+            .await?;
+        // NODATA: the name is its own apex, so say for how long (RFC 2308).
+        if response.answers().is_empty() {
+            if let Some(query) = request.queries().first() {
+                response.add_name_server(local_soa(query.name(), ttl));
+            }
+        }
+        Ok(response.to_bytes()?)
+    }
+
+    /// Replace an AAAA answer that has no usable AAAA record with addresses
+    /// synthesized from the name's A records. Local zones and upstream
+    /// answers alike; anything that does not qualify is passed on unchanged.
+    async fn apply_dns64(
+        &self,
+        dns64: &Dns64,
+        data: &[u8],
+        response: Vec<u8>,
+        client: IpAddr,
+        depth: u8,
+    ) -> anyhow::Result<Vec<u8>> {
+        let request = Message::from_bytes(data)?;
+        let Some(query) = request.queries().first() else {
+            return Ok(response);
+        };
+        let qname = query.name().to_string();
+        let qname = qname.trim_end_matches('.').to_lowercase();
+        if query.query_type() != RecordType::AAAA || !dns64.applies_to(client, &qname) {
+            return Ok(response);
+        }
+        // A validating client must see the real, signed answer (RFC 6147 §5.5).
+        let dnssec_ok = request.extensions().as_ref().is_some_and(|e| e.dnssec_ok());
+        if dnssec_ok && request.checking_disabled() {
+            return Ok(response);
+        }
+        let Ok(mut aaaa) = Message::from_bytes(&response) else {
+            return Ok(response);
+        };
+        if !dns64.wants_synthesis(&aaaa) {
+            return Ok(response);
+        }
+
+        let mut a_request = request.clone();
+        let mut queries = a_request.take_queries();
+        queries[0].set_query_type(RecordType::A);
+        a_request.add_queries(queries);
+        let a_response = self
+            .answer_at_depth(&a_request.to_bytes()?, client, depth)
+            .await?;
+        let a_response = Message::from_bytes(&a_response)?;
+        if a_response.response_code() != ResponseCode::NoError {
+            return Ok(response);
+        }
+
+        // A synthesized record lives no longer than the missing AAAA may be
+        // cached (RFC 6147 §5.1.7).
+        let negative_ttl = aaaa.name_servers().iter().find_map(|r| match r.data() {
+            Some(hickory_proto::rr::RData::SOA(soa)) => Some(soa.minimum().min(r.ttl())),
+            _ => None,
+        });
+        let Some(answers) = dns64.synthesize_answers(&a_response, negative_ttl) else {
+            return Ok(response);
+        };
+        debug!("dns64: synthesized {} AAAA for {}", answers.len(), qname);
+        aaaa.take_answers();
+        aaaa.take_name_servers();
+        aaaa.insert_answers(answers);
+        aaaa.set_authoritative(false);
+        Ok(aaaa.to_bytes()?)
     }
 
     /// Resolve from local authoritative zone data.
     fn resolve_from_local(
         &self,
-        db: &Db,
+        zone: &CompiledZone,
         request: &Message,
         qname: &LowerName,
         qtype: RecordType,
+        client: IpAddr,
         authoritative: bool,
     ) -> anyhow::Result<Vec<u8>> {
-        use microdns_core::types::RecordType as MicroRecordType;
-
         let mut response = Message::new();
         response.set_id(request.id());
         response.set_message_type(MessageType::Response);
@@ -157,122 +685,208 @@ impl Resolver {
             response.add_query(query.clone());
         }
 
-        let fqdn = qname.to_string();
-        let fqdn = fqdn.trim_end_matches('.');
-
-        // Handle SOA queries
-        if qtype == RecordType::SOA {
-            if let Ok(Some(zone)) = db.find_zone_for_fqdn(fqdn) {
-                if let Some(soa) = build_soa_record_proto(&zone) {
-                    response.add_answer(soa);
-                }
-            }
-            response.set_response_code(ResponseCode::NoError);
+        let served = matches!(
+            qtype,
+            RecordType::A
+                | RecordType::AAAA
+                | RecordType::CNAME
+                | RecordType::MX
+                | RecordType::NS
+                | RecordType::PTR
+                | RecordType::SOA
+                | RecordType::SRV
+                | RecordType::TXT
+                | RecordType::CAA
+        );
+        if !served {
+            response.set_response_code(ResponseCode::NotImp);
             return Ok(response.to_bytes()?);
         }
 
-        // Convert to our type
-        let micro_rtype = match qtype {
-            RecordType::A => Some(MicroRecordType::A),
-            RecordType::AAAA => Some(MicroRecordType::AAAA),
-            RecordType::CNAME => Some(MicroRecordType::CNAME),
-            RecordType::MX => Some(MicroRecordType::MX),
-            RecordType::NS => Some(MicroRecordType::NS),
-            RecordType::PTR => Some(MicroRecordType::PTR),
-            RecordType::SRV => Some(MicroRecordType::SRV),
-            RecordType::TXT => Some(MicroRecordType::TXT),
-            RecordType::CAA => Some(MicroRecordType::CAA),
-            _ => None,
-        };
-
-        if let Some(rtype) = micro_rtype {
-            let records = db.query_fqdn(fqdn, rtype).unwrap_or_default();
-            if records.is_empty() {
-                // Add SOA to authority section
-                if let Ok(Some(zone)) = db.find_zone_for_fqdn(fqdn) {
-                    if let Some(soa) = build_soa_record_proto(&zone) {
-                        response.add_name_server(soa);
-                    }
-                }
-                // NOERROR if the name exists (but no records of this type),
-                // NXDOMAIN only if the name truly doesn't exist.
-                // Critical for systemd-resolved parallel A+AAAA lookups.
-                let name_exists = db.fqdn_exists(fqdn).unwrap_or(false);
-                if name_exists {
-                    response.set_response_code(ResponseCode::NoError);
-                } else {
-                    response.set_response_code(ResponseCode::NXDomain);
-                }
-            } else {
-                for record in &records {
-                    if let Some(dns_record) = record_to_proto(record, db) {
-                        response.add_answer(dns_record);
-                    }
-                }
+        let client = topology::client_address(request, client);
+        let steering = self.topology.current();
+        let records =
+            zone.lookup_preferring(qname, qtype, &|member| steering.prefers(client, member));
+        if records.is_empty() {
+            // Add SOA to authority section
+            if let Some(soa) = zone.soa() {
+                response.add_name_server(soa.clone());
+            }
+            // NOERROR if the name exists (but no records of this type),
+            // NXDOMAIN only if the name truly doesn't exist.
+            // Critical for systemd-resolved parallel A+AAAA lookups.
+            if zone.name_exists(qname) {
                 response.set_response_code(ResponseCode::NoError);
+            } else {
+                response.set_response_code(ResponseCode::NXDomain);
             }
         } else {
-            response.set_response_code(ResponseCode::NotImp);
+            response.insert_answers(records);
+            response.set_response_code(ResponseCode::NoError);
         }
 
         Ok(response.to_bytes()?)
     }
 
     /// Forward a query to upstream servers and cache the result.
+    ///
+    /// Concurrent queries for the same cache key share a single upstream
+    /// lookup: the first one goes upstream, the rest wait for its answer and
+    /// get it back under their own ID.
     async fn forward_query(
         &self,
         raw_request: &[u8],
         request: &Message,
-        servers: &[SocketAddr],
+        servers: &[Upstream],
         cache_key: &CacheKey,
     ) -> anyhow::Result<Vec<u8>> {
-        // Try each server in order
-        for server in servers {
-            match self.send_query(raw_request, *server).await {
-                Ok(response_bytes) => {
-                    // Cache the response
-                    if let Ok(resp_msg) = Message::from_bytes(&response_bytes) {
-                        let ttl = cache::min_ttl_from_response(&resp_msg);
-                        if ttl > 0 && resp_msg.response_code() == ResponseCode::NoError {
-                            self.cache.insert(
-                                cache_key.clone(),
-                                response_bytes.clone(),
-                                ttl,
-                            );
-                        }
+        let lead = match self.inflight.join(cache_key) {
+            Join::Leader(lead) => lead,
+            Join::Follower(mut rx) => {
+                debug!("coalescing {} with an in-flight upstream query", cache_key.name);
+                return match rx.recv().await {
+                    Ok(shared) => Ok(self.rewrite_response_id(&shared, request.id())),
+                    // The leader gave up without an answer; ask ourselves.
+                    Err(_) => {
+                        let response = self
+                            .query_upstreams(raw_request, request, servers, cache_key)
+                            .await;
+                        Ok(self.rewrite_response_id(&response, request.id()))
                     }
+                };
+            }
+        };
 
-                    // Rewrite response ID to match request
-                    return Ok(self.rewrite_response_id(&response_bytes, request.id()));
-                }
-                Err(e) => {
-                    warn!("upstream {} failed: {}", server, e);
-                    continue;
-                }
+        let response = self
+            .query_upstreams(raw_request, request, servers, cache_key)
+            .await;
+        lead.complete(&response);
+
+        // Rewrite response ID to match request
+        Ok(self.rewrite_response_id(&response, request.id()))
+    }
+
+    /// Try upstreams fastest-healthy-first, caching the first answer. Returns
+    /// the response as received, or SERVFAIL when every upstream failed.
+    async fn query_upstreams(
+        &self,
+        raw_request: &[u8],
+        request: &Message,
+        servers: &[Upstream],
+        cache_key: &CacheKey,
+    ) -> Vec<u8> {
+        let order = self.upstreams.order(servers);
+        let mut remaining = order.as_slice();
+
+        if self.race_upstreams && order.len() >= 2 {
+            if let Some(response) = self.race(raw_request, &order[0], &order[1]).await {
+                return self.accept_response(cache_key, response);
+            }
+            remaining = &order[2..];
+        }
+
+        for server in remaining {
+            if let Some(response) = self.timed_query(raw_request, server).await {
+                return self.accept_response(cache_key, response);
             }
         }
 
         // All upstreams failed
-        Ok(self.make_error_response(request, ResponseCode::ServFail))
+        self.make_error_response(request, ResponseCode::ServFail)
+    }
+
+    /// Ask two upstreams at once. The first answer wins; if one fails, the
+    /// other is still waited for.
+    async fn race(&self, raw_request: &[u8], a: &Upstream, b: &Upstream) -> Option<Vec<u8>> {
+        let first = self.timed_query(raw_request, a);
+        let second = self.timed_query(raw_request, b);
+        tokio::pin!(first, second);
+        tokio::select! {
+            r = &mut first => match r {
+                Some(response) => Some(response),
+                None => second.await,
+            },
+            r = &mut second => match r {
+                Some(response) => Some(response),
+                None => first.await,
+            },
+        }
+    }
+
+    /// Query one upstream, recording the outcome on the scoreboard.
+    async fn timed_query(&self, raw_request: &[u8], server: &Upstream) -> Option<Vec<u8>> {
+        let started = Instant::now();
+        match self.send_query(raw_request, server).await {
+            Ok(response) => {
+                self.upstreams.record_success(server, started.elapsed());
+                Some(response)
+            }
+            Err(e) => {
+                warn!("upstream {} failed: {}", server, e);
+                self.upstreams.record_failure(server);
+                None
+            }
+        }
+    }
+
+    /// Cache an upstream answer and return it as the client should see it.
+    ///
+    /// When the query carried a client subnet, the option is taken out of the
+    /// answer, which is cached for that subnet or — at scope 0 — for everyone.
+    fn accept_response(&self, cache_key: &CacheKey, response: Vec<u8>) -> Vec<u8> {
+        let Some(subnet) = cache_key.subnet else {
+            self.cache_response(cache_key, &response);
+            return response;
+        };
+        let Ok(mut message) = Message::from_bytes(&response) else {
+            return response;
+        };
+        let scope = ecs::take_scope(&mut message, subnet);
+        let response = message.to_bytes().unwrap_or(response);
+        match scope {
+            Some(0) => self.cache_response(&cache_key.clone().with_subnet(None), &response),
+            Some(_) => self.cache_response(cache_key, &response),
+            None => debug!(
+                "not caching {}: the answer's client subnet does not match {subnet}",
+                cache_key.name
+            ),
+        }
+        response
+    }
+
+    /// Cache an upstream answer, positive or negative, for as long as the
+    /// cache's TTL policy allows.
+    fn cache_response(&self, cache_key: &CacheKey, response_bytes: &[u8]) {
+        if let Ok(resp_msg) = Message::from_bytes(response_bytes) {
+            self.cache.insert_response(cache_key.clone(), &resp_msg);
+        }
     }
 
     /// Send a raw DNS query to a server and return the response bytes.
+    #[tracing::instrument(name = "recursor.upstream", skip_all, fields(upstream = %server))]
     async fn send_query(
         &self,
         data: &[u8],
-        server: SocketAddr,
+        server: &Upstream,
     ) -> anyhow::Result<Vec<u8>> {
-        let socket = UdpSocket::bind("0.0.0.0:0").await?;
-        socket.send_to(data, server).await?;
-
-        let mut buf = vec![0u8; 4096];
-        let timeout = tokio::time::timeout(
-            std::time::Duration::from_secs(5),
-            socket.recv_from(&mut buf),
-        )
-        .await??;
-
-        Ok(buf[..timeout.0].to_vec())
+        let Some(dnstap) = self.dnstap.as_ref().filter(|d| d.sample(data)) else {
+            return self.transport.query(data, server).await;
+        };
+        let (protocol, peer) = match server {
+            Upstream::Plain { addr, .. } if self.transport.config().persistent_tcp => {
+                (Protocol::Tcp, Some(*addr))
+            }
+            Upstream::Plain { addr, .. } => (Protocol::Udp, Some(*addr)),
+            Upstream::Tls { host, port, .. } => (
+                Protocol::Dot,
+                host.parse().ok().map(|ip| SocketAddr::new(ip, *port)),
+            ),
+            Upstream::Https { .. } => (Protocol::Doh, None),
+        };
+        dnstap.log(dnstap::MessageType::ForwarderQuery, protocol, peer, data);
+        let response = self.transport.query(data, server).await?;
+        dnstap.log(dnstap::MessageType::ForwarderResponse, protocol, peer, &response);
+        Ok(response)
     }
 
     /// Rewrite the ID field in a DNS response to match a different request ID.
@@ -304,6 +918,28 @@ impl Resolver {
         response.to_bytes().unwrap_or_default()
     }
 
+    /// REFUSED, with an Extended DNS Error saying recursion is not offered to
+    /// this client.
+    fn make_refusal(&self, request: &Message) -> Vec<u8> {
+        let mut response = Message::new();
+        response.set_id(request.id());
+        response.set_message_type(MessageType::Response);
+        response.set_op_code(OpCode::Query);
+        response.set_recursion_desired(request.recursion_desired());
+        response.set_recursion_available(false);
+        response.set_response_code(ResponseCode::Refused);
+        for query in request.queries() {
+            response.add_query(query.clone());
+        }
+        ede::attach(
+            &mut response,
+            request,
+            ede::PROHIBITED,
+            "recursion not available to this client",
+        );
+        response.to_bytes().unwrap_or_default()
+    }
+
     pub fn cache(&self) -> &DnsCache {
         &self.cache
     }
@@ -311,30 +947,42 @@ impl Resolver {
     pub fn cache_arc(&self) -> Arc<DnsCache> {
         self.cache.clone()
     }
+
+    pub fn upstreams_arc(&self) -> Arc<UpstreamTracker> {
+        self.upstreams.clone()
+    }
+
+    /// Handle to the recursion ACL, for whatever keeps it current.
+    pub fn access(&self) -> AccessState {
+        self.access.clone()
+    }
+
+    /// Handle to the response policy in force.
+    pub fn rpz(&self) -> RpzState {
+        self.rpz.clone()
+    }
+
+    /// Handle to the local data in force.
+    pub fn local_data(&self) -> LocalDataState {
+        self.local_data.clone()
+    }
+
+    pub fn inflight(&self) -> &InFlight {
+        &self.inflight
+    }
 }
 
-// Helper: convert our Record to a hickory DNS Record for local zone responses.
-// Reuses the conversion logic pattern from microdns-auth.
-fn record_to_proto(
-    record: &microdns_core::types::Record,
-    db: &Db,
-) -> Option<hickory_proto::rr::Record> {
+/// Convert record data to its wire form. `None` for what cannot be expressed —
+/// a malformed name — or is not served yet (CAA).
+pub(crate) fn record_data_to_rdata(
+    data: &microdns_core::types::RecordData,
+) -> Option<hickory_proto::rr::RData> {
     use hickory_proto::rr::rdata::{CNAME, MX, NS, PTR, SOA, SRV, TXT};
-    use hickory_proto::rr::{RData, Record as DnsRecord};
+    use hickory_proto::rr::RData;
     use microdns_core::types::RecordData;
     use std::str::FromStr;
 
-    let fqdn_str = if record.name == "@" {
-        let zone = db.find_zone_for_fqdn(&record.name).ok()??;
-        ensure_fqdn(&zone.name)
-    } else {
-        let zone = db.get_zone(&record.zone_id).ok()??;
-        format!("{}.{}.", record.name, zone.name)
-    };
-
-    let name = Name::from_str(&fqdn_str).ok()?;
-
-    let rdata = match &record.data {
+    let rdata = match data {
         RecordData::A(addr) => RData::A((*addr).into()),
         RecordData::AAAA(addr) => RData::AAAA((*addr).into()),
         RecordData::CNAME(n) => RData::CNAME(CNAME(Name::from_str(&ensure_fqdn(n)).ok()?)),
@@ -361,34 +1009,7 @@ fn record_to_proto(
         RecordData::TXT(text) => RData::TXT(TXT::new(vec![text.clone()])),
         RecordData::CAA(_) => return None, // Simplified for now
     };
-
-    Some(DnsRecord::from_rdata(name, record.ttl, rdata))
-}
-
-fn build_soa_record_proto(
-    zone: &microdns_core::types::Zone,
-) -> Option<hickory_proto::rr::Record> {
-    use hickory_proto::rr::rdata::SOA;
-    use hickory_proto::rr::{Name, RData, Record as DnsRecord};
-    use std::str::FromStr;
-
-    let zone_name = Name::from_str(&ensure_fqdn(&zone.name)).ok()?;
-    let mname = Name::from_str(&ensure_fqdn(&zone.soa.mname)).ok()?;
-    let rname = Name::from_str(&ensure_fqdn(&zone.soa.rname)).ok()?;
-
-    let rdata = RData::SOA(SOA::new(
-        mname,
-        rname,
-        zone.soa.serial,
-        zone.soa.refresh as i32,
-        zone.soa.retry as i32,
-        zone.soa.expire as i32,
-        zone.soa.minimum,
-    ));
-
-    let mut record = DnsRecord::from_rdata(zone_name, zone.default_ttl, rdata);
-    record.set_record_type(RecordType::SOA);
-    Some(record)
+    Some(rdata)
 }
 
 fn ensure_fqdn(name: &str) -> String {
@@ -399,6 +1020,36 @@ fn ensure_fqdn(name: &str) -> String {
     }
 }
 
+/// The SOA a local-data name answers NODATA with, negative-cached for `ttl`.
+fn local_soa(name: &Name, ttl: u32) -> Record {
+    use hickory_proto::rr::rdata::SOA;
+
+    let rname = Name::from_ascii("nobody.invalid.").unwrap_or_else(|_| Name::root());
+    let soa = SOA::new(name.clone(), rname, 1, 3600, 1200, 604_800, ttl);
+    Record::from_rdata(name.clone(), ttl, RData::SOA(soa))
+}
+
+/// Whether an answer hands the question on to another zone's servers rather
+/// than answering it.
+fn is_referral(response: &Message) -> bool {
+    !response.authoritative()
+        && response.answers().is_empty()
+        && response
+            .name_servers()
+            .iter()
+            .any(|r| r.record_type() == RecordType::NS)
+}
+
+/// Whether an NXDOMAIN answer proves `name` does not exist: nothing in the
+/// answer section, and the SOA of a zone `name` is in.
+fn denies(response: &Message, name: &Name) -> bool {
+    response.answers().is_empty()
+        && response
+            .name_servers()
+            .iter()
+            .any(|r| r.record_type() == RecordType::SOA && r.name().zone_of(name))
+}
+
 /// Check if a raw DNS response has RCODE = SERVFAIL (2).
 fn is_servfail(response: &[u8]) -> bool {
     response.len() >= 4 && (response[3] & 0x0F) == 2
@@ -408,6 +1059,14 @@ fn is_servfail(response: &[u8]) -> bool {
 mod tests {
     use super::*;
 
+    use std::net::SocketAddr;
+    use tokio::net::UdpSocket;
+
+    /// A client on loopback, which the default ACL lets recurse.
+    const LOCAL: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+    /// A client out on the internet, which it does not.
+    const OUTSIDER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 5));
+
     #[test]
     fn test_is_servfail_true() {
         // Byte 3, bits 0-3 = 2 (SERVFAIL)
@@ -439,4 +1098,765 @@ mod tests {
         let response = vec![0x00, 0x00, 0x00, 0xF2]; // all upper bits set, RCODE=2
         assert!(is_servfail(&response));
     }
+
+    /// A fake upstream that answers every A query after `delay`, counting
+    /// how many queries actually reached it.
+    async fn slow_upstream(
+        delay: std::time::Duration,
+    ) -> (SocketAddr, Arc<std::sync::atomic::AtomicUsize>) {
+        use hickory_proto::rr::{RData, Record};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let Ok((len, src)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let query = Message::from_bytes(&buf[..len]).unwrap();
+                let mut response = Message::new();
+                response.set_id(query.id());
+                response.set_message_type(MessageType::Response);
+                response.add_query(query.queries()[0].clone());
+                response.add_answer(Record::from_rdata(
+                    query.queries()[0].name().clone(),
+                    300,
+                    RData::A("192.0.2.1".parse::<std::net::Ipv4Addr>().unwrap().into()),
+                ));
+                tokio::time::sleep(delay).await;
+                let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+            }
+        });
+        (addr, hits)
+    }
+
+    fn query_message(id: u16, name: &str) -> Message {
+        use hickory_proto::op::Query;
+        use std::str::FromStr;
+
+        let mut msg = Message::new();
+        msg.set_id(id);
+        msg.set_recursion_desired(true);
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        msg
+    }
+
+    fn query_bytes(id: u16, name: &str) -> Vec<u8> {
+        query_message(id, name).to_bytes().unwrap()
+    }
+
+    #[tokio::test]
+    async fn concurrent_identical_queries_share_one_upstream_lookup() {
+        use std::collections::HashMap;
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = slow_upstream(std::time::Duration::from_millis(200)).await;
+        let mut zones = HashMap::new();
+        zones.insert("example.test".to_string(), vec![upstream.to_string()]);
+        let resolver = Arc::new(Resolver::new(
+            Arc::new(DnsCache::new(100)),
+            Arc::new(ForwardTable::from_config(&zones)),
+            None,
+        ));
+
+        let handles: Vec<_> = (0..20u16)
+            .map(|id| {
+                let resolver = resolver.clone();
+                tokio::spawn(async move {
+                    resolver.resolve(&query_bytes(id, "host.example.test."), LOCAL).await
+                })
+            })
+            .collect();
+
+        for (id, handle) in handles.into_iter().enumerate() {
+            let response = Message::from_bytes(&handle.await.unwrap().unwrap()).unwrap();
+            assert_eq!(response.id(), id as u16, "each waiter gets its own ID back");
+            assert_eq!(response.answers().len(), 1);
+        }
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(resolver.inflight().is_empty());
+    }
+
+    fn forwarding_resolver(servers: &[SocketAddr]) -> Resolver {
+        let mut zones = std::collections::HashMap::new();
+        zones.insert(
+            "example.test".to_string(),
+            servers.iter().map(|s| s.to_string()).collect(),
+        );
+        Resolver::new(
+            Arc::new(DnsCache::new(100)),
+            Arc::new(ForwardTable::from_config(&zones)),
+            None,
+        )
+        .with_transport(TransportConfig {
+            timeout: std::time::Duration::from_millis(200),
+            retries: 0,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn a_dead_upstream_is_skipped_after_it_fails() {
+        use std::sync::atomic::Ordering;
+
+        let dead = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let (alive, hits) = slow_upstream(std::time::Duration::ZERO).await;
+        let resolver = forwarding_resolver(&[dead.local_addr().unwrap(), alive]);
+
+        resolver.resolve(&query_bytes(1, "a.example.test."), LOCAL).await.unwrap();
+        let started = std::time::Instant::now();
+        resolver.resolve(&query_bytes(2, "b.example.test."), LOCAL).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(150));
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let mut buf = [0u8; 512];
+        dead.recv_from(&mut buf).await.unwrap();
+        assert!(
+            dead.try_recv_from(&mut buf).is_err(),
+            "the dead upstream was asked only once"
+        );
+        let board = resolver.upstreams_arc().snapshot();
+        assert!(board[0].healthy && !board[1].healthy);
+    }
+
+    #[tokio::test]
+    async fn racing_takes_the_first_answer() {
+        let (slow, _) = slow_upstream(std::time::Duration::from_millis(150)).await;
+        let (fast, _) = slow_upstream(std::time::Duration::ZERO).await;
+        let resolver = forwarding_resolver(&[slow, fast]).with_upstream_race(true);
+
+        let started = std::time::Instant::now();
+        let response = resolver.resolve(&query_bytes(7, "host.example.test."), LOCAL).await.unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_millis(100));
+        assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+    }
+
+    /// A database holding one empty local zone, `lan.test`.
+    fn local_zone_db() -> (Db, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Db::open(&dir.path().join("t.redb")).unwrap();
+        add_zone(&db, "lan.test");
+        (db, dir)
+    }
+
+    fn add_zone(db: &Db, name: &str) -> uuid::Uuid {
+        use chrono::Utc;
+        use microdns_core::types::{SoaData, Zone};
+
+        let id = uuid::Uuid::new_v4();
+        db.create_zone(
+            name,
+            &Zone {
+                id,
+                name: name.into(),
+                soa: SoaData {
+                    mname: format!("ns.{name}"),
+                    rname: format!("admin.{name}"),
+                    serial: 1,
+                    refresh: 3600,
+                    retry: 900,
+                    expire: 604800,
+                    minimum: 30,
+                },
+                default_ttl: 30,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+        )
+        .unwrap();
+        id
+    }
+
+    fn add_record(db: &Db, zone_id: uuid::Uuid, name: &str, data: microdns_core::types::RecordData) {
+        use chrono::Utc;
+        use microdns_core::types::{Record, RecordSource};
+
+        db.create_record(&Record {
+            id: uuid::Uuid::new_v4(),
+            zone_id,
+            name: name.into(),
+            ttl: 60,
+            data,
+            enabled: true,
+            health_check: None,
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        })
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn outsiders_are_refused_recursion_without_asking_upstream() {
+        use hickory_proto::op::Edns;
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = slow_upstream(std::time::Duration::ZERO).await;
+        let resolver = forwarding_resolver(&[upstream]);
+
+        let mut request = query_message(9, "host.example.test.");
+        request.set_edns(Edns::new());
+        let response = resolver
+            .resolve(&request.to_bytes().unwrap(), OUTSIDER)
+            .await
+            .unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert_eq!(ede::extract(&response).map(|(code, _)| code), Some(ede::PROHIBITED));
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        // Without EDNS in the request there is no OPT record to carry the reason.
+        let response = resolver
+            .resolve(&query_bytes(10, "host.example.test."), OUTSIDER)
+            .await
+            .unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert!(response.extensions().is_none());
+
+        // The cache is recursion too: a name an insider looked up stays private.
+        resolver.resolve(&query_bytes(11, "host.example.test."), LOCAL).await.unwrap();
+        let response = resolver
+            .resolve(&query_bytes(12, "host.example.test."), OUTSIDER)
+            .await
+            .unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    #[tokio::test]
+    async fn ipv6_lan_clients_recurse_under_the_default_acl() {
+        use std::net::Ipv6Addr;
+
+        let (upstream, _hits) = slow_upstream(std::time::Duration::ZERO).await;
+        let resolver = forwarding_resolver(&[upstream]);
+
+        let clients = [
+            IpAddr::V6(Ipv6Addr::LOCALHOST),
+            "fd12:3456::10".parse().unwrap(),
+            "fe80::1".parse().unwrap(),
+        ];
+        for (id, client) in (20..).zip(clients) {
+            let response = resolver
+                .resolve(&query_bytes(id, "host.example.test."), client)
+                .await
+                .unwrap();
+            let response = Message::from_bytes(&response).unwrap();
+            assert_eq!(response.response_code(), ResponseCode::NoError, "{client}");
+            assert_eq!(response.answers().len(), 1, "{client}");
+        }
+
+        let outsider: IpAddr = "2001:db8::5".parse().unwrap();
+        let response = resolver
+            .resolve(&query_bytes(30, "host.example.test."), outsider)
+            .await
+            .unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    /// A fake authoritative server for `corp.test` that only has
+    /// `host.corp.test`; logs every name it is asked about.
+    async fn corp_upstream() -> (SocketAddr, Arc<std::sync::Mutex<Vec<String>>>) {
+        use hickory_proto::rr::rdata::SOA;
+        use hickory_proto::rr::{RData, Record};
+        use std::str::FromStr;
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let Ok((len, src)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let query = Message::from_bytes(&buf[..len]).unwrap();
+                let name = query.queries()[0].name().clone();
+                let lower = name.to_lowercase().to_ascii();
+                log.lock().unwrap().push(lower.trim_end_matches('.').to_string());
+
+                let mut response = Message::new();
+                response.set_id(query.id());
+                response.set_message_type(MessageType::Response);
+                response.set_authoritative(true);
+                response.add_query(query.queries()[0].clone());
+                match lower.as_str() {
+                    "host.corp.test." => {
+                        response.add_answer(Record::from_rdata(
+                            name,
+                            300,
+                            RData::A("192.0.2.7".parse::<std::net::Ipv4Addr>().unwrap().into()),
+                        ));
+                    }
+                    "corp.test." => {}
+                    // An alias to a name that does not exist: the NXDOMAIN
+                    // is about the target, not about `alias.corp.test`.
+                    "alias.corp.test." => {
+                        let target = Name::from_str("gone.elsewhere.test.").unwrap();
+                        response.set_response_code(ResponseCode::NXDomain);
+                        response.add_answer(Record::from_rdata(
+                            name,
+                            300,
+                            RData::CNAME(hickory_proto::rr::rdata::CNAME(target)),
+                        ));
+                    }
+                    _ => {
+                        let apex = Name::from_str("corp.test.").unwrap();
+                        let soa = SOA::new(apex.clone(), apex.clone(), 1, 3600, 600, 86400, 60);
+                        response.set_response_code(ResponseCode::NXDomain);
+                        response.add_name_server(Record::from_rdata(apex, 60, RData::SOA(soa)));
+                    }
+                }
+                let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+            }
+        });
+        (addr, seen)
+    }
+
+    #[tokio::test]
+    async fn forward_zone_queries_are_minimised_unless_opted_out() {
+        let (upstream, seen) = corp_upstream().await;
+        let resolver_for = |entry: String| {
+            let mut zones = std::collections::HashMap::new();
+            zones.insert("corp.test".to_string(), vec![entry]);
+            Resolver::new(
+                Arc::new(DnsCache::new(100)),
+                Arc::new(ForwardTable::from_config(&zones)),
+                None,
+            )
+        };
+
+        // The full name under a missing branch never leaves the resolver.
+        let resolver = resolver_for(upstream.to_string());
+        let response = resolver
+            .resolve(&query_bytes(1, "a.b.missing.corp.test."), LOCAL)
+            .await
+            .unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert_eq!(response.id(), 1);
+        assert_eq!(response.name_servers().len(), 1, "the SOA comes along");
+        assert_eq!(*seen.lock().unwrap(), ["missing.corp.test"]);
+
+        // An existing ancestor leads on to the full question.
+        seen.lock().unwrap().clear();
+        let response = resolver
+            .resolve(&query_bytes(2, "x.host.corp.test."), LOCAL)
+            .await
+            .unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+        assert_eq!(*seen.lock().unwrap(), ["host.corp.test", "x.host.corp.test"]);
+
+        // So does an NXDOMAIN that comes with a CNAME: it denies the target.
+        seen.lock().unwrap().clear();
+        resolver
+            .resolve(&query_bytes(5, "www.alias.corp.test."), LOCAL)
+            .await
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["alias.corp.test", "www.alias.corp.test"]);
+
+        // Opted out: the name goes as asked.
+        seen.lock().unwrap().clear();
+        let resolver = resolver_for(format!("{upstream}?no-qmin"));
+        resolver
+            .resolve(&query_bytes(3, "a.b.missing.corp.test."), LOCAL)
+            .await
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["a.b.missing.corp.test"]);
+
+        // And so it does with minimisation turned off for the resolver.
+        seen.lock().unwrap().clear();
+        let resolver = resolver_for(upstream.to_string()).with_qname_minimisation(false);
+        resolver
+            .resolve(&query_bytes(4, "c.d.missing.corp.test."), LOCAL)
+            .await
+            .unwrap();
+        assert_eq!(*seen.lock().unwrap(), ["c.d.missing.corp.test"]);
+    }
+
+    #[tokio::test]
+    async fn local_zones_are_answered_to_outsiders_unless_turned_off() {
+        use microdns_core::config::RecursionAccessConfig;
+
+        let (db, _dir) = local_zone_db();
+        let access = AccessState::new(&RecursionAccessConfig::default());
+        let resolver = Resolver::new(
+            Arc::new(DnsCache::new(100)),
+            Arc::new(ForwardTable::from_config(&Default::default())),
+            Some(db),
+        )
+        .with_access(access.clone());
+
+        let response = resolver.resolve(&query_bytes(1, "nope.lan.test."), OUTSIDER).await.unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::NXDomain,
+            "answered from the local zone"
+        );
+
+        access.replace(&RecursionAccessConfig {
+            answer_local_to_all: false,
+            ..Default::default()
+        });
+        let response = resolver.resolve(&query_bytes(2, "nope.lan.test."), OUTSIDER).await.unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    /// A resolver forwarding `example.test` to `upstream`, with the policy
+    /// zone `rpz.lo` and the local zone `lan.test` in its database.
+    fn policy_resolver(upstream: SocketAddr) -> (Resolver, Db, uuid::Uuid, tempfile::TempDir) {
+        use microdns_core::config::RpzConfig;
+
+        let (db, dir) = local_zone_db();
+        let rpz_zone = add_zone(&db, "rpz.lo");
+        let rpz = RpzState::new();
+        rpz.refresh(&db, &RpzConfig { zones: vec!["rpz.lo".into()] });
+
+        let mut zones = std::collections::HashMap::new();
+        zones.insert("example.test".to_string(), vec![upstream.to_string()]);
+        let resolver = Resolver::new(
+            Arc::new(DnsCache::new(100)),
+            Arc::new(ForwardTable::from_config(&zones)),
+            Some(db.clone()),
+        )
+        .with_rpz(rpz);
+        (resolver, db, rpz_zone, dir)
+    }
+
+    fn refresh_policy(resolver: &Resolver, db: &Db, zone: uuid::Uuid) {
+        db.increment_soa_serial(&zone).unwrap();
+        assert!(resolver.rpz().refresh(
+            db,
+            &microdns_core::config::RpzConfig { zones: vec!["rpz.lo".into()] }
+        ));
+    }
+
+    #[tokio::test]
+    async fn policy_blocks_names_before_asking_upstream() {
+        use hickory_proto::op::Edns;
+        use microdns_core::types::RecordData;
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = slow_upstream(std::time::Duration::ZERO).await;
+        let (resolver, db, rpz, _dir) = policy_resolver(upstream);
+        add_record(&db, rpz, "*.ads.example.test", RecordData::CNAME(".".into()));
+        add_record(&db, rpz, "quiet.example.test", RecordData::CNAME("*.".into()));
+        refresh_policy(&resolver, &db, rpz);
+
+        let mut request = query_message(1, "x.ads.example.test.");
+        request.set_edns(Edns::new());
+        let response = resolver.resolve(&request.to_bytes().unwrap(), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert_eq!(ede::extract(&response).map(|(code, _)| code), Some(ede::BLOCKED));
+
+        let response = resolver.resolve(&query_bytes(2, "quiet.example.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+
+        assert_eq!(hits.load(Ordering::SeqCst), 0, "blocked names never leave the gateway");
+        assert_eq!(resolver.rpz().status()[0].hits, 2);
+    }
+
+    #[tokio::test]
+    async fn policy_filters_answers_by_address_unless_passed_through() {
+        use microdns_core::types::RecordData;
+
+        // The fake upstream answers everything with 192.0.2.1.
+        let (upstream, _) = slow_upstream(std::time::Duration::ZERO).await;
+        let (resolver, db, rpz, _dir) = policy_resolver(upstream);
+        add_record(&db, rpz, "24.0.2.0.192.rpz-ip", RecordData::CNAME(".".into()));
+        add_record(&db, rpz, "ok.example.test", RecordData::CNAME("rpz-passthru.".into()));
+        refresh_policy(&resolver, &db, rpz);
+
+        let response = resolver.resolve(&query_bytes(1, "host.example.test."), LOCAL).await.unwrap();
+        assert_eq!(
+            Message::from_bytes(&response).unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        let response = resolver.resolve(&query_bytes(2, "ok.example.test."), LOCAL).await.unwrap();
+        assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn policy_local_data_redirects_to_a_walled_garden() {
+        use microdns_core::types::RecordData;
+
+        let (upstream, _) = slow_upstream(std::time::Duration::ZERO).await;
+        let (resolver, db, rpz, _dir) = policy_resolver(upstream);
+        let lan = db.get_zone_by_name("lan.test").unwrap().unwrap().id;
+        add_record(&db, lan, "garden", RecordData::A("192.168.1.5".parse().unwrap()));
+        add_record(&db, rpz, "malware.example.test", RecordData::CNAME("garden.lan.test".into()));
+        add_record(&db, rpz, "fixed.example.test", RecordData::A("192.168.1.6".parse().unwrap()));
+        refresh_policy(&resolver, &db, rpz);
+
+        let response = resolver.resolve(&query_bytes(1, "malware.example.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        let answers: Vec<_> = response.answers().iter().map(|r| r.record_type()).collect();
+        assert_eq!(answers, vec![RecordType::CNAME, RecordType::A]);
+
+        let response = resolver.resolve(&query_bytes(2, "fixed.example.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].name().to_string(), "fixed.example.test.");
+    }
+
+    #[tokio::test]
+    async fn local_data_is_answered_without_asking_upstream() {
+        use microdns_core::config::{LocalDataConfig, LocalDataRecord};
+        use microdns_core::types::RecordData;
+        use std::sync::atomic::Ordering;
+
+        let (upstream, hits) = slow_upstream(std::time::Duration::ZERO).await;
+        let local_data = LocalDataState::new();
+        local_data.refresh(&LocalDataConfig {
+            records: vec![
+                LocalDataRecord {
+                    name: "printer.example.test".into(),
+                    ttl: 60,
+                    data: RecordData::A("192.168.1.40".parse().unwrap()),
+                },
+                LocalDataRecord {
+                    name: "scanner.example.test".into(),
+                    ttl: 60,
+                    data: RecordData::CNAME("printer.example.test.".into()),
+                },
+            ],
+            hosts_files: Vec::new(),
+        });
+        let resolver = forwarding_resolver(&[upstream]).with_local_data(local_data);
+
+        let response = resolver.resolve(&query_bytes(1, "Printer.Example.Test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data().and_then(|d| d.as_a()).map(|a| a.0),
+            Some("192.168.1.40".parse().unwrap())
+        );
+
+        let response = resolver.resolve(&query_bytes(2, "scanner.example.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        let answers: Vec<_> = response.answers().iter().map(|r| r.record_type()).collect();
+        assert_eq!(answers, vec![RecordType::CNAME, RecordType::A]);
+
+        // The name is ours: a type it has no record of is empty, not forwarded.
+        let mut request = query_message(3, "printer.example.test.");
+        let mut queries = request.take_queries();
+        queries[0].set_query_type(RecordType::AAAA);
+        request.add_queries(queries);
+        let response = resolver.resolve(&request.to_bytes().unwrap(), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+        let soa = &response.name_servers()[0];
+        assert_eq!(soa.record_type(), RecordType::SOA);
+        assert_eq!(soa.name().to_ascii(), "printer.example.test.");
+        assert_eq!(soa.ttl(), 60);
+        assert_eq!(hits.load(Ordering::SeqCst), 0);
+
+        resolver.resolve(&query_bytes(4, "other.example.test."), LOCAL).await.unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    /// A fake CDN: answers `cdn.example.test` with an address that depends on
+    /// the client subnet it is sent, scoped to /24, and anything else the same
+    /// for everyone. Counts queries and remembers whether each carried ECS.
+    async fn ecs_upstream() -> (SocketAddr, Arc<std::sync::Mutex<Vec<Option<String>>>>) {
+        use hickory_proto::op::Edns;
+        use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+        use hickory_proto::rr::{RData, Record};
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let log = seen.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1500];
+            loop {
+                let Ok((len, src)) = socket.recv_from(&mut buf).await else {
+                    return;
+                };
+                let query = Message::from_bytes(&buf[..len]).unwrap();
+                let subnet = ecs::read(&query).map(|(s, _)| s);
+                log.lock().unwrap().push(subnet.map(|s| s.to_string()));
+
+                let name = query.queries()[0].name().clone();
+                let mut response = Message::new();
+                response.set_id(query.id());
+                response.set_message_type(MessageType::Response);
+                response.add_query(query.queries()[0].clone());
+                let answer = match subnet {
+                    Some(subnet) if name.to_lowercase().to_ascii().starts_with("cdn.") => {
+                        let mut edns = Edns::new();
+                        edns.options_mut().insert(EdnsOption::Subnet(ClientSubnet::new(
+                            subnet.addr(),
+                            subnet.prefix(),
+                            24,
+                        )));
+                        response.set_edns(edns);
+                        let IpAddr::V4(v4) = subnet.addr() else { unreachable!() };
+                        let [a, b, c, _] = v4.octets();
+                        std::net::Ipv4Addr::new(a, b, c, 80)
+                    }
+                    _ => "192.0.2.1".parse().unwrap(),
+                };
+                response.add_answer(Record::from_rdata(name, 300, RData::A(answer.into())));
+                let _ = socket.send_to(&response.to_bytes().unwrap(), src).await;
+            }
+        });
+        (addr, seen)
+    }
+
+    #[tokio::test]
+    async fn client_subnet_answers_are_cached_per_subnet() {
+        use microdns_core::config::{EcsConfig, RecursionAccessConfig};
+
+        let (upstream, seen) = ecs_upstream().await;
+        let resolver = forwarding_resolver(&[upstream])
+            .with_access(AccessState::new(&RecursionAccessConfig {
+                allow_recursion: vec!["0.0.0.0/0".into()],
+                ..Default::default()
+            }))
+            .with_ecs(Ecs::from_config(&EcsConfig::default()));
+        let answer = |response: Vec<u8>| {
+            let response = Message::from_bytes(&response).unwrap();
+            assert!(ecs::read(&response).is_none(), "the upstream's option is not passed on");
+            response.answers()[0].data().and_then(|d| d.as_a()).map(|a| a.0.to_string())
+        };
+        let client_a: IpAddr = "198.51.100.7".parse().unwrap();
+        let client_b: IpAddr = "203.0.113.9".parse().unwrap();
+
+        let r = resolver.resolve(&query_bytes(1, "cdn.example.test."), client_a).await.unwrap();
+        assert_eq!(answer(r).as_deref(), Some("198.51.100.80"));
+        let r = resolver.resolve(&query_bytes(2, "cdn.example.test."), client_b).await.unwrap();
+        assert_eq!(answer(r).as_deref(), Some("203.0.113.80"));
+        // A neighbour of the first client is served that client's answer.
+        let neighbour: IpAddr = "198.51.100.200".parse().unwrap();
+        let r = resolver.resolve(&query_bytes(3, "cdn.example.test."), neighbour).await.unwrap();
+        assert_eq!(answer(r).as_deref(), Some("198.51.100.80"));
+        assert_eq!(seen.lock().unwrap().len(), 2);
+
+        // Scope 0 (no option back) is cached for everyone.
+        resolver.resolve(&query_bytes(4, "www.example.test."), client_a).await.unwrap();
+        resolver.resolve(&query_bytes(5, "www.example.test."), client_b).await.unwrap();
+        assert_eq!(seen.lock().unwrap().len(), 3);
+
+        // A private client's address never leaves.
+        resolver.resolve(&query_bytes(6, "other.example.test."), LOCAL).await.unwrap();
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![
+                Some("198.51.100.0/24".to_string()),
+                Some("203.0.113.0/24".to_string()),
+                Some("198.51.100.0/24".to_string()),
+                None,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_private_subnet_from_the_client_is_stripped_and_echoed() {
+        use microdns_core::config::EcsConfig;
+
+        let (upstream, seen) = ecs_upstream().await;
+        let resolver =
+            forwarding_resolver(&[upstream]).with_ecs(Ecs::from_config(&EcsConfig::default()));
+        let with_option = |id, name: &str, subnet: ecs::Subnet| {
+            ecs::with_subnet(&query_bytes(id, name), subnet).unwrap()
+        };
+        let private = ecs::Subnet::new("10.1.2.0".parse().unwrap(), 24);
+
+        let response = resolver
+            .resolve(&with_option(1, "cdn.example.test.", private), LOCAL)
+            .await
+            .unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![None], "nothing went upstream");
+        assert_eq!(ecs::read(&response), Some((private, 0)), "echoed, scope 0");
+        assert_eq!(
+            response.answers()[0].data().and_then(|d| d.as_a()).map(|a| a.0.to_string()),
+            Some("192.0.2.1".to_string())
+        );
+
+        // That answer is everyone's, and still echoes each client's option.
+        let other = ecs::Subnet::new("10.9.0.0".parse().unwrap(), 16);
+        let response = resolver
+            .resolve(&with_option(2, "cdn.example.test.", other), LOCAL)
+            .await
+            .unwrap();
+        assert_eq!(ecs::read(&Message::from_bytes(&response).unwrap()), Some((other, 0)));
+        let response = resolver.resolve(&query_bytes(3, "cdn.example.test."), LOCAL).await.unwrap();
+        assert!(ecs::read(&Message::from_bytes(&response).unwrap()).is_none());
+        assert_eq!(seen.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn dns64_synthesizes_aaaa_for_names_with_only_an_a_record() {
+        use microdns_core::config::Dns64Config;
+        use microdns_core::types::RecordData;
+
+        let (db, _dir) = local_zone_db();
+        let lan = db.get_zone_by_name("lan.test").unwrap().unwrap().id;
+        add_record(&db, lan, "v4only", RecordData::A("198.51.100.7".parse().unwrap()));
+        add_record(&db, lan, "dual", RecordData::AAAA("2001:db8::7".parse().unwrap()));
+        add_record(&db, lan, "legacy", RecordData::A("198.51.100.8".parse().unwrap()));
+        let resolver = Resolver::new(
+            Arc::new(DnsCache::new(100)),
+            Arc::new(ForwardTable::from_config(&Default::default())),
+            Some(db),
+        )
+        .with_dns64(Dns64::from_config(&Dns64Config {
+            exclude_names: vec!["legacy.lan.test".into()],
+            ..Default::default()
+        }));
+
+        let aaaa = |id, name: &str| {
+            let mut request = query_message(id, name);
+            let mut queries = request.take_queries();
+            queries[0].set_query_type(RecordType::AAAA);
+            request.add_queries(queries);
+            request.to_bytes().unwrap()
+        };
+
+        let response = resolver.resolve(&aaaa(1, "v4only.lan.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(
+            response.answers()[0].data().and_then(|d| d.as_aaaa()).map(|a| a.0),
+            Some("64:ff9b::198.51.100.7".parse().unwrap())
+        );
+        assert!(response.answers()[0].ttl() <= 30, "capped by the zone's negative TTL");
+
+        // A real AAAA wins, and excluded names keep their empty answer.
+        let response = resolver.resolve(&aaaa(3, "dual.lan.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(
+            response.answers()[0].data().and_then(|d| d.as_aaaa()).map(|a| a.0),
+            Some("2001:db8::7".parse().unwrap())
+        );
+        let response = resolver.resolve(&aaaa(4, "legacy.lan.test."), LOCAL).await.unwrap();
+        let response = Message::from_bytes(&response).unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
 }
@@ -0,0 +1,634 @@
+//! Response Policy Zones: rules, kept as ordinary zones, for what the recursor
+//! must not resolve as asked.
+//!
+//! A policy zone is a zone in the database like any other — created locally,
+//! filled by the blocklist importer, or mirrored from a primary over AXFR — and
+//! its records are read as rules in the RPZ format:
+//!
+//! | Owner (relative to the policy zone) | Trigger |
+//! |---|---|
+//! | `ads.example.com`, `*.example.com` | the query name (QNAME) |
+//! | `32.1.2.0.192.rpz-ip`, `48.zz.db8.2001.rpz-ip` | an address in the answer |
+//! | `ns1.evil.example.rpz-nsdname` | a name server named in the answer |
+//!
+//! and the records at that owner decide what happens: `CNAME .` answers
+//! NXDOMAIN, `CNAME *.` answers NODATA, `CNAME rpz-passthru.` exempts the name
+//! from every later rule, and anything else is local data served in place of
+//! the real answer.
+//!
+//! Zones are consulted in configured order and the first that matches wins;
+//! within a zone a QNAME rule beats an IP rule beats an NSDNAME rule. The
+//! compiled rules are swapped in whole by a watcher, so a query always sees one
+//! consistent set.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use hickory_proto::op::Message;
+use hickory_proto::rr::RData;
+use microdns_core::config::RpzConfig;
+use microdns_core::db::Db;
+use microdns_core::net::IpNet;
+use microdns_core::types::{Record, RecordData};
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+const PASSTHRU: &str = "rpz-passthru";
+const IP_SUFFIX: &str = ".rpz-ip";
+const NSDNAME_SUFFIX: &str = ".rpz-nsdname";
+
+/// What a matching rule does to the query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    NxDomain,
+    NoData,
+    /// Answer as if no policy existed, and stop looking for one.
+    Passthru,
+    /// Answer with these records, owned by the query name.
+    LocalData(Vec<(u32, RecordData)>),
+}
+
+/// Which part of the query or answer a rule matched on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    Qname,
+    Ip,
+    Nsdname,
+}
+
+/// A rule that matched.
+#[derive(Debug)]
+pub struct Hit<'a> {
+    pub zone: &'a str,
+    pub trigger: Trigger,
+    pub action: &'a Action,
+}
+
+/// One policy zone, compiled.
+#[derive(Debug)]
+pub struct PolicyZone {
+    name: String,
+    /// Serial and last-change time of the zone this was compiled from; `None`
+    /// when the zone does not exist.
+    version: Option<(u32, i64)>,
+    qname: HashMap<String, Action>,
+    nsdname: HashMap<String, Action>,
+    /// Most specific first.
+    ip: Vec<(IpNet, Action)>,
+    hits: AtomicU64,
+}
+
+impl PolicyZone {
+    /// Compile a zone's records into rules. Records that are not rules — the
+    /// apex SOA and NS, or triggers this resolver does not implement — are
+    /// skipped.
+    pub fn compile(name: &str, version: Option<(u32, i64)>, records: &[Record]) -> Self {
+        let mut by_owner: HashMap<String, Vec<(u32, RecordData)>> = HashMap::new();
+        for record in records.iter().filter(|r| r.enabled) {
+            let owner = record.name.trim_end_matches('.').to_lowercase();
+            if owner == "@" || owner.is_empty() {
+                continue;
+            }
+            by_owner
+                .entry(owner)
+                .or_default()
+                .push((record.ttl, record.data.clone()));
+        }
+
+        let mut zone = Self {
+            name: name.to_string(),
+            version,
+            qname: HashMap::new(),
+            nsdname: HashMap::new(),
+            ip: Vec::new(),
+            hits: AtomicU64::new(0),
+        };
+
+        for (owner, data) in by_owner {
+            let Some(action) = action_for(&data) else {
+                debug!("rpz {name}: no usable action at '{owner}'");
+                continue;
+            };
+            if let Some(trigger) = owner.strip_suffix(IP_SUFFIX) {
+                match parse_ip_trigger(trigger) {
+                    Some(net) => zone.ip.push((net, action)),
+                    None => warn!("rpz {name}: ignoring malformed IP trigger '{owner}'"),
+                }
+            } else if let Some(trigger) = owner.strip_suffix(NSDNAME_SUFFIX) {
+                zone.nsdname.insert(trigger.to_string(), action);
+            } else if owner.split('.').any(|label| label.starts_with("rpz-")) {
+                debug!("rpz {name}: unsupported trigger '{owner}'");
+            } else {
+                zone.qname.insert(owner, action);
+            }
+        }
+        zone.ip
+            .sort_by_key(|(net, _)| std::cmp::Reverse(net.prefix_len()));
+        zone
+    }
+
+    fn missing(name: &str) -> Self {
+        Self::compile(name, None, &[])
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn len(&self) -> usize {
+        self.qname.len() + self.nsdname.len() + self.ip.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn hit<'a>(&'a self, trigger: Trigger, action: &'a Action) -> Hit<'a> {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Hit {
+            zone: &self.name,
+            trigger,
+            action,
+        }
+    }
+
+    fn check_qname(&self, qname: &str) -> Option<Hit<'_>> {
+        lookup_name(&self.qname, qname).map(|a| self.hit(Trigger::Qname, a))
+    }
+
+    fn check_ip(&self, ip: IpAddr) -> Option<Hit<'_>> {
+        self.ip
+            .iter()
+            .find(|(net, _)| net.contains(ip))
+            .map(|(_, a)| self.hit(Trigger::Ip, a))
+    }
+
+    fn check_nsdname(&self, ns: &str) -> Option<Hit<'_>> {
+        lookup_name(&self.nsdname, ns).map(|a| self.hit(Trigger::Nsdname, a))
+    }
+}
+
+/// The action a set of records at one owner name calls for.
+fn action_for(data: &[(u32, RecordData)]) -> Option<Action> {
+    if let Some((ttl, RecordData::CNAME(target))) =
+        data.iter().find(|(_, d)| matches!(d, RecordData::CNAME(_)))
+    {
+        let target = target.trim_end_matches('.').to_lowercase();
+        return match target.as_str() {
+            "" => Some(Action::NxDomain),
+            "*" => Some(Action::NoData),
+            PASSTHRU => Some(Action::Passthru),
+            t if t.starts_with("rpz-") => None,
+            _ => Some(Action::LocalData(vec![(*ttl, RecordData::CNAME(target))])),
+        };
+    }
+    let records: Vec<_> = data
+        .iter()
+        .filter(|(_, d)| !matches!(d, RecordData::SOA(_) | RecordData::NS(_)))
+        .cloned()
+        .collect();
+    (!records.is_empty()).then_some(Action::LocalData(records))
+}
+
+/// Exact match, then the most specific wildcard covering the name.
+fn lookup_name<'a>(rules: &'a HashMap<String, Action>, name: &str) -> Option<&'a Action> {
+    if rules.is_empty() {
+        return None;
+    }
+    if let Some(action) = rules.get(name) {
+        return Some(action);
+    }
+    let mut rest = name;
+    while let Some((_, parent)) = rest.split_once('.') {
+        if let Some(action) = rules.get(&format!("*.{parent}")) {
+            return Some(action);
+        }
+        rest = parent;
+    }
+    rules.get("*")
+}
+
+/// Parse the labels before `.rpz-ip`: a prefix length, then the address with
+/// its labels reversed — `24.0.2.0.192` is 192.0.2.0/24, and `zz` stands for
+/// the `::` of an IPv6 address.
+fn parse_ip_trigger(labels: &str) -> Option<IpNet> {
+    let (prefix, addr) = labels.split_once('.')?;
+    let prefix: u8 = prefix.parse().ok()?;
+    let parts: Vec<&str> = addr.rsplit('.').collect();
+
+    let ip: IpAddr = if parts.len() == 4 && !parts.contains(&"zz") {
+        parts.join(".").parse().ok()?
+    } else {
+        let mut text = parts.join(":").replace("zz", "");
+        if text.starts_with(':') {
+            text.insert(0, ':');
+        }
+        if text.ends_with(':') {
+            text.push(':');
+        }
+        text.parse().ok()?
+    };
+    IpNet::parse(&format!("{ip}/{prefix}"))
+}
+
+/// Every policy zone in force, in precedence order.
+#[derive(Debug, Default)]
+pub struct PolicySet {
+    zones: Vec<Arc<PolicyZone>>,
+}
+
+impl PolicySet {
+    pub fn new(zones: Vec<PolicyZone>) -> Self {
+        Self {
+            zones: zones.into_iter().map(Arc::new).collect(),
+        }
+    }
+
+    /// Whether there are no rules at all, so the answer path can skip policy.
+    pub fn is_empty(&self) -> bool {
+        self.zones.iter().all(|z| z.is_empty())
+    }
+
+    /// The rule for a query name, checked before anything is resolved.
+    pub fn check_qname(&self, qname: &str) -> Option<Hit<'_>> {
+        self.zones.iter().find_map(|z| z.check_qname(qname))
+    }
+
+    /// The rule an answer triggers: a name the answer CNAMEs to, an address
+    /// in it, or a name server it names.
+    pub fn check_response(&self, response: &Message) -> Option<Hit<'_>> {
+        let records = response
+            .answers()
+            .iter()
+            .chain(response.name_servers())
+            .filter_map(|r| r.data());
+
+        let mut targets = Vec::new();
+        let mut ips = Vec::new();
+        let mut servers = Vec::new();
+        for rdata in records {
+            match rdata {
+                RData::CNAME(c) => targets.push(normalize(&c.0.to_string())),
+                RData::A(a) => ips.push(IpAddr::V4(a.0)),
+                RData::AAAA(a) => ips.push(IpAddr::V6(a.0)),
+                RData::NS(ns) => servers.push(normalize(&ns.0.to_string())),
+                _ => {}
+            }
+        }
+
+        self.zones.iter().find_map(|zone| {
+            targets
+                .iter()
+                .find_map(|t| zone.check_qname(t))
+                .or_else(|| ips.iter().find_map(|ip| zone.check_ip(*ip)))
+                .or_else(|| servers.iter().find_map(|ns| zone.check_nsdname(ns)))
+        })
+    }
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+/// How one policy zone stands, for the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyZoneStatus {
+    pub zone: String,
+    /// Whether the zone exists in the database. A configured zone that does
+    /// not — a typo, or a mirror that has not transferred yet — applies nothing.
+    pub loaded: bool,
+    pub serial: Option<u32>,
+    pub qname_rules: usize,
+    pub ip_rules: usize,
+    pub nsdname_rules: usize,
+    pub hits: u64,
+}
+
+/// Shared handle to the policy in force.
+#[derive(Clone, Default)]
+pub struct RpzState {
+    inner: Arc<Mutex<Arc<PolicySet>>>,
+}
+
+impl RpzState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The policy to apply to one query.
+    pub fn current(&self) -> Arc<PolicySet> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// Recompile whatever changed — the zone list, or the contents of a zone
+    /// (by serial and modification time). Unchanged zones keep their compiled
+    /// rules and hit counts. Returns whether anything changed.
+    pub fn refresh(&self, db: &Db, config: &RpzConfig) -> bool {
+        let current = self.current();
+        let mut changed = current.zones.len() != config.zones.len();
+        let mut zones = Vec::with_capacity(config.zones.len());
+
+        for (i, configured) in config.zones.iter().enumerate() {
+            let name = normalize(configured);
+            let zone = match db.get_zone_by_name(&name) {
+                Ok(zone) => zone,
+                Err(e) => {
+                    warn!("rpz {name}: could not read zone: {e}");
+                    None
+                }
+            };
+            let version = zone
+                .as_ref()
+                .map(|z| (z.soa.serial, z.updated_at.timestamp_micros()));
+
+            let previous = current.zones.get(i);
+            if let Some(prev) = previous.filter(|p| p.name == name && p.version == version) {
+                zones.push(prev.clone());
+                continue;
+            }
+            changed = true;
+
+            let compiled = match zone {
+                Some(zone) => match db.list_records(&zone.id) {
+                    Ok(records) => PolicyZone::compile(&name, version, &records),
+                    Err(e) => {
+                        warn!("rpz {name}: could not read records: {e}");
+                        PolicyZone::missing(&name)
+                    }
+                },
+                None => PolicyZone::missing(&name),
+            };
+            info!("rpz {name}: loaded {} rule(s)", compiled.len());
+            zones.push(Arc::new(compiled));
+        }
+
+        if changed {
+            *self.inner.lock().unwrap() = Arc::new(PolicySet { zones });
+        }
+        changed
+    }
+
+    pub fn status(&self) -> Vec<PolicyZoneStatus> {
+        self.current()
+            .zones
+            .iter()
+            .map(|z| PolicyZoneStatus {
+                zone: z.name.clone(),
+                loaded: z.version.is_some(),
+                serial: z.version.map(|(serial, _)| serial),
+                qname_rules: z.qname.len(),
+                ip_rules: z.ip.len(),
+                nsdname_rules: z.nsdname.len(),
+                hits: z.hits.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// One line describing what is in force, for the log on startup or change.
+    pub fn summary(&self) -> String {
+        let set = self.current();
+        if set.zones.is_empty() {
+            return "no policy zones".to_string();
+        }
+        let rules: usize = set.zones.iter().map(|z| z.len()).sum();
+        format!("{} policy zone(s), {rules} rule(s)", set.zones.len())
+    }
+}
+
+/// Hostnames a hosts file maps for the machine itself, never worth blocking.
+const HOSTS_BOILERPLATE: &[&str] = &[
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "ip6-localhost",
+    "ip6-loopback",
+    "ip6-localnet",
+    "ip6-mcastprefix",
+    "ip6-allnodes",
+    "ip6-allrouters",
+    "ip6-allhosts",
+];
+
+/// The domains in a blocklist, deduplicated, in the order they appear.
+///
+/// Takes a hosts file (`0.0.0.0 ads.example.com`), a plain list of one domain
+/// per line, or the domain-only subset of adblock syntax (`||ads.example.com^`),
+/// mixed freely. Comments (`#`, `!`) and anything that is not a multi-label
+/// domain are skipped — a stray `com` must not block a whole TLD.
+pub fn parse_blocklist(text: &str) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut names = Vec::new();
+
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() || line.starts_with('!') || line.starts_with('[') {
+            continue;
+        }
+        let mut tokens = line.split_whitespace().peekable();
+        if tokens.peek().is_some_and(|t| t.parse::<IpAddr>().is_ok()) {
+            tokens.next();
+        }
+        for token in tokens {
+            let name = token
+                .strip_prefix("||")
+                .unwrap_or(token)
+                .trim_end_matches('^')
+                .trim_matches('.')
+                .to_lowercase();
+            if is_blockable(&name) && seen.insert(name.clone()) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+fn is_blockable(name: &str) -> bool {
+    name.contains('.')
+        && name.len() <= 253
+        && !HOSTS_BOILERPLATE.contains(&name)
+        && name.parse::<IpAddr>().is_err()
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::MessageType;
+    use hickory_proto::rr::Record as DnsRecord;
+    use microdns_core::types::RecordSource;
+    use std::str::FromStr;
+
+    fn rule(name: &str, data: RecordData) -> Record {
+        let now = Default::default();
+        Record {
+            id: Default::default(),
+            zone_id: Default::default(),
+            name: name.into(),
+            ttl: 60,
+            data,
+            enabled: true,
+            health_check: None,
+            source: RecordSource::Manual,
+            origin: None,
+            created_at: now,
+            updated_at: now,
+        }
+    }
+
+    fn cname(name: &str, target: &str) -> Record {
+        rule(name, RecordData::CNAME(target.into()))
+    }
+
+    fn policy() -> PolicySet {
+        PolicySet::new(vec![PolicyZone::compile(
+            "rpz.lo",
+            Some((1, 0)),
+            &[
+                cname("ads.example.com", "."),
+                cname("*.tracker.example", "."),
+                cname("quiet.example.com", "*."),
+                cname("ok.tracker.example", "rpz-passthru."),
+                rule(
+                    "portal.example.com",
+                    RecordData::A("192.168.1.1".parse().unwrap()),
+                ),
+                cname("24.0.2.0.192.rpz-ip", "."),
+                cname("ns1.evil.example.rpz-nsdname", "."),
+                cname("128.1.zz.db8.2001.rpz-ip", "*."),
+            ],
+        )])
+    }
+
+    #[test]
+    fn qname_rules_match_exactly_or_by_wildcard() {
+        let policy = policy();
+        let action = |name| policy.check_qname(name).map(|h| h.action.clone());
+
+        assert_eq!(action("ads.example.com"), Some(Action::NxDomain));
+        assert_eq!(action("quiet.example.com"), Some(Action::NoData));
+        assert_eq!(action("a.b.tracker.example"), Some(Action::NxDomain));
+        assert_eq!(action("ok.tracker.example"), Some(Action::Passthru));
+        // A wildcard covers subdomains only, as in any zone.
+        assert_eq!(action("tracker.example"), None);
+        assert_eq!(action("www.example.com"), None);
+        assert!(matches!(
+            action("portal.example.com"),
+            Some(Action::LocalData(ref records)) if records.len() == 1
+        ));
+    }
+
+    #[test]
+    fn ip_triggers_parse_in_reversed_rpz_form() {
+        assert_eq!(
+            parse_ip_trigger("24.0.2.0.192"),
+            IpNet::parse("192.0.2.0/24")
+        );
+        assert_eq!(
+            parse_ip_trigger("128.1.zz.db8.2001"),
+            IpNet::parse("2001:db8::1/128")
+        );
+        assert_eq!(parse_ip_trigger("64.zz.1"), IpNet::parse("1::/64"));
+        assert!(parse_ip_trigger("33.1.2.0.192").is_none());
+        assert!(parse_ip_trigger("nonsense").is_none());
+    }
+
+    #[test]
+    fn answers_trigger_ip_and_nsdname_rules() {
+        let policy = policy();
+        let answer = |rdata: RData| {
+            let mut msg = Message::new();
+            msg.set_message_type(MessageType::Response);
+            msg.add_answer(DnsRecord::from_rdata(
+                hickory_proto::rr::Name::from_str("host.example.net.").unwrap(),
+                60,
+                rdata,
+            ));
+            msg
+        };
+
+        let hit = policy
+            .check_response(&answer(RData::A(
+                "192.0.2.77".parse::<std::net::Ipv4Addr>().unwrap().into(),
+            )))
+            .unwrap();
+        assert_eq!((hit.trigger, hit.action), (Trigger::Ip, &Action::NxDomain));
+
+        let hit = policy
+            .check_response(&answer(RData::AAAA(
+                "2001:db8::1".parse::<std::net::Ipv6Addr>().unwrap().into(),
+            )))
+            .unwrap();
+        assert_eq!(hit.action, &Action::NoData);
+
+        let ns = RData::NS(hickory_proto::rr::rdata::NS(
+            hickory_proto::rr::Name::from_str("NS1.Evil.Example.").unwrap(),
+        ));
+        assert_eq!(
+            policy.check_response(&answer(ns)).unwrap().trigger,
+            Trigger::Nsdname
+        );
+
+        let cname = RData::CNAME(hickory_proto::rr::rdata::CNAME(
+            hickory_proto::rr::Name::from_str("x.tracker.example.").unwrap(),
+        ));
+        assert_eq!(
+            policy.check_response(&answer(cname)).unwrap().trigger,
+            Trigger::Qname
+        );
+
+        let clean = RData::A("198.51.100.1".parse::<std::net::Ipv4Addr>().unwrap().into());
+        assert!(policy.check_response(&answer(clean)).is_none());
+    }
+
+    #[test]
+    fn earlier_zones_take_precedence() {
+        let policy = PolicySet::new(vec![
+            PolicyZone::compile(
+                "allow.lo",
+                Some((1, 0)),
+                &[cname("ads.example.com", "rpz-passthru.")],
+            ),
+            PolicyZone::compile("block.lo", Some((1, 0)), &[cname("*.example.com", ".")]),
+        ]);
+        let hit = policy.check_qname("ads.example.com").unwrap();
+        assert_eq!((hit.zone, hit.action), ("allow.lo", &Action::Passthru));
+        assert_eq!(
+            policy.check_qname("cdn.example.com").unwrap().zone,
+            "block.lo"
+        );
+    }
+
+    #[test]
+    fn blocklists_parse_from_hosts_and_domain_lists() {
+        let text = "\
+# StevenBlack-style hosts
+127.0.0.1 localhost
+0.0.0.0 0.0.0.0
+0.0.0.0 ads.example.com tracker.example.net # trailing comment
+ADS.example.com
+||metrics.example.org^
+! adblock comment
+com
+bad_label!.example
+";
+        assert_eq!(
+            parse_blocklist(text),
+            vec![
+                "ads.example.com",
+                "tracker.example.net",
+                "metrics.example.org"
+            ]
+        );
+    }
+}
@@ -0,0 +1,903 @@
+use crate::tls;
+use crate::upstream::Upstream;
+use dashmap::DashMap;
+use hickory_proto::op::{Message, MessageType, Query};
+use hickory_proto::serialize::binary::BinDecodable;
+use microdns_core::config::DnsRecursorConfig;
+use rand::random;
+use rustls::pki_types::ServerName;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::Semaphore;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+/// Largest UDP response we accept before falling back to TCP.
+const MAX_UDP_RESPONSE: usize = 4096;
+
+/// Media type for DNS messages over HTTPS (RFC 8484 §6).
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Where the question section starts, right after the header.
+const QUESTION_OFFSET: usize = 12;
+
+/// Stream connections each upstream may have open at once.
+const POOL_SIZE: usize = 4;
+
+/// How queries reach an upstream server.
+#[derive(Debug, Clone)]
+pub struct TransportConfig {
+    /// Time to wait for each attempt before retransmitting or giving up.
+    pub timeout: Duration,
+    /// UDP retransmissions to the same server after the first attempt.
+    pub retries: u32,
+    /// Query plain upstreams over long-lived TCP connections instead of UDP.
+    pub persistent_tcp: bool,
+    /// Randomize the case of plain-DNS query names and insist the answer
+    /// echoes it, unless the upstream opts out.
+    pub randomize_case: bool,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(2000),
+            retries: 1,
+            persistent_tcp: false,
+            randomize_case: true,
+        }
+    }
+}
+
+impl TransportConfig {
+    pub fn from_config(config: &DnsRecursorConfig) -> Self {
+        Self {
+            timeout: Duration::from_millis(config.upstream_timeout_ms.max(1)),
+            retries: config.upstream_retries,
+            persistent_tcp: config.upstream_tcp,
+            randomize_case: config.upstream_randomize_case,
+        }
+    }
+}
+
+/// Sends queries upstream and collects answers we can trust.
+///
+/// Every query goes out under a fresh random ID, and a response is only taken
+/// if its ID and question match what was asked — anything else arriving on the
+/// socket is an off-path spoofing attempt or a stray, and is ignored. A
+/// truncated UDP answer is retried over TCP.
+///
+/// Plain queries also go out with the query name in random letter case (the
+/// "0x20" trick), and the answer must echo that case exactly: a spoofer has to
+/// guess one more bit per letter. The caller gets the answer back with the
+/// question in its own case.
+///
+/// Encrypted upstreams keep their connections open between queries: a few TLS
+/// sessions per DoT server, and an HTTP connection pool per DoH pin set.
+pub struct Transport {
+    config: TransportConfig,
+    tcp: Pool<TcpStream>,
+    tls: Pool<TlsStream<TcpStream>>,
+    /// TLS client settings per pin set (the empty set means CA verification).
+    tls_configs: DashMap<Vec<[u8; 32]>, Arc<rustls::ClientConfig>>,
+    https_clients: DashMap<Vec<[u8; 32]>, reqwest::Client>,
+}
+
+impl Transport {
+    pub fn new(config: TransportConfig) -> Self {
+        Self {
+            config,
+            tcp: Pool::default(),
+            tls: Pool::default(),
+            tls_configs: DashMap::new(),
+            https_clients: DashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &TransportConfig {
+        &self.config
+    }
+
+    /// Send a raw DNS query to `server` and return the raw response. The
+    /// response carries the upstream query ID, not the caller's.
+    pub async fn query(&self, data: &[u8], server: &Upstream) -> anyhow::Result<Vec<u8>> {
+        let request = Message::from_bytes(data)?;
+        let id: u16 = random();
+        let mut outgoing = data.to_vec();
+        outgoing[..2].copy_from_slice(&id.to_be_bytes());
+        let qname = match server {
+            Upstream::Plain {
+                randomize_case: true,
+                ..
+            } if self.config.randomize_case => randomize_case(&mut outgoing),
+            _ => None,
+        };
+        let expect = Expected {
+            id,
+            queries: request.queries(),
+            qname: qname.clone().map(|span| &outgoing[span]),
+        };
+
+        match server {
+            Upstream::Plain { addr, .. } => {
+                let mut response = self.query_plain(&outgoing, *addr, &expect).await?;
+                // Hand back the question as it was asked; answer names that
+                // point at it by compression follow along.
+                if let Some(span) = qname {
+                    if let Some(echoed) = response.get_mut(span.clone()) {
+                        echoed.copy_from_slice(&data[span]);
+                    }
+                }
+                Ok(response)
+            }
+            Upstream::Tls {
+                host,
+                port,
+                server_name,
+                pins,
+                ..
+            } => {
+                let key = server.to_string();
+                let connect = || self.connect_tls(host, *port, server_name, pins);
+                self.tls
+                    .exchange(key, self.config.timeout, connect, &outgoing, &expect)
+                    .await
+            }
+            Upstream::Https { url, pins, .. } => {
+                self.query_https(&outgoing, url, pins, &expect).await
+            }
+        }
+    }
+
+    async fn query_plain(
+        &self,
+        data: &[u8],
+        server: SocketAddr,
+        expect: &Expected<'_>,
+    ) -> anyhow::Result<Vec<u8>> {
+        if self.config.persistent_tcp {
+            let connect = || async move { Ok(TcpStream::connect(server).await?) };
+            return self
+                .tcp
+                .exchange(server.to_string(), self.config.timeout, connect, data, expect)
+                .await;
+        }
+
+        let response = self.query_udp(data, server, expect).await?;
+        if is_truncated(&response) {
+            debug!("truncated answer from {server}, retrying over TCP");
+            return self.query_tcp(data, server, expect).await;
+        }
+        Ok(response)
+    }
+
+    async fn query_udp(
+        &self,
+        data: &[u8],
+        server: SocketAddr,
+        expect: &Expected<'_>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let bind: SocketAddr = if server.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        // A connected socket only receives datagrams from the server itself.
+        socket.connect(server).await?;
+
+        let mut buf = vec![0u8; MAX_UDP_RESPONSE];
+        for attempt in 0..=self.config.retries {
+            socket.send(data).await?;
+            match tokio::time::timeout(self.config.timeout, recv_matching(&socket, &mut buf, expect))
+                .await
+            {
+                Ok(result) => return result,
+                Err(_) => debug!("upstream {server} timed out (attempt {})", attempt + 1),
+            }
+        }
+        anyhow::bail!("no answer after {} attempt(s)", self.config.retries + 1)
+    }
+
+    /// One query over a fresh TCP connection.
+    async fn query_tcp(
+        &self,
+        data: &[u8],
+        server: SocketAddr,
+        expect: &Expected<'_>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut stream =
+            tokio::time::timeout(self.config.timeout, TcpStream::connect(server)).await??;
+        tokio::time::timeout(self.config.timeout, exchange_stream(&mut stream, data, expect))
+            .await?
+    }
+
+    async fn connect_tls(
+        &self,
+        host: &str,
+        port: u16,
+        server_name: &str,
+        pins: &[[u8; 32]],
+    ) -> anyhow::Result<TlsStream<TcpStream>> {
+        let config = self
+            .tls_configs
+            .entry(pins.to_vec())
+            .or_insert_with(|| tls::client_config(pins))
+            .clone();
+        let name = ServerName::try_from(server_name.to_string())?;
+        let tcp = TcpStream::connect((host, port)).await?;
+        Ok(TlsConnector::from(config).connect(name, tcp).await?)
+    }
+
+    /// RFC 8484 POST. reqwest keeps the connection alive between queries.
+    async fn query_https(
+        &self,
+        data: &[u8],
+        url: &str,
+        pins: &[[u8; 32]],
+        expect: &Expected<'_>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let client = match self.https_clients.get(pins) {
+            Some(c) => c.clone(),
+            None => {
+                let client = reqwest::Client::builder()
+                    .use_preconfigured_tls((*tls::client_config(pins)).clone())
+                    .build()?;
+                self.https_clients.insert(pins.to_vec(), client.clone());
+                client
+            }
+        };
+
+        let response = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, DNS_MESSAGE)
+            .header(reqwest::header::ACCEPT, DNS_MESSAGE)
+            .body(data.to_vec())
+            .timeout(self.config.timeout)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            anyhow::bail!("HTTP {}", response.status());
+        }
+        let body = response.bytes().await?;
+        if !expect.matches(&body) || is_truncated(&body) {
+            anyhow::bail!("DoH answer does not match the query");
+        }
+        Ok(body.to_vec())
+    }
+}
+
+/// Stream connections kept open per upstream. Each carries one query at a
+/// time, and up to [`POOL_SIZE`] are open at once, so a slow answer only holds
+/// up its own connection.
+struct Pool<S> {
+    upstreams: DashMap<String, Arc<Slots<S>>>,
+}
+
+struct Slots<S> {
+    idle: std::sync::Mutex<Vec<S>>,
+    open: Semaphore,
+}
+
+impl<S> Default for Pool<S> {
+    fn default() -> Self {
+        Self {
+            upstreams: DashMap::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Pool<S> {
+    /// One query over an idle pooled connection to the upstream, or a new one
+    /// if none is idle. A reused connection the server has since closed is
+    /// replaced once.
+    async fn exchange<F, Fut>(
+        &self,
+        key: String,
+        timeout: Duration,
+        connect: F,
+        data: &[u8],
+        expect: &Expected<'_>,
+    ) -> anyhow::Result<Vec<u8>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<S>>,
+    {
+        let slots = self
+            .upstreams
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(Slots {
+                    idle: std::sync::Mutex::new(Vec::new()),
+                    open: Semaphore::new(POOL_SIZE),
+                })
+            })
+            .clone();
+        let _permit = tokio::time::timeout(timeout, slots.open.acquire()).await??;
+
+        let idle = slots.idle.lock().unwrap().pop();
+        if let Some(mut stream) = idle {
+            match tokio::time::timeout(timeout, exchange_stream(&mut stream, data, expect)).await {
+                Ok(Ok(response)) => {
+                    slots.idle.lock().unwrap().push(stream);
+                    return Ok(response);
+                }
+                _ => debug!("pooled upstream connection went stale, reconnecting"),
+            }
+        }
+
+        let mut stream = tokio::time::timeout(timeout, connect()).await??;
+        let response =
+            tokio::time::timeout(timeout, exchange_stream(&mut stream, data, expect)).await??;
+        slots.idle.lock().unwrap().push(stream);
+        Ok(response)
+    }
+}
+
+/// What a genuine answer to our query must look like.
+struct Expected<'a> {
+    id: u16,
+    queries: &'a [Query],
+    /// The query name as sent, when its case was randomized: the answer's
+    /// question must carry it byte for byte.
+    qname: Option<&'a [u8]>,
+}
+
+impl Expected<'_> {
+    fn matches(&self, response: &[u8]) -> bool {
+        if response.len() < 12 || u16::from_be_bytes([response[0], response[1]]) != self.id {
+            return false;
+        }
+        // A truncated answer only has to get us to TCP, where it is checked in
+        // full; some servers send it with an empty question section.
+        if is_truncated(response) {
+            return true;
+        }
+        if let Some(qname) = self.qname {
+            if response.get(QUESTION_OFFSET..QUESTION_OFFSET + qname.len()) != Some(qname) {
+                return false;
+            }
+        }
+        match Message::from_bytes(response) {
+            Ok(msg) => msg.message_type() == MessageType::Response && msg.queries() == self.queries,
+            Err(_) => false,
+        }
+    }
+}
+
+async fn recv_matching(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+    expect: &Expected<'_>,
+) -> anyhow::Result<Vec<u8>> {
+    loop {
+        let len = socket.recv(buf).await?;
+        if expect.matches(&buf[..len]) {
+            return Ok(buf[..len].to_vec());
+        }
+        debug!("discarding upstream response that does not match the query");
+    }
+}
+
+/// DNS over a stream (TCP or TLS): 2-byte length prefix, then the message.
+async fn exchange_stream<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    data: &[u8],
+    expect: &Expected<'_>,
+) -> anyhow::Result<Vec<u8>> {
+    let len = u16::try_from(data.len())?;
+    stream.write_all(&len.to_be_bytes()).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+
+    let len = stream.read_u16().await? as usize;
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf).await?;
+    if !expect.matches(&buf) || is_truncated(&buf) {
+        anyhow::bail!("stream answer does not match the query");
+    }
+    Ok(buf)
+}
+
+/// Flip the case of each letter in the query name of `message` at random.
+/// Returns where the name sits, or `None` for a message without one.
+fn randomize_case(message: &mut [u8]) -> Option<Range<usize>> {
+    if message.get(4..6)? == [0, 0] {
+        return None;
+    }
+    let mut pos = QUESTION_OFFSET;
+    loop {
+        let len = usize::from(*message.get(pos)?);
+        if len == 0 {
+            return Some(QUESTION_OFFSET..pos + 1);
+        }
+        // The first name in a message is never compressed.
+        if len & 0xc0 != 0 {
+            return None;
+        }
+        for byte in message.get_mut(pos + 1..pos + 1 + len)? {
+            if byte.is_ascii_alphabetic() && random::<bool>() {
+                *byte ^= 0x20;
+            }
+        }
+        pos += 1 + len;
+    }
+}
+
+/// Check if a raw DNS message has the TC (truncated) flag set.
+fn is_truncated(response: &[u8]) -> bool {
+    response.len() >= 3 && (response[2] & 0x02) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::rr::{Name, RData, Record, RecordType};
+    use hickory_proto::serialize::binary::BinEncodable;
+    use std::str::FromStr;
+    use tokio::net::TcpListener;
+
+    fn query_bytes(name: &str) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.set_id(4242);
+        msg.set_recursion_desired(true);
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        msg.to_bytes().unwrap()
+    }
+
+    fn answer(query: &Message, truncated: bool) -> Message {
+        let mut response = Message::new();
+        response.set_id(query.id());
+        response.set_message_type(MessageType::Response);
+        response.add_query(query.queries()[0].clone());
+        if truncated {
+            response.set_truncated(true);
+        } else {
+            response.add_answer(Record::from_rdata(
+                query.queries()[0].name().clone(),
+                300,
+                RData::A("192.0.2.7".parse::<std::net::Ipv4Addr>().unwrap().into()),
+            ));
+        }
+        response
+    }
+
+    fn fast() -> TransportConfig {
+        TransportConfig {
+            timeout: Duration::from_millis(500),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn truncation_flag_is_read_from_the_header() {
+        assert!(is_truncated(&[0x00, 0x00, 0x82, 0x00]));
+        assert!(!is_truncated(&[0x00, 0x00, 0x80, 0x00]));
+        assert!(!is_truncated(&[0x00]));
+    }
+
+    #[tokio::test]
+    async fn mismatched_responses_are_ignored() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+            let query = Message::from_bytes(&buf[..len]).unwrap();
+
+            // A forged answer with the wrong ID, then one for the wrong name.
+            let mut forged = answer(&query, false);
+            forged.set_id(query.id().wrapping_add(1));
+            socket.send_to(&forged.to_bytes().unwrap(), src).await.unwrap();
+            let mut other = Message::new();
+            other.set_id(query.id());
+            other.set_message_type(MessageType::Response);
+            other.add_query(Query::query(Name::from_str("evil.test.").unwrap(), RecordType::A));
+            socket.send_to(&other.to_bytes().unwrap(), src).await.unwrap();
+
+            socket
+                .send_to(&answer(&query, false).to_bytes().unwrap(), src)
+                .await
+                .unwrap();
+        });
+
+        let transport = Transport::new(fast());
+        let response = transport.query(&query_bytes("host.example.test."), &Upstream::plain(server)).await.unwrap();
+        let msg = Message::from_bytes(&response).unwrap();
+        assert_eq!(msg.queries()[0].name().to_string(), "host.example.test.");
+        assert_eq!(msg.answers().len(), 1);
+    }
+
+    /// A UDP upstream answering every query, with the question name as it
+    /// arrived or folded to lowercase. Sends each name it saw to the channel.
+    async fn echo_upstream(
+        fold_case: bool,
+    ) -> (SocketAddr, tokio::sync::mpsc::UnboundedReceiver<String>) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        let (seen_tx, seen) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            loop {
+                let (len, src) = socket.recv_from(&mut buf).await.unwrap();
+                let mut query = Message::from_bytes(&buf[..len]).unwrap();
+                let _ = seen_tx.send(query.queries()[0].name().to_string());
+                if fold_case {
+                    let mut queries = query.take_queries();
+                    let name = queries[0].name().to_lowercase();
+                    queries[0].set_name(name);
+                    query.add_queries(queries);
+                }
+                let response = answer(&query, false).to_bytes().unwrap();
+                socket.send_to(&response, src).await.unwrap();
+            }
+        });
+        (server, seen)
+    }
+
+    #[tokio::test]
+    async fn query_names_go_out_in_random_case_and_come_back_as_asked() {
+        let (server, mut seen) = echo_upstream(false).await;
+        let transport = Transport::new(fast());
+        let name = "case-randomization.example.test.";
+
+        let mut sent = std::collections::HashSet::new();
+        for _ in 0..4 {
+            let response = transport
+                .query(&query_bytes(name), &Upstream::plain(server))
+                .await
+                .unwrap();
+            let response = Message::from_bytes(&response).unwrap();
+            assert_eq!(response.queries()[0].name().to_string(), name);
+            let wire = seen.recv().await.unwrap();
+            assert!(wire.eq_ignore_ascii_case(name));
+            sent.insert(wire);
+        }
+        assert!(sent.len() > 1, "the case differs from query to query");
+    }
+
+    #[tokio::test]
+    async fn an_upstream_that_folds_case_is_not_believed_unless_opted_out() {
+        let (server, _seen) = echo_upstream(true).await;
+        let transport = Transport::new(TransportConfig {
+            timeout: Duration::from_millis(100),
+            retries: 0,
+            ..Default::default()
+        });
+        let name = "case-randomization.example.test.";
+
+        assert!(transport
+            .query(&query_bytes(name), &Upstream::plain(server))
+            .await
+            .is_err());
+
+        let legacy = Upstream::parse(&format!("{server}?no-0x20")).unwrap();
+        let response = transport.query(&query_bytes(name), &legacy).await.unwrap();
+        assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_truncated_answer_is_retried_over_tcp() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tcp.local_addr().unwrap();
+        let udp = UdpSocket::bind(server).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            let (len, src) = udp.recv_from(&mut buf).await.unwrap();
+            let query = Message::from_bytes(&buf[..len]).unwrap();
+            udp.send_to(&answer(&query, true).to_bytes().unwrap(), src)
+                .await
+                .unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap() as usize;
+            let mut buf = vec![0u8; len];
+            stream.read_exact(&mut buf).await.unwrap();
+            let query = Message::from_bytes(&buf).unwrap();
+            let bytes = answer(&query, false).to_bytes().unwrap();
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+            stream.write_all(&bytes).await.unwrap();
+        });
+
+        let transport = Transport::new(fast());
+        let response = transport
+            .query(&query_bytes("big.example.test."), &Upstream::plain(server))
+            .await
+            .unwrap();
+        let msg = Message::from_bytes(&response).unwrap();
+        assert!(!msg.truncated());
+        assert_eq!(msg.answers().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn a_silent_upstream_is_retried_then_given_up_on() {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server = socket.local_addr().unwrap();
+        let transport = Transport::new(TransportConfig {
+            timeout: Duration::from_millis(50),
+            retries: 2,
+            ..Default::default()
+        });
+
+        let err = transport
+            .query(&query_bytes("host.example.test."), &Upstream::plain(server))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("3 attempt"));
+
+        let mut buf = vec![0u8; 512];
+        for _ in 0..3 {
+            socket.recv_from(&mut buf).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn persistent_tcp_reuses_one_connection() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tcp.local_addr().unwrap();
+        let accepted = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = tcp.accept().await.unwrap();
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                tokio::spawn(async move {
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut buf = vec![0u8; len as usize];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        let query = Message::from_bytes(&buf).unwrap();
+                        let bytes = answer(&query, false).to_bytes().unwrap();
+                        stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                        stream.write_all(&bytes).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let transport = Transport::new(TransportConfig {
+            persistent_tcp: true,
+            ..fast()
+        });
+        for name in ["a.example.test.", "b.example.test.", "c.example.test."] {
+            let response = transport
+                .query(&query_bytes(name), &Upstream::plain(server))
+                .await
+                .unwrap();
+            assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+        }
+        assert_eq!(accepted.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_slow_answer_does_not_hold_up_other_queries() {
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = tcp.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = tcp.accept().await.unwrap();
+                tokio::spawn(async move {
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut buf = vec![0u8; len as usize];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        let query = Message::from_bytes(&buf).unwrap();
+                        let name = query.queries()[0].name().to_lowercase();
+                        if name.to_string().starts_with("slow") {
+                            // Never answered: the connection is stuck.
+                            std::future::pending::<()>().await;
+                        }
+                        let bytes = answer(&query, false).to_bytes().unwrap();
+                        stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                        stream.write_all(&bytes).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let transport = Arc::new(Transport::new(TransportConfig {
+            persistent_tcp: true,
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        }));
+        let slow = {
+            let transport = transport.clone();
+            tokio::spawn(async move {
+                transport
+                    .query(&query_bytes("slow.example.test."), &Upstream::plain(server))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let started = std::time::Instant::now();
+        let response = transport
+            .query(&query_bytes("fast.example.test."), &Upstream::plain(server))
+            .await
+            .unwrap();
+        assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+        // Well inside the slow query's timeout, which a shared connection
+        // would have had to wait out.
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(!slow.is_finished());
+        slow.abort();
+    }
+
+    /// A TLS acceptor with a fresh self-signed certificate for `dns.test`,
+    /// and the pin for its key.
+    fn tls_acceptor() -> (tokio_rustls::TlsAcceptor, [u8; 32]) {
+        use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+
+        let certified = rcgen::generate_simple_self_signed(vec!["dns.test".into()]).unwrap();
+        let cert = certified.cert.der().clone();
+        let pin = tls::spki_sha256(&cert).unwrap();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(
+            certified.key_pair.serialize_der(),
+        ));
+        let config = rustls::ServerConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)
+        .unwrap();
+        (tokio_rustls::TlsAcceptor::from(Arc::new(config)), pin)
+    }
+
+    /// A DoT server for `dns.test`. Returns its address and the pin for its
+    /// key.
+    async fn dot_server() -> (SocketAddr, [u8; 32]) {
+        let (acceptor, pin) = tls_acceptor();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(tcp).await else {
+                        return;
+                    };
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut buf = vec![0u8; len as usize];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        let query = Message::from_bytes(&buf).unwrap();
+                        let bytes = answer(&query, false).to_bytes().unwrap();
+                        stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                        stream.write_all(&bytes).await.unwrap();
+                        stream.flush().await.unwrap();
+                    }
+                });
+            }
+        });
+        (addr, pin)
+    }
+
+    #[tokio::test]
+    async fn dot_with_a_matching_pin_answers() {
+        let (addr, pin) = dot_server().await;
+        let upstream = Upstream::Tls {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            server_name: "dns.test".into(),
+            pins: vec![pin],
+            minimise: true,
+        };
+
+        let transport = Transport::new(fast());
+        for name in ["a.example.test.", "b.example.test."] {
+            let response = transport.query(&query_bytes(name), &upstream).await.unwrap();
+            assert_eq!(Message::from_bytes(&response).unwrap().answers().len(), 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn dot_refuses_a_server_whose_key_is_not_pinned() {
+        let (addr, _) = dot_server().await;
+        let transport = Transport::new(fast());
+
+        let wrong_pin = Upstream::Tls {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            server_name: "dns.test".into(),
+            pins: vec![[0u8; 32]],
+            minimise: true,
+        };
+        assert!(transport.query(&query_bytes("a.example.test."), &wrong_pin).await.is_err());
+
+        // Unpinned, a self-signed certificate does not chain to a public CA.
+        let unpinned = Upstream::Tls {
+            host: addr.ip().to_string(),
+            port: addr.port(),
+            server_name: "dns.test".into(),
+            pins: vec![],
+            minimise: true,
+        };
+        assert!(transport.query(&query_bytes("a.example.test."), &unpinned).await.is_err());
+    }
+
+    /// A DoH server for `dns.test` answering RFC 8484 POSTs over HTTP/1.1.
+    /// Returns its address and the pin for its key.
+    async fn doh_server() -> (SocketAddr, [u8; 32]) {
+        use tokio::io::{AsyncBufReadExt, BufReader};
+
+        let (acceptor, pin) = tls_acceptor();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (tcp, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(stream) = acceptor.accept(tcp).await else {
+                        return;
+                    };
+                    let mut stream = BufReader::new(stream);
+                    loop {
+                        let mut content_length = 0;
+                        let mut line = String::new();
+                        loop {
+                            line.clear();
+                            if stream.read_line(&mut line).await.unwrap_or(0) == 0 {
+                                return;
+                            }
+                            if line == "\r\n" {
+                                break;
+                            }
+                            let lower = line.to_ascii_lowercase();
+                            if let Some(len) = lower.strip_prefix("content-length:") {
+                                content_length = len.trim().parse().unwrap();
+                            }
+                        }
+                        let mut body = vec![0u8; content_length];
+                        stream.read_exact(&mut body).await.unwrap();
+                        let query = Message::from_bytes(&body).unwrap();
+                        let bytes = answer(&query, false).to_bytes().unwrap();
+                        let head = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: {DNS_MESSAGE}\r\n\
+                             content-length: {}\r\n\r\n",
+                            bytes.len()
+                        );
+                        stream.write_all(head.as_bytes()).await.unwrap();
+                        stream.write_all(&bytes).await.unwrap();
+                        stream.flush().await.unwrap();
+                    }
+                });
+            }
+        });
+        (addr, pin)
+    }
+
+    #[tokio::test]
+    async fn doh_with_a_matching_pin_answers() {
+        let (addr, pin) = doh_server().await;
+        let url = format!("https://{addr}/dns-query");
+        let transport = Transport::new(fast());
+
+        let upstream = Upstream::Https {
+            url: url.clone(),
+            pins: vec![pin],
+            minimise: true,
+        };
+        for name in ["a.example.test.", "b.example.test."] {
+            let response = transport.query(&query_bytes(name), &upstream).await.unwrap();
+            let response = Message::from_bytes(&response).unwrap();
+            assert_eq!(response.queries()[0].name().to_string(), name);
+            assert_eq!(response.answers().len(), 1);
+        }
+
+        let wrong_pin = Upstream::Https {
+            url,
+            pins: vec![[0u8; 32]],
+            minimise: true,
+        };
+        assert!(transport.query(&query_bytes("a.example.test."), &wrong_pin).await.is_err());
+    }
+}
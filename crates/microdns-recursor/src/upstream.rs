@@ -0,0 +1,613 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use dashmap::DashMap;
+use serde::Serialize;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// First back-off after an upstream fails; doubles per consecutive failure.
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+
+/// Longest an upstream is skipped before it is given another chance.
+const BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+/// Weight of the newest sample in the smoothed RTT (1/8, as TCP does).
+const SRTT_WEIGHT: f64 = 0.125;
+
+/// Default port for DNS over TLS (RFC 7858).
+pub const DOT_PORT: u16 = 853;
+
+/// An upstream server and how to talk to it.
+///
+/// Written in config and in `DnsForwarder.servers` as:
+/// - `10.0.1.1` or `10.0.1.1:53` — plain DNS over UDP, TCP on truncation;
+///   `?no-0x20` leaves the query name's case alone for a server that does not
+///   echo it back exactly
+/// - `tls://1.1.1.1:853#cloudflare-dns.com` — DNS over TLS; the fragment is the
+///   name the certificate must carry, defaulting to the host
+/// - `https://dns.google/dns-query` — DNS over HTTPS (RFC 8484)
+///
+/// Either encrypted form takes `?pin-sha256=<base64>` (repeatable): the SHA-256
+/// of the server's SubjectPublicKeyInfo. With pins set, a matching key is what
+/// authenticates the server instead of a CA chain — RFC 7858's out-of-band
+/// key-pinned profile, for resolvers with self-signed or private-CA certs.
+///
+/// Any form takes `?no-qmin` to send the server full query names even where
+/// QNAME minimisation would apply, for a server that mishandles the minimised
+/// queries (`10.0.1.1?no-0x20&no-qmin` for both opt-outs).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Upstream {
+    Plain {
+        addr: SocketAddr,
+        /// Whether the query name's case may be randomized for this server.
+        randomize_case: bool,
+        /// Whether this server may be sent minimised query names.
+        minimise: bool,
+    },
+    Tls {
+        host: String,
+        port: u16,
+        server_name: String,
+        pins: Vec<[u8; 32]>,
+        minimise: bool,
+    },
+    Https {
+        url: String,
+        pins: Vec<[u8; 32]>,
+        minimise: bool,
+    },
+}
+
+impl Upstream {
+    /// Parse a server entry. Returns `None` for anything unrecognised.
+    pub fn parse(entry: &str) -> Option<Self> {
+        let entry = entry.trim();
+        if let Some(rest) = entry.strip_prefix("tls://") {
+            let (rest, fragment) = match rest.split_once('#') {
+                Some((r, f)) => (r, Some(f)),
+                None => (rest, None),
+            };
+            let (authority, pins, minimise) = split_params(rest)?;
+            let (host, port) = split_host_port(&authority, DOT_PORT)?;
+            let server_name = fragment.filter(|f| !f.is_empty()).unwrap_or(&host).to_string();
+            return Some(Self::Tls {
+                host,
+                port,
+                server_name,
+                pins,
+                minimise,
+            });
+        }
+        if entry.starts_with("https://") {
+            let (url, pins, minimise) = split_params(entry)?;
+            let authority = url["https://".len()..].split('/').next()?;
+            split_host_port(authority, 443)?;
+            return Some(Self::Https {
+                url: url.to_string(),
+                pins,
+                minimise,
+            });
+        }
+        let (entry, params) = entry.split_once('?').unwrap_or((entry, ""));
+        let (mut randomize_case, mut minimise) = (true, true);
+        for param in params.split('&').filter(|p| !p.is_empty()) {
+            match param {
+                "no-0x20" => randomize_case = false,
+                "no-qmin" => minimise = false,
+                _ => return None,
+            }
+        }
+        // Accept "host:port" or just "host" (default port 53)
+        let addr = entry.parse::<SocketAddr>().ok().or_else(|| {
+            entry
+                .parse::<IpAddr>()
+                .ok()
+                .map(|ip| SocketAddr::new(ip, 53))
+        })?;
+        Some(Self::Plain {
+            addr,
+            randomize_case,
+            minimise,
+        })
+    }
+
+    /// A plain upstream at `addr`, with the defaults.
+    pub fn plain(addr: SocketAddr) -> Self {
+        Self::Plain {
+            addr,
+            randomize_case: true,
+            minimise: true,
+        }
+    }
+
+    /// Whether queries to this upstream leave the host encrypted.
+    pub fn is_encrypted(&self) -> bool {
+        !matches!(self, Self::Plain { .. })
+    }
+
+    /// Whether this upstream may be sent minimised query names.
+    pub fn minimise(&self) -> bool {
+        match self {
+            Self::Plain { minimise, .. }
+            | Self::Tls { minimise, .. }
+            | Self::Https { minimise, .. } => *minimise,
+        }
+    }
+
+    /// The server this entry reaches, whatever it is allowed to be sent.
+    fn server(&self) -> Server {
+        match self {
+            Self::Plain { addr, .. } => Server::Plain(*addr),
+            Self::Tls {
+                host,
+                port,
+                server_name,
+                ..
+            } => Server::Tls {
+                host: host.clone(),
+                port: *port,
+                server_name: server_name.clone(),
+            },
+            Self::Https { url, .. } => Server::Https(url.clone()),
+        }
+    }
+}
+
+/// An upstream's transport identity: the address, DoT endpoint and name, or
+/// DoH URL. Entries that differ only in their opt-outs reach the same server.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Server {
+    Plain(SocketAddr),
+    Tls {
+        host: String,
+        port: u16,
+        server_name: String,
+    },
+    Https(String),
+}
+
+impl fmt::Display for Server {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain(addr) => write!(f, "{addr}"),
+            Self::Tls {
+                host,
+                port,
+                server_name,
+            } => {
+                if host.contains(':') {
+                    write!(f, "tls://[{host}]:{port}")?;
+                } else {
+                    write!(f, "tls://{host}:{port}")?;
+                }
+                if server_name != host {
+                    write!(f, "#{server_name}")?;
+                }
+                Ok(())
+            }
+            Self::Https(url) => write!(f, "{url}"),
+        }
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Plain {
+                addr,
+                randomize_case,
+                minimise,
+            } => {
+                write!(f, "{addr}")?;
+                let opt_outs: Vec<&str> = [(!randomize_case, "no-0x20"), (!minimise, "no-qmin")]
+                    .into_iter()
+                    .filter_map(|(set, param)| set.then_some(param))
+                    .collect();
+                if !opt_outs.is_empty() {
+                    write!(f, "?{}", opt_outs.join("&"))?;
+                }
+                Ok(())
+            }
+            Self::Tls {
+                host,
+                port,
+                server_name,
+                minimise,
+                ..
+            } => {
+                if host.contains(':') {
+                    write!(f, "tls://[{host}]:{port}")?;
+                } else {
+                    write!(f, "tls://{host}:{port}")?;
+                }
+                if !minimise {
+                    write!(f, "?no-qmin")?;
+                }
+                if server_name != host {
+                    write!(f, "#{server_name}")?;
+                }
+                Ok(())
+            }
+            Self::Https { url, minimise, .. } => {
+                write!(f, "{url}")?;
+                if !minimise {
+                    let sep = if url.contains('?') { '&' } else { '?' };
+                    write!(f, "{sep}no-qmin")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Pull `pin-sha256` and `no-qmin` parameters out of the query string,
+/// returning what is left, the pins, and whether minimisation is allowed. Any
+/// other parameter is kept (a DoH path may need its own).
+fn split_params(s: &str) -> Option<(String, Vec<[u8; 32]>, bool)> {
+    let Some((base, query)) = s.split_once('?') else {
+        return Some((s.to_string(), Vec::new(), true));
+    };
+    let mut pins = Vec::new();
+    let mut minimise = true;
+    let mut kept = Vec::new();
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        if param == "no-qmin" {
+            minimise = false;
+            continue;
+        }
+        match param.strip_prefix("pin-sha256=") {
+            Some(pin) => {
+                let bytes = BASE64.decode(pin).ok()?;
+                pins.push(<[u8; 32]>::try_from(bytes.as_slice()).ok()?);
+            }
+            None => kept.push(param),
+        }
+    }
+    let rest = if kept.is_empty() {
+        base.to_string()
+    } else {
+        format!("{base}?{}", kept.join("&"))
+    };
+    Some((rest, pins, minimise))
+}
+
+/// Split `host`, `host:port`, `[v6]` or `[v6]:port`.
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    if authority.is_empty() {
+        return None;
+    }
+    if let Some(rest) = authority.strip_prefix('[') {
+        let (host, after) = rest.split_once(']')?;
+        host.parse::<std::net::Ipv6Addr>().ok()?;
+        let port = match after.strip_prefix(':') {
+            Some(p) => p.parse().ok()?,
+            None if after.is_empty() => default_port,
+            None => return None,
+        };
+        return Some((host.to_string(), port));
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => Some((host.to_string(), port.parse().ok()?)),
+        Some(_) => None,
+        None => Some((authority.to_string(), default_port)),
+    }
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    /// Smoothed round-trip time in milliseconds, once anything has answered.
+    srtt_ms: Option<f64>,
+    queries: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    backoff_until: Option<Instant>,
+    last_success: Option<Instant>,
+}
+
+impl ServerState {
+    fn backed_off(&self, now: Instant) -> bool {
+        self.backoff_until.is_some_and(|t| t > now)
+    }
+}
+
+/// Where a server stands in [`UpstreamTracker::order`]: backed off, smoothed
+/// RTT, end of back-off.
+type Rank = (bool, f64, Option<Instant>);
+
+fn by_rank(a: &Rank, b: &Rank) -> std::cmp::Ordering {
+    a.0.cmp(&b.0).then_with(|| {
+        if a.0 {
+            a.2.cmp(&b.2)
+        } else {
+            a.1.total_cmp(&b.1)
+        }
+    })
+}
+
+/// Per-upstream latency and failure history, shared by every forward zone.
+///
+/// Keyed by server rather than by zone, so a forwarder that is dead for one
+/// zone is known to be dead for every zone that lists it — whether the zone
+/// came from the config file or from the database, and whatever opt-outs
+/// each zone gives it.
+#[derive(Default)]
+pub struct UpstreamTracker {
+    servers: DashMap<Server, ServerState>,
+}
+
+/// One row of the scoreboard, as reported over the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub server: String,
+    /// Smoothed RTT, or `null` for a server that has never answered.
+    pub srtt_ms: Option<u64>,
+    pub queries: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    /// False while the server is being skipped after failing.
+    pub healthy: bool,
+    /// Seconds until a backed-off server is tried again.
+    pub backoff_remaining_secs: Option<u64>,
+    pub last_success_secs_ago: Option<u64>,
+}
+
+impl UpstreamTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Order `servers` by preference: healthy servers fastest first, then
+    /// servers in back-off, soonest to recover first. A server nobody has
+    /// heard from yet counts as fast, so a new forwarder gets measured. Ties
+    /// keep configured order.
+    ///
+    /// Backed-off servers stay in the list as a last resort: if every upstream
+    /// is down, asking a dead one costs nothing more than not asking at all.
+    pub fn order(&self, servers: &[Upstream]) -> Vec<Upstream> {
+        let now = Instant::now();
+        let mut ranked: Vec<(Upstream, Rank)> = servers
+            .iter()
+            .map(|upstream| (upstream.clone(), self.rank(&upstream.server(), now)))
+            .collect();
+        ranked.sort_by(|a, b| by_rank(&a.1, &b.1));
+        ranked.into_iter().map(|r| r.0).collect()
+    }
+
+    fn rank(&self, server: &Server, now: Instant) -> Rank {
+        match self.servers.get(server) {
+            Some(s) => (s.backed_off(now), s.srtt_ms.unwrap_or(0.0), s.backoff_until),
+            None => (false, 0.0, None),
+        }
+    }
+
+    pub fn record_success(&self, upstream: &Upstream, rtt: Duration) {
+        let server = upstream.server();
+        let label = server.to_string();
+        metrics::histogram!("microdns_recursor_upstream_rtt_seconds", "upstream" => label)
+            .record(rtt.as_secs_f64());
+        let mut s = self.servers.entry(server).or_default();
+        let sample = rtt.as_secs_f64() * 1000.0;
+        s.srtt_ms = Some(match s.srtt_ms {
+            Some(srtt) => srtt + SRTT_WEIGHT * (sample - srtt),
+            None => sample,
+        });
+        s.queries += 1;
+        s.consecutive_failures = 0;
+        s.backoff_until = None;
+        s.last_success = Some(Instant::now());
+    }
+
+    pub fn record_failure(&self, upstream: &Upstream) {
+        let server = upstream.server();
+        let label = server.to_string();
+        metrics::counter!("microdns_recursor_upstream_failures_total", "upstream" => label)
+            .increment(1);
+        let mut s = self.servers.entry(server).or_default();
+        s.queries += 1;
+        s.failures += 1;
+        s.consecutive_failures = s.consecutive_failures.saturating_add(1);
+        let backoff = BACKOFF_BASE
+            .saturating_mul(1 << (s.consecutive_failures - 1).min(16))
+            .min(BACKOFF_MAX);
+        s.backoff_until = Some(Instant::now() + backoff);
+    }
+
+    /// The scoreboard, healthiest and fastest first.
+    pub fn snapshot(&self) -> Vec<UpstreamStatus> {
+        let now = Instant::now();
+        let mut known: Vec<(Server, Rank)> = self
+            .servers
+            .iter()
+            .map(|e| (e.key().clone(), self.rank(e.key(), now)))
+            .collect();
+        known.sort_by(|a, b| by_rank(&a.1, &b.1));
+        known
+            .into_iter()
+            .filter_map(|(server, _)| {
+                let s = self.servers.get(&server)?;
+                Some(UpstreamStatus {
+                    server: server.to_string(),
+                    srtt_ms: s.srtt_ms.map(|ms| ms.round() as u64),
+                    queries: s.queries,
+                    failures: s.failures,
+                    consecutive_failures: s.consecutive_failures,
+                    healthy: !s.backed_off(now),
+                    backoff_remaining_secs: s
+                        .backoff_until
+                        .filter(|t| *t > now)
+                        .map(|t| (t - now).as_secs()),
+                    last_success_secs_ago: s.last_success.map(|t| t.elapsed().as_secs()),
+                })
+            })
+            .collect()
+    }
+
+    pub fn clear(&self) {
+        self.servers.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Upstream {
+        Upstream::parse(s).unwrap()
+    }
+
+    #[test]
+    fn plain_entries_default_to_port_53() {
+        assert_eq!(addr("10.0.1.1"), Upstream::plain("10.0.1.1:53".parse().unwrap()));
+        assert_eq!(addr("[2001:db8::1]:5353").to_string(), "[2001:db8::1]:5353");
+        assert!(Upstream::parse("not-an-address").is_none());
+    }
+
+    #[test]
+    fn plain_entries_can_opt_out_of_case_randomization() {
+        let legacy = addr("10.0.1.1?no-0x20");
+        assert_eq!(
+            legacy,
+            Upstream::Plain {
+                addr: "10.0.1.1:53".parse().unwrap(),
+                randomize_case: false,
+                minimise: true,
+            }
+        );
+        assert_eq!(legacy.to_string(), "10.0.1.1:53?no-0x20");
+        assert!(Upstream::parse("10.0.1.1:53?bogus").is_none());
+    }
+
+    #[test]
+    fn any_entry_can_opt_out_of_qname_minimisation() {
+        let both = addr("10.0.1.1?no-0x20&no-qmin");
+        assert!(!both.minimise());
+        assert_eq!(both.to_string(), "10.0.1.1:53?no-0x20&no-qmin");
+        assert!(addr("10.0.1.1").minimise());
+
+        let dot = addr("tls://10.0.1.1?no-qmin#dns.corp.local");
+        assert!(!dot.minimise());
+        assert_eq!(dot.to_string(), "tls://10.0.1.1:853?no-qmin#dns.corp.local");
+
+        let doh = addr("https://dns.corp.local/dns-query?ct&no-qmin");
+        assert!(!doh.minimise());
+        assert_eq!(doh.to_string(), "https://dns.corp.local/dns-query?ct&no-qmin");
+        assert_eq!(addr(&doh.to_string()), doh);
+    }
+
+    #[test]
+    fn tls_entries_carry_their_certificate_name() {
+        let named = addr("tls://1.1.1.1#cloudflare-dns.com");
+        assert_eq!(
+            named,
+            Upstream::Tls {
+                host: "1.1.1.1".into(),
+                port: DOT_PORT,
+                server_name: "cloudflare-dns.com".into(),
+                pins: vec![],
+                minimise: true,
+            }
+        );
+        assert_eq!(named.to_string(), "tls://1.1.1.1:853#cloudflare-dns.com");
+        assert!(named.is_encrypted());
+
+        let Upstream::Tls { server_name, port, .. } = addr("tls://dns.quad9.net:8853") else {
+            panic!("expected a TLS upstream");
+        };
+        assert_eq!((server_name.as_str(), port), ("dns.quad9.net", 8853));
+    }
+
+    #[test]
+    fn pins_are_taken_out_of_the_query_string() {
+        let pin = BASE64.encode([7u8; 32]);
+        let Upstream::Https { url, pins, .. } =
+            addr(&format!("https://dns.example/dns-query?pin-sha256={pin}&ct"))
+        else {
+            panic!("expected an HTTPS upstream");
+        };
+        assert_eq!(url, "https://dns.example/dns-query?ct");
+        assert_eq!(pins, vec![[7u8; 32]]);
+
+        assert!(
+            Upstream::parse("tls://1.1.1.1?pin-sha256=c2hvcnQ=").is_none(),
+            "a pin that is not a SHA-256 digest is rejected"
+        );
+    }
+
+    #[test]
+    fn unknown_servers_keep_configured_order() {
+        let tracker = UpstreamTracker::new();
+        let servers = [addr("10.0.0.1:53"), addr("10.0.0.2:53")];
+        assert_eq!(tracker.order(&servers), servers.to_vec());
+    }
+
+    #[test]
+    fn the_fastest_healthy_server_goes_first() {
+        let tracker = UpstreamTracker::new();
+        let slow = addr("10.0.0.1:53");
+        let fast = addr("10.0.0.2:53");
+        tracker.record_success(&slow, Duration::from_millis(80));
+        tracker.record_success(&fast, Duration::from_millis(5));
+        assert_eq!(tracker.order(&[slow.clone(), fast.clone()]), vec![fast, slow]);
+    }
+
+    #[test]
+    fn a_failed_server_is_tried_last_until_it_answers_again() {
+        let tracker = UpstreamTracker::new();
+        let dead = addr("10.0.0.1:53");
+        let alive = addr("10.0.0.2:53");
+        tracker.record_success(&alive, Duration::from_millis(50));
+        tracker.record_failure(&dead);
+        assert_eq!(
+            tracker.order(&[dead.clone(), alive.clone()]),
+            vec![alive.clone(), dead.clone()]
+        );
+
+        let status = tracker.snapshot();
+        let row = status.iter().find(|s| s.server == "10.0.0.1:53").unwrap();
+        assert!(!row.healthy);
+        assert_eq!(row.consecutive_failures, 1);
+
+        tracker.record_success(&dead, Duration::from_millis(1));
+        assert_eq!(tracker.order(&[dead.clone(), alive.clone()]), vec![dead, alive]);
+    }
+
+    #[test]
+    fn back_off_grows_with_each_consecutive_failure_and_is_capped() {
+        let tracker = UpstreamTracker::new();
+        let dead = addr("10.0.0.1:53");
+        tracker.record_failure(&dead);
+        let first = tracker.snapshot()[0].backoff_remaining_secs.unwrap();
+        tracker.record_failure(&dead);
+        let second = tracker.snapshot()[0].backoff_remaining_secs.unwrap();
+        assert!(second > first);
+
+        for _ in 0..40 {
+            tracker.record_failure(&dead);
+        }
+        assert!(tracker.snapshot()[0].backoff_remaining_secs.unwrap() <= BACKOFF_MAX.as_secs());
+    }
+
+    #[test]
+    fn srtt_is_smoothed() {
+        let tracker = UpstreamTracker::new();
+        let server = addr("10.0.0.1:53");
+        tracker.record_success(&server, Duration::from_millis(100));
+        tracker.record_success(&server, Duration::from_millis(20));
+        assert_eq!(tracker.snapshot()[0].srtt_ms, Some(90));
+    }
+
+    #[test]
+    fn opt_outs_do_not_split_a_servers_history() {
+        let tracker = UpstreamTracker::new();
+        tracker.record_failure(&addr("10.0.0.1:53"));
+        tracker.record_failure(&addr("10.0.0.1:53?no-0x20&no-qmin"));
+        tracker.record_failure(&addr("tls://10.0.0.2#dns.corp.test"));
+        tracker.record_failure(&addr("tls://10.0.0.2?no-qmin#dns.corp.test"));
+
+        let status = tracker.snapshot();
+        assert_eq!(status.len(), 2, "{status:?}");
+        assert!(status.iter().all(|row| row.consecutive_failures == 2));
+        assert!(status.iter().any(|row| row.server == "tls://10.0.0.2:853#dns.corp.test"));
+    }
+}
@@ -1,4 +1,5 @@
 mod log_layer;
+mod telemetry;
 
 use anyhow::Result;
 use clap::Parser;
@@ -7,6 +8,7 @@ use microdns_auth::server::AuthServer;
 use microdns_core::config::Config;
 use microdns_core::db::Db;
 use microdns_core::log_buffer::LogBuffer;
+use telemetry::Telemetry;
 use microdns_core::types::{DhcpDbReservation, DhcpPool, DnsForwarder, InstanceMode};
 use microdns_federation::heartbeat::HeartbeatTracker;
 use std::net::SocketAddr;
@@ -117,8 +119,22 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Initialize logging
-    let log_buffer = init_logging(&config.logging);
+    // Initialize logging (and trace export, when configured)
+    let (log_buffer, telemetry) = init_logging(&config.logging, &config.instance.id)?;
+
+    // Metrics are recorded from here on and served by the REST API. Histograms
+    // keep samples until rendered, so they are folded in on a timer as well.
+    let metrics = microdns_api::metrics::install()?;
+    {
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let mut upkeep = tokio::time::interval(std::time::Duration::from_secs(5));
+            loop {
+                upkeep.tick().await;
+                metrics.run_upkeep();
+            }
+        });
+    }
 
     info!(
         instance_id = %config.instance.id,
@@ -397,16 +413,82 @@ async fn main() -> Result<()> {
         }));
     }
 
+    // dnstap: one writer, shared by the auth server and the recursor.
+    let dnstap = match config.logging.dnstap {
+        Some(ref dnstap_config) => {
+            microdns_core::dnstap::Dnstap::start(dnstap_config, &config.instance.id)?
+        }
+        None => None,
+    };
+
+    // Topology steering for load-balanced groups, shared by the auth server
+    // and the recursor. Stored in the database and applied live;
+    // `[dns.loadbalancer] topology` seeds it on first run only.
+    let topology = {
+        use microdns_core::config::LbTopologyConfig;
+        let section = microdns_api::rest::lb_topology::CONFIG_SECTION;
+        if let Some(ref lb_config) = config.dns.loadbalancer {
+            match db.get_runtime_section::<LbTopologyConfig>(section) {
+                Ok(None) => {
+                    let seed = LbTopologyConfig::from_lb(lb_config);
+                    if let Err(e) = db.set_runtime_section(section, &seed) {
+                        warn!("could not seed lb topology: {e}");
+                    } else {
+                        info!("lb topology: seeded stored rules from [dns.loadbalancer]");
+                    }
+                }
+                Ok(Some(_)) => {}
+                Err(e) => warn!("could not read stored lb topology: {e}"),
+            }
+        }
+        let stored = db
+            .get_runtime_section::<LbTopologyConfig>(section)
+            .unwrap_or_default()
+            .unwrap_or_default();
+        let topology = microdns_core::topology::TopologyState::new(&stored);
+
+        let watcher_db = db.clone();
+        let watcher_state = topology.clone();
+        let mut rx = shutdown_rx.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut tick = tokio::time::interval(std::time::Duration::from_secs(10));
+            tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            loop {
+                tokio::select! {
+                    _ = tick.tick() => {
+                        let stored = watcher_db
+                            .get_runtime_section::<LbTopologyConfig>(section)
+                            .unwrap_or_default()
+                            .unwrap_or_default();
+                        if watcher_state.replace(&stored) {
+                            info!("lb topology: rules changed — {}", watcher_state.summary());
+                        }
+                    }
+                    _ = rx.changed() => {
+                        if *rx.borrow() {
+                            break;
+                        }
+                    }
+                }
+            }
+        }));
+        topology
+    };
+
     // Start auth DNS server
     if let Some(ref auth_config) = config.dns.auth {
         if auth_config.enabled {
             let addr: SocketAddr = auth_config.listen.parse()?;
             let mut server = AuthServer::new(addr, db.clone())
                 .with_query_tracker(query_tracker.clone())
-                .with_transfer_state(transfer_state.clone());
+                .with_transfer_state(transfer_state.clone())
+                .with_topology(topology.clone());
             if let Some(acceptor) = notify_acceptor.take() {
                 server = server.with_notify_acceptor(acceptor);
             }
+            if let Some(ref dnstap) = dnstap {
+                server = server.with_dnstap(dnstap.clone());
+            }
             let rx = shutdown_rx.clone();
             tasks.push(tokio::spawn(async move {
                 if let Err(e) = server.run(rx).await {
@@ -523,14 +605,152 @@ async fn main() -> Result<()> {
 
     // Start recursive DNS server
     let mut recursor_cache = None;
+    let mut recursor_upstreams = None;
+    let mut recursor_rpz = None;
+    let mut recursor_local_data = None;
     if let Some(ref recursor_config) = config.dns.recursor {
         if recursor_config.enabled {
-            let server = microdns_recursor::RecursorServer::new(
+            let mut server = microdns_recursor::RecursorServer::new(
                 recursor_config,
                 Some(db.clone()),
-            )?;
+            )?
+            .with_topology(topology.clone());
+            if let Some(ref dnstap) = dnstap {
+                server = server.with_dnstap(dnstap.clone());
+            }
             // Share the recursor cache with the REST API so mutations can invalidate it
             recursor_cache = Some(server.resolver().cache_arc());
+            recursor_upstreams = Some(server.resolver().upstreams_arc());
+
+            // Warm start: load the cache saved at the last shutdown, and save
+            // it again at the next one.
+            if recursor_config.cache_persist {
+                let cache = server.resolver().cache_arc();
+                let loaded = cache.hydrate(&db);
+                info!("recursor cache: loaded {loaded} saved entries");
+
+                let db_save = db.clone();
+                let mut shutdown_save = shutdown_rx.clone();
+                tasks.push(tokio::spawn(async move {
+                    while shutdown_save.changed().await.is_ok() {
+                        if *shutdown_save.borrow() {
+                            break;
+                        }
+                    }
+                    match cache.save(&db_save) {
+                        Ok(saved) => info!("recursor cache: saved {saved} entries"),
+                        Err(e) => warn!("recursor cache: save failed: {e}"),
+                    }
+                }));
+            }
+
+            // Recursion access control, response policy and local data are
+            // stored in the database and applied live; `[dns.recursor]` seeds
+            // them on first run only.
+            {
+                use microdns_core::config::{LocalDataConfig, RecursionAccessConfig, RpzConfig};
+                let access_section = microdns_api::rest::recursion_access::CONFIG_SECTION;
+                let rpz_section = microdns_api::rest::rpz::CONFIG_SECTION;
+                let local_data_section = microdns_api::rest::local_data::CONFIG_SECTION;
+
+                match db.get_runtime_section::<RecursionAccessConfig>(access_section) {
+                    Ok(None) => {
+                        let seed = RecursionAccessConfig::from_recursor(recursor_config);
+                        if let Err(e) = db.set_runtime_section(access_section, &seed) {
+                            warn!("could not seed recursion access settings: {e}");
+                        } else {
+                            info!("recursion access: seeded stored settings from [dns.recursor]");
+                        }
+                    }
+                    Ok(Some(_)) => {}
+                    Err(e) => warn!("could not read stored recursion access settings: {e}"),
+                }
+                match db.get_runtime_section::<RpzConfig>(rpz_section) {
+                    Ok(None) => {
+                        let seed = RpzConfig::from_recursor(recursor_config);
+                        if let Err(e) = db.set_runtime_section(rpz_section, &seed) {
+                            warn!("could not seed response policy settings: {e}");
+                        } else {
+                            info!("rpz: seeded stored settings from [dns.recursor]");
+                        }
+                    }
+                    Ok(Some(_)) => {}
+                    Err(e) => warn!("could not read stored response policy settings: {e}"),
+                }
+                match db.get_runtime_section::<LocalDataConfig>(local_data_section) {
+                    Ok(None) => {
+                        let seed = LocalDataConfig::from_recursor(recursor_config);
+                        if let Err(e) = db.set_runtime_section(local_data_section, &seed) {
+                            warn!("could not seed local data settings: {e}");
+                        } else {
+                            info!("local data: seeded stored settings from [dns.recursor]");
+                        }
+                    }
+                    Ok(Some(_)) => {}
+                    Err(e) => warn!("could not read stored local data settings: {e}"),
+                }
+
+                let access = server.resolver().access();
+                let rpz = server.resolver().rpz();
+                recursor_rpz = Some(rpz.clone());
+                let local_data = server.resolver().local_data();
+                let hosts_dir = recursor_config.hosts_dir.clone().unwrap_or_else(|| {
+                    config
+                        .database
+                        .path
+                        .parent()
+                        .map(std::path::Path::to_path_buf)
+                        .unwrap_or_default()
+                });
+                local_data.set_hosts_policy(microdns_recursor::local_data::HostsPolicy::new(
+                    hosts_dir,
+                    recursor_config.hosts_files.clone(),
+                ));
+                recursor_local_data = Some(local_data.clone());
+                let refresh = move |db: &microdns_core::db::Db, startup: bool| {
+                    let stored = db
+                        .get_runtime_section::<RecursionAccessConfig>(access_section)
+                        .unwrap_or_default()
+                        .unwrap_or_default();
+                    if access.replace(&stored) {
+                        info!("recursion access: {}", access.summary());
+                    }
+                    let stored = db
+                        .get_runtime_section::<RpzConfig>(rpz_section)
+                        .unwrap_or_default()
+                        .unwrap_or_default();
+                    if rpz.refresh(db, &stored) || startup {
+                        info!("rpz: {}", rpz.summary());
+                    }
+                    // Also picks up edits to the hosts files.
+                    let stored = db
+                        .get_runtime_section::<LocalDataConfig>(local_data_section)
+                        .unwrap_or_default()
+                        .unwrap_or_default();
+                    if local_data.refresh(&stored) {
+                        info!("local data: {}", local_data.summary());
+                    }
+                };
+                refresh(&db, true);
+
+                let watcher_db = db.clone();
+                let mut rx = shutdown_rx.clone();
+                tasks.push(tokio::spawn(async move {
+                    let mut tick = tokio::time::interval(std::time::Duration::from_secs(10));
+                    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+                    loop {
+                        tokio::select! {
+                            _ = tick.tick() => refresh(&watcher_db, false),
+                            _ = rx.changed() => {
+                                if *rx.borrow() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }));
+            }
+
             let rx = shutdown_rx.clone();
             tasks.push(tokio::spawn(async move {
                 if let Err(e) = server.run(rx).await {
@@ -549,6 +769,9 @@ async fn main() -> Result<()> {
                 "http" => ProbeType::Http,
                 "https" => ProbeType::Https,
                 "tcp" => ProbeType::Tcp,
+                "dns" => ProbeType::Dns,
+                "tls" => ProbeType::Tls,
+                "grpc" => ProbeType::Grpc,
                 "tcp_half_open" | "tcp-half-open" | "half_open" | "halfopen" => {
                     ProbeType::TcpHalfOpen
                 }
@@ -759,17 +982,27 @@ async fn main() -> Result<()> {
                 .with_peers(config.instance.peers.clone())
                 .with_dhcp_status(dhcp_status)
                 .with_log_buffer(log_buffer.clone())
-                .with_dashboard_addr(dashboard_addr);
+                .with_dashboard_addr(dashboard_addr)
+                .with_metrics(metrics.clone());
 
             if let Some(cache) = recursor_cache.clone() {
                 api = api.with_recursor_cache(cache);
             }
+            if let Some(upstreams) = recursor_upstreams.clone() {
+                api = api.with_recursor_upstreams(upstreams);
+            }
+            if let Some(rpz) = recursor_rpz.clone() {
+                api = api.with_recursor_rpz(rpz);
+            }
+            if let Some(local_data) = recursor_local_data.clone() {
+                api = api.with_recursor_local_data(local_data);
+            }
 
             if config.instance.mode == InstanceMode::Coordinator {
                 api = api.with_heartbeat_tracker(heartbeat_tracker.clone());
             }
 
-            if let Some(handles) = lb_handles.take() {
+            if let Some(handles) = lb_handles.clone() {
                 api = api.with_lb(handles);
             }
             if let Some(handle) = mdns_handle.clone() {
@@ -832,6 +1065,9 @@ async fn main() -> Result<()> {
             if config.instance.mode == InstanceMode::Coordinator {
                 grpc = grpc.with_heartbeat_tracker(heartbeat_tracker.clone());
             }
+            if let Some(handles) = lb_handles.take() {
+                grpc = grpc.with_lb(handles);
+            }
 
             let rx = shutdown_rx.clone();
             tasks.push(tokio::spawn(async move {
@@ -872,6 +1108,11 @@ async fn main() -> Result<()> {
         warn!("shutdown timed out after 8s, exiting");
     }
 
+    // Export the spans still batched
+    if let Some(telemetry) = telemetry {
+        let _ = tokio::task::spawn_blocking(move || telemetry.shutdown()).await;
+    }
+
     info!("microdns stopped");
     Ok(())
 }
@@ -1033,7 +1274,10 @@ fn migrate_toml_to_db(config: &Config, db: &Db) {
     info!("TOML → database migration complete");
 }
 
-fn init_logging(config: &microdns_core::config::LoggingConfig) -> Arc<LogBuffer> {
+fn init_logging(
+    config: &microdns_core::config::LoggingConfig,
+    instance_id: &str,
+) -> Result<(Arc<LogBuffer>, Option<Telemetry>)> {
     use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::util::SubscriberInitExt;
     use tracing_subscriber::EnvFilter;
@@ -1044,12 +1288,18 @@ fn init_logging(config: &microdns_core::config::LoggingConfig) -> Arc<LogBuffer>
     let log_buffer = Arc::new(LogBuffer::new(1000));
     let buffer_layer = log_layer::LogBufferLayer::new(log_buffer.clone());
 
+    let telemetry = match config.otlp {
+        Some(ref otlp) => Telemetry::start(otlp, instance_id)?,
+        None => None,
+    };
+
     match config.format.as_str() {
         "json" => {
             tracing_subscriber::registry()
                 .with(filter)
                 .with(tracing_subscriber::fmt::layer().json())
                 .with(buffer_layer)
+                .with(telemetry.as_ref().map(|t| t.layer()))
                 .init();
         }
         _ => {
@@ -1057,9 +1307,10 @@ fn init_logging(config: &microdns_core::config::LoggingConfig) -> Arc<LogBuffer>
                 .with(filter)
                 .with(tracing_subscriber::fmt::layer())
                 .with(buffer_layer)
+                .with(telemetry.as_ref().map(|t| t.layer()))
                 .init();
         }
     }
 
-    log_buffer
+    Ok((log_buffer, telemetry))
 }
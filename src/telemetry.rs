@@ -0,0 +1,144 @@
+//! OpenTelemetry traces, exported over OTLP/gRPC.
+//!
+//! The servers open `tracing` spans around the work worth following — a DNS
+//! query, a resolution and its upstream attempts, a DHCP packet, a REST or gRPC
+//! request — and the layer built here turns them into OpenTelemetry spans.
+//! Trace context travels between instances in W3C `traceparent` headers.
+
+use microdns_core::config::OtlpConfig;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, Tracer, TracerProvider};
+use opentelemetry_sdk::{runtime, Resource};
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// How long an export may take before it is given up.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A running exporter. Spans are batched and sent in the background; call
+/// [`Telemetry::shutdown`] before exiting so the last batch is not lost.
+pub struct Telemetry {
+    provider: TracerProvider,
+}
+
+impl Telemetry {
+    /// Start exporting to the configured collector, or `None` when tracing is
+    /// off. Must be called within a Tokio runtime.
+    pub fn start(config: &OtlpConfig, instance_id: &str) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(&config.endpoint)
+            .with_timeout(EXPORT_TIMEOUT)
+            .build()?;
+        let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio.clamp(0.0, 1.0),
+        )));
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_sampler(sampler)
+            .with_resource(Resource::new([
+                KeyValue::new("service.name", config.service_name.clone()),
+                KeyValue::new("service.instance.id", instance_id.to_string()),
+            ]))
+            .build();
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        Ok(Some(Self { provider }))
+    }
+
+    /// The layer to add to the `tracing` subscriber.
+    pub fn layer<S>(&self) -> OpenTelemetryLayer<S, Tracer>
+    where
+        S: Subscriber + for<'span> LookupSpan<'span>,
+    {
+        tracing_opentelemetry::layer().with_tracer(self.provider.tracer("microdns"))
+    }
+
+    /// Send what has been batched and stop. Blocks until the collector has
+    /// answered or the export timed out, so call it from a blocking thread.
+    pub fn shutdown(&self) {
+        if let Err(e) = self.provider.shutdown() {
+            tracing::warn!("otlp: shutdown failed: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use tokio::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// A collector that hands every exported span name to the test.
+    struct Collector(mpsc::UnboundedSender<String>);
+
+    #[tonic::async_trait]
+    impl TraceService for Collector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            for resource in request.into_inner().resource_spans {
+                for scope in resource.scope_spans {
+                    for span in scope.spans {
+                        let _ = self.0.send(span.name);
+                    }
+                }
+            }
+            Ok(tonic::Response::new(ExportTraceServiceResponse {
+                partial_success: None,
+            }))
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_reach_the_collector() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        tokio::spawn(
+            tonic::transport::Server::builder()
+                .add_service(TraceServiceServer::new(Collector(tx)))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+
+        let config = OtlpConfig {
+            endpoint: format!("http://{addr}"),
+            ..Default::default()
+        };
+        let telemetry = Telemetry::start(&config, "test").unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(telemetry.layer());
+        tracing::subscriber::with_default(subscriber, || {
+            let _query = tracing::info_span!("dns.query").entered();
+            let _upstream = tracing::info_span!("recursor.upstream").entered();
+        });
+        tokio::task::spawn_blocking(move || telemetry.shutdown())
+            .await
+            .unwrap();
+
+        let mut names = Vec::new();
+        while let Ok(Some(name)) =
+            tokio::time::timeout(Duration::from_secs(5), rx.recv()).await
+        {
+            names.push(name);
+            if names.len() == 2 {
+                break;
+            }
+        }
+        names.sort();
+        assert_eq!(names, ["dns.query", "recursor.upstream"]);
+    }
+}